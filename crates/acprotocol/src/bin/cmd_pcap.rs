@@ -2,12 +2,21 @@ use anyhow::Result;
 use clap::{Parser, Subcommand};
 
 use acprotocol::cli::pcap::{
-    DirectionFilter, OutputFormat, SortField, format_parsed_messages, format_raw_messages,
-    output_messages, print_summary,
+    ChatFormat, DirectionFilter, MessageFilter, MessageSummary, OutputFormat, SortField,
+    format_bandwidth, format_chat, format_inventory, format_packets, format_reliability,
+    format_sessions, format_timing, output_messages, print_summary, select_packets,
+    stream_messages,
 };
 use acprotocol::cli::tui;
-use acprotocol::network::FragmentAssembler;
 use acprotocol::network::pcap;
+use acprotocol::network::udp::LINKTYPE_ETHERNET;
+use acprotocol::network::{
+    BandwidthAnalyzer, CaptureAnonymizer, DatagramStream, FragmentAssembler,
+    FragmentAssemblerOptions, LiveStream, MessageStream, ReliabilityAnalyzer, SessionTracker,
+    TimingAnalyzer, UdpProxy,
+};
+use acprotocol::world::{ChatLog, Inventory};
+use std::net::{SocketAddr, ToSocketAddrs};
 
 #[derive(Parser)]
 #[command(name = "pcap")]
@@ -66,11 +75,185 @@ pub enum Commands {
         raw: bool,
     },
 
-    /// Launch interactive TUI
-    Tui {
+    /// Show decoded AC packets (headers, optional headers and fragments)
+    Packets {
+        /// PCAP file to parse
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// Limit number of results
+        #[arg(short, long)]
+        limit: Option<usize>,
+
+        /// Output format
+        #[arg(short, long, default_value = "jsonl")]
+        output: OutputFormat,
+
+        /// Show passwords and GLS tickets in login requests instead of redacting them
+        #[arg(long)]
+        reveal_credentials: bool,
+    },
+
+    /// Report packet loss, retransmissions and ACK latency per session
+    Reliability {
+        /// PCAP file to parse
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// Output format
+        #[arg(short, long, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Report round trip times and clock drift per session from echo and
+    /// time sync headers
+    Timing {
+        /// PCAP file to parse
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// Output format
+        #[arg(short, long, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Show each client's logical session, following it from the login
+    /// server across referrals and server switches
+    Sessions {
+        /// PCAP file to parse
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// Output format
+        #[arg(short, long, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Show the player's equipped items, main pack and side packs at the end
+    /// of a capture
+    Inventory {
+        /// PCAP file to parse
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// Output format
+        #[arg(short, long, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Export the chat heard and sent in a capture: speech, tells, emotes,
+    /// channels and system text
+    Chat {
+        /// PCAP file to parse
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// Output format
+        #[arg(short, long, default_value = "text")]
+        output: ChatFormat,
+    },
+
+    /// Show message counts and reassembly diagnostics, or traffic per
+    /// session with --bandwidth
+    Stats {
         /// PCAP file to parse
         #[arg(value_name = "FILE", required = true)]
         file: String,
+
+        /// Report bytes per second by direction, message type and queue,
+        /// alongside the FLOW headers each side sent
+        #[arg(long)]
+        bandwidth: bool,
+
+        /// Output format for --bandwidth
+        #[arg(short, long, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Copy the packets of matching messages into a new capture
+    Filter {
+        /// PCAP file to read
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// PCAP file to write
+        #[arg(value_name = "OUTPUT", required = true)]
+        output: String,
+
+        /// Filter by message ID
+        #[arg(short = 'i', long)]
+        id: Option<u32>,
+
+        /// Filter by message type (substring match)
+        #[arg(short = 't', long)]
+        filter_type: Option<String>,
+
+        /// Filter by opcode (hex like 0xF7B1 or decimal like 63409)
+        #[arg(short = 'c', long)]
+        filter_opcode: Option<String>,
+
+        /// Filter by direction (Send/Recv)
+        #[arg(short = 'd', long)]
+        direction: Option<DirectionFilter>,
+    },
+
+    /// Write a copy of a capture with names, chat and addresses replaced
+    Anonymize {
+        /// PCAP file to read
+        #[arg(value_name = "FILE", required = true)]
+        file: String,
+
+        /// PCAP file to write
+        #[arg(value_name = "OUTPUT", required = true)]
+        output: String,
+    },
+
+    /// Relay a client's traffic to a server and show its messages as they
+    /// arrive
+    Live {
+        /// Local address for the client to connect to. The port after it is
+        /// relayed to the port after the server's.
+        #[arg(short = 'L', long, default_value = "127.0.0.1:9000")]
+        listen: SocketAddr,
+
+        /// Server to relay to, as host:port
+        #[arg(short = 'S', long)]
+        server: String,
+
+        /// Also record the relayed traffic to this PCAP file
+        #[arg(short = 'w', long)]
+        write: Option<String>,
+
+        /// Output format
+        #[arg(short, long, default_value = "jsonl")]
+        output: OutputFormat,
+
+        /// Print raw message data as hex instead of parsed content
+        #[arg(long)]
+        raw: bool,
+    },
+
+    /// Launch interactive TUI
+    Tui {
+        /// PCAP file to parse
+        #[arg(value_name = "FILE", required_unless_present = "live")]
+        file: Option<String>,
+
+        /// Relay a client's traffic to --server and show it as it arrives
+        #[arg(long, requires = "server", conflicts_with = "file")]
+        live: bool,
+
+        /// Local address for the client to connect to with --live
+        #[arg(short = 'L', long, default_value = "127.0.0.1:9000")]
+        listen: SocketAddr,
+
+        /// Server to relay to with --live, as host:port
+        #[arg(short = 'S', long)]
+        server: Option<String>,
+
+        /// Also record the relayed traffic to this PCAP file
+        #[arg(short = 'w', long, requires = "live")]
+        write: Option<String>,
     },
 }
 
@@ -87,6 +270,38 @@ fn setup_tracing() {
         .init();
 }
 
+/// Relay `listen` to `server` and the port after each to the port after
+/// the other, which is where AC servers take the ConnectResponse
+fn bind_proxy(listen: SocketAddr, server: &str) -> Result<UdpProxy> {
+    let server = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No address found for server '{server}'"))?;
+    let next = |address: SocketAddr| match address.port() {
+        // Let the OS pick both ports
+        0 => address,
+        port => SocketAddr::new(address.ip(), port.wrapping_add(1)),
+    };
+    let proxy = UdpProxy::bind(&[(listen, server), (next(listen), next(server))])?;
+    eprintln!(
+        "Relaying {} to {server}",
+        proxy
+            .local_addrs()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" and ")
+    );
+    Ok(proxy)
+}
+
+/// Note datagrams an analysis left out because they couldn't be parsed
+fn report_skipped(skipped: usize) {
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} malformed datagrams");
+    }
+}
+
 fn main() -> Result<()> {
     #[cfg(feature = "tracing")]
     setup_tracing();
@@ -109,35 +324,37 @@ fn main() -> Result<()> {
         }) => {
             let file_path = file;
 
-            // Load PCAP file and parse packets
-            let mut assembler = FragmentAssembler::new();
-            let mut messages = Vec::new();
-
-            let pcap_iter = pcap::open(&file_path)?;
-            for packet_result in pcap_iter {
-                let packet = packet_result?;
-                let parsed_messages = assembler.parse_packet_payload(&packet.data)?;
-                messages.extend(parsed_messages);
-            }
+            // Messages are reassembled lazily as they're consumed, so only
+            // sorting needs the (filtered) messages held in memory
+            let mut stream = MessageStream::new(pcap::open(&file_path)?);
+            let mut error = None;
+            let messages = stream
+                .by_ref()
+                .map_while(|result| result.map_err(|e| error = Some(e)).ok());
+            let filter = MessageFilter::new(
+                id,
+                filter_type.as_deref(),
+                filter_opcode.as_deref(),
+                direction,
+            )?;
 
             if summary {
-                print_summary(&messages);
-            } else if id.is_none()
-                && filter_type.is_none()
-                && filter_opcode.is_none()
-                && direction.is_none()
-                && limit.is_none()
-            {
-                // If no filters are applied, print all messages (like the original cat command)
-                if raw {
-                    format_raw_messages(&messages, output);
-                } else {
-                    format_parsed_messages(&messages, output);
+                let mut totals = MessageSummary::default();
+                for message in messages {
+                    totals.add(&message);
+                }
+                if let Some(e) = error {
+                    return Err(e.into());
                 }
+                print_summary(&totals, stream.diagnostics());
+                report_skipped(stream.skipped());
+            } else if matches!(sort, SortField::Id) && !reverse {
+                // Messages already come out in id order
+                stream_messages(messages, &filter, limit, output, raw);
             } else {
-                // If any filters are applied, use the filtering logic
+                let filtered: Vec<_> = messages.filter(|m| filter.matches(m)).collect();
                 output_messages(
-                    &messages,
+                    &filtered,
                     id,
                     filter_type.as_deref(),
                     filter_opcode.as_deref(),
@@ -147,15 +364,250 @@ fn main() -> Result<()> {
                     limit,
                     output,
                     raw,
+                )?;
+            }
+
+            if let Some(e) = error {
+                return Err(e.into());
+            }
+            if !summary {
+                report_skipped(stream.skipped());
+            }
+        }
+        Some(Commands::Packets {
+            file,
+            limit,
+            output,
+            reveal_credentials,
+        }) => {
+            let assembler = FragmentAssembler::with_options(FragmentAssemblerOptions {
+                reveal_credentials,
+                ..Default::default()
+            });
+            let mut datagrams = DatagramStream::with_assembler(pcap::open(&file)?, assembler);
+            let mut packets = Vec::new();
+
+            for datagram in datagrams.by_ref() {
+                packets.extend(datagram?.0.packets);
+                if limit.is_some_and(|lim| packets.len() >= lim) {
+                    break;
+                }
+            }
+
+            if let Some(lim) = limit {
+                packets.truncate(lim);
+            }
+            format_packets(&packets, output);
+            report_skipped(datagrams.skipped());
+        }
+        Some(Commands::Reliability { file, output }) => {
+            let mut datagrams = DatagramStream::new(pcap::open(&file)?);
+            let mut analyzer = ReliabilityAnalyzer::new();
+            for datagram in datagrams.by_ref() {
+                let (parsed, time) = datagram?;
+                for record in &parsed.packets {
+                    analyzer.add_packet(record, Some(time));
+                }
+            }
+
+            format_reliability(&analyzer.finish(), output);
+            report_skipped(datagrams.skipped());
+        }
+        Some(Commands::Timing { file, output }) => {
+            let mut datagrams = DatagramStream::new(pcap::open(&file)?);
+            let mut analyzer = TimingAnalyzer::new();
+            for datagram in datagrams.by_ref() {
+                let (parsed, time) = datagram?;
+                for record in &parsed.packets {
+                    analyzer.add_packet(record, Some(time));
+                }
+            }
+
+            format_timing(&analyzer.finish(), output);
+            report_skipped(datagrams.skipped());
+        }
+        Some(Commands::Sessions { file, output }) => {
+            let mut datagrams = DatagramStream::new(pcap::open(&file)?);
+            let mut tracker = SessionTracker::new();
+            for datagram in datagrams.by_ref() {
+                let (parsed, time) = datagram?;
+                tracker.add_datagram(&parsed, Some(time));
+            }
+
+            format_sessions(tracker.sessions(), output);
+            report_skipped(datagrams.skipped());
+        }
+        Some(Commands::Inventory { file, output }) => {
+            let mut stream = MessageStream::new(pcap::open(&file)?);
+            let mut inventory = Inventory::new();
+            for message in stream.by_ref() {
+                inventory.apply_raw(&message?);
+            }
+
+            format_inventory(&inventory.layout(), output);
+            report_skipped(stream.skipped());
+        }
+        Some(Commands::Chat { file, output }) => {
+            let mut stream = MessageStream::new(pcap::open(&file)?);
+            let mut log = ChatLog::new();
+            for message in stream.by_ref() {
+                log.apply_raw(&message?);
+            }
+
+            format_chat(log.lines(), output);
+            report_skipped(stream.skipped());
+        }
+        Some(Commands::Stats {
+            file,
+            bandwidth: true,
+            output,
+        }) => {
+            let mut datagrams = DatagramStream::new(pcap::open(&file)?);
+            let mut analyzer = BandwidthAnalyzer::new();
+            for datagram in datagrams.by_ref() {
+                let (parsed, time) = datagram?;
+                analyzer.add_datagram(&parsed, Some(time));
+            }
+
+            format_bandwidth(&analyzer.finish(), output);
+            report_skipped(datagrams.skipped());
+        }
+        Some(Commands::Stats { file, .. }) => {
+            let mut stream = MessageStream::new(pcap::open(&file)?);
+            let mut totals = MessageSummary::default();
+            for message in stream.by_ref() {
+                totals.add(&message?);
+            }
+            print_summary(&totals, stream.diagnostics());
+            report_skipped(stream.skipped());
+        }
+        Some(Commands::Filter {
+            file,
+            output,
+            id,
+            filter_type,
+            filter_opcode,
+            direction,
+        }) => {
+            let filter = MessageFilter::new(
+                id,
+                filter_type.as_deref(),
+                filter_opcode.as_deref(),
+                direction,
+            )?;
+            let selection = select_packets(pcap::open(&file)?, &filter)?;
+
+            // Second pass to copy the selected packets as they were captured
+            let mut writer = None;
+            for (index, packet) in pcap::open(&file)?.enumerate() {
+                let packet = packet?;
+                if !selection.packets.contains(&index) {
+                    continue;
+                }
+                let writer = match &mut writer {
+                    Some(writer) => writer,
+                    None => writer.insert(pcap::create(&output, packet.link_type)?),
+                };
+                writer.write_packet(&packet)?;
+            }
+            match writer {
+                Some(mut writer) => writer.flush()?,
+                None => pcap::create(&output, LINKTYPE_ETHERNET)?.flush()?,
+            }
+
+            println!(
+                "Wrote {} packets holding {} messages to {}",
+                selection.packets.len(),
+                selection.messages,
+                output
+            );
+            report_skipped(selection.skipped);
+        }
+        Some(Commands::Anonymize { file, output }) => {
+            let mut anonymizer = CaptureAnonymizer::new();
+            anonymizer.scan(pcap::open(&file)?)?;
+
+            let mut writer = pcap::create(&output, LINKTYPE_ETHERNET)?;
+            anonymizer.write(pcap::open(&file)?, &mut writer)?;
+            writer.flush()?;
+
+            let stats = anonymizer.stats();
+            println!(
+                "Wrote {} packets to {}, anonymizing {} of {} messages",
+                stats.packets, output, stats.anonymized, stats.messages
+            );
+            if stats.blanked > 0 {
+                println!(
+                    "{} messages had a replacement that didn't fit and were blanked",
+                    stats.blanked
                 );
             }
+            if stats.undecoded + stats.incomplete > 0 {
+                println!(
+                    "{} undecodable and {} incomplete messages were blanked",
+                    stats.undecoded, stats.incomplete
+                );
+            }
+            if stats.dropped_frames > 0 {
+                println!(
+                    "Left out {} frames that weren't AC traffic",
+                    stats.dropped_frames
+                );
+            }
+        }
+        Some(Commands::Live {
+            listen,
+            server,
+            write,
+            output,
+            raw,
+        }) => {
+            let proxy = bind_proxy(listen, &server)?;
+            let filter = MessageFilter::default();
+            let mut error = None;
+            match write {
+                Some(write) => {
+                    let stream =
+                        LiveStream::recording(proxy, pcap::create(write, LINKTYPE_ETHERNET)?)?;
+                    let messages =
+                        stream.map_while(|result| result.map_err(|e| error = Some(e)).ok());
+                    stream_messages(messages, &filter, None, output, raw);
+                }
+                None => {
+                    let messages = LiveStream::new(proxy)
+                        .map_while(|result| result.map_err(|e| error = Some(e)).ok());
+                    stream_messages(messages, &filter, None, output, raw);
+                }
+            }
+            if let Some(e) = error {
+                return Err(e.into());
+            }
         }
-        Some(Commands::Tui { file }) => {
+        Some(Commands::Tui {
+            file,
+            live,
+            listen,
+            server,
+            write,
+        }) => {
             // Launch the TUI
-            let file_path = file;
-
-            let path = std::path::Path::new(&file_path);
-            tui::run(path)?;
+            match (file, server) {
+                (_, Some(server)) if live => {
+                    let proxy = bind_proxy(listen, &server)?;
+                    match write {
+                        Some(write) => tui::run_live(LiveStream::recording(
+                            proxy,
+                            pcap::create(write, LINKTYPE_ETHERNET)?,
+                        )?)?,
+                        None => tui::run_live(LiveStream::new(proxy))?,
+                    }
+                }
+                (Some(file_path), _) => {
+                    let path = std::path::Path::new(&file_path);
+                    tui::run(path)?;
+                }
+                _ => unreachable!("clap requires a file or --live with --server"),
+            }
         }
         None => {}
     }
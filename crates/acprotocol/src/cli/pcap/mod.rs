@@ -2,6 +2,12 @@ mod output;
 mod processing;
 mod types;
 
-pub use output::{format_parsed_messages, format_raw_messages, print_summary};
-pub use processing::output_messages;
-pub use types::{DirectionFilter, OutputFormat, RawMessageOutput, SortField};
+pub use output::{
+    MessageSummary, format_bandwidth, format_chat, format_inventory, format_packets,
+    format_parsed_messages, format_raw_messages, format_reliability, format_sessions,
+    format_timing, print_summary,
+};
+pub use processing::{
+    CaptureSelection, MessageFilter, output_messages, select_packets, stream_messages,
+};
+pub use types::{ChatFormat, DirectionFilter, OutputFormat, RawMessageOutput, SortField};
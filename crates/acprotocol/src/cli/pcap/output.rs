@@ -1,8 +1,16 @@
+use std::borrow::Borrow;
 use std::collections::HashMap;
 
-use crate::network::RawMessage;
+use crate::message::Direction;
+use crate::network::{
+    BandwidthReport, ChecksumStatus, DirectionBandwidth, EvictionReason, FlowReliability,
+    LatencyStats, LogicalSession, PacketRecord, RawMessage, ReassemblyDiagnostics,
+    ReliabilityReport, TimingReport, TrafficCount, format_capture_time,
+};
 
-use super::types::{OutputFormat, RawMessageOutput};
+use crate::world::{ChatChannel, ChatLine, InventoryItem, InventoryLayout};
+
+use super::types::{ChatFormat, OutputFormat, RawMessageOutput};
 
 /// Truncate a string to a maximum length, adding "..." if truncated
 pub fn truncate(s: &str, max_len: usize) -> String {
@@ -13,24 +21,43 @@ pub fn truncate(s: &str, max_len: usize) -> String {
     }
 }
 
+/// Message counts collected one message at a time, so a summary doesn't
+/// need the whole capture in memory
+#[derive(Debug, Default)]
+pub struct MessageSummary {
+    pub total: usize,
+    pub send: usize,
+    pub recv: usize,
+    pub type_counts: HashMap<String, usize>,
+}
+
+impl MessageSummary {
+    pub fn add(&mut self, message: &RawMessage) {
+        self.total += 1;
+        match message.direction {
+            Direction::ClientToServer => self.send += 1,
+            Direction::ServerToClient => self.recv += 1,
+        }
+        match self.type_counts.get_mut(&message.message_type) {
+            Some(count) => *count += 1,
+            None => {
+                self.type_counts.insert(message.message_type.clone(), 1);
+            }
+        }
+    }
+}
+
 /// Print summary statistics for a collection of messages
-pub fn print_summary(messages: &[RawMessage]) {
+pub fn print_summary(summary: &MessageSummary, diagnostics: &ReassemblyDiagnostics) {
     println!("=== PCAP Summary ===\n");
 
-    println!("Messages: {}", messages.len());
+    println!("Messages: {}", summary.total);
 
-    let send_msgs = messages.iter().filter(|m| m.direction == "Send").count();
-    let recv_msgs = messages.iter().filter(|m| m.direction == "Recv").count();
     println!("\nMessages by Direction:");
-    println!("  Send (C→S): {send_msgs}");
-    println!("  Recv (S→C): {recv_msgs}");
-
-    let mut type_counts: HashMap<&str, usize> = HashMap::new();
-    for msg in messages {
-        *type_counts.entry(&msg.message_type).or_insert(0) += 1;
-    }
+    println!("  Send (C→S): {}", summary.send);
+    println!("  Recv (S→C): {}", summary.recv);
 
-    let mut sorted_types: Vec<_> = type_counts.iter().collect();
+    let mut sorted_types: Vec<_> = summary.type_counts.iter().collect();
     sorted_types.sort_by(|a, b| b.1.cmp(a.1));
 
     println!("\nMessage Types (top 20):");
@@ -41,49 +68,98 @@ pub fn print_summary(messages: &[RawMessage]) {
     if sorted_types.len() > 20 {
         println!("  ... and {} more types", sorted_types.len() - 20);
     }
+
+    print_reassembly_diagnostics(diagnostics);
+}
+
+/// Print fragment reassembly problems found in a capture
+fn print_reassembly_diagnostics(diagnostics: &ReassemblyDiagnostics) {
+    println!("\nReassembly:");
+    println!("  Completed messages:   {:>5}", diagnostics.completed);
+    println!(
+        "  Duplicate chunks:     {:>5}",
+        diagnostics.duplicate_chunks
+    );
+    println!(
+        "  Retransmitted chunks: {:>5}",
+        diagnostics.retransmitted_chunks
+    );
+    println!(
+        "  Out-of-range chunks:  {:>5}",
+        diagnostics.out_of_range_chunks
+    );
+    println!(
+        "  Malformed fragments:  {:>5}",
+        diagnostics.malformed_fragments
+    );
+    println!(
+        "  Malformed datagrams:  {:>5}",
+        diagnostics.malformed_datagrams
+    );
+    println!(
+        "  Invalid checksums:    {:>5}",
+        diagnostics.invalid_checksums
+    );
+    let dropped = diagnostics.dropped_total();
+    println!("  Dropped messages:     {:>5}", dropped);
+
+    if dropped == 0 {
+        return;
+    }
+
+    for (label, reason) in [
+        ("packet age", EvictionReason::PacketAge),
+        ("time", EvictionReason::Time),
+        ("memory cap", EvictionReason::MemoryCap),
+        ("end of capture", EvictionReason::EndOfCapture),
+    ] {
+        let count = diagnostics.dropped_for(reason);
+        if count > 0 {
+            println!("    {label:18} {count:>5}");
+        }
+    }
+
+    println!("\nDropped Messages (first 20):");
+    for dropped in diagnostics.dropped.iter().take(20) {
+        println!(
+            "  seq {:>8}  id {:#010x}  group {:>3}  {:>3}/{:<3} chunks  {} -> {}",
+            dropped.sequence,
+            dropped.id,
+            dropped.group,
+            dropped.received,
+            dropped.count,
+            dropped.source,
+            dropped.destination
+        );
+    }
+
+    if dropped > 20 {
+        println!("  ... and {} more", dropped - 20);
+    }
 }
 
 /// Helper function to format and output messages in raw format (with hex data)
-pub fn format_raw_messages<'a, I>(messages: I, output: OutputFormat)
+///
+/// JSONL and table output are written as messages arrive, so `messages` can
+/// be a lazy stream.
+pub fn format_raw_messages<I>(messages: I, output: OutputFormat)
 where
-    I: IntoIterator<Item = &'a RawMessage>,
+    I: IntoIterator,
+    I::Item: Borrow<RawMessage>,
 {
-    let messages: Vec<_> = messages.into_iter().collect();
+    let messages = messages.into_iter();
 
     match output {
         OutputFormat::Jsonl => {
             for msg in messages {
-                let raw_output = RawMessageOutput {
-                    id: msg.id,
-                    opcode: msg.opcode,
-                    message_type: msg.message_type.clone(),
-                    direction: msg.direction.clone(),
-                    queue: msg.queue.as_ref().map(|q| format!("{:?}", q)),
-                    data_len: msg.data.len(),
-                    raw: hex::encode(&msg.data),
-                    sequence: msg.sequence,
-                    iteration: msg.iteration,
-                    header_flags: msg.header_flags,
-                };
-                println!("{}", serde_json::to_string(&raw_output).unwrap());
+                println!(
+                    "{}",
+                    serde_json::to_string(&raw_output(msg.borrow())).unwrap()
+                );
             }
         }
         OutputFormat::Json => {
-            let raw_outputs: Vec<_> = messages
-                .iter()
-                .map(|msg| RawMessageOutput {
-                    id: msg.id,
-                    opcode: msg.opcode,
-                    message_type: msg.message_type.clone(),
-                    direction: msg.direction.clone(),
-                    queue: msg.queue.as_ref().map(|q| format!("{:?}", q)),
-                    data_len: msg.data.len(),
-                    raw: hex::encode(&msg.data),
-                    sequence: msg.sequence,
-                    iteration: msg.iteration,
-                    header_flags: msg.header_flags,
-                })
-                .collect();
+            let raw_outputs: Vec<_> = messages.map(|msg| raw_output(msg.borrow())).collect();
             println!("{}", serde_json::to_string_pretty(&raw_outputs).unwrap());
         }
         OutputFormat::Table => {
@@ -93,6 +169,7 @@ where
             );
             println!("{}", "-".repeat(140));
             for msg in messages {
+                let msg = msg.borrow();
                 let hex_data = hex::encode(&msg.data);
                 let truncated_hex = if hex_data.len() > 50 {
                     format!("{}...", &hex_data[..50])
@@ -103,7 +180,7 @@ where
                     "{:>6}  {:40}  {:>6}  {:#06x}  {:>6}  {}",
                     msg.id,
                     truncate(&msg.message_type, 40),
-                    msg.direction,
+                    msg.direction(),
                     msg.opcode,
                     msg.data.len(),
                     truncated_hex
@@ -113,34 +190,519 @@ where
     }
 }
 
+fn raw_output(msg: &RawMessage) -> RawMessageOutput {
+    RawMessageOutput {
+        id: msg.id,
+        opcode: msg.opcode,
+        message_type: msg.message_type.clone(),
+        direction: msg.direction().to_string(),
+        queue: msg.queue.as_ref().map(|q| format!("{:?}", q)),
+        data_len: msg.data.len(),
+        raw: hex::encode(&msg.data),
+        sequence: msg.sequence,
+        iteration: msg.iteration,
+        header_flags: msg.header_flags,
+        first_time: msg.first_time.map(format_capture_time),
+        last_time: msg.last_time.map(format_capture_time),
+    }
+}
+
 /// Helper function to format and output messages in parsed format (JSON serialization)
-pub fn format_parsed_messages<'a, I>(messages: I, output: OutputFormat)
+///
+/// JSONL and table output are written as messages arrive, so `messages` can
+/// be a lazy stream.
+pub fn format_parsed_messages<I>(messages: I, output: OutputFormat)
 where
-    I: IntoIterator<Item = &'a RawMessage>,
+    I: IntoIterator,
+    I::Item: Borrow<RawMessage>,
 {
-    let messages: Vec<_> = messages.into_iter().collect();
+    let messages = messages.into_iter();
 
     match output {
         OutputFormat::Jsonl => {
             for msg in messages {
-                println!("{}", serde_json::to_string(&msg).unwrap());
+                println!("{}", serde_json::to_string(msg.borrow()).unwrap());
             }
         }
         OutputFormat::Json => {
+            let messages: Vec<_> = messages.collect();
+            let messages: Vec<&RawMessage> = messages.iter().map(Borrow::borrow).collect();
             println!("{}", serde_json::to_string_pretty(&messages).unwrap());
         }
         OutputFormat::Table => {
             println!("{:>6}  {:40}  {:>6}  {:>10}", "ID", "Type", "Dir", "OpCode");
             println!("{}", "-".repeat(70));
             for msg in messages {
+                let msg = msg.borrow();
                 println!(
                     "{:>6}  {:40}  {:>6}  {:#06x}",
                     msg.id,
                     truncate(&msg.message_type, 40),
-                    msg.direction,
+                    msg.direction(),
                     msg.opcode
                 );
             }
         }
     }
 }
+
+/// Output decoded packet records, one per AC packet
+pub fn format_packets(packets: &[PacketRecord], output: OutputFormat) {
+    match output {
+        OutputFormat::Jsonl => {
+            for packet in packets {
+                println!("{}", serde_json::to_string(packet).unwrap());
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(packets).unwrap());
+        }
+        OutputFormat::Table => {
+            println!(
+                "{:>10}  {:>4}  {:>5}  {:>5}  {:>5}  {:10}  Flags",
+                "Sequence", "Dir", "Size", "Iter", "Frags", "Checksum"
+            );
+            println!("{}", "-".repeat(100));
+            for packet in packets {
+                let direction = match packet.direction {
+                    Some(Direction::ClientToServer) => "Send",
+                    Some(Direction::ServerToClient) => "Recv",
+                    None => "?",
+                };
+                let flags: Vec<_> = packet
+                    .header
+                    .flags
+                    .iter_names()
+                    .map(|(name, _)| name)
+                    .collect();
+                let checksum = match packet.checksum {
+                    ChecksumStatus::Valid => "ok",
+                    ChecksumStatus::Invalid => "BAD",
+                    ChecksumStatus::Unverified => "encrypted",
+                };
+                println!(
+                    "{:>10}  {:>4}  {:>5}  {:>5}  {:>5}  {:10}  {}",
+                    packet.header.sequence,
+                    direction,
+                    packet.header.size,
+                    packet.header.iteration,
+                    packet.fragments.len(),
+                    checksum,
+                    flags.join(" | ")
+                );
+            }
+        }
+    }
+}
+
+/// Output a reliability report, one session per line for JSONL
+pub fn format_reliability(report: &ReliabilityReport, output: OutputFormat) {
+    match output {
+        OutputFormat::Jsonl => {
+            for session in &report.sessions {
+                println!("{}", serde_json::to_string(session).unwrap());
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(report).unwrap());
+        }
+        OutputFormat::Table => {
+            println!("=== Reliability ===");
+            for session in &report.sessions {
+                println!("\nSession {} <-> {}", session.client, session.server);
+                print_flow_reliability("Send (C→S)", &session.client_to_server);
+                print_flow_reliability("Recv (S→C)", &session.server_to_client);
+            }
+            if report.unknown_direction > 0 {
+                println!(
+                    "\nSkipped {} packets of unknown direction",
+                    report.unknown_direction
+                );
+            }
+            if report.invalid_checksums > 0 {
+                println!(
+                    "\nSkipped {} packets with invalid checksums",
+                    report.invalid_checksums
+                );
+            }
+        }
+    }
+}
+
+fn print_flow_reliability(label: &str, flow: &FlowReliability) {
+    println!("  {label}:");
+    println!("    Packets:              {:>6}", flow.packets);
+    println!("    Retransmissions:      {:>6}", flow.retransmissions);
+    println!("    Duplicates:           {:>6}", flow.duplicates);
+    println!("    Out of order:         {:>6}", flow.out_of_order);
+    println!("    Recovered:            {:>6}", flow.recovered);
+    println!("    Lost:                 {:>6}", flow.lost);
+    println!(
+        "    Retransmit requests:  {:>6}  ({} lost after capture, {} rejected, {} unanswered)",
+        flow.retransmit_requests,
+        flow.lost_after_capture,
+        flow.rejected_requests,
+        flow.unanswered_requests
+    );
+    println!("    ACKs:                 {:>6}", flow.acks);
+    println!(
+        "    ACK latency:          {}",
+        format_latency(&flow.ack_latency)
+    );
+}
+
+fn format_latency(latency: &LatencyStats) -> String {
+    match (latency.min, latency.mean(), latency.max) {
+        (Some(min), Some(mean), Some(max)) => format!(
+            "min {:.1} ms, mean {:.1} ms, max {:.1} ms over {} samples",
+            min.as_secs_f64() * 1000.0,
+            mean.as_secs_f64() * 1000.0,
+            max.as_secs_f64() * 1000.0,
+            latency.samples
+        ),
+        _ => "-".to_string(),
+    }
+}
+
+/// Output a timing report, one session per line for JSONL
+pub fn format_timing(report: &TimingReport, output: OutputFormat) {
+    match output {
+        OutputFormat::Jsonl => {
+            for session in &report.sessions {
+                println!("{}", serde_json::to_string(session).unwrap());
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(report).unwrap());
+        }
+        OutputFormat::Table => {
+            println!("=== Timing ===");
+            for session in &report.sessions {
+                println!("\nSession {} <-> {}", session.client, session.server);
+                println!("  Round trip:     {}", format_latency(&session.rtt_stats));
+                println!("  Unanswered:     {:>6}", session.unanswered_echoes);
+
+                for (label, clock) in [
+                    ("Server clock:", &session.server_clock),
+                    ("Client clock:", &session.client_clock),
+                ] {
+                    match clock {
+                        Some(clock) => println!(
+                            "  {label:15} offset {:+.3} s, drift {} over {} samples",
+                            clock.offset,
+                            clock
+                                .drift_ppm
+                                .map_or("-".to_string(), |ppm| format!("{ppm:+.1} ppm")),
+                            clock.samples
+                        ),
+                        None => println!("  {label:15} -"),
+                    }
+                }
+
+                for (label, clock) in [
+                    ("Client ticks:", &session.client_header_clock),
+                    ("Server ticks:", &session.server_header_clock),
+                ] {
+                    match clock {
+                        Some(clock) => println!(
+                            "  {label:15} {} per second, tick {} at {}",
+                            clock
+                                .ticks_per_second
+                                .map_or("-".to_string(), |rate| format!("{rate:.3}")),
+                            clock.reference_tick,
+                            format_capture_time(clock.reference_time)
+                        ),
+                        None => println!("  {label:15} -"),
+                    }
+                }
+
+                if !session.rtt.is_empty() {
+                    println!("\n  {:27}  {:>9}", "Echo sent", "RTT (ms)");
+                    for sample in &session.rtt {
+                        println!(
+                            "  {:27}  {:>9.1}",
+                            format_capture_time(sample.time),
+                            sample.rtt.as_secs_f64() * 1000.0
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Output a bandwidth report, one session per line for JSONL
+pub fn format_bandwidth(report: &BandwidthReport, output: OutputFormat) {
+    match output {
+        OutputFormat::Jsonl => {
+            for session in &report.sessions {
+                println!("{}", serde_json::to_string(session).unwrap());
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(report).unwrap());
+        }
+        OutputFormat::Table => {
+            println!("=== Bandwidth ===");
+            for session in &report.sessions {
+                println!(
+                    "\nSession {} <-> {} over {:.1} s",
+                    session.client, session.server, session.duration
+                );
+                print_direction_bandwidth("Send (C→S)", &session.client_to_server);
+                print_direction_bandwidth("Recv (S→C)", &session.server_to_client);
+            }
+        }
+    }
+}
+
+fn print_direction_bandwidth(label: &str, bandwidth: &DirectionBandwidth) {
+    println!("  {label}:");
+    println!(
+        "    Packets:   {:>6}  {:>10} bytes  {:>10} B/s",
+        bandwidth.packets.count,
+        bandwidth.packets.bytes,
+        format_rate(&bandwidth.packets)
+    );
+
+    let flow = &bandwidth.flow;
+    println!(
+        "    FLOW:      {:>6} reports, {} bytes advertised, {} captured, {} unmatched",
+        flow.reports, flow.advertised_bytes, flow.captured_bytes, flow.unmatched_reports
+    );
+
+    for (heading, counts) in [
+        ("Queue", &bandwidth.queues),
+        ("Message type", &bandwidth.message_types),
+    ] {
+        if counts.is_empty() {
+            continue;
+        }
+        let mut counts: Vec<_> = counts.iter().collect();
+        counts.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+        println!(
+            "\n    {:<40} {:>8} {:>10} {:>10}",
+            heading, "Count", "Bytes", "B/s"
+        );
+        for (name, count) in counts {
+            println!(
+                "    {:<40} {:>8} {:>10} {:>10}",
+                truncate(name, 40),
+                count.count,
+                count.bytes,
+                format_rate(count)
+            );
+        }
+    }
+    println!();
+}
+
+fn format_rate(count: &TrafficCount) -> String {
+    count
+        .bytes_per_second
+        .map_or("-".to_string(), |rate| format!("{rate:.1}"))
+}
+
+/// Output the logical sessions in a capture, one per line for JSONL
+pub fn format_sessions(sessions: &[LogicalSession], output: OutputFormat) {
+    match output {
+        OutputFormat::Jsonl => {
+            for session in sessions {
+                println!("{}", serde_json::to_string(session).unwrap());
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(sessions).unwrap());
+        }
+        OutputFormat::Table => {
+            println!("=== Sessions ===");
+            for (index, session) in sessions.iter().enumerate() {
+                println!(
+                    "\nSession {}: account {}, character {}",
+                    index + 1,
+                    session.account.as_deref().unwrap_or("-"),
+                    match (&session.character_name, session.character_id) {
+                        (Some(name), Some(id)) => format!("{name} (0x{id:08X})"),
+                        (None, Some(id)) => format!("0x{id:08X}"),
+                        _ => "-".to_string(),
+                    }
+                );
+                if let (Some(first), Some(last)) =
+                    (session.first_message_id, session.last_message_id)
+                {
+                    println!("  Messages {first}..={last} ({} total)", session.messages());
+                }
+                for connection in &session.connections {
+                    println!(
+                        "  {} <-> {}  {:<12} {:>6} packets  {}",
+                        connection.client,
+                        connection.server,
+                        format!("{:?}", connection.origin),
+                        connection.packets,
+                        connection
+                            .first_time
+                            .map_or("-".to_string(), format_capture_time)
+                    );
+                }
+            }
+        }
+    }
+}
+
+pub fn format_inventory(layout: &InventoryLayout, output: OutputFormat) {
+    match output {
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(layout).unwrap()),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(layout).unwrap()),
+        OutputFormat::Table => {
+            let Some(player) = layout.player else {
+                println!("No inventory messages for the player");
+                return;
+            };
+            println!("=== Inventory of 0x{:08X} ===", player.0);
+            println!("Burden: {}", layout.burden);
+
+            println!("\nEquipped:");
+            for item in &layout.equipped {
+                println!("  {:<40} {:?}", inventory_item(item), item.slot);
+            }
+            println!("\nMain pack:");
+            for item in &layout.pack {
+                println!("  {}", inventory_item(item));
+            }
+            for pack in &layout.side_packs {
+                println!("\n{}:", inventory_item(&pack.pack));
+                for item in &pack.contents {
+                    println!("  {}", inventory_item(item));
+                }
+            }
+        }
+    }
+}
+
+/// An item's name and id, with its stack size and burden when known
+fn inventory_item(item: &InventoryItem) -> String {
+    let mut text = format!(
+        "{} (0x{:08X})",
+        item.name.as_deref().unwrap_or("?"),
+        item.id.0
+    );
+    if let Some(stack_size) = item.stack_size.filter(|&size| size > 1) {
+        text.push_str(&format!(" x{stack_size}"));
+    }
+    if let Some(burden) = item.burden {
+        text.push_str(&format!(" [{burden} bu]"));
+    }
+    text
+}
+
+pub fn format_chat(lines: &[ChatLine], output: ChatFormat) {
+    match output {
+        ChatFormat::Json => println!("{}", serde_json::to_string_pretty(lines).unwrap()),
+        ChatFormat::Text => {
+            for line in lines {
+                let time = line.time.map_or("-".to_string(), format_capture_time);
+                let arrow = match line.direction {
+                    Direction::ClientToServer => "->",
+                    Direction::ServerToClient => "<-",
+                };
+                let body = line.text.trim_end();
+                let text = match (&line.channel, line.sender_name.as_deref()) {
+                    (_, None | Some("")) => body.to_string(),
+                    (ChatChannel::Emote, Some(sender)) => format!("{sender} {body}"),
+                    (_, Some(sender)) => format!("{sender}: {body}"),
+                };
+                println!("{time} {arrow} [{}] {text}", line.channel);
+            }
+        }
+        ChatFormat::Csv => {
+            println!("message_index,time,direction,channel,kind,sender_id,sender_name,text");
+            for line in lines {
+                println!("{}", chat_csv_row(line));
+            }
+        }
+    }
+}
+
+/// One line of chat as a CSV record, in the order of `format_chat`'s header
+fn chat_csv_row(line: &ChatLine) -> String {
+    let fields = [
+        line.message_index.to_string(),
+        line.time.map(format_capture_time).unwrap_or_default(),
+        format!("{:?}", line.direction),
+        line.channel.to_string(),
+        line.kind
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        line.sender_id
+            .map(|id| format!("0x{:08X}", id.0))
+            .unwrap_or_default(),
+        line.sender_name.clone().unwrap_or_default(),
+        line.text.clone(),
+    ];
+    let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+    fields.join(",")
+}
+
+/// Quote a CSV field when it holds a delimiter, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{ChatFragmentType, ChatType};
+    use crate::types::ObjectId;
+    use std::time::Duration;
+
+    fn line(text: &str) -> ChatLine {
+        ChatLine {
+            message_index: 7,
+            time: Some(Duration::from_micros(1_763_490_291_123_456)),
+            direction: Direction::ServerToClient,
+            channel: ChatChannel::Turbine(ChatType::Trade),
+            kind: None,
+            sender_id: Some(ObjectId(0x5000_0001)),
+            sender_name: Some("Galahad".to_string()),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_chat_csv_row() {
+        assert_eq!(
+            chat_csv_row(&line("WTS sword")),
+            "7,2025-11-18T18:24:51.123456Z,ServerToClient,Trade,,0x50000001,Galahad,WTS sword"
+        );
+
+        let mut quoted = line("say \"hi\", then\nleave");
+        quoted.time = None;
+        quoted.channel = ChatChannel::System;
+        quoted.kind = Some(ChatFragmentType::Combat);
+        quoted.sender_id = None;
+        quoted.sender_name = None;
+        assert_eq!(
+            chat_csv_row(&quoted),
+            format!(
+                "7,,ServerToClient,System,{},,,\"say \"\"hi\"\", then\nleave\"",
+                ChatFragmentType::Combat
+            )
+        );
+    }
+
+    #[test]
+    fn test_chat_json_times_are_timestamps() {
+        let json = serde_json::to_value(line("hi")).unwrap();
+        assert_eq!(json["time"], "2025-11-18T18:24:51.123456Z");
+
+        let mut untimed = line("hi");
+        untimed.time = None;
+        let json = serde_json::to_value(untimed).unwrap();
+        assert!(json.get("time").is_none());
+    }
+}
@@ -1,9 +1,78 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Read};
+
+use anyhow::Result;
+
 use crate::cli::parse_opcode_filter;
-use crate::network::RawMessage;
+use crate::network::pcap::PcapIterator;
+use crate::network::{DatagramStream, FragmentKey, RawMessage};
 
 use super::output::{format_parsed_messages, format_raw_messages};
 use super::types::{DirectionFilter, OutputFormat, SortField};
 
+/// Criteria a message has to meet to be output
+#[derive(Clone, Default)]
+pub struct MessageFilter {
+    pub id: Option<u32>,
+    /// Substring of the message type, matched case-insensitively
+    pub filter_type: Option<String>,
+    pub opcode: Option<u32>,
+    pub direction: Option<DirectionFilter>,
+}
+
+impl MessageFilter {
+    /// Fails if `filter_opcode` isn't a hex or decimal opcode
+    pub fn new(
+        id: Option<u32>,
+        filter_type: Option<&str>,
+        filter_opcode: Option<&str>,
+        direction: Option<DirectionFilter>,
+    ) -> Result<Self> {
+        Ok(Self {
+            id,
+            filter_type: filter_type.map(str::to_lowercase),
+            // Parse opcode filter if provided
+            opcode: filter_opcode.map(parse_opcode_filter).transpose()?,
+            direction,
+        })
+    }
+
+    /// Whether `message` meets every criterion
+    pub fn matches(&self, m: &RawMessage) -> bool {
+        if let Some(msg_id) = self.id
+            && m.id != msg_id
+        {
+            return false;
+        }
+        if let Some(ft) = &self.filter_type
+            && !m.message_type.to_lowercase().contains(ft)
+        {
+            return false;
+        }
+        if let Some(oc) = self.opcode
+            && m.opcode != oc
+        {
+            return false;
+        }
+        if let Some(d) = self.direction {
+            match d {
+                DirectionFilter::Send => {
+                    if m.direction() != "Send" {
+                        return false;
+                    }
+                }
+                DirectionFilter::Recv => {
+                    if m.direction() != "Recv" {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
 /// Filter, sort, and output messages based on provided criteria
 #[allow(clippy::too_many_arguments)]
 pub fn output_messages(
@@ -17,51 +86,17 @@ pub fn output_messages(
     limit: Option<usize>,
     output: OutputFormat,
     raw: bool,
-) {
-    // Parse opcode filter if provided
-    let opcode_filter: Option<u32> = filter_opcode.and_then(|s| parse_opcode_filter(s).ok());
-
-    let mut filtered: Vec<&RawMessage> = messages
-        .iter()
-        .filter(|m| {
-            if let Some(msg_id) = id
-                && m.id != msg_id
-            {
-                return false;
-            }
-            if let Some(ft) = filter_type
-                && !m.message_type.to_lowercase().contains(&ft.to_lowercase())
-            {
-                return false;
-            }
-            if let Some(oc) = opcode_filter
-                && m.opcode != oc
-            {
-                return false;
-            }
-            if let Some(d) = direction {
-                match d {
-                    DirectionFilter::Send => {
-                        if m.direction != "Send" {
-                            return false;
-                        }
-                    }
-                    DirectionFilter::Recv => {
-                        if m.direction != "Recv" {
-                            return false;
-                        }
-                    }
-                }
-            }
-            true
-        })
-        .collect();
+) -> Result<()> {
+    let filter = MessageFilter::new(id, filter_type, filter_opcode, direction)?;
+
+    let mut filtered: Vec<&RawMessage> = messages.iter().filter(|m| filter.matches(m)).collect();
 
     filtered.sort_by(|a, b| {
         let cmp = match sort {
             SortField::Id => a.id.cmp(&b.id),
             SortField::Type => a.message_type.cmp(&b.message_type),
-            SortField::Direction => a.direction.cmp(&b.direction),
+            SortField::Direction => a.direction().cmp(b.direction()),
+            SortField::Time => a.first_time.cmp(&b.first_time),
         };
         if reverse { cmp.reverse() } else { cmp }
     });
@@ -75,4 +110,106 @@ pub fn output_messages(
     } else {
         format_parsed_messages(filtered, output);
     }
+    Ok(())
+}
+
+/// Filter and output messages as they arrive, without sorting
+///
+/// Messages come out of the assembler in id order, so this matches
+/// `output_messages` sorted by id. Reading stops as soon as `limit` messages
+/// have been output.
+pub fn stream_messages<I>(
+    messages: I,
+    filter: &MessageFilter,
+    limit: Option<usize>,
+    output: OutputFormat,
+    raw: bool,
+) where
+    I: IntoIterator,
+    I::Item: Borrow<RawMessage>,
+{
+    let filtered = messages
+        .into_iter()
+        .filter(|m| filter.matches(m.borrow()))
+        .take(limit.unwrap_or(usize::MAX));
+
+    if raw {
+        format_raw_messages(filtered, output);
+    } else {
+        format_parsed_messages(filtered, output);
+    }
+}
+
+/// Packets of a capture that carry part of a matching message
+#[derive(Debug, Default)]
+pub struct CaptureSelection {
+    /// Indexes of the packets to keep, in capture order
+    pub packets: BTreeSet<usize>,
+    /// Number of messages that matched
+    pub messages: usize,
+    /// Datagrams skipped because none of their packets could be read
+    pub skipped: usize,
+}
+
+/// Find the packets carrying any fragment of a message that matches `filter`
+///
+/// A message's fragments can be spread over several packets, so the packets
+/// holding each pending message are remembered until it completes or the
+/// assembler gives up on it. Writing the selected packets out again yields a
+/// capture in which every matching message reassembles.
+pub fn select_packets<R: Read>(
+    packets: PcapIterator<R>,
+    filter: &MessageFilter,
+) -> io::Result<CaptureSelection> {
+    let mut datagrams = DatagramStream::new(packets);
+    let mut selection = CaptureSelection::default();
+    // Packets holding chunks of each incomplete message
+    let mut pending: HashMap<FragmentKey, Vec<usize>> = HashMap::new();
+
+    // One datagram per captured packet, skipped ones included, so indexes
+    // line up with the capture
+    let mut index = 0;
+    while let Some(datagram) = datagrams.next() {
+        let (parsed, _) = datagram?;
+        let assembler = datagrams.assembler();
+
+        // Messages come out in the order their last chunks went in
+        let mut messages = parsed.messages.iter();
+        for record in &parsed.packets {
+            for fragment in &record.fragments {
+                let key = FragmentKey::new(
+                    record.source,
+                    record.destination,
+                    fragment.sequence,
+                    fragment.id,
+                );
+                if fragment.is_complete {
+                    let mut holders = pending.remove(&key).unwrap_or_default();
+                    if holders.last() != Some(&index) {
+                        holders.push(index);
+                    }
+                    if let Some(message) = messages.next()
+                        && filter.matches(message)
+                    {
+                        selection.messages += 1;
+                        selection.packets.extend(holders);
+                    }
+                } else if assembler.is_pending(&key) {
+                    let holders = pending.entry(key).or_default();
+                    if holders.last() != Some(&index) {
+                        holders.push(index);
+                    }
+                }
+                // Anything else is a resent chunk of a message that already
+                // completed, which the assembler ignores
+            }
+        }
+
+        // Forget messages the assembler gave up on
+        pending.retain(|key, _| assembler.is_pending(key));
+        index += 1;
+    }
+
+    selection.skipped = datagrams.skipped();
+    Ok(selection)
 }
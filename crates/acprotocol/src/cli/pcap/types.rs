@@ -17,6 +17,12 @@ pub struct RawMessageOutput {
     pub iteration: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub header_flags: Option<u32>,
+    /// Capture time of the packet holding the first fragment to arrive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_time: Option<String>,
+    /// Capture time of the packet that completed the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_time: Option<String>,
 }
 
 #[derive(Clone, Copy, ValueEnum)]
@@ -30,6 +36,8 @@ pub enum SortField {
     Id,
     Type,
     Direction,
+    /// Capture time of the message's first fragment
+    Time,
 }
 
 #[derive(Clone, Copy, ValueEnum)]
@@ -38,3 +46,11 @@ pub enum OutputFormat {
     Json,
     Table,
 }
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ChatFormat {
+    /// One line per message, as the chat window shows it
+    Text,
+    Json,
+    Csv,
+}
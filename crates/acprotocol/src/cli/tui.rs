@@ -19,14 +19,49 @@ use ratatui::{
     widgets::{Block, Borders, Paragraph, Row, Table},
 };
 use serde_json::Value;
-use std::io;
+use std::io::{self, Write};
+use std::time::Duration;
 
-use crate::network::{FragmentAssembler, RawMessage};
+use crate::network::{LiveStream, MessageStream, RawMessage, format_capture_time};
 
 // Border height in terminal UI (top and bottom borders)
 const BORDER_HEIGHT: usize = 2;
 
 pub fn run(path: &Path) -> Result<()> {
+    // Load pcap data
+    let packets = load_packets(path)?;
+
+    run_with(App::new(packets, "Messages".to_string()), |_| {})
+}
+
+/// Run the TUI on messages relayed by a proxy, adding them as they arrive
+pub fn run_live<W: Write>(mut stream: LiveStream<W>) -> Result<()> {
+    let listening = format!("Messages (live on {})", stream.proxy().local_addrs()[0]);
+    let app = App::new(Vec::new(), listening);
+
+    run_with(app, |app| {
+        let mut added = false;
+        while let Some(result) = stream.try_next() {
+            match result {
+                Ok(msg) => {
+                    app.packets.push(packet_info(&msg));
+                    added = true;
+                }
+                // Malformed datagrams are only counted, so this is a
+                // socket or recording error the stream can't recover from
+                Err(e) => app.title = format!("Messages (live, stopped: {e})"),
+            }
+        }
+        // New messages arrive in id order, so only other sorts need redoing
+        if added && !(app.sort_column == SortColumn::Id && app.sort_ascending) {
+            app.apply_sort();
+        }
+    })
+}
+
+/// Run the TUI, calling `poll` to let the caller update the app before each
+/// redraw
+fn run_with(mut app: App, poll: impl FnMut(&mut App)) -> Result<()> {
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -34,17 +69,11 @@ pub fn run(path: &Path) -> Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Load pcap data
-    let packets = load_packets(path)?;
-
-    // Create app state
-    let mut app = App::new(packets);
-
     // Apply initial sort by Id ascending
     app.apply_sort();
 
     // Run the TUI
-    let res = run_app(&mut terminal, &mut app);
+    let res = run_app(&mut terminal, &mut app, poll);
 
     // Restore terminal
     disable_raw_mode()?;
@@ -66,6 +95,8 @@ struct PacketInfo {
     id: u32,
     direction: String,
     timestamp: String,
+    /// Capture time of the first fragment, for sorting
+    time: Option<Duration>,
     flags: String,
     packet_type: String,
     size: usize,
@@ -87,6 +118,7 @@ struct App {
     selected_column: usize, // Index of the column header being selected (0-7)
     column_rects: Vec<(u16, u16, SortColumn)>, // (start, end, column)
     list_pane_right: u16,   // Right boundary of list pane
+    title: String,          // Title of the list pane
 }
 
 enum FocusedPane {
@@ -107,7 +139,7 @@ enum SortColumn {
 }
 
 impl App {
-    fn new(packets: Vec<PacketInfo>) -> Self {
+    fn new(packets: Vec<PacketInfo>, title: String) -> Self {
         App {
             packets,
             selected: 0,
@@ -121,6 +153,7 @@ impl App {
             selected_column: 0,
             column_rects: Vec::new(),
             list_pane_right: 0,
+            title,
         }
     }
 
@@ -206,7 +239,7 @@ impl App {
             let cmp = match self.sort_column {
                 SortColumn::Id => a.id.cmp(&b.id),
                 SortColumn::Direction => a.direction.cmp(&b.direction),
-                SortColumn::Timestamp => a.timestamp.cmp(&b.timestamp),
+                SortColumn::Timestamp => a.time.cmp(&b.time),
                 SortColumn::Flags => a.flags.cmp(&b.flags),
                 SortColumn::MessageType => a.packet_type.cmp(&b.packet_type),
                 SortColumn::Size => a.size.cmp(&b.size),
@@ -261,8 +294,14 @@ impl App {
     }
 }
 
-fn run_app(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut App) -> io::Result<()> {
+fn run_app(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+    mut poll: impl FnMut(&mut App),
+) -> io::Result<()> {
     loop {
+        poll(app);
+
         // Get detail lines before drawing (for Enter key handling)
         let detail_lines = if !app.packets.is_empty() {
             let packet = &app.packets[app.selected];
@@ -647,7 +686,7 @@ fn ui(f: &mut ratatui::Frame, app: &mut App) {
 
     // Calculate column positions for mouse click detection
     // These should match the Constraint values below
-    let col_widths = [4, 4, 11, 15, 20, 5, 10, 4];
+    let col_widths = [4, 4, 12, 15, 20, 5, 10, 4];
     let col_headers = [
         SortColumn::Id,
         SortColumn::Direction,
@@ -673,7 +712,7 @@ fn ui(f: &mut ratatui::Frame, app: &mut App) {
         [
             Constraint::Length(4),  // #
             Constraint::Length(4),  // Dir
-            Constraint::Length(11), // Timestamp
+            Constraint::Length(12), // Timestamp
             Constraint::Length(15), // Flags
             Constraint::Min(20),    // Message Type (expandable)
             Constraint::Length(5),  // Size
@@ -690,7 +729,7 @@ fn ui(f: &mut ratatui::Frame, app: &mut App) {
         };
         Block::default()
             .borders(Borders::ALL)
-            .title("Messages")
+            .title(app.title.as_str())
             .style(style)
     });
 
@@ -776,32 +815,32 @@ fn ui(f: &mut ratatui::Frame, app: &mut App) {
 fn load_packets(path: &Path) -> Result<Vec<PacketInfo>> {
     use crate::network::pcap;
 
-    let mut assembler = FragmentAssembler::new();
-    let mut messages: Vec<RawMessage> = vec![];
-
-    let pcap_iter = pcap::open(path)?;
-    for packet_result in pcap_iter {
-        let packet = packet_result?;
-        let parsed_messages = assembler.parse_packet_payload(&packet.data)?;
-        messages.extend(parsed_messages);
-    }
-
     let mut packet_infos = Vec::new();
 
-    for msg in messages {
-        let info = PacketInfo {
-            id: msg.id,
-            direction: msg.direction.clone(),
-            timestamp: "".to_string(),
-            flags: "".to_string(),
-            packet_type: msg.message_type.clone(),
-            size: msg.data.len(),
-            opcode: format!("{:#06x}", msg.opcode),
-            sequence: msg.sequence,
-            raw_json: serde_json::to_string(&msg).unwrap_or_default(),
-        };
-        packet_infos.push(info);
+    // Only the display rows are kept, not the messages themselves. Malformed
+    // datagrams are skipped, and counted in the stream's diagnostics.
+    for msg in MessageStream::new(pcap::open(path)?) {
+        packet_infos.push(packet_info(&msg?));
     }
 
     Ok(packet_infos)
 }
+
+fn packet_info(msg: &RawMessage) -> PacketInfo {
+    PacketInfo {
+        id: msg.id,
+        direction: msg.direction().to_string(),
+        // Time of day is enough to tell messages in a capture apart
+        timestamp: msg
+            .first_time
+            .map(|time| format_capture_time(time)[11..23].to_string())
+            .unwrap_or_default(),
+        time: msg.first_time,
+        flags: "".to_string(),
+        packet_type: msg.message_type.clone(),
+        size: msg.data.len(),
+        opcode: format!("{:#06x}", msg.opcode),
+        sequence: msg.sequence,
+        raw_json: serde_json::to_string(msg).unwrap_or_default(),
+    }
+}
@@ -8,7 +8,7 @@ use crate::gameactions;
 use crate::gameevents;
 
 /// Message direction
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Direction {
     ClientToServer,
     ServerToClient,
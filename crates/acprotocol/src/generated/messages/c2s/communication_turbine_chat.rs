@@ -370,18 +370,7 @@ impl CommunicationTurbineChatType1 {
         write_i32(writer, self.transport_id)?;
         write_i32(writer, self.cookie)?;
         write_u32(writer, self.payload_size)?;
-        // Write nested switch variant fields
-        match &self.blob_dispatch_type {
-            CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(variant_struct) => {
-                write_u32(writer, variant_struct.room_id)?;
-                write_string32l(writer, &variant_struct.display_name.0, false)?;
-                write_string32l(writer, &variant_struct.text.0, false)?;
-                write_u32(writer, variant_struct.extra_data_size)?;
-                variant_struct.speaker_id.write(writer)?;
-                write_i32(writer, variant_struct.h_result)?;
-                write_u32(writer, variant_struct.chat_type)?;
-            },
-        }
+        self.blob_dispatch_type.write(writer)?;
         Ok(())
     }
 }
@@ -399,6 +388,7 @@ impl CommunicationTurbineChatType1BlobDispatchTypeVariant {
 
         match self {
             Self::Type1(variant_struct) => {
+                write_u8(writer, 0x01)?;
                 write_u32(writer, variant_struct.room_id)?;
                 write_string32l(writer, &variant_struct.display_name.0, false)?;
                 write_string32l(writer, &variant_struct.text.0, false)?;
@@ -437,20 +427,7 @@ impl CommunicationTurbineChatType3 {
         write_i32(writer, self.transport_id)?;
         write_i32(writer, self.cookie)?;
         write_u32(writer, self.payload_size)?;
-        // Write nested switch variant fields
-        match &self.blob_dispatch_type {
-            CommunicationTurbineChatType3BlobDispatchTypeVariant::Type2(variant_struct) => {
-                write_u32(writer, variant_struct.context_id)?;
-                write_u32(writer, variant_struct.response_id)?;
-                write_u32(writer, variant_struct.method_id)?;
-                write_u32(writer, variant_struct.room_id)?;
-                write_string32l(writer, &variant_struct.text.0, false)?;
-                write_u32(writer, variant_struct.extra_data_size)?;
-                variant_struct.speaker_id.write(writer)?;
-                write_i32(writer, variant_struct.h_result)?;
-                write_u32(writer, variant_struct.chat_type)?;
-            },
-        }
+        self.blob_dispatch_type.write(writer)?;
         Ok(())
     }
 }
@@ -468,6 +445,7 @@ impl CommunicationTurbineChatType3BlobDispatchTypeVariant {
 
         match self {
             Self::Type2(variant_struct) => {
+                write_u8(writer, 0x02)?;
                 write_u32(writer, variant_struct.context_id)?;
                 write_u32(writer, variant_struct.response_id)?;
                 write_u32(writer, variant_struct.method_id)?;
@@ -508,15 +486,7 @@ impl CommunicationTurbineChatType5 {
         write_i32(writer, self.transport_id)?;
         write_i32(writer, self.cookie)?;
         write_u32(writer, self.payload_size)?;
-        // Write nested switch variant fields
-        match &self.blob_dispatch_type {
-            CommunicationTurbineChatType5BlobDispatchTypeVariant::Type1(variant_struct) => {
-                write_u32(writer, variant_struct.context_id)?;
-                write_u32(writer, variant_struct.response_id)?;
-                write_u32(writer, variant_struct.method_id)?;
-                write_i32(writer, variant_struct.h_result)?;
-            },
-        }
+        self.blob_dispatch_type.write(writer)?;
         Ok(())
     }
 }
@@ -534,6 +504,7 @@ impl CommunicationTurbineChatType5BlobDispatchTypeVariant {
 
         match self {
             Self::Type1(variant_struct) => {
+                write_u8(writer, 0x01)?;
                 write_u32(writer, variant_struct.context_id)?;
                 write_u32(writer, variant_struct.response_id)?;
                 write_u32(writer, variant_struct.method_id)?;
@@ -52,10 +52,12 @@ pub type WORD = u16;
 pub type DWORD = u32;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct PackedWORD {}
+#[serde(transparent)]
+pub struct PackedWORD(pub i16);
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct PackedDWORD {}
+#[serde(transparent)]
+pub struct PackedDWORD(pub u32);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -2643,8 +2645,7 @@ impl crate::writers::ACWritable for WString {
 
 impl PackedWORD {
     pub fn read(reader: &mut dyn ACReader) -> Result<Self, Box<dyn std::error::Error>> {
-        crate::readers::read_packed_word(reader)?;
-        Ok(Self {})
+        Ok(Self(crate::readers::read_packed_word(reader)?))
     }
 }
 
@@ -2655,7 +2656,8 @@ impl crate::readers::ACDataType for PackedWORD {
 }
 
 impl PackedWORD {
-    pub fn write(&self, _writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn write(&self, writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {
+        crate::writers::write_packed_word(writer, self.0)?;
         Ok(())
     }
 }
@@ -2668,8 +2670,7 @@ impl crate::writers::ACWritable for PackedWORD {
 
 impl PackedDWORD {
     pub fn read(reader: &mut dyn ACReader) -> Result<Self, Box<dyn std::error::Error>> {
-        crate::readers::read_packed_dword(reader)?;
-        Ok(Self {})
+        Ok(Self(crate::readers::read_packed_dword(reader)?))
     }
 }
 
@@ -2680,7 +2681,8 @@ impl crate::readers::ACDataType for PackedDWORD {
 }
 
 impl PackedDWORD {
-    pub fn write(&self, _writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn write(&self, writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {
+        crate::writers::write_packed_dword(writer, self.0)?;
         Ok(())
     }
 }
@@ -7660,16 +7662,7 @@ impl WindowPropertyType1000008D {
 
         write_u32(writer, 268435597)?;
         write_u32(writer, self.unknown_c)?;
-        // Write nested switch variant fields
-        match &self.title_source {
-            WindowPropertyType1000008DTitleSourceVariant::Type0(variant_struct) => {
-                write_u32(writer, variant_struct.string_id)?;
-                write_u32(writer, variant_struct.file_id)?;
-            },
-            WindowPropertyType1000008DTitleSourceVariant::Type1(variant_struct) => {
-                write_string32l(writer, &variant_struct.value_a.0, false)?;
-            },
-        }
+        self.title_source.write(writer)?;
         Ok(())
     }
 }
@@ -7687,10 +7680,12 @@ impl WindowPropertyType1000008DTitleSourceVariant {
 
         match self {
             Self::Type0(variant_struct) => {
+                write_u8(writer, 0x00)?;
                 write_u32(writer, variant_struct.string_id)?;
                 write_u32(writer, variant_struct.file_id)?;
             },
             Self::Type1(variant_struct) => {
+                write_u8(writer, 0x01)?;
                 write_string32l(writer, &variant_struct.value_a.0, false)?;
             },
         }
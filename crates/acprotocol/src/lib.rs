@@ -15,7 +15,11 @@ pub mod dat;
 pub mod filter;
 pub mod network;
 pub mod readers;
+pub mod world;
 pub mod writers;
 
+#[cfg(test)]
+pub(crate) mod test_support;
+
 #[cfg(feature = "cli")]
 pub mod cli;
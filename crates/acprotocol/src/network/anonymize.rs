@@ -0,0 +1,1297 @@
+use encoding_rs::WINDOWS_1252;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Range;
+
+use crate::enums::{ObjectDescriptionFlag, PacketHeaderFlags, PropertyString};
+use crate::message::{
+    C2SMessage, Direction, GameActionMessage, GameEventMessage, MessageKind, S2CMessage,
+};
+use crate::messages::{c2s, s2c};
+use crate::readers::ACDataType;
+use crate::types::{LoginRequestHeader, PublicWeenieDesc, SocketAddress, WString};
+use crate::writers::ACWritable;
+
+use super::checksum::{seal_packet, sealing_key};
+use super::fragment_impl::FRAGMENT_CHUNK_SIZE;
+use super::packet::PacketHeader;
+use super::packet_builder::FRAGMENT_HEADER_SIZE;
+use super::packet_parser::{FragmentAssembler, FragmentKey};
+use super::packet_reader::PacketReader;
+use super::packet_record::OptionalHeaders;
+use super::pcap::{Packet, PcapIterator, PcapWriter};
+use super::udp::{LINKTYPE_ETHERNET, UdpDatagram};
+
+/// Replaces identifying details with consistent pseudonyms
+///
+/// Account and character names map to generated names and IP addresses to
+/// private ones, the same original always getting the same replacement.
+/// Free text (chat, emotes, system messages that may quote names) is masked
+/// instead, and passwords and GLS tickets are blanked. Replacements have the
+/// same encoded length as the original, so messages keep their size and
+/// fragment layout, until there are more names of one length than pseudonyms
+/// of that length and pseudonyms grow longer.
+#[derive(Debug, Default)]
+pub struct Anonymizer {
+    /// Pseudonyms by name and encoded length
+    names: HashMap<(String, usize), String>,
+    addresses: HashMap<IpAddr, IpAddr>,
+    /// Fields replaced in the message last passed to `message`
+    edits: Vec<Edit>,
+}
+
+/// What `Anonymizer::message_data` made of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnonymizedData {
+    /// Nothing identifying to replace
+    Unchanged,
+    /// Identifying fields overwritten where they were
+    Replaced(Vec<u8>),
+    /// A replacement didn't fit where the original was, so everything after
+    /// the opcode was zeroed instead
+    Blanked(Vec<u8>),
+}
+
+/// A string field replaced in a decoded message, both sides encoded as on
+/// the wire
+#[derive(Debug)]
+struct Edit {
+    original: Vec<u8>,
+    replacement: Vec<u8>,
+    /// Length of the characters that end the original, after its length
+    characters: usize,
+}
+
+/// Reads a message while noting the bytes each read covered, which is what
+/// tells a decoded string apart from the same bytes inside other fields
+struct ReadRecorder<'a> {
+    cursor: Cursor<&'a [u8]>,
+    reads: Vec<Range<usize>>,
+}
+
+impl<'a> ReadRecorder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            cursor: Cursor::new(data),
+            reads: Vec::new(),
+        }
+    }
+
+    /// Where the original of `edit` was read as a string: starting with a
+    /// read of its length and ending with one of exactly its characters
+    fn strings(&self, edit: &Edit) -> Vec<Range<usize>> {
+        let data = *self.cursor.get_ref();
+        let starts: HashSet<usize> = self.reads.iter().map(|read| read.start).collect();
+        self.reads
+            .iter()
+            .filter(|read| read.len() == edit.characters)
+            .filter_map(|read| Some(read.end.checked_sub(edit.original.len())?..read.end))
+            .filter(|span| starts.contains(&span.start) && data[span.clone()] == edit.original)
+            .collect()
+    }
+}
+
+impl Read for ReadRecorder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.cursor.position() as usize;
+        let read = self.cursor.read(buf)?;
+        if read > 0 {
+            self.reads.push(start..start + read);
+        }
+        Ok(read)
+    }
+}
+
+impl Seek for ReadRecorder<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(position)
+    }
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pseudonym for an account or character name, as many bytes long as
+    /// the name once encoded in Windows-1252 like the protocol's strings
+    pub fn name(&mut self, name: &str) -> String {
+        self.pseudonym(name, WINDOWS_1252.encode(name).0.len())
+    }
+
+    fn pseudonym(&mut self, name: &str, length: usize) -> String {
+        if name.is_empty() {
+            return String::new();
+        }
+        let key = (name.to_string(), length);
+        if let Some(pseudonym) = self.names.get(&key) {
+            return pseudonym.clone();
+        }
+
+        // Count up in base 26, padded to the original's length: Aaaa, Aaab,
+        // ... Once the count no longer fits, the pseudonym takes as many
+        // letters as it needs, so no two names ever share one.
+        let mut n = self.names.len();
+        let mut letters = Vec::new();
+        while n > 0 || letters.len() < length {
+            letters.push(b'a' + (n % 26) as u8);
+            n /= 26;
+        }
+        letters.reverse();
+        letters[0] = letters[0].to_ascii_uppercase();
+        let pseudonym = String::from_utf8(letters).expect("letters are ASCII");
+
+        self.names.insert(key, pseudonym.clone());
+        pseudonym
+    }
+
+    /// Private address standing in for `address`: 10.0.0.0/8 for IPv4 and
+    /// fd00::/8 for IPv6
+    pub fn address(&mut self, address: IpAddr) -> IpAddr {
+        let next = self.addresses.len() as u32 + 1;
+        *self
+            .addresses
+            .entry(address)
+            .or_insert_with(|| match address {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(0x0A00_0000 | next)),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(0xFD00u128 << 112 | next as u128)),
+            })
+    }
+
+    pub fn socket_address(&mut self, address: &mut SocketAddress) {
+        // sin_addr is in network byte order, which reads back little-endian
+        let original = Ipv4Addr::from(address.address.to_le_bytes());
+        if let IpAddr::V4(pseudonym) = self.address(IpAddr::V4(original)) {
+            address.address = u32::from_le_bytes(pseudonym.octets());
+        }
+    }
+
+    pub fn login_request(&mut self, request: &mut LoginRequestHeader) {
+        match request {
+            LoginRequestHeader::Type2(request) => {
+                request.account = self.name(&request.account);
+                request.account_to_login_as = self.name(&request.account_to_login_as);
+                let length = request.password.0.encode_utf16().count();
+                request.password = WString("x".repeat(length));
+            }
+            LoginRequestHeader::Type40000002(request) => {
+                request.account = self.name(&request.account);
+                request.account_to_login_as = self.name(&request.account_to_login_as);
+                request.gls_ticket = "x".repeat(request.gls_ticket.chars().count());
+            }
+        }
+    }
+
+    /// Replace identifying fields in a decoded message, returning whether
+    /// there were any
+    ///
+    /// Covers logins, character lists, friends and squelches, players'
+    /// objects and descriptions, and the chat messages, actions and events.
+    /// Anything else is left alone.
+    pub fn message(&mut self, message: &mut MessageKind) -> bool {
+        self.edits.clear();
+        match message {
+            MessageKind::C2S(message) => self.c2s_message(message),
+            MessageKind::S2C(message) => self.s2c_message(message),
+        }
+    }
+
+    /// Anonymize a serialized message
+    ///
+    /// The message is decoded to find its identifying fields, noting where
+    /// each string was read from, and the strings replaced are then
+    /// overwritten there, so everything else stays exactly as it was sent.
+    /// Returns an error if the message can't be decoded.
+    pub fn message_data(
+        &mut self,
+        data: &[u8],
+        direction: Direction,
+    ) -> io::Result<AnonymizedData> {
+        let mut reader = ReadRecorder::new(data);
+        let mut message = MessageKind::read(&mut reader, direction)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if !self.message(&mut message) {
+            return Ok(AnonymizedData::Unchanged);
+        }
+
+        let mut anonymized = data.to_vec();
+        for edit in &self.edits {
+            let strings = reader.strings(edit);
+            if strings.is_empty() || edit.replacement.len() != edit.original.len() {
+                return Ok(AnonymizedData::Blanked(blank(data)));
+            }
+            for string in strings {
+                anonymized[string].copy_from_slice(&edit.replacement);
+            }
+        }
+        Ok(AnonymizedData::Replaced(anonymized))
+    }
+
+    /// Replace a name in a message being anonymized
+    fn rename(&mut self, name: &mut String) {
+        let pseudonym = self.name(name);
+        self.edit(encode_string(name), encode_string(&pseudonym), name);
+        *name = pseudonym;
+    }
+
+    /// Replace a name sent in a chat room, which TurbineChat writes as a
+    /// String32L rather than a `WString`
+    fn rename_chat(&mut self, name: &mut WString) {
+        let pseudonym = self.name(&name.0);
+        self.edit(
+            encode_string32l(&name.0),
+            encode_string32l(&pseudonym),
+            &name.0,
+        );
+        name.0 = pseudonym;
+    }
+
+    /// Mask free text in a message being anonymized. Always reports a change
+    /// so callers can return it directly.
+    fn mask(&mut self, text: &mut String) -> bool {
+        let masked = mask(text);
+        self.edit(encode_string(text), encode_string(&masked), text);
+        *text = masked;
+        true
+    }
+
+    fn mask_chat(&mut self, text: &mut WString) -> bool {
+        let masked = mask(&text.0);
+        self.edit(
+            encode_string32l(&text.0),
+            encode_string32l(&masked),
+            &text.0,
+        );
+        text.0 = masked;
+        true
+    }
+
+    /// Note that the string `text`, encoded as `original`, is to become
+    /// `replacement`
+    fn edit(&mut self, original: Vec<u8>, replacement: Vec<u8>, text: &str) {
+        if original != replacement {
+            self.edits.push(Edit {
+                original,
+                replacement,
+                characters: WINDOWS_1252.encode(text).0.len(),
+            });
+        }
+    }
+
+    fn c2s_message(&mut self, message: &mut C2SMessage) -> bool {
+        match message {
+            C2SMessage::OrderedGameAction { action, .. } => self.game_action(action),
+            C2SMessage::LoginSendEnterWorld(message) => {
+                self.rename(&mut message.account);
+                true
+            }
+            C2SMessage::CharacterCharacterDelete(message) => {
+                self.rename(&mut message.account);
+                true
+            }
+            C2SMessage::SocialSendFriendsCommand(message) => {
+                self.rename(&mut message.player);
+                true
+            }
+            C2SMessage::CommunicationTurbineChat(c2s::CommunicationTurbineChat::Type1(message)) => {
+                let c2s::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(event) =
+                    &mut message.blob_dispatch_type;
+                self.rename_chat(&mut event.display_name);
+                self.mask_chat(&mut event.text)
+            }
+            C2SMessage::CommunicationTurbineChat(c2s::CommunicationTurbineChat::Type3(message)) => {
+                let c2s::CommunicationTurbineChatType3BlobDispatchTypeVariant::Type2(request) =
+                    &mut message.blob_dispatch_type;
+                self.mask_chat(&mut request.text)
+            }
+            _ => false,
+        }
+    }
+
+    fn game_action(&mut self, action: &mut GameActionMessage) -> bool {
+        match action {
+            GameActionMessage::CommunicationTalk(action) => self.mask(&mut action.message),
+            GameActionMessage::CommunicationTalkDirect(action) => self.mask(&mut action.message),
+            GameActionMessage::CommunicationTalkDirectByName(action) => {
+                self.rename(&mut action.target_name);
+                self.mask(&mut action.message)
+            }
+            GameActionMessage::CommunicationChannelBroadcast(action) => {
+                self.rename(&mut action.sender_name);
+                self.mask(&mut action.message)
+            }
+            GameActionMessage::CommunicationEmote(action) => self.mask(&mut action.message),
+            GameActionMessage::CommunicationSoulEmote(action) => self.mask(&mut action.message),
+            GameActionMessage::CommunicationSetAFKMessage(action) => self.mask(&mut action.message),
+            GameActionMessage::CommunicationModifyCharacterSquelch(action) => {
+                self.rename(&mut action.character_name);
+                true
+            }
+            GameActionMessage::CommunicationModifyAccountSquelch(action) => {
+                self.rename(&mut action.character_name);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn s2c_message(&mut self, message: &mut S2CMessage) -> bool {
+        match message {
+            S2CMessage::OrderedGameEvent { event, .. } => self.game_event(event),
+            S2CMessage::LoginLoginCharacterSet(message) => {
+                let characters = message.characters.list.iter_mut();
+                for character in characters.chain(message.deleted_characters.list.iter_mut()) {
+                    self.rename(&mut character.name);
+                }
+                self.rename(&mut message.account);
+                true
+            }
+            S2CMessage::CommunicationHearSpeech(message) => {
+                self.rename(&mut message.sender_name);
+                self.mask(&mut message.message)
+            }
+            S2CMessage::CommunicationHearRangedSpeech(message) => {
+                self.rename(&mut message.sender_name);
+                self.mask(&mut message.message)
+            }
+            S2CMessage::CommunicationHearEmote(message) => {
+                self.rename(&mut message.sender_name);
+                self.mask(&mut message.text)
+            }
+            S2CMessage::CommunicationHearSoulEmote(message) => {
+                self.rename(&mut message.sender_name);
+                self.mask(&mut message.text)
+            }
+            S2CMessage::CommunicationTextboxString(message) => self.mask(&mut message.text),
+            S2CMessage::CommunicationTurbineChat(s2c::CommunicationTurbineChat::Type1(message)) => {
+                let s2c::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(event) =
+                    &mut message.blob_dispatch_type;
+                self.rename_chat(&mut event.display_name);
+                self.mask_chat(&mut event.text)
+            }
+            S2CMessage::CommunicationTurbineChat(s2c::CommunicationTurbineChat::Type3(message)) => {
+                let s2c::CommunicationTurbineChatType3BlobDispatchTypeVariant::Type2(request) =
+                    &mut message.blob_dispatch_type;
+                self.mask_chat(&mut request.text)
+            }
+            S2CMessage::ItemCreateObject(message) => self.weenie(&mut message.weenie_description),
+            S2CMessage::ItemUpdateObject(message) => self.weenie(&mut message.weenie_desc),
+            _ => false,
+        }
+    }
+
+    fn game_event(&mut self, event: &mut GameEventMessage) -> bool {
+        match event {
+            GameEventMessage::CommunicationHearDirectSpeech(event) => {
+                self.rename(&mut event.sender_name);
+                self.mask(&mut event.message)
+            }
+            GameEventMessage::CommunicationChannelBroadcast(event) => self.mask(&mut event.message),
+            GameEventMessage::CommunicationPopUpString(event) => self.mask(&mut event.message),
+            GameEventMessage::CommunicationTransientString(event) => self.mask(&mut event.message),
+            GameEventMessage::CommunicationWeenieErrorWithString(event) => {
+                self.mask(&mut event.text)
+            }
+            GameEventMessage::LoginPlayerDescription(event) => {
+                let Some(strings) = &mut event.base_qualities.string_properties else {
+                    return false;
+                };
+                let mut changed = false;
+                for (property, value) in strings.table.iter_mut() {
+                    if matches!(
+                        property,
+                        PropertyString::Name
+                            | PropertyString::MonarchsName
+                            | PropertyString::HouseOwnerName
+                    ) {
+                        self.rename(value);
+                        changed = true;
+                    }
+                }
+                changed
+            }
+            _ => false,
+        }
+    }
+
+    /// Only players are named after someone; other objects keep their names
+    fn weenie(&mut self, weenie: &mut PublicWeenieDesc) -> bool {
+        if !weenie.behavior.contains(ObjectDescriptionFlag::PLAYER) {
+            return false;
+        }
+        self.rename(&mut weenie.name);
+        true
+    }
+}
+
+/// Mask letters and digits in free text, keeping its layout and its length
+/// once encoded in Windows-1252
+fn mask(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| {
+            let masked = if c.is_alphabetic() {
+                'x'
+            } else if c.is_numeric() {
+                '0'
+            } else {
+                c
+            };
+            // Characters Windows-1252 lacks become numeric character references
+            let length = WINDOWS_1252.encode(c.encode_utf8(&mut [0; 4])).0.len();
+            std::iter::repeat_n(masked, if masked == c { 1 } else { length })
+        })
+        .collect()
+}
+
+/// A string as the protocol encodes it, leaving out the padding after it
+fn encode_string(text: &str) -> Vec<u8> {
+    let (encoded, _, _) = WINDOWS_1252.encode(text);
+    let mut data = Vec::with_capacity(6 + encoded.len());
+    if encoded.len() >= 32767 {
+        data.extend_from_slice(&(-1i16).to_le_bytes());
+        data.extend_from_slice(&(encoded.len() as i32).to_le_bytes());
+    } else {
+        data.extend_from_slice(&(encoded.len() as i16).to_le_bytes());
+    }
+    data.extend_from_slice(&encoded);
+    data
+}
+
+/// The anonymized login request in place of `original`, keeping whatever
+/// followed the header, or zeros if either it couldn't be decoded or the
+/// anonymized header doesn't have the original's length
+fn login_request_data(original: &[u8], anonymized: Option<&LoginRequestHeader>) -> Vec<u8> {
+    let mut reader = PacketReader::new(original);
+    if let Some(anonymized) = anonymized
+        && LoginRequestHeader::read(&mut reader).is_ok()
+    {
+        let length = reader.position();
+        let mut data = Vec::with_capacity(original.len());
+        if anonymized.write(&mut Cursor::new(&mut data)).is_ok() && data.len() == length {
+            data.extend_from_slice(&original[length..]);
+            return data;
+        }
+    }
+    vec![0; original.len()]
+}
+
+/// A copy of a message, or its first chunk, with everything after the opcode
+/// zeroed
+fn blank(data: &[u8]) -> Vec<u8> {
+    let mut blanked = data.to_vec();
+    blanked[4.min(data.len())..].fill(0);
+    blanked
+}
+
+/// A String32L as the protocol encodes it: the length of everything after
+/// it, then the string's packed length and Windows-1252 bytes
+fn encode_string32l(text: &str) -> Vec<u8> {
+    let (encoded, _, _) = WINDOWS_1252.encode(text);
+    if encoded.is_empty() {
+        return 0u32.to_le_bytes().to_vec();
+    }
+    let mut data = Vec::with_capacity(6 + encoded.len());
+    if encoded.len() > 255 {
+        data.extend_from_slice(&(2 + encoded.len() as u32).to_le_bytes());
+        data.push((encoded.len() >> 8) as u8 | 0x80);
+    } else {
+        data.extend_from_slice(&(1 + encoded.len() as u32).to_le_bytes());
+    }
+    data.push(encoded.len() as u8);
+    data.extend_from_slice(&encoded);
+    data
+}
+
+/// What `CaptureAnonymizer` did to a capture
+#[derive(Debug, Default, Clone)]
+pub struct AnonymizeStats {
+    /// Packets written to the anonymized capture
+    pub packets: usize,
+    /// Frames left out because they aren't well-formed AC traffic over UDP
+    pub dropped_frames: usize,
+    /// Messages reassembled from the capture
+    pub messages: usize,
+    /// Messages that had identifying fields replaced
+    pub anonymized: usize,
+    /// Anonymized messages with a replacement that didn't fit where the
+    /// original was, which had everything after the opcode zeroed instead
+    pub blanked: usize,
+    /// Messages that couldn't be decoded, which had everything after the
+    /// opcode zeroed
+    pub undecoded: usize,
+    /// Messages that never completed, which had their fragments zeroed
+    /// after the opcode
+    pub incomplete: usize,
+}
+
+/// Writes an anonymized copy of a capture
+///
+/// Messages are spread over several packets, so the capture is read twice:
+/// `scan` reassembles every message and works out its anonymized form, then
+/// `write` rebuilds each packet around the anonymized fragments, re-sealing
+/// checksums with the keys the originals were sealed with. Fragments of
+/// messages the scan couldn't check are zeroed rather than copied. Frames
+/// are rewritten as Ethernet with anonymized addresses and fresh IP and UDP
+/// headers.
+#[derive(Debug, Default)]
+pub struct CaptureAnonymizer {
+    anonymizer: Anonymizer,
+    /// Anonymized data of each rewritten message, keyed by the original
+    /// endpoints like the assembler keys its fragments
+    messages: HashMap<FragmentKey, Vec<u8>>,
+    /// Messages with nothing to replace, which are copied as they are
+    clean: HashSet<FragmentKey>,
+    stats: AnonymizeStats,
+}
+
+impl CaptureAnonymizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> &AnonymizeStats {
+        &self.stats
+    }
+
+    /// First pass: reassemble the capture's messages and anonymize them
+    pub fn scan<R: Read>(&mut self, packets: PcapIterator<R>) -> io::Result<()> {
+        let mut assembler = FragmentAssembler::new();
+
+        for packet in packets {
+            let packet = packet?;
+            assembler.set_capture_time(packet.time());
+            // Malformed datagrams are left out by `write`
+            let Ok(parsed) = assembler.parse_link_packets(packet.link_type, &packet.data) else {
+                continue;
+            };
+            // Pair each message with the key of the fragment that completed it
+            let completed = parsed.packets.iter().flat_map(|record| {
+                record
+                    .fragments
+                    .iter()
+                    .filter(|fragment| fragment.is_complete)
+                    .map(|fragment| {
+                        FragmentKey::new(
+                            record.source,
+                            record.destination,
+                            fragment.sequence,
+                            fragment.id,
+                        )
+                    })
+            });
+            for (key, message) in completed.zip(parsed.messages) {
+                self.stats.messages += 1;
+                let data = match self
+                    .anonymizer
+                    .message_data(&message.data, message.direction)
+                {
+                    Ok(AnonymizedData::Replaced(data)) => data,
+                    Ok(AnonymizedData::Blanked(data)) => {
+                        self.stats.blanked += 1;
+                        data
+                    }
+                    Ok(AnonymizedData::Unchanged) => {
+                        self.clean.insert(key);
+                        continue;
+                    }
+                    Err(_) => {
+                        self.stats.undecoded += 1;
+                        self.messages.insert(key, blank(&message.data));
+                        continue;
+                    }
+                };
+                self.stats.anonymized += 1;
+                self.messages.insert(key, data);
+            }
+        }
+
+        assembler.finish();
+        self.stats.incomplete = assembler.diagnostics().dropped_total() as usize;
+        Ok(())
+    }
+
+    /// Second pass: write the anonymized packets
+    ///
+    /// `packets` must be the same capture given to `scan`, and `writer` must
+    /// have been created for `LINKTYPE_ETHERNET`.
+    pub fn write<R: Read, W: Write>(
+        &mut self,
+        packets: PcapIterator<R>,
+        writer: &mut PcapWriter<W>,
+    ) -> io::Result<()> {
+        for packet in packets {
+            let packet = packet?;
+            let Some(datagram) = UdpDatagram::from_link_frame(packet.link_type, &packet.data)
+            else {
+                self.stats.dropped_frames += 1;
+                continue;
+            };
+            let Ok(payload) = self.anonymize_datagram(&datagram) else {
+                self.stats.dropped_frames += 1;
+                continue;
+            };
+
+            let source = SocketAddr::new(
+                self.anonymizer.address(datagram.source.ip()),
+                datagram.source.port(),
+            );
+            let destination = SocketAddr::new(
+                self.anonymizer.address(datagram.destination.ip()),
+                datagram.destination.port(),
+            );
+            let anonymized = UdpDatagram {
+                source,
+                destination,
+                payload: &payload,
+            };
+            writer.write_packet(&Packet {
+                ts_sec: packet.ts_sec,
+                ts_usec: packet.ts_usec,
+                data: anonymized.to_ethernet_frame()?,
+                link_type: LINKTYPE_ETHERNET,
+            })?;
+            self.stats.packets += 1;
+        }
+        Ok(())
+    }
+
+    fn anonymize_datagram(&mut self, datagram: &UdpDatagram) -> io::Result<Vec<u8>> {
+        let payload = datagram.payload;
+        let mut anonymized = Vec::with_capacity(payload.len());
+        let mut position = 0;
+
+        while position < payload.len() {
+            let mut reader = PacketReader::new(&payload[position..]);
+            let header =
+                PacketHeader::read(&mut reader).map_err(|e| io::Error::other(e.to_string()))?;
+            let packet_end = position + PacketHeader::BASE_SIZE + header.size as usize;
+            let packet = payload.get(position..packet_end).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Packet shorter than its header size",
+                )
+            })?;
+            anonymized.extend(self.anonymize_packet(datagram, header, packet)?);
+            position = packet_end;
+        }
+
+        Ok(anonymized)
+    }
+
+    fn anonymize_packet(
+        &mut self,
+        datagram: &UdpDatagram,
+        mut header: PacketHeader,
+        packet: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let key = sealing_key(&header, packet)?;
+
+        let mut reader = PacketReader::new(packet);
+        reader.set_position(PacketHeader::BASE_SIZE);
+        let mut optional = OptionalHeaders::read(&mut reader, header.flags, packet.len(), true)?;
+        let optional_end = reader.position();
+
+        if let Some(address) = &mut optional.logon_server_addr {
+            self.anonymizer.socket_address(address);
+        }
+        if let Some(referral) = &mut optional.referral {
+            self.anonymizer.socket_address(&mut referral.address);
+        }
+        let mut login_request = optional.login_request.take();
+        if let Some(login_request) = &mut login_request {
+            self.anonymizer.login_request(login_request);
+        }
+
+        let mut body = Vec::with_capacity(packet.len());
+        optional.write(&mut Cursor::new(&mut body))?;
+        if header.flags.contains(PacketHeaderFlags::LOGIN_REQUEST) {
+            // Nothing else follows the login request, which runs to the end of
+            // the packet
+            let original = &packet[PacketHeader::BASE_SIZE + body.len()..optional_end];
+            body.extend(login_request_data(original, login_request.as_ref()));
+        }
+
+        if header.flags.contains(PacketHeaderFlags::BLOB_FRAGMENTS) {
+            let mut position = optional_end;
+            while position < packet.len() {
+                let fragment = packet
+                    .get(position..position + FRAGMENT_HEADER_SIZE)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "Fragment header too short")
+                    })?;
+                let sequence =
+                    u32::from_le_bytes([fragment[0], fragment[1], fragment[2], fragment[3]]);
+                let id = u32::from_le_bytes([fragment[4], fragment[5], fragment[6], fragment[7]]);
+                let size = u16::from_le_bytes([fragment[10], fragment[11]]) as usize;
+                let index = u16::from_le_bytes([fragment[12], fragment[13]]) as usize;
+                let fragment_end = position + size.max(FRAGMENT_HEADER_SIZE);
+                let original = packet
+                    .get(position + FRAGMENT_HEADER_SIZE..fragment_end)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "Fragment data too short")
+                    })?;
+
+                let key = FragmentKey::new(datagram.source, datagram.destination, sequence, id);
+                let start = index * FRAGMENT_CHUNK_SIZE;
+                let chunk = match self.messages.get(&key) {
+                    Some(data) => data
+                        .get(start..start + original.len())
+                        .map(<[u8]>::to_vec)
+                        .unwrap_or_else(|| vec![0; original.len()]),
+                    None if self.clean.contains(&key) => original.to_vec(),
+                    // Never completed, or in a datagram the scan couldn't read
+                    None if index == 0 => blank(original),
+                    None => vec![0; original.len()],
+                };
+
+                body.extend_from_slice(&fragment[..10]);
+                body.extend_from_slice(
+                    &((FRAGMENT_HEADER_SIZE + chunk.len()) as u16).to_le_bytes(),
+                );
+                body.extend_from_slice(&fragment[12..]);
+                body.extend_from_slice(&chunk);
+                position = fragment_end;
+            }
+        }
+
+        header.size = body.len() as u16;
+        let mut anonymized = Vec::with_capacity(PacketHeader::BASE_SIZE + body.len());
+        header
+            .write(&mut Cursor::new(&mut anonymized))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        anonymized.extend_from_slice(&body);
+        seal_packet(&mut anonymized, key)?;
+        Ok(anonymized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{ACQualitiesFlags, AuthFlags, ChatFragmentType, FragmentGroup};
+    use crate::network::checksum::{ChecksumStatus, PacketChecksum};
+    use crate::network::{MessageStream, PacketBuilder};
+    use crate::test_support::{
+        obj_desc, physics_desc, player_description, serialize, turbine_chat_event,
+        turbine_chat_request, weenie_desc,
+    };
+    use crate::types::{LoginRequestHeaderType2, ObjectId, PackableHashTable};
+
+    #[test]
+    fn test_names_are_consistent_and_keep_their_length() {
+        let mut anonymizer = Anonymizer::new();
+        let first = anonymizer.name("Sir Galahad");
+        let second = anonymizer.name("Bob");
+
+        assert_eq!(first, "Aaaaaaaaaaa");
+        assert_eq!(second, "Aab");
+        assert_eq!(anonymizer.name("Sir Galahad"), first);
+        assert_eq!(anonymizer.name(""), "");
+    }
+
+    #[test]
+    fn test_names_grow_once_a_length_runs_out() {
+        let mut anonymizer = Anonymizer::new();
+        let pseudonyms: Vec<String> = (0..30)
+            .map(|i| anonymizer.name(&char::from_u32(0xC0 + i).unwrap().to_string()))
+            .collect();
+
+        assert_eq!(pseudonyms[0], "A");
+        assert_eq!(pseudonyms[25], "Z");
+        assert_eq!(pseudonyms[26], "Ba");
+        let unique: std::collections::HashSet<_> = pseudonyms.iter().collect();
+        assert_eq!(unique.len(), pseudonyms.len());
+        // Longer names still get a pseudonym of their own length
+        assert_eq!(anonymizer.name("Bob"), "Abe");
+    }
+
+    #[test]
+    fn test_names_keep_their_encoded_length() {
+        let mut anonymizer = Anonymizer::new();
+
+        // One byte each in Windows-1252
+        assert_eq!(anonymizer.name("Zoë"), "Aaa");
+        // Written as a numeric character reference
+        assert_eq!(anonymizer.name("Ω").len(), "&#937;".len());
+        assert_eq!(mask("Ωal 9"), "xxxxxxxx 0");
+        assert_eq!(
+            encode_string32l(&mask("Ωal")).len(),
+            encode_string32l("Ωal").len()
+        );
+    }
+
+    #[test]
+    fn test_replacement_that_does_not_fit_is_blanked() {
+        let mut anonymizer = Anonymizer::new();
+        for letter in 'A'..='Z' {
+            anonymizer.name(&letter.to_string());
+        }
+        let mut data = Vec::new();
+        hear_speech("hi", "é")
+            .write(&mut Cursor::new(&mut data))
+            .unwrap();
+        let mut named = Vec::new();
+        hear_speech("hi", "Bo")
+            .write(&mut Cursor::new(&mut named))
+            .unwrap();
+
+        // Every one-letter pseudonym is taken, so another one-letter name would need two
+        let AnonymizedData::Blanked(blanked) = anonymizer
+            .message_data(&data, Direction::ServerToClient)
+            .unwrap()
+        else {
+            panic!("expected the message to be blanked");
+        };
+        assert_eq!(blanked.len(), data.len());
+        assert_eq!(blanked[..4], data[..4]);
+        assert!(blanked[4..].iter().all(|&b| b == 0));
+
+        assert!(matches!(
+            anonymizer.message_data(&named, Direction::ServerToClient),
+            Ok(AnonymizedData::Replaced(_))
+        ));
+    }
+
+    #[test]
+    fn test_only_string_fields_are_overwritten() {
+        // A sender id whose bytes happen to spell the sender's encoded name
+        let encoded_name = encode_string("Bo");
+        let mut message = hear_speech("hi", "Bo");
+        let S2CMessage::CommunicationHearSpeech(speech) = &mut message else {
+            unreachable!()
+        };
+        speech.sender_id = ObjectId(u32::from_le_bytes(encoded_name[..4].try_into().unwrap()));
+        let mut data = Vec::new();
+        message.write(&mut Cursor::new(&mut data)).unwrap();
+
+        let AnonymizedData::Replaced(anonymized) = Anonymizer::new()
+            .message_data(&data, Direction::ServerToClient)
+            .unwrap()
+        else {
+            panic!("expected the message to be rewritten");
+        };
+        let S2CMessage::CommunicationHearSpeech(speech) =
+            S2CMessage::read(&mut Cursor::new(&anonymized)).unwrap()
+        else {
+            unreachable!()
+        };
+        assert_eq!(speech.sender_name, "Aa");
+        assert_eq!(speech.message, "xx");
+        assert_eq!(&speech.sender_id.0.to_le_bytes()[..], &encoded_name[..4]);
+    }
+
+    #[test]
+    fn test_addresses_are_consistent() {
+        let mut anonymizer = Anonymizer::new();
+        let server: IpAddr = "198.51.100.7".parse().unwrap();
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+
+        assert_eq!(
+            anonymizer.address(server),
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            anonymizer.address(client),
+            "fd00::2".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            anonymizer.address(server),
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+
+        let mut address = SocketAddress {
+            family: 2,
+            port: 9000u16.swap_bytes(),
+            address: u32::from_le_bytes([198, 51, 100, 7]),
+            empty: 0,
+        };
+        anonymizer.socket_address(&mut address);
+        assert_eq!(address.address.to_le_bytes(), [10, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_login_request_credentials_replaced() {
+        let mut request = LoginRequestHeader::Type2(LoginRequestHeaderType2 {
+            client_version: "1802".to_string(),
+            length: 0,
+            flags: AuthFlags::None,
+            sequence: 1,
+            account: "myaccount".to_string(),
+            account_to_login_as: String::new(),
+            password: WString("hunter2".to_string()),
+        });
+        Anonymizer::new().login_request(&mut request);
+
+        let LoginRequestHeader::Type2(request) = request else {
+            unreachable!()
+        };
+        assert_eq!(request.account, "Aaaaaaaaa");
+        assert_eq!(request.account_to_login_as, "");
+        assert_eq!(request.password.0, "xxxxxxx");
+        assert_eq!(request.client_version, "1802");
+    }
+
+    #[test]
+    fn test_login_request_rewritten_in_place() {
+        let request = LoginRequestHeader::Type2(LoginRequestHeaderType2 {
+            client_version: "1802".to_string(),
+            length: 0,
+            flags: AuthFlags::None,
+            sequence: 1,
+            account: "myaccount".to_string(),
+            account_to_login_as: String::new(),
+            password: WString("hunter2".to_string()),
+        });
+        let packet = |request: &LoginRequestHeader, trailer: &[u8]| {
+            let mut body = Vec::new();
+            request.write(&mut Cursor::new(&mut body)).unwrap();
+            body.extend_from_slice(trailer);
+            let mut header = PacketHeader::with_flags(PacketHeaderFlags::LOGIN_REQUEST);
+            header.size = body.len() as u16;
+            let mut packet = Vec::new();
+            header.write(&mut Cursor::new(&mut packet)).unwrap();
+            packet.extend_from_slice(&body);
+            seal_packet(&mut packet, None).unwrap();
+            packet
+        };
+        let datagram = |payload| UdpDatagram {
+            source: "192.0.2.33:50000".parse().unwrap(),
+            destination: "198.51.100.7:9000".parse().unwrap(),
+            payload,
+        };
+
+        let original = packet(&request, &[1, 2, 3]);
+        let anonymized = CaptureAnonymizer::new()
+            .anonymize_datagram(&datagram(&original))
+            .unwrap();
+
+        let mut expected = request;
+        Anonymizer::new().login_request(&mut expected);
+        assert_eq!(anonymized, packet(&expected, &[1, 2, 3]));
+
+        // One that can't be decoded is zeroed, without changing its size
+        let mut undecodable = original[..PacketHeader::BASE_SIZE].to_vec();
+        undecodable.extend_from_slice(&[0xFF; 5]);
+        undecodable[16..18].copy_from_slice(&5u16.to_le_bytes());
+        let anonymized = CaptureAnonymizer::new()
+            .anonymize_datagram(&datagram(&undecodable))
+            .unwrap();
+        assert_eq!(anonymized.len(), undecodable.len());
+        assert_eq!(&anonymized[PacketHeader::BASE_SIZE..], &[0; 5]);
+    }
+
+    fn hear_speech(message: &str, sender_name: &str) -> S2CMessage {
+        S2CMessage::CommunicationHearSpeech(s2c::CommunicationHearSpeech {
+            message: message.to_string(),
+            sender_name: sender_name.to_string(),
+            sender_id: ObjectId(0x5000_0001),
+            type_: ChatFragmentType::Speech,
+        })
+    }
+
+    fn pcap(datagrams: &[(SocketAddr, SocketAddr, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        for (i, (source, destination, payload)) in datagrams.iter().enumerate() {
+            let datagram = UdpDatagram {
+                source: *source,
+                destination: *destination,
+                payload,
+            };
+            writer
+                .write_packet(&Packet {
+                    ts_sec: i as u32,
+                    ts_usec: 0,
+                    data: datagram.to_ethernet_frame().unwrap(),
+                    link_type: LINKTYPE_ETHERNET,
+                })
+                .unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn test_capture_anonymized_end_to_end() {
+        let server: SocketAddr = "198.51.100.7:9000".parse().unwrap();
+        let client: SocketAddr = "192.0.2.33:50000".parse().unwrap();
+
+        // Spans several packets, with encrypted checksums
+        let text = "Meet me at the Arwic lifestone at 10, Galahad! ".repeat(25);
+        let mut builder = PacketBuilder::new().with_isaac_seed(0x5EED);
+        builder
+            .queue_message(FragmentGroup::Private, &hear_speech(&text, "Galahad"))
+            .unwrap();
+        builder
+            .queue_message(FragmentGroup::Private, &hear_speech("hi", "Galahad"))
+            .unwrap();
+        let datagrams: Vec<_> = builder
+            .build_all()
+            .unwrap()
+            .into_iter()
+            .map(|packet| (server, client, packet))
+            .collect();
+        assert!(datagrams.len() > 1);
+        let capture = pcap(&datagrams);
+
+        let mut anonymizer = CaptureAnonymizer::new();
+        anonymizer
+            .scan(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap())
+            .unwrap();
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        anonymizer
+            .write(
+                PcapIterator::<&[u8]>::from_bytes(&capture).unwrap(),
+                &mut writer,
+            )
+            .unwrap();
+        let stats = anonymizer.stats();
+        assert_eq!(stats.packets, datagrams.len());
+        assert_eq!((stats.messages, stats.anonymized, stats.blanked), (2, 2, 0));
+        let output = writer.into_inner();
+
+        let mut keys = crate::network::Isaac::new(0x5EED);
+        for packet in PcapIterator::<&[u8]>::from_bytes(&output).unwrap() {
+            let packet = packet.unwrap();
+            let datagram = UdpDatagram::from_link_frame(packet.link_type, &packet.data).unwrap();
+            assert_eq!(datagram.source, "10.0.0.1:9000".parse().unwrap());
+            assert_eq!(datagram.destination, "10.0.0.2:50000".parse().unwrap());
+
+            let checksum = PacketChecksum::compute(datagram.payload).unwrap();
+            let sealed = u32::from_le_bytes(datagram.payload[8..12].try_into().unwrap());
+            assert_eq!(checksum.recover_key(sealed), keys.next_key());
+        }
+
+        let messages: Vec<_> =
+            MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&output).unwrap())
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(messages.len(), 2);
+        let expected = [
+            hear_speech(&mask(&text), "Aaaaaaa"),
+            hear_speech("xx", "Aaaaaaa"),
+        ];
+        for (message, expected) in messages.iter().zip(&expected) {
+            let mut data = Vec::new();
+            expected.write(&mut Cursor::new(&mut data)).unwrap();
+            assert_eq!(message.data, data);
+        }
+    }
+
+    #[test]
+    fn test_unencrypted_packets_stay_valid() {
+        let server: SocketAddr = "198.51.100.7:9000".parse().unwrap();
+        let client: SocketAddr = "192.0.2.33:50000".parse().unwrap();
+        let mut builder = PacketBuilder::new();
+        builder
+            .queue_message(FragmentGroup::Private, &hear_speech("hello", "Galahad"))
+            .unwrap();
+        let capture = pcap(&[(server, client, builder.build_packet().unwrap().unwrap())]);
+
+        let mut anonymizer = CaptureAnonymizer::new();
+        anonymizer
+            .scan(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap())
+            .unwrap();
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        anonymizer
+            .write(
+                PcapIterator::<&[u8]>::from_bytes(&capture).unwrap(),
+                &mut writer,
+            )
+            .unwrap();
+        let output = writer.into_inner();
+
+        let packet = PcapIterator::<&[u8]>::from_bytes(&output)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let parsed = FragmentAssembler::new()
+            .parse_link_packets(packet.link_type, &packet.data)
+            .unwrap();
+        assert_eq!(parsed.packets[0].checksum, ChecksumStatus::Valid);
+        assert_eq!(parsed.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_messages_that_cannot_be_checked_are_zeroed() {
+        let server: SocketAddr = "198.51.100.7:9000".parse().unwrap();
+        let client: SocketAddr = "192.0.2.33:50000".parse().unwrap();
+
+        let mut undecodable = 0xDEAD_BEEFu32.to_le_bytes().to_vec();
+        undecodable.extend_from_slice(b"a note from Galahad");
+        let mut builder = PacketBuilder::new();
+        builder
+            .queue_bytes(FragmentGroup::Private, &undecodable)
+            .unwrap();
+        let text = "Meet me at the Arwic lifestone, Galahad! ".repeat(25);
+        builder
+            .queue_message(FragmentGroup::Private, &hear_speech(&text, "Galahad"))
+            .unwrap();
+        let mut datagrams: Vec<_> = builder
+            .build_all()
+            .unwrap()
+            .into_iter()
+            .map(|packet| (server, client, packet))
+            .collect();
+        // The speech never completes without its last chunk
+        datagrams.pop();
+        assert!(datagrams.len() > 1);
+        let capture = pcap(&datagrams);
+
+        let mut anonymizer = CaptureAnonymizer::new();
+        anonymizer
+            .scan(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap())
+            .unwrap();
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        anonymizer
+            .write(
+                PcapIterator::<&[u8]>::from_bytes(&capture).unwrap(),
+                &mut writer,
+            )
+            .unwrap();
+        let stats = anonymizer.stats();
+        assert_eq!(stats.packets, datagrams.len());
+        assert_eq!((stats.undecoded, stats.incomplete), (1, 1));
+        let output = writer.into_inner();
+
+        let messages: Vec<_> =
+            MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&output).unwrap())
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, blank(&undecodable));
+        assert_eq!(&messages[0].data[..4], &undecodable[..4]);
+
+        let contains = |needle: &[u8]| output.windows(needle.len()).any(|w| w == needle);
+        assert!(!contains(b"Galahad"));
+        assert!(!contains(b"Arwic"));
+    }
+
+    #[test]
+    fn test_no_player_name_survives() {
+        let server: SocketAddr = "198.51.100.7:9000".parse().unwrap();
+        let client: SocketAddr = "192.0.2.33:50000".parse().unwrap();
+
+        let mut player = weenie_desc("Galahad");
+        player.behavior = ObjectDescriptionFlag::PLAYER;
+        let mut description = player_description();
+        description.base_qualities.string_properties = Some(PackableHashTable {
+            count: 2,
+            max_size: 0,
+            table: [
+                (PropertyString::Name, "Galahad".to_string()),
+                (PropertyString::MonarchsName, "Lancelot".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        });
+        description.qualities.flags = ACQualitiesFlags::empty();
+        description.qualities.attributes = None;
+        let received = [
+            turbine_chat_event("Galahad", "WTS Galahad's sword"),
+            S2CMessage::ItemCreateObject(s2c::ItemCreateObject {
+                object_id: ObjectId(0x5000_0001),
+                object_description: obj_desc(0),
+                physics_description: physics_desc(),
+                weenie_description: player.clone(),
+            }),
+            S2CMessage::ItemUpdateObject(s2c::ItemUpdateObject {
+                object_id: ObjectId(0x5000_0001),
+                object_desc: obj_desc(0),
+                physics_desc: physics_desc(),
+                weenie_desc: player,
+            }),
+            S2CMessage::OrderedGameEvent {
+                object_id: 0x5000_0001,
+                sequence: 1,
+                event: Box::new(GameEventMessage::LoginPlayerDescription(Box::new(
+                    description,
+                ))),
+            },
+        ];
+        let mut sent: Vec<Vec<u8>> = received.iter().map(serialize).collect();
+        sent.push(serialize(&turbine_chat_request(
+            "Galahad",
+            "LFG with Lancelot",
+        )));
+        let mut from_server = PacketBuilder::new();
+        for data in &sent[..received.len()] {
+            from_server
+                .queue_bytes(FragmentGroup::Private, data)
+                .unwrap();
+        }
+        let mut from_client = PacketBuilder::new();
+        from_client
+            .queue_bytes(FragmentGroup::Private, &sent[received.len()])
+            .unwrap();
+
+        let mut datagrams: Vec<_> = from_server
+            .build_all()
+            .unwrap()
+            .into_iter()
+            .map(|packet| (server, client, packet))
+            .collect();
+        datagrams.extend(
+            from_client
+                .build_all()
+                .unwrap()
+                .into_iter()
+                .map(|packet| (client, server, packet)),
+        );
+        let capture = pcap(&datagrams);
+
+        let mut anonymizer = CaptureAnonymizer::new();
+        anonymizer
+            .scan(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap())
+            .unwrap();
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        anonymizer
+            .write(
+                PcapIterator::<&[u8]>::from_bytes(&capture).unwrap(),
+                &mut writer,
+            )
+            .unwrap();
+        let stats = anonymizer.stats();
+        assert_eq!((stats.messages, stats.anonymized, stats.blanked), (5, 5, 0));
+        let output = writer.into_inner();
+
+        // Only the names and free text change; the Turbine chat headers and
+        // the weenie class ids and icons the decoder skips are untouched
+        let replacements = [
+            (encode_string("Galahad"), encode_string("Aaaaaaa")),
+            (encode_string("Lancelot"), encode_string("Aaaaaaab")),
+            (encode_string32l("Galahad"), encode_string32l("Aaaaaaa")),
+            (
+                encode_string32l("WTS Galahad's sword"),
+                encode_string32l("xxx xxxxxxx'x xxxxx"),
+            ),
+            (
+                encode_string32l("LFG with Lancelot"),
+                encode_string32l("xxx xxxx xxxxxxxx"),
+            ),
+        ];
+        let messages: Vec<_> =
+            MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&output).unwrap())
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(messages.len(), sent.len());
+        for (message, original) in messages.iter().zip(&sent) {
+            let mut expected = original.clone();
+            for (name, pseudonym) in &replacements {
+                while let Some(start) = expected
+                    .windows(name.len())
+                    .position(|window| window == name.as_slice())
+                {
+                    expected[start..start + name.len()].copy_from_slice(pseudonym);
+                }
+            }
+            assert_ne!(&expected, original);
+            assert_eq!(message.data, expected);
+        }
+
+        let contains = |needle: &[u8]| output.windows(needle.len()).any(|w| w == needle);
+        for name in ["Galahad", "Lancelot"] {
+            assert!(!contains(name.as_bytes()), "{name} survived");
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::message::Direction;
+
+use super::checksum::ChecksumStatus;
+use super::packet::PacketHeader;
+use super::packet_record::ParsedDatagram;
+use super::session_table::SessionTable;
+
+/// How many of a side's most recent header time ticks are kept to match
+/// against the FLOW reports the other side sends back
+const FLOW_TICK_HISTORY: usize = 64;
+
+/// Amount of traffic of one kind
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TrafficCount {
+    /// Packets or messages, depending on what's being counted
+    pub count: u64,
+    pub bytes: u64,
+    /// `bytes` averaged over the session's duration
+    pub bytes_per_second: Option<f64>,
+}
+
+impl TrafficCount {
+    fn add(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes as u64;
+    }
+
+    fn set_rate(&mut self, duration: Option<f64>) {
+        self.bytes_per_second = duration.map(|seconds| self.bytes as f64 / seconds);
+    }
+}
+
+/// What the receiving side said about a direction's traffic in its FLOW
+/// headers, next to what the capture saw
+///
+/// Each FLOW header gives the bytes received during one of the sender's
+/// header time ticks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FlowReports {
+    pub reports: u64,
+    /// Bytes the receiver said it got, over every report
+    pub advertised_bytes: u64,
+    /// Bytes the capture saw sent in the ticks that were reported on. Less
+    /// than `advertised_bytes` means the capture missed packets; more means
+    /// the receiver did.
+    pub captured_bytes: u64,
+    /// Reports on ticks the capture saw no packets in, e.g. from before it
+    /// started
+    pub unmatched_reports: u64,
+}
+
+/// Traffic sent in one direction of a session
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DirectionBandwidth {
+    /// AC packets and their size, headers included
+    pub packets: TrafficCount,
+    /// Messages and the size of their data, by message type
+    pub message_types: BTreeMap<String, TrafficCount>,
+    /// Messages and the size of their data, by message queue
+    pub queues: BTreeMap<String, TrafficCount>,
+    pub flow: FlowReports,
+}
+
+/// Traffic in both directions of one session
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionBandwidth {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    /// Capture time between the session's first and last packet, in seconds
+    pub duration: f64,
+    pub client_to_server: DirectionBandwidth,
+    pub server_to_client: DirectionBandwidth,
+}
+
+/// Everything `BandwidthAnalyzer` found in a capture
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BandwidthReport {
+    /// One entry per session, in the order they were first seen
+    pub sessions: Vec<SessionBandwidth>,
+}
+
+/// Tracking state for one direction
+#[derive(Debug, Default)]
+struct DirectionState {
+    bandwidth: DirectionBandwidth,
+    /// Bytes sent in each recent header time tick, oldest first
+    ticks: VecDeque<(u16, u64)>,
+}
+
+impl DirectionState {
+    fn sent(&mut self, tick: u16, bytes: usize) {
+        self.bandwidth.packets.add(bytes);
+        match self.ticks.back_mut() {
+            Some((last, total)) if *last == tick => *total += bytes as u64,
+            _ => {
+                self.ticks.push_back((tick, bytes as u64));
+                if self.ticks.len() > FLOW_TICK_HISTORY {
+                    self.ticks.pop_front();
+                }
+            }
+        }
+    }
+
+    fn flow_reported(&mut self, tick: u16, bytes: u32) {
+        let flow = &mut self.bandwidth.flow;
+        flow.reports += 1;
+        flow.advertised_bytes += bytes as u64;
+        let captured: u64 = self
+            .ticks
+            .iter()
+            .filter(|(sent, _)| *sent == tick)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        if captured == 0 {
+            flow.unmatched_reports += 1;
+        }
+        flow.captured_bytes += captured;
+    }
+
+    fn finish(mut self, duration: Option<f64>) -> DirectionBandwidth {
+        let bandwidth = &mut self.bandwidth;
+        bandwidth.packets.set_rate(duration);
+        for count in bandwidth.message_types.values_mut() {
+            count.set_rate(duration);
+        }
+        for count in bandwidth.queues.values_mut() {
+            count.set_rate(duration);
+        }
+        self.bandwidth
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    client_to_server: DirectionState,
+    server_to_client: DirectionState,
+    first_time: Option<Duration>,
+    last_time: Option<Duration>,
+}
+
+impl SessionState {
+    fn directions(&mut self, direction: Direction) -> (&mut DirectionState, &mut DirectionState) {
+        match direction {
+            Direction::ClientToServer => (&mut self.client_to_server, &mut self.server_to_client),
+            Direction::ServerToClient => (&mut self.server_to_client, &mut self.client_to_server),
+        }
+    }
+
+    fn finish(self, client: SocketAddr, server: SocketAddr) -> SessionBandwidth {
+        let duration = match (self.first_time, self.last_time) {
+            (Some(first), Some(last)) => last.saturating_sub(first).as_secs_f64(),
+            _ => 0.0,
+        };
+        let rate_duration = (duration > 0.0).then_some(duration);
+        SessionBandwidth {
+            client,
+            server,
+            duration,
+            client_to_server: self.client_to_server.finish(rate_duration),
+            server_to_client: self.server_to_client.finish(rate_duration),
+        }
+    }
+}
+
+/// Measures traffic per session, direction, message type and queue, and
+/// compares it with what each side advertises in its FLOW headers
+///
+/// Feed it every parsed datagram of a capture in order, along with its
+/// capture time, then call `finish` for the report. Packets of unknown
+/// direction or with invalid checksums are left out.
+#[derive(Debug, Default)]
+pub struct BandwidthAnalyzer {
+    sessions: SessionTable<SessionState>,
+}
+
+impl BandwidthAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_datagram(&mut self, datagram: &ParsedDatagram, time: Option<Duration>) {
+        // Every packet in a datagram shares its endpoints, so the messages
+        // completed in it belong to the session of the first packet counted
+        let mut messages_session = None;
+        for packet in &datagram.packets {
+            if packet.checksum == ChecksumStatus::Invalid {
+                continue;
+            }
+            let (Some(direction), Some(index)) = (
+                packet.direction,
+                self.sessions.session_index(packet, SessionState::default),
+            ) else {
+                continue;
+            };
+            messages_session.get_or_insert(index);
+
+            let session = self.sessions.get_mut(index);
+            if let Some(time) = time {
+                session.first_time.get_or_insert(time);
+                session.last_time = Some(time);
+            }
+            let (sent, received) = session.directions(direction);
+            sent.sent(
+                packet.header.time,
+                PacketHeader::BASE_SIZE + packet.header.size as usize,
+            );
+            if let Some(flow) = &packet.optional.flow {
+                received.flow_reported(flow.interval, flow.bytes);
+            }
+        }
+
+        let Some(index) = messages_session else {
+            return;
+        };
+        let session = self.sessions.get_mut(index);
+        for message in &datagram.messages {
+            let (sent, _) = session.directions(message.direction);
+            let bandwidth = &mut sent.bandwidth;
+            bandwidth
+                .message_types
+                .entry(message.message_type.clone())
+                .or_default()
+                .add(message.data.len());
+            let queue = message
+                .queue
+                .as_ref()
+                .map_or("Unknown".to_string(), |queue| format!("{queue:?}"));
+            bandwidth
+                .queues
+                .entry(queue)
+                .or_default()
+                .add(message.data.len());
+        }
+    }
+
+    /// Turn every session's byte counts into rates over the time it was seen
+    pub fn finish(self) -> BandwidthReport {
+        BandwidthReport {
+            sessions: self
+                .sessions
+                .into_sessions()
+                .map(|(client, server, session)| session.finish(client, server))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::PacketHeaderFlags;
+    use crate::network::{PacketRecord, RawMessage};
+    use crate::types::FlowHeader;
+
+    fn packet(direction: Direction, tick: u16, size: u16) -> PacketRecord {
+        let mut packet = PacketRecord::between(
+            "192.0.2.33:50000",
+            "198.51.100.7:9000",
+            direction,
+            PacketHeaderFlags::ENCRYPTED_CHECKSUM,
+        );
+        packet.header.time = tick;
+        packet.header.size = size;
+        packet
+    }
+
+    fn at(seconds: u64) -> Option<Duration> {
+        Some(Duration::from_secs(1_763_490_000 + seconds))
+    }
+
+    #[test]
+    fn test_bandwidth_by_direction_type_and_queue() {
+        let mut analyzer = BandwidthAnalyzer::new();
+
+        let message = RawMessage::from_fragment(
+            vec![0xDE, 0xF7, 0x00, 0x00],
+            1,
+            0,
+            Direction::ServerToClient,
+        )
+        .unwrap();
+        let message_type = message.message_type.clone();
+        let datagram = ParsedDatagram {
+            packets: vec![packet(Direction::ServerToClient, 100, 80)],
+            messages: vec![message],
+        };
+        analyzer.add_datagram(&datagram, at(0));
+
+        let datagram = ParsedDatagram {
+            packets: vec![packet(Direction::ClientToServer, 7, 30)],
+            messages: Vec::new(),
+        };
+        analyzer.add_datagram(&datagram, at(10));
+
+        let report = analyzer.finish();
+        assert_eq!(report.sessions.len(), 1);
+        let session = &report.sessions[0];
+        assert_eq!(session.duration, 10.0);
+
+        let server = &session.server_to_client;
+        assert_eq!(server.packets.count, 1);
+        assert_eq!(server.packets.bytes, 100);
+        assert_eq!(server.packets.bytes_per_second, Some(10.0));
+        let messages = &server.message_types[&message_type];
+        assert_eq!((messages.count, messages.bytes), (1, 4));
+        assert_eq!(server.queues.len(), 1);
+
+        let client = &session.client_to_server;
+        assert_eq!(client.packets.bytes, 50);
+        assert!(client.message_types.is_empty());
+    }
+
+    #[test]
+    fn test_messages_counted_when_first_packet_is_not() {
+        let mut analyzer = BandwidthAnalyzer::new();
+
+        let message = RawMessage::from_fragment(
+            vec![0xDE, 0xF7, 0x00, 0x00],
+            1,
+            0,
+            Direction::ServerToClient,
+        )
+        .unwrap();
+        let message_type = message.message_type.clone();
+        let mut invalid = packet(Direction::ServerToClient, 100, 80);
+        invalid.checksum = ChecksumStatus::Invalid;
+        let datagram = ParsedDatagram {
+            packets: vec![invalid, packet(Direction::ServerToClient, 100, 40)],
+            messages: vec![message],
+        };
+        analyzer.add_datagram(&datagram, at(0));
+
+        let report = analyzer.finish();
+        let server = &report.sessions[0].server_to_client;
+        assert_eq!(server.packets.count, 1);
+        assert_eq!(server.message_types[&message_type].count, 1);
+    }
+
+    #[test]
+    fn test_flow_reports_matched_to_ticks() {
+        let mut analyzer = BandwidthAnalyzer::new();
+        let send = |analyzer: &mut BandwidthAnalyzer, packet: PacketRecord| {
+            let datagram = ParsedDatagram {
+                packets: vec![packet],
+                messages: Vec::new(),
+            };
+            analyzer.add_datagram(&datagram, None);
+        };
+
+        send(&mut analyzer, packet(Direction::ServerToClient, 500, 380));
+        send(&mut analyzer, packet(Direction::ServerToClient, 500, 100));
+        send(&mut analyzer, packet(Direction::ServerToClient, 501, 24));
+
+        let flow = |tick, bytes| {
+            let mut report = packet(Direction::ClientToServer, 9, 6);
+            report.header.flags |= PacketHeaderFlags::FLOW;
+            report.optional.flow = Some(FlowHeader {
+                bytes,
+                interval: tick,
+            });
+            report
+        };
+        send(&mut analyzer, flow(500, 520));
+        send(&mut analyzer, flow(501, 44));
+        send(&mut analyzer, flow(499, 12));
+
+        let report = analyzer.finish();
+        let session = &report.sessions[0];
+        assert_eq!(
+            session.server_to_client.flow,
+            FlowReports {
+                reports: 3,
+                advertised_bytes: 576,
+                captured_bytes: 564,
+                unmatched_reports: 1,
+            }
+        );
+        assert_eq!(session.client_to_server.flow.reports, 0);
+        assert_eq!(session.duration, 0.0);
+        assert_eq!(session.server_to_client.packets.bytes_per_second, None);
+    }
+
+    #[test]
+    fn test_connect_request_starts_one_session() {
+        let mut analyzer = BandwidthAnalyzer::new();
+        let mut connect = packet(Direction::ServerToClient, 1, 40);
+        connect.header.flags |= PacketHeaderFlags::CONNECT_REQUEST;
+        let message = RawMessage::from_fragment(
+            vec![0xDE, 0xF7, 0x00, 0x00],
+            1,
+            0,
+            Direction::ServerToClient,
+        )
+        .unwrap();
+        analyzer.add_datagram(
+            &ParsedDatagram {
+                packets: vec![connect],
+                messages: vec![message],
+            },
+            at(0),
+        );
+
+        let report = analyzer.finish();
+        assert_eq!(report.sessions.len(), 1);
+        let server = &report.sessions[0].server_to_client;
+        assert_eq!(server.packets.count, 1);
+        assert_eq!(server.message_types.len(), 1);
+    }
+}
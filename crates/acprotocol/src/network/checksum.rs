@@ -0,0 +1,473 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io;
+
+use crate::enums::PacketHeaderFlags;
+use crate::readers::ACDataType;
+
+use super::packet::PacketHeader;
+use super::packet_reader::PacketReader;
+use super::packet_record::OptionalHeaders;
+
+/// Value the checksum field holds while the header itself is being hashed
+pub const CHECKSUM_PLACEHOLDER: u32 = 0xBADD70DD;
+
+/// Number of upcoming ISAAC keys a `KeyWindow` will search, since packets
+/// can arrive out of order or not at all
+pub const KEY_LOOKAHEAD: usize = 256;
+
+/// AC's 32-bit hash: the length in the high half plus the sum of every
+/// little-endian word, with any trailing bytes added most significant first
+pub fn hash32(data: &[u8]) -> u32 {
+    let mut checksum = (data.len() as u32) << 16;
+
+    let mut words = data.chunks_exact(4);
+    for word in &mut words {
+        checksum = checksum.wrapping_add(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+    }
+
+    for (i, &byte) in words.remainder().iter().enumerate() {
+        checksum = checksum.wrapping_add((byte as u32) << (8 * (3 - i)));
+    }
+
+    checksum
+}
+
+/// The two halves of a packet's checksum
+///
+/// Packets flagged `ENCRYPTED_CHECKSUM` XOR the payload half with the next
+/// key from the sender's ISAAC stream, which is what stops a third party
+/// from injecting packets into a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketChecksum {
+    /// Hash of the 20-byte header with the checksum field set to `CHECKSUM_PLACEHOLDER`
+    pub header: u32,
+    /// Hash of the optional headers plus each fragment's header and data
+    pub payload: u32,
+}
+
+impl PacketChecksum {
+    /// Compute the checksum halves for a single packet: its 20-byte header
+    /// followed by `header.size` bytes of payload
+    pub fn compute(packet: &[u8]) -> io::Result<Self> {
+        let mut reader = PacketReader::new(packet);
+        let header =
+            PacketHeader::read(&mut reader).map_err(|e| io::Error::other(e.to_string()))?;
+        let packet_end = PacketHeader::BASE_SIZE + header.size as usize;
+        if packet.len() < packet_end {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Packet shorter than its header size",
+            ));
+        }
+
+        let mut header_bytes = [0u8; PacketHeader::BASE_SIZE];
+        header_bytes.copy_from_slice(&packet[..PacketHeader::BASE_SIZE]);
+        header_bytes[8..12].copy_from_slice(&CHECKSUM_PLACEHOLDER.to_le_bytes());
+
+        // Optional headers are hashed as one block, so only their extent matters
+        OptionalHeaders::read(&mut reader, header.flags, packet_end, true)?;
+        let optional_end = reader.position();
+        let mut payload = hash32(&packet[PacketHeader::BASE_SIZE..optional_end]);
+
+        if header.flags.contains(PacketHeaderFlags::BLOB_FRAGMENTS) {
+            let mut position = optional_end;
+            while position < packet_end {
+                if packet_end - position < 16 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Fragment header too short",
+                    ));
+                }
+                let size = u16::from_le_bytes([packet[position + 10], packet[position + 11]]);
+                let fragment_end = position + (size as usize).max(16);
+                if fragment_end > packet_end {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Fragment data too short",
+                    ));
+                }
+                payload = payload
+                    .wrapping_add(hash32(&packet[position..position + 16]))
+                    .wrapping_add(hash32(&packet[position + 16..fragment_end]));
+                position = fragment_end;
+            }
+        }
+
+        Ok(Self {
+            header: hash32(&header_bytes),
+            payload,
+        })
+    }
+
+    /// The checksum with no ISAAC key applied
+    pub fn plain(&self) -> u32 {
+        self.header.wrapping_add(self.payload)
+    }
+
+    /// The checksum with the payload half encrypted by `key`
+    pub fn encrypted(&self, key: u32) -> u32 {
+        self.header.wrapping_add(self.payload ^ key)
+    }
+
+    /// The ISAAC key that would produce `checksum` for this packet
+    pub fn recover_key(&self, checksum: u32) -> u32 {
+        checksum.wrapping_sub(self.header) ^ self.payload
+    }
+}
+
+/// Compute a packet's checksum and write it into its header
+///
+/// `key` must be the sender's next ISAAC key when the packet is flagged
+/// `ENCRYPTED_CHECKSUM` and is ignored otherwise. Returns the checksum.
+pub fn seal_packet(packet: &mut [u8], key: Option<u32>) -> io::Result<u32> {
+    let checksum = PacketChecksum::compute(packet)?;
+    let flags = PacketHeaderFlags::from_bits_retain(u32::from_le_bytes([
+        packet[4], packet[5], packet[6], packet[7],
+    ]));
+
+    let value = if flags.contains(PacketHeaderFlags::ENCRYPTED_CHECKSUM) {
+        let key = key.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Encrypted checksum requires an ISAAC key",
+            )
+        })?;
+        checksum.encrypted(key)
+    } else {
+        checksum.plain()
+    };
+
+    packet[8..12].copy_from_slice(&value.to_le_bytes());
+    Ok(value)
+}
+
+/// The ISAAC key `packet` was sealed with, or `None` if its checksum isn't
+/// encrypted, so a changed copy can be sealed with the same key
+pub(crate) fn sealing_key(header: &PacketHeader, packet: &[u8]) -> io::Result<Option<u32>> {
+    if !header.flags.contains(PacketHeaderFlags::ENCRYPTED_CHECKSUM) {
+        return Ok(None);
+    }
+    Ok(Some(
+        PacketChecksum::compute(packet)?.recover_key(header.checksum),
+    ))
+}
+
+/// The ISAAC generator AC uses to key encrypted checksums
+///
+/// Servers seed one stream per direction with the `outgoing_seed` and
+/// `incoming_seed` from the `ConnectRequestHeader`. Keys are handed out from
+/// the end of each 256-word block.
+#[derive(Clone)]
+pub struct Isaac {
+    mm: [u32; 256],
+    results: [u32; 256],
+    offset: usize,
+    a: u32,
+    b: u32,
+    c: u32,
+}
+
+impl Isaac {
+    pub fn new(seed: u32) -> Self {
+        let mut isaac = Self {
+            mm: [0; 256],
+            results: [0; 256],
+            offset: 255,
+            a: 0,
+            b: 0,
+            c: 0,
+        };
+
+        let mut abcdefgh = [0x9E37_79B9u32; 8];
+        for _ in 0..4 {
+            shuffle(&mut abcdefgh);
+        }
+        for pass in 0..2 {
+            for j in (0..256).step_by(8) {
+                let source = if pass == 0 {
+                    &isaac.results[j..j + 8]
+                } else {
+                    &isaac.mm[j..j + 8]
+                };
+                for (value, &add) in abcdefgh.iter_mut().zip(source) {
+                    *value = value.wrapping_add(add);
+                }
+                shuffle(&mut abcdefgh);
+                isaac.mm[j..j + 8].copy_from_slice(&abcdefgh);
+            }
+        }
+
+        isaac.a = seed;
+        isaac.b = seed;
+        isaac.c = seed;
+        isaac.scramble();
+        isaac
+    }
+
+    /// The next key in the stream
+    pub fn next_key(&mut self) -> u32 {
+        let key = self.results[self.offset];
+        if self.offset > 0 {
+            self.offset -= 1;
+        } else {
+            self.scramble();
+            self.offset = 255;
+        }
+        key
+    }
+
+    fn scramble(&mut self) {
+        self.c = self.c.wrapping_add(1);
+        self.b = self.b.wrapping_add(self.c);
+
+        for i in 0..256 {
+            let x = self.mm[i];
+            self.a ^= match i & 3 {
+                0 => self.a << 13,
+                1 => self.a >> 6,
+                2 => self.a << 2,
+                _ => self.a >> 16,
+            };
+            self.a = self.a.wrapping_add(self.mm[(i + 128) & 0xFF]);
+            let y = self.mm[((x >> 2) & 0xFF) as usize]
+                .wrapping_add(self.a)
+                .wrapping_add(self.b);
+            self.mm[i] = y;
+            self.b = self.mm[((y >> 10) & 0xFF) as usize].wrapping_add(x);
+            self.results[i] = self.b;
+        }
+    }
+}
+
+impl std::fmt::Debug for Isaac {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Isaac")
+            .field("offset", &self.offset)
+            .finish_non_exhaustive()
+    }
+}
+
+fn shuffle(x: &mut [u32; 8]) {
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *x;
+    a ^= b << 11;
+    d = d.wrapping_add(a);
+    b = b.wrapping_add(c);
+    b ^= c >> 2;
+    e = e.wrapping_add(b);
+    c = c.wrapping_add(d);
+    c ^= d << 8;
+    f = f.wrapping_add(c);
+    d = d.wrapping_add(e);
+    d ^= e >> 16;
+    g = g.wrapping_add(d);
+    e = e.wrapping_add(f);
+    e ^= f << 10;
+    h = h.wrapping_add(e);
+    f = f.wrapping_add(g);
+    f ^= g >> 4;
+    a = a.wrapping_add(f);
+    g = g.wrapping_add(h);
+    g ^= h << 8;
+    b = b.wrapping_add(g);
+    h = h.wrapping_add(a);
+    h ^= a >> 9;
+    c = c.wrapping_add(h);
+    a = a.wrapping_add(b);
+    *x = [a, b, c, d, e, f, g, h];
+}
+
+/// Tracks which keys of one ISAAC stream a receiver will still accept
+///
+/// Keys may be used out of order because packets get lost or reordered, and
+/// retransmitted packets reuse the key of the original, so both the next
+/// `KEY_LOOKAHEAD` keys and the most recently used ones are accepted.
+#[derive(Debug, Clone)]
+pub struct KeyWindow {
+    isaac: Isaac,
+    upcoming: VecDeque<u32>,
+    used: VecDeque<u32>,
+}
+
+impl KeyWindow {
+    pub fn new(seed: u32) -> Self {
+        let mut window = Self {
+            isaac: Isaac::new(seed),
+            upcoming: VecDeque::with_capacity(KEY_LOOKAHEAD),
+            used: VecDeque::with_capacity(KEY_LOOKAHEAD),
+        };
+        window.refill();
+        window
+    }
+
+    /// Accept `key` if it's one of the upcoming or recently used keys
+    pub fn accept(&mut self, key: u32) -> bool {
+        if let Some(index) = self.upcoming.iter().position(|&k| k == key) {
+            self.upcoming.remove(index);
+            if self.used.len() == KEY_LOOKAHEAD {
+                self.used.pop_front();
+            }
+            self.used.push_back(key);
+            self.refill();
+            return true;
+        }
+        self.used.contains(&key)
+    }
+
+    fn refill(&mut self) {
+        while self.upcoming.len() < KEY_LOOKAHEAD {
+            self.upcoming.push_back(self.isaac.next_key());
+        }
+    }
+}
+
+/// Result of checking a packet's checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ChecksumStatus {
+    /// The checksum matched
+    Valid,
+    /// The checksum is wrong, or the packet is too short to checksum
+    Invalid,
+    /// The checksum is encrypted and the session's ISAAC seeds weren't seen,
+    /// e.g. because the capture started after the ConnectRequest
+    Unverified,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A packet with an ack sequence and one fragment
+    fn packet(flags: PacketHeaderFlags) -> Vec<u8> {
+        let fragment_data = [0xDE, 0xF7, 0x00, 0x00, 0x42];
+        let mut body = Vec::new();
+        body.extend_from_slice(&7u32.to_le_bytes()); // ack sequence
+        body.extend_from_slice(&3u32.to_le_bytes()); // fragment sequence
+        body.extend_from_slice(&0x8000_0001u32.to_le_bytes()); // id
+        body.extend_from_slice(&1u16.to_le_bytes()); // count
+        body.extend_from_slice(&(16 + fragment_data.len() as u16).to_le_bytes()); // size
+        body.extend_from_slice(&0u16.to_le_bytes()); // index
+        body.extend_from_slice(&9u16.to_le_bytes()); // group
+        body.extend_from_slice(&fragment_data);
+
+        let flags = flags | PacketHeaderFlags::ACK_SEQUENCE | PacketHeaderFlags::BLOB_FRAGMENTS;
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&5u32.to_le_bytes()); // sequence
+        packet.extend_from_slice(&flags.bits().to_le_bytes());
+        packet.extend_from_slice(&0u32.to_le_bytes()); // checksum
+        packet.extend_from_slice(&0x0Bu16.to_le_bytes()); // id
+        packet.extend_from_slice(&0u16.to_le_bytes()); // time
+        packet.extend_from_slice(&(body.len() as u16).to_le_bytes()); // size
+        packet.extend_from_slice(&0u16.to_le_bytes()); // iteration
+        packet.extend_from_slice(&body);
+        packet
+    }
+
+    #[test]
+    fn test_hash32() {
+        assert_eq!(hash32(&[]), 0);
+        assert_eq!(hash32(&[0x01, 0x02, 0x03, 0x04]), 0x0004_0000 + 0x0403_0201);
+        // Trailing bytes are added from the most significant byte down
+        assert_eq!(
+            hash32(&[0x01, 0x00, 0x00, 0x00, 0xAA, 0xBB]),
+            0x0006_0000 + 0x0000_0001 + 0xAABB_0000
+        );
+    }
+
+    #[test]
+    fn test_compute_splits_optional_headers_and_fragments() {
+        let packet = packet(PacketHeaderFlags::NONE);
+        let checksum = PacketChecksum::compute(&packet).unwrap();
+
+        let mut header = packet[..20].to_vec();
+        header[8..12].copy_from_slice(&CHECKSUM_PLACEHOLDER.to_le_bytes());
+        assert_eq!(checksum.header, hash32(&header));
+
+        let payload = hash32(&packet[20..24])
+            .wrapping_add(hash32(&packet[24..40]))
+            .wrapping_add(hash32(&packet[40..]));
+        assert_eq!(checksum.payload, payload);
+    }
+
+    #[test]
+    fn test_compute_rejects_truncated_packet() {
+        let mut packet = packet(PacketHeaderFlags::NONE);
+        packet.pop();
+        assert!(PacketChecksum::compute(&packet).is_err());
+    }
+
+    #[test]
+    fn test_seal_plain_packet() {
+        let mut packet = packet(PacketHeaderFlags::NONE);
+        let sealed = seal_packet(&mut packet, None).unwrap();
+
+        assert_eq!(
+            u32::from_le_bytes(packet[8..12].try_into().unwrap()),
+            sealed
+        );
+        // The checksum field doesn't feed into its own value
+        assert_eq!(PacketChecksum::compute(&packet).unwrap().plain(), sealed);
+    }
+
+    #[test]
+    fn test_seal_encrypted_packet() {
+        let mut packet = packet(PacketHeaderFlags::ENCRYPTED_CHECKSUM);
+        assert!(seal_packet(&mut packet, None).is_err());
+
+        let key = Isaac::new(0x1234_5678).next_key();
+        let sealed = seal_packet(&mut packet, Some(key)).unwrap();
+
+        let checksum = PacketChecksum::compute(&packet).unwrap();
+        assert_ne!(checksum.plain(), sealed);
+        assert_eq!(checksum.recover_key(sealed), key);
+    }
+
+    #[test]
+    fn test_sealing_key() {
+        let header = |packet: &[u8]| PacketHeader::read(&mut PacketReader::new(packet)).unwrap();
+
+        let mut plain = packet(PacketHeaderFlags::NONE);
+        seal_packet(&mut plain, None).unwrap();
+        assert_eq!(sealing_key(&header(&plain), &plain).unwrap(), None);
+
+        let mut encrypted = packet(PacketHeaderFlags::ENCRYPTED_CHECKSUM);
+        let key = Isaac::new(0x1234_5678).next_key();
+        seal_packet(&mut encrypted, Some(key)).unwrap();
+        assert_eq!(
+            sealing_key(&header(&encrypted), &encrypted).unwrap(),
+            Some(key)
+        );
+    }
+
+    #[test]
+    fn test_isaac_streams() {
+        let mut first = Isaac::new(0xCAFE_F00D);
+        let mut second = Isaac::new(0xCAFE_F00D);
+        let mut other = Isaac::new(0xCAFE_F00E);
+
+        // Run past a block boundary to cover rescrambling
+        let keys: Vec<u32> = (0..600).map(|_| first.next_key()).collect();
+        assert_eq!(
+            keys,
+            (0..600).map(|_| second.next_key()).collect::<Vec<_>>()
+        );
+        assert_ne!(keys, (0..600).map(|_| other.next_key()).collect::<Vec<_>>());
+        assert_ne!(keys[..256], keys[256..512]);
+    }
+
+    #[test]
+    fn test_key_window() {
+        let mut isaac = Isaac::new(42);
+        let keys: Vec<u32> = (0..KEY_LOOKAHEAD + 2).map(|_| isaac.next_key()).collect();
+        let mut window = KeyWindow::new(42);
+
+        // Out of order
+        assert!(window.accept(keys[1]));
+        assert!(window.accept(keys[0]));
+        // Retransmission reuses a key
+        assert!(window.accept(keys[1]));
+        // The window slides forward as keys are used
+        assert!(window.accept(keys[KEY_LOOKAHEAD + 1]));
+        assert!(!window.accept(keys[0] ^ 1));
+    }
+}
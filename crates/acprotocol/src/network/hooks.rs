@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+
+use crate::enums;
+use crate::message::{
+    C2SMessage, Direction, GameActionMessage, GameEventMessage, MessageKind, S2CMessage,
+};
+use crate::writers::ACWritable;
+
+/// Opcode of the C2S message wrapping every game action
+const ORDERED_GAME_ACTION: u32 = enums::C2SMessage::OrderedGameAction as u32;
+
+/// Opcode of the S2C message wrapping every game event
+const ORDERED_GAME_EVENT: u32 = enums::S2CMessage::OrderedGameEvent as u32;
+
+/// What a hook wants done with the message it was given
+///
+/// When several hooks see the same message the strongest action wins:
+/// `Drop` over `Rewrite` over `Forward`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HookAction {
+    /// Pass the message on as it arrived
+    Forward,
+    /// The hook changed the message, so serialize it again before passing
+    /// it on
+    Rewrite,
+    /// Don't pass the message on
+    Drop,
+}
+
+/// What `MessageHooks::apply` decided for a serialized message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookOutcome {
+    Forward,
+    /// The message as the hooks left it, serialized again
+    Rewrite(Vec<u8>),
+    Drop,
+}
+
+/// Messages `MessageHooks` has been given
+#[derive(Debug, Default, Clone)]
+pub struct HookStats {
+    /// Messages passed to `apply`
+    pub messages: usize,
+    /// Messages a hook changed
+    pub rewritten: usize,
+    /// Messages a hook dropped
+    pub dropped: usize,
+    /// Hooked messages that couldn't be decoded and were passed on unchanged
+    pub undecoded: usize,
+    /// Rewritten messages that didn't decode back to themselves once
+    /// serialized, and were passed on unchanged
+    pub unwritable: usize,
+}
+
+type Hook<T> = Box<dyn FnMut(&mut T) -> HookAction + Send>;
+
+/// Callbacks that observe, drop or rewrite decoded messages, keyed by the
+/// opcode of the message, game action or game event they want
+///
+/// Only messages some hook is registered for get decoded, so everything
+/// else passes through at the cost of reading its opcode. A game action
+/// or event is offered to the hooks for `OrderedGameAction` or
+/// `OrderedGameEvent` first and then to the hooks for its own opcode. Hooks
+/// for the same opcode run in the order they were added, stopping at the
+/// first to drop the message.
+#[derive(Default)]
+pub struct MessageHooks {
+    c2s: HashMap<u32, Vec<Hook<C2SMessage>>>,
+    s2c: HashMap<u32, Vec<Hook<S2CMessage>>>,
+    game_actions: HashMap<u32, Vec<Hook<GameActionMessage>>>,
+    game_events: HashMap<u32, Vec<Hook<GameEventMessage>>>,
+    stats: HookStats,
+}
+
+impl MessageHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hook client-to-server messages with the given opcode
+    pub fn on_c2s<F>(&mut self, opcode: enums::C2SMessage, hook: F) -> &mut Self
+    where
+        F: FnMut(&mut C2SMessage) -> HookAction + Send + 'static,
+    {
+        self.c2s
+            .entry(opcode as u32)
+            .or_default()
+            .push(Box::new(hook));
+        self
+    }
+
+    /// Hook server-to-client messages with the given opcode
+    pub fn on_s2c<F>(&mut self, opcode: enums::S2CMessage, hook: F) -> &mut Self
+    where
+        F: FnMut(&mut S2CMessage) -> HookAction + Send + 'static,
+    {
+        self.s2c
+            .entry(opcode as u32)
+            .or_default()
+            .push(Box::new(hook));
+        self
+    }
+
+    /// Hook the game action with the given opcode
+    pub fn on_game_action<F>(&mut self, opcode: enums::GameAction, hook: F) -> &mut Self
+    where
+        F: FnMut(&mut GameActionMessage) -> HookAction + Send + 'static,
+    {
+        self.game_actions
+            .entry(opcode as u32)
+            .or_default()
+            .push(Box::new(hook));
+        self
+    }
+
+    /// Hook the game event with the given opcode
+    pub fn on_game_event<F>(&mut self, opcode: enums::GameEvent, hook: F) -> &mut Self
+    where
+        F: FnMut(&mut GameEventMessage) -> HookAction + Send + 'static,
+    {
+        self.game_events
+            .entry(opcode as u32)
+            .or_default()
+            .push(Box::new(hook));
+        self
+    }
+
+    pub fn stats(&self) -> &HookStats {
+        &self.stats
+    }
+
+    /// Whether any hook wants the serialized message `data`
+    pub fn is_hooked(&self, data: &[u8], direction: Direction) -> bool {
+        let Some(opcode) = read_word(data, 0) else {
+            return false;
+        };
+        match direction {
+            Direction::ClientToServer => {
+                self.c2s.contains_key(&opcode)
+                    || (opcode == ORDERED_GAME_ACTION
+                        && read_word(data, 8).is_some_and(|a| self.game_actions.contains_key(&a)))
+            }
+            Direction::ServerToClient => {
+                self.s2c.contains_key(&opcode)
+                    || (opcode == ORDERED_GAME_EVENT
+                        && read_word(data, 12).is_some_and(|e| self.game_events.contains_key(&e)))
+            }
+        }
+    }
+
+    /// Run the hooks for a serialized message, serializing it again if any
+    /// of them changed it
+    ///
+    /// Returns an error if the message is hooked but can't be decoded, or if
+    /// a hook changed it and the generated writers don't serialize it in a
+    /// form its reader accepts.
+    pub fn apply(&mut self, data: &[u8], direction: Direction) -> io::Result<HookOutcome> {
+        self.stats.messages += 1;
+        if !self.is_hooked(data, direction) {
+            return Ok(HookOutcome::Forward);
+        }
+
+        let mut message = match MessageKind::read(&mut Cursor::new(data), direction) {
+            Ok(message) => message,
+            Err(e) => {
+                self.stats.undecoded += 1;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+            }
+        };
+        let action = match &mut message {
+            MessageKind::C2S(message) => self.c2s_message(message, data),
+            MessageKind::S2C(message) => self.s2c_message(message, data),
+        };
+
+        match action {
+            HookAction::Forward => Ok(HookOutcome::Forward),
+            HookAction::Drop => {
+                self.stats.dropped += 1;
+                Ok(HookOutcome::Drop)
+            }
+            HookAction::Rewrite => {
+                let mut rewritten = Vec::with_capacity(data.len());
+                let mut writer = Cursor::new(&mut rewritten);
+                match &message {
+                    MessageKind::C2S(message) => message.write(&mut writer),
+                    MessageKind::S2C(message) => message.write(&mut writer),
+                }
+                .map_err(|e| io::Error::other(e.to_string()))?;
+                if !round_trips(&rewritten, direction) {
+                    self.stats.unwritable += 1;
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "rewritten message doesn't decode back to itself",
+                    ));
+                }
+                self.stats.rewritten += 1;
+                Ok(HookOutcome::Rewrite(rewritten))
+            }
+        }
+    }
+
+    fn c2s_message(&mut self, message: &mut C2SMessage, data: &[u8]) -> HookAction {
+        let opcode = read_word(data, 0).unwrap_or_default();
+        let action = run_hooks(self.c2s.get_mut(&opcode), message, HookAction::Forward);
+        match message {
+            C2SMessage::OrderedGameAction {
+                action: game_action,
+                ..
+            } => {
+                let opcode = read_word(data, 8).unwrap_or_default();
+                run_hooks(self.game_actions.get_mut(&opcode), game_action, action)
+            }
+            _ => action,
+        }
+    }
+
+    fn s2c_message(&mut self, message: &mut S2CMessage, data: &[u8]) -> HookAction {
+        let opcode = read_word(data, 0).unwrap_or_default();
+        let action = run_hooks(self.s2c.get_mut(&opcode), message, HookAction::Forward);
+        match message {
+            S2CMessage::OrderedGameEvent { event, .. } => {
+                let opcode = read_word(data, 12).unwrap_or_default();
+                run_hooks(self.game_events.get_mut(&opcode), event.as_mut(), action)
+            }
+            _ => action,
+        }
+    }
+}
+
+/// Run `hooks` on `message` until one drops it, starting from what earlier
+/// hooks decided
+fn run_hooks<T>(
+    hooks: Option<&mut Vec<Hook<T>>>,
+    message: &mut T,
+    action: HookAction,
+) -> HookAction {
+    let mut action = action;
+    for hook in hooks.into_iter().flatten() {
+        if action == HookAction::Drop {
+            break;
+        }
+        action = action.max(hook(message));
+    }
+    action
+}
+
+/// Whether the serialized message `data` decodes, using all of its bytes,
+/// to a message that serializes back to `data`
+///
+/// A hook can leave a message inconsistent, such as a flag set for a field
+/// it didn't fill in, which its writer then leaves out.
+fn round_trips(data: &[u8], direction: Direction) -> bool {
+    let mut reader = Cursor::new(data);
+    let Ok(message) = MessageKind::read(&mut reader, direction) else {
+        return false;
+    };
+    if reader.position() != data.len() as u64 {
+        return false;
+    }
+    let mut again = Vec::with_capacity(data.len());
+    let mut writer = Cursor::new(&mut again);
+    let written = match &message {
+        MessageKind::C2S(message) => message.write(&mut writer),
+        MessageKind::S2C(message) => message.write(&mut writer),
+    };
+    written.is_ok() && again == data
+}
+
+/// The little-endian word at `offset`, if the data is long enough
+fn read_word(data: &[u8], offset: usize) -> Option<u32> {
+    let word = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+/// Offset of the sequence in an `OrderedGameAction` or `OrderedGameEvent`,
+/// the messages whose sequences a receiver expects to be contiguous
+pub(crate) fn ordered_sequence_offset(data: &[u8], direction: Direction) -> Option<usize> {
+    match (direction, read_word(data, 0)?) {
+        (Direction::ClientToServer, ORDERED_GAME_ACTION) => Some(4),
+        (Direction::ServerToClient, ORDERED_GAME_EVENT) => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::ChatFragmentType;
+    use crate::gameactions;
+    use crate::messages::s2c;
+    use crate::test_support::{obj_desc, physics_desc, serialize, turbine_chat_event, weenie_desc};
+    use crate::types::{ObjectId, WString};
+
+    fn talk(sequence: u32, text: &str) -> C2SMessage {
+        C2SMessage::OrderedGameAction {
+            sequence,
+            action: GameActionMessage::CommunicationTalk(gameactions::CommunicationTalk {
+                message: text.to_string(),
+            }),
+        }
+    }
+
+    fn hear_speech(message: &str) -> S2CMessage {
+        S2CMessage::CommunicationHearSpeech(s2c::CommunicationHearSpeech {
+            message: message.to_string(),
+            sender_name: "Galahad".to_string(),
+            sender_id: ObjectId(0x5000_0001),
+            type_: ChatFragmentType::Speech,
+        })
+    }
+
+    #[test]
+    fn test_unhooked_messages_are_forwarded() {
+        let mut hooks = MessageHooks::new();
+        hooks.on_game_action(enums::GameAction::CommunicationEmote, |_| HookAction::Drop);
+
+        let data = serialize(&talk(1, "hello"));
+        assert!(!hooks.is_hooked(&data, Direction::ClientToServer));
+        assert_eq!(
+            hooks.apply(&data, Direction::ClientToServer).unwrap(),
+            HookOutcome::Forward
+        );
+        // Not even decoded
+        assert_eq!(
+            hooks
+                .apply(&[0xB1, 0xF7], Direction::ClientToServer)
+                .unwrap(),
+            HookOutcome::Forward
+        );
+    }
+
+    #[test]
+    fn test_game_action_rewritten() {
+        let mut hooks = MessageHooks::new();
+        hooks.on_game_action(enums::GameAction::CommunicationTalk, |action| {
+            if let GameActionMessage::CommunicationTalk(talk) = action {
+                talk.message = talk.message.to_uppercase();
+            }
+            HookAction::Rewrite
+        });
+
+        let outcome = hooks
+            .apply(&serialize(&talk(7, "hello")), Direction::ClientToServer)
+            .unwrap();
+        assert_eq!(outcome, HookOutcome::Rewrite(serialize(&talk(7, "HELLO"))));
+        assert_eq!(hooks.stats().rewritten, 1);
+    }
+
+    #[test]
+    fn test_drop_wins_and_stops_later_hooks() {
+        let mut hooks = MessageHooks::new();
+        hooks
+            .on_s2c(enums::S2CMessage::CommunicationHearSpeech, |_| {
+                HookAction::Rewrite
+            })
+            .on_s2c(enums::S2CMessage::CommunicationHearSpeech, |_| {
+                HookAction::Drop
+            })
+            .on_s2c(enums::S2CMessage::CommunicationHearSpeech, |_| {
+                panic!("runs after a drop")
+            });
+
+        let outcome = hooks
+            .apply(&serialize(&hear_speech("hi")), Direction::ServerToClient)
+            .unwrap();
+        assert_eq!(outcome, HookOutcome::Drop);
+        assert_eq!((hooks.stats().messages, hooks.stats().dropped), (1, 1));
+    }
+
+    #[test]
+    fn test_undecodable_hooked_message_is_an_error() {
+        let mut hooks = MessageHooks::new();
+        hooks.on_s2c(enums::S2CMessage::CommunicationHearSpeech, |_| {
+            HookAction::Forward
+        });
+
+        let mut data = serialize(&hear_speech("hi"));
+        data.truncate(6);
+        assert!(hooks.apply(&data, Direction::ServerToClient).is_err());
+        assert_eq!(hooks.stats().undecoded, 1);
+    }
+
+    #[test]
+    fn test_nested_switch_message_rewritten() {
+        let mut hooks = MessageHooks::new();
+        hooks.on_s2c(enums::S2CMessage::CommunicationTurbineChat, |message| {
+            if let S2CMessage::CommunicationTurbineChat(s2c::CommunicationTurbineChat::Type1(
+                chat,
+            )) = message
+                && let s2c::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(blob) =
+                    &mut chat.blob_dispatch_type
+            {
+                blob.text = WString(blob.text.0.to_uppercase());
+            }
+            HookAction::Rewrite
+        });
+
+        let data = serialize(&turbine_chat_event("Galahad", "hello"));
+        assert_eq!(
+            hooks.apply(&data, Direction::ServerToClient).unwrap(),
+            HookOutcome::Rewrite(serialize(&turbine_chat_event("Galahad", "HELLO")))
+        );
+        assert_eq!(hooks.stats().rewritten, 1);
+    }
+
+    #[test]
+    fn test_rewrite_that_does_not_decode_is_an_error() {
+        let mut hooks = MessageHooks::new();
+        hooks.on_s2c(enums::S2CMessage::ItemUpdateObject, |message| {
+            if let S2CMessage::ItemUpdateObject(update) = message {
+                // Claims a plural name without giving one
+                update.weenie_desc.header |= 0x0000_0001;
+            }
+            HookAction::Rewrite
+        });
+
+        let data = serialize(&S2CMessage::ItemUpdateObject(s2c::ItemUpdateObject {
+            object_id: ObjectId(0x5000_0001),
+            object_desc: obj_desc(0),
+            physics_desc: physics_desc(),
+            weenie_desc: weenie_desc("Galahad"),
+        }));
+        assert!(hooks.apply(&data, Direction::ServerToClient).is_err());
+        assert_eq!((hooks.stats().rewritten, hooks.stats().unwritable), (0, 1));
+    }
+}
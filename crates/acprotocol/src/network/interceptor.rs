@@ -0,0 +1,687 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::io::{self, Cursor};
+
+use crate::enums::PacketHeaderFlags;
+use crate::message::Direction;
+use crate::readers::ACDataType;
+use crate::writers::ACWritable;
+
+use super::checksum::{seal_packet, sealing_key};
+use super::fragment_impl::{ChunkStatus, FRAGMENT_CHUNK_SIZE, PendingFragment};
+use super::hooks::{HookOutcome, MessageHooks, ordered_sequence_offset};
+use super::packet::PacketHeader;
+use super::packet_builder::{FRAGMENT_HEADER_SIZE, MAX_PACKET_PAYLOAD};
+use super::packet_reader::PacketReader;
+use super::packet_record::OptionalHeaders;
+
+/// Messages still being reassembled before the oldest is given up on
+const MAX_PENDING_MESSAGES: usize = 256;
+
+/// Bytes the buffers of messages being reassembled may take up before the
+/// oldest is given up on
+const MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+
+/// Completed messages remembered so retransmitted chunks aren't delivered
+/// twice
+const MAX_COMPLETED_MESSAGES: usize = 1024;
+
+/// Sent packets kept around to answer retransmissions with
+const MAX_SENT_PACKETS: usize = 256;
+
+/// Dropped sequences kept apart before the lowest is folded into a count
+const MAX_DROPPED_SEQUENCES: usize = 1024;
+
+/// A message being reassembled, along with the fragment group it goes out in
+#[derive(Debug)]
+struct PendingMessage {
+    fragment: PendingFragment,
+    group: u16,
+    /// When its first chunk arrived, counted in messages started
+    started: u64,
+}
+
+/// Sequences of dropped messages, each of which moves the sequences above it
+/// down by one
+///
+/// Past `MAX_DROPPED_SEQUENCES` the lowest are only counted, on the
+/// assumption that every message below them has long since gone through.
+#[derive(Debug, Default)]
+struct DroppedSequences {
+    /// Dropped sequences no longer kept apart
+    folded: u32,
+    recent: BTreeSet<u32>,
+}
+
+impl DroppedSequences {
+    fn insert(&mut self, sequence: u32) {
+        self.recent.insert(sequence);
+        if self.recent.len() > MAX_DROPPED_SEQUENCES {
+            self.recent.pop_first();
+            self.folded += 1;
+        }
+    }
+
+    /// A sequence moved down past the dropped ones below it
+    fn closed_up(&self, sequence: u32) -> u32 {
+        let below = self.folded + self.recent.range(..sequence).count() as u32;
+        sequence.wrapping_sub(below)
+    }
+}
+
+/// Rewrites one direction of a session, passing every message through
+/// `MessageHooks` on its way
+///
+/// Each packet goes out with the sequence, optional headers and ISAAC key of
+/// the packet it replaces, so acks, retransmit requests and the receiver's
+/// key stream stay in step. What changes are the fragments: a message is
+/// held back until all of its chunks have arrived, and then its fragments,
+/// rewritten or not, are queued for the next packets that carry fragments,
+/// as many to a packet as fit. A rewritten message that grows can therefore
+/// finish going out a packet or two later than the original would have.
+/// Fragment sequences, and the sequences of ordered game actions and
+/// events, close up over dropped messages so the receiver doesn't wait on
+/// them.
+#[derive(Debug)]
+pub struct Interceptor {
+    direction: Direction,
+    pending: BTreeMap<(u32, u32), PendingMessage>,
+    /// Pending messages oldest first, by when their first chunk arrived
+    pending_order: BTreeSet<(u64, (u32, u32))>,
+    /// Total size of the pending messages' buffers
+    pending_bytes: usize,
+    /// Messages started so far, to tell which pending one is oldest
+    started: u64,
+    completed: HashSet<(u32, u32)>,
+    completed_order: VecDeque<(u32, u32)>,
+    /// Serialized fragments waiting for a packet to go out in
+    queue: VecDeque<Vec<u8>>,
+    /// Fragment sequences of the messages dropped so far
+    dropped_messages: DroppedSequences,
+    /// Sequences of the ordered game actions or events dropped so far
+    dropped_ordered: DroppedSequences,
+    /// Packets sent by their sequence, for answering retransmissions
+    sent: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Interceptor {
+    pub fn new(direction: Direction) -> Self {
+        Self {
+            direction,
+            pending: BTreeMap::new(),
+            pending_order: BTreeSet::new(),
+            pending_bytes: 0,
+            started: 0,
+            completed: HashSet::new(),
+            completed_order: VecDeque::new(),
+            queue: VecDeque::new(),
+            dropped_messages: DroppedSequences::default(),
+            dropped_ordered: DroppedSequences::default(),
+            sent: BTreeMap::new(),
+        }
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Fragments of completed messages that haven't gone out yet
+    pub fn queued_fragments(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Rewrite the AC packets in one UDP datagram
+    pub fn rewrite(&mut self, payload: &[u8], hooks: &mut MessageHooks) -> io::Result<Vec<u8>> {
+        let mut rewritten = Vec::with_capacity(payload.len());
+        let mut position = 0;
+
+        while position < payload.len() {
+            let mut reader = PacketReader::new(&payload[position..]);
+            let header =
+                PacketHeader::read(&mut reader).map_err(|e| io::Error::other(e.to_string()))?;
+            let packet_end = position + PacketHeader::BASE_SIZE + header.size as usize;
+            let packet = payload.get(position..packet_end).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Packet shorter than its header size",
+                )
+            })?;
+            rewritten.extend(self.rewrite_packet(header, packet, hooks)?);
+            position = packet_end;
+        }
+
+        Ok(rewritten)
+    }
+
+    fn rewrite_packet(
+        &mut self,
+        mut header: PacketHeader,
+        packet: &[u8],
+        hooks: &mut MessageHooks,
+    ) -> io::Result<Vec<u8>> {
+        let key = sealing_key(&header, packet)?;
+
+        if header
+            .flags
+            .intersects(PacketHeaderFlags::LOGIN_REQUEST | PacketHeaderFlags::CONNECT_REQUEST)
+        {
+            // A new connection starts its sequences over
+            *self = Self::new(self.direction);
+        }
+
+        // The receiver missed what we sent, not what the sender sent
+        if header.flags.contains(PacketHeaderFlags::RETRANSMISSION)
+            && let Some(sent) = self.sent.get(&header.sequence)
+        {
+            let mut resent = sent.clone();
+            let flags = u32::from_le_bytes([resent[4], resent[5], resent[6], resent[7]])
+                | PacketHeaderFlags::RETRANSMISSION.bits();
+            resent[4..8].copy_from_slice(&flags.to_le_bytes());
+            seal_packet(&mut resent, key)?;
+            return Ok(resent);
+        }
+
+        let mut reader = PacketReader::new(packet);
+        reader.set_position(PacketHeader::BASE_SIZE);
+        OptionalHeaders::read(&mut reader, header.flags, packet.len(), true)?;
+        let optional_end = reader.position();
+        let mut body = packet[PacketHeader::BASE_SIZE..optional_end].to_vec();
+
+        // Only packets that carried fragments take them, since packets
+        // without any don't use up a sequence
+        if header.flags.contains(PacketHeaderFlags::BLOB_FRAGMENTS) {
+            let mut position = optional_end;
+            while position < packet.len() {
+                let fragment = packet
+                    .get(position..position + FRAGMENT_HEADER_SIZE)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "Fragment header too short")
+                    })?;
+                let word = |offset: usize| {
+                    u32::from_le_bytes([
+                        fragment[offset],
+                        fragment[offset + 1],
+                        fragment[offset + 2],
+                        fragment[offset + 3],
+                    ])
+                };
+                let half =
+                    |offset: usize| u16::from_le_bytes([fragment[offset], fragment[offset + 1]]);
+                let size = half(10) as usize;
+                let fragment_end = position + size.max(FRAGMENT_HEADER_SIZE);
+                let chunk = packet
+                    .get(position + FRAGMENT_HEADER_SIZE..fragment_end)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "Fragment data too short")
+                    })?;
+                self.add_chunk(
+                    (word(0), word(4)),
+                    half(8),
+                    half(12),
+                    half(14),
+                    chunk,
+                    hooks,
+                )?;
+                position = fragment_end;
+            }
+
+            while let Some(fragment) = self.queue.front() {
+                if body.len() + fragment.len() > MAX_PACKET_PAYLOAD {
+                    break;
+                }
+                body.extend(self.queue.pop_front().expect("front was just checked"));
+            }
+        }
+
+        header.size = body.len() as u16;
+        let mut rewritten = Vec::with_capacity(PacketHeader::BASE_SIZE + body.len());
+        header
+            .write(&mut Cursor::new(&mut rewritten))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        rewritten.extend_from_slice(&body);
+        seal_packet(&mut rewritten, key)?;
+
+        if header.flags.contains(PacketHeaderFlags::BLOB_FRAGMENTS)
+            && !header.flags.contains(PacketHeaderFlags::RETRANSMISSION)
+        {
+            self.sent.insert(header.sequence, rewritten.clone());
+            if self.sent.len() > MAX_SENT_PACKETS {
+                self.sent.pop_first();
+            }
+        }
+        Ok(rewritten)
+    }
+
+    /// Store a chunk, and once its message is complete run the hooks on it
+    /// and queue whatever they leave
+    fn add_chunk(
+        &mut self,
+        key: (u32, u32),
+        count: u16,
+        index: u16,
+        group: u16,
+        chunk: &[u8],
+        hooks: &mut MessageHooks,
+    ) -> io::Result<()> {
+        if self.completed.contains(&key) {
+            return Ok(());
+        }
+        if !self.pending.contains_key(&key) {
+            // The buffer is sized by the count off the wire, which nothing
+            // has checked yet, so it has to fit before it's allocated
+            let bytes = count as usize * FRAGMENT_CHUNK_SIZE;
+            if bytes > MAX_PENDING_BYTES {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("message of {count} fragments is too large to hold back"),
+                ));
+            }
+            while self.pending.len() >= MAX_PENDING_MESSAGES
+                || self.pending_bytes + bytes > MAX_PENDING_BYTES
+            {
+                let Some(&(_, oldest)) = self.pending_order.first() else {
+                    break;
+                };
+                self.remove_pending(&oldest);
+            }
+            self.started += 1;
+            self.pending_order.insert((self.started, key));
+            self.pending_bytes += bytes;
+            self.pending.insert(
+                key,
+                PendingMessage {
+                    fragment: PendingFragment::new(key.0, count),
+                    group,
+                    started: self.started,
+                },
+            );
+        }
+        let pending = self.pending.get_mut(&key).expect("entry was just added");
+        if pending
+            .fragment
+            .add_chunk(chunk, index as usize, chunk.len())
+            == ChunkStatus::OutOfRange
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("fragment {index} doesn't fit a message of {count} fragments"),
+            ));
+        }
+        if !pending.fragment.is_complete() {
+            return Ok(());
+        }
+
+        let pending = self.remove_pending(&key).expect("entry was just added");
+        self.completed.insert(key);
+        self.completed_order.push_back(key);
+        if self.completed_order.len() > MAX_COMPLETED_MESSAGES
+            && let Some(oldest) = self.completed_order.pop_front()
+        {
+            self.completed.remove(&oldest);
+        }
+
+        let original = pending.fragment.into_data();
+        let mut data = match hooks.apply(&original, self.direction) {
+            // Hooks that can't see a message can't object to it either
+            Ok(HookOutcome::Forward) | Err(_) => original,
+            Ok(HookOutcome::Rewrite(data)) => data,
+            Ok(HookOutcome::Drop) => {
+                self.dropped_messages.insert(key.0);
+                if let Some(offset) = ordered_sequence_offset(&original, self.direction)
+                    && let Some(field) = original.get(offset..offset + 4)
+                {
+                    self.dropped_ordered
+                        .insert(u32::from_le_bytes([field[0], field[1], field[2], field[3]]));
+                }
+                return Ok(());
+            }
+        };
+
+        if let Some(offset) = ordered_sequence_offset(&data, self.direction)
+            && let Some(field) = data.get_mut(offset..offset + 4)
+        {
+            let sequence = u32::from_le_bytes([field[0], field[1], field[2], field[3]]);
+            let sequence = self.dropped_ordered.closed_up(sequence);
+            field.copy_from_slice(&sequence.to_le_bytes());
+        }
+        self.queue_fragments(
+            self.dropped_messages.closed_up(key.0),
+            key.1,
+            pending.group,
+            &data,
+        )
+    }
+
+    /// Stop holding back a message, releasing its share of the byte budget
+    fn remove_pending(&mut self, key: &(u32, u32)) -> Option<PendingMessage> {
+        let pending = self.pending.remove(key)?;
+        self.pending_order.remove(&(pending.started, *key));
+        self.pending_bytes -= pending.fragment.fragment.data.len();
+        Some(pending)
+    }
+
+    fn queue_fragments(
+        &mut self,
+        sequence: u32,
+        id: u32,
+        group: u16,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(FRAGMENT_CHUNK_SIZE).collect()
+        };
+        let count = u16::try_from(chunks.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "rewritten message of {} bytes needs more than {} fragments",
+                    data.len(),
+                    u16::MAX
+                ),
+            )
+        })?;
+
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            fragment.extend_from_slice(&sequence.to_le_bytes());
+            fragment.extend_from_slice(&id.to_le_bytes());
+            fragment.extend_from_slice(&count.to_le_bytes());
+            fragment
+                .extend_from_slice(&((FRAGMENT_HEADER_SIZE + chunk.len()) as u16).to_le_bytes());
+            fragment.extend_from_slice(&(index as u16).to_le_bytes());
+            fragment.extend_from_slice(&group.to_le_bytes());
+            fragment.extend_from_slice(chunk);
+            self.queue.push_back(fragment);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{self, FragmentGroup};
+    use crate::gameactions;
+    use crate::message::{C2SMessage, GameActionMessage};
+    use crate::network::checksum::{ChecksumStatus, Isaac, PacketChecksum};
+    use crate::network::hooks::HookAction;
+    use crate::network::{FragmentAssembler, PacketBuilder, UdpDatagram};
+
+    fn talk(sequence: u32, text: &str) -> C2SMessage {
+        C2SMessage::OrderedGameAction {
+            sequence,
+            action: GameActionMessage::CommunicationTalk(gameactions::CommunicationTalk {
+                message: text.to_string(),
+            }),
+        }
+    }
+
+    fn serialize(message: &C2SMessage) -> Vec<u8> {
+        let mut data = Vec::new();
+        message.write(&mut Cursor::new(&mut data)).unwrap();
+        data
+    }
+
+    /// Run client packets through an interceptor and reassemble the result
+    fn intercept(
+        packets: &[Vec<u8>],
+        hooks: &mut MessageHooks,
+    ) -> (
+        Vec<Vec<u8>>,
+        Vec<crate::network::PacketRecord>,
+        Vec<Vec<u8>>,
+    ) {
+        let mut interceptor = Interceptor::new(Direction::ClientToServer);
+        let mut assembler = FragmentAssembler::new();
+        let mut records = Vec::new();
+        let mut messages = Vec::new();
+        let mut rewritten = Vec::new();
+        for packet in packets {
+            let packet = interceptor.rewrite(packet, hooks).unwrap();
+            let parsed = assembler
+                .parse_datagram_packets(&UdpDatagram {
+                    source: "10.0.0.2:50000".parse().unwrap(),
+                    destination: "10.0.0.1:9000".parse().unwrap(),
+                    payload: &packet,
+                })
+                .unwrap();
+            records.extend(parsed.packets);
+            messages.extend(parsed.messages.into_iter().map(|m| m.data));
+            rewritten.push(packet);
+        }
+        (rewritten, records, messages)
+    }
+
+    #[test]
+    fn test_rewritten_message_spills_into_later_packets() {
+        let mut builder = PacketBuilder::new().with_isaac_seed(0x5EED);
+        builder
+            .queue_message(FragmentGroup::Private, &talk(1, "hi"))
+            .unwrap();
+        let mut packets = builder.build_all().unwrap();
+        builder
+            .queue_message(FragmentGroup::Private, &talk(2, "bye"))
+            .unwrap();
+        packets.extend(builder.build_all().unwrap());
+        assert_eq!(packets.len(), 2);
+
+        let long = "x".repeat(FRAGMENT_CHUNK_SIZE);
+        let mut hooks = MessageHooks::new();
+        let replacement = long.clone();
+        hooks.on_game_action(enums::GameAction::CommunicationTalk, move |action| {
+            if let GameActionMessage::CommunicationTalk(talk) = action
+                && talk.message == "hi"
+            {
+                talk.message = replacement.clone();
+                return HookAction::Rewrite;
+            }
+            HookAction::Forward
+        });
+
+        let (rewritten, _, messages) = intercept(&packets, &mut hooks);
+        // The long message's second fragment goes out with "bye"
+        assert_eq!(
+            messages,
+            vec![serialize(&talk(1, &long)), serialize(&talk(2, "bye"))]
+        );
+
+        let mut keys = Isaac::new(0x5EED);
+        for (original, packet) in packets.iter().zip(&rewritten) {
+            assert_eq!(original[..4], packet[..4]);
+            let checksum = PacketChecksum::compute(packet).unwrap();
+            let sealed = u32::from_le_bytes(packet[8..12].try_into().unwrap());
+            assert_eq!(checksum.recover_key(sealed), keys.next_key());
+        }
+    }
+
+    #[test]
+    fn test_dropped_message_closes_up_sequences() {
+        let mut builder = PacketBuilder::new();
+        for (sequence, text) in [(1, "one"), (2, "two"), (3, "three")] {
+            builder
+                .queue_message(FragmentGroup::Private, &talk(sequence, text))
+                .unwrap();
+        }
+        let packets = builder.build_all().unwrap();
+
+        let mut hooks = MessageHooks::new();
+        hooks.on_game_action(
+            enums::GameAction::CommunicationTalk,
+            |action| match action {
+                GameActionMessage::CommunicationTalk(talk) if talk.message == "two" => {
+                    HookAction::Drop
+                }
+                _ => HookAction::Forward,
+            },
+        );
+
+        let (_, records, messages) = intercept(&packets, &mut hooks);
+        assert_eq!(
+            messages,
+            vec![serialize(&talk(1, "one")), serialize(&talk(2, "three"))]
+        );
+        let sequences: Vec<_> = records
+            .iter()
+            .flat_map(|record| record.fragments.iter().map(|f| f.sequence))
+            .collect();
+        assert_eq!(sequences, vec![1, 2]);
+        assert!(
+            records
+                .iter()
+                .all(|record| record.checksum == ChecksumStatus::Valid)
+        );
+        assert_eq!(hooks.stats().dropped, 1);
+    }
+
+    #[test]
+    fn test_drop_only_moves_later_sequences_down() {
+        // The first message arrives last, after the one after it is dropped
+        let mut builder = PacketBuilder::new();
+        let mut packets = Vec::new();
+        for (sequence, text) in [(1, "one"), (2, "two"), (3, "three")] {
+            builder
+                .queue_message(FragmentGroup::Private, &talk(sequence, text))
+                .unwrap();
+            packets.push(builder.build_packet().unwrap().unwrap());
+        }
+        packets.rotate_left(1);
+
+        let mut hooks = MessageHooks::new();
+        hooks.on_game_action(
+            enums::GameAction::CommunicationTalk,
+            |action| match action {
+                GameActionMessage::CommunicationTalk(talk) if talk.message == "two" => {
+                    HookAction::Drop
+                }
+                _ => HookAction::Forward,
+            },
+        );
+
+        let (_, records, messages) = intercept(&packets, &mut hooks);
+        assert_eq!(
+            messages,
+            vec![serialize(&talk(2, "three")), serialize(&talk(1, "one"))]
+        );
+        let sequences: Vec<_> = records
+            .iter()
+            .flat_map(|record| record.fragments.iter().map(|f| f.sequence))
+            .collect();
+        assert_eq!(sequences, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_oldest_pending_message_given_up_first() {
+        let mut interceptor = Interceptor::new(Direction::ClientToServer);
+        let mut hooks = MessageHooks::new();
+        // Started first, but with the highest sequence
+        let first = (MAX_PENDING_MESSAGES as u32 + 1, 1);
+        interceptor
+            .add_chunk(first, 2, 0, 0, &[0; 4], &mut hooks)
+            .unwrap();
+        for sequence in 1..=MAX_PENDING_MESSAGES as u32 {
+            interceptor
+                .add_chunk((sequence, 1), 2, 0, 0, &[0; 4], &mut hooks)
+                .unwrap();
+        }
+
+        assert_eq!(interceptor.pending.len(), MAX_PENDING_MESSAGES);
+        assert!(!interceptor.pending.contains_key(&first));
+        assert!(interceptor.pending.contains_key(&(1, 1)));
+    }
+
+    #[test]
+    fn test_pending_bytes_are_capped() {
+        let mut interceptor = Interceptor::new(Direction::ClientToServer);
+        let mut hooks = MessageHooks::new();
+
+        // More than the whole budget is refused before anything is allocated
+        assert!(
+            interceptor
+                .add_chunk((1, 1), u16::MAX, 0, 0, &[0; 4], &mut hooks)
+                .is_err()
+        );
+        assert!(interceptor.pending.is_empty());
+
+        // Two messages over half the budget each can't both be held back
+        let count = (MAX_PENDING_BYTES / FRAGMENT_CHUNK_SIZE / 2 + 1) as u16;
+        for sequence in [2, 3] {
+            interceptor
+                .add_chunk((sequence, 1), count, 0, 0, &[0; 4], &mut hooks)
+                .unwrap();
+        }
+        assert_eq!(interceptor.pending.keys().collect::<Vec<_>>(), [&(3, 1)]);
+        assert_eq!(
+            interceptor.pending_bytes,
+            count as usize * FRAGMENT_CHUNK_SIZE
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_chunk_rejected() {
+        let mut interceptor = Interceptor::new(Direction::ClientToServer);
+        let mut hooks = MessageHooks::new();
+
+        assert!(
+            interceptor
+                .add_chunk((1, 1), 2, 2, 0, &[0; 4], &mut hooks)
+                .is_err()
+        );
+        assert!(
+            interceptor
+                .add_chunk((1, 1), 2, 1, 0, &[0; FRAGMENT_CHUNK_SIZE + 1], &mut hooks)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_retransmission_resends_rewritten_packet() {
+        let mut builder = PacketBuilder::new();
+        builder
+            .queue_message(FragmentGroup::Private, &talk(1, "hi"))
+            .unwrap();
+        let packet = builder.build_packet().unwrap().unwrap();
+        let mut retransmitted = packet.clone();
+        let flags = u32::from_le_bytes(retransmitted[4..8].try_into().unwrap())
+            | PacketHeaderFlags::RETRANSMISSION.bits();
+        retransmitted[4..8].copy_from_slice(&flags.to_le_bytes());
+        seal_packet(&mut retransmitted, None).unwrap();
+
+        let mut hooks = MessageHooks::new();
+        hooks.on_game_action(enums::GameAction::CommunicationTalk, |action| {
+            if let GameActionMessage::CommunicationTalk(talk) = action {
+                talk.message = "hello".to_string();
+            }
+            HookAction::Rewrite
+        });
+        let mut interceptor = Interceptor::new(Direction::ClientToServer);
+        let sent = interceptor.rewrite(&packet, &mut hooks).unwrap();
+        let resent = interceptor.rewrite(&retransmitted, &mut hooks).unwrap();
+
+        assert_eq!(resent[12..], sent[12..]);
+        assert_eq!(
+            u32::from_le_bytes(resent[4..8].try_into().unwrap()),
+            u32::from_le_bytes(sent[4..8].try_into().unwrap())
+                | PacketHeaderFlags::RETRANSMISSION.bits()
+        );
+        // The hook only ran once
+        assert_eq!(hooks.stats().rewritten, 1);
+    }
+
+    #[test]
+    fn test_old_dropped_sequences_are_folded_into_a_count() {
+        let mut dropped = DroppedSequences::default();
+        for sequence in 0..MAX_DROPPED_SEQUENCES as u32 + 10 {
+            dropped.insert(sequence * 2);
+        }
+        assert_eq!(dropped.recent.len(), MAX_DROPPED_SEQUENCES);
+        assert_eq!(dropped.folded, 10);
+
+        let above = (MAX_DROPPED_SEQUENCES as u32 + 10) * 2 + 1;
+        assert_eq!(
+            dropped.closed_up(above),
+            above - MAX_DROPPED_SEQUENCES as u32 - 10
+        );
+        assert_eq!(dropped.closed_up(41), 41 - 21);
+    }
+}
@@ -18,7 +18,12 @@ pub struct Message {
 
 impl Message {
     /// Parse a message from assembled fragment data
-    pub fn from_fragment(data: Vec<u8>, sequence: u32, id: u32) -> io::Result<Self> {
+    pub fn from_fragment(
+        data: Vec<u8>,
+        sequence: u32,
+        id: u32,
+        direction: Direction,
+    ) -> io::Result<Self> {
         if data.len() < 4 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
@@ -26,10 +31,6 @@ impl Message {
             ));
         }
 
-        // Determine direction based on opcode
-        let opcode = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-        let direction = Self::determine_direction(opcode);
-
         // Parse the message
         let mut cursor = Cursor::new(&data[..]);
         let reader: &mut dyn ACReader = &mut cursor;
@@ -43,18 +44,4 @@ impl Message {
             message,
         })
     }
-
-    /// Determine message direction based on opcode
-    fn determine_direction(opcode: u32) -> Direction {
-        use crate::enums::{C2SMessage, S2CMessage};
-
-        if C2SMessage::try_from(opcode).is_ok() {
-            Direction::ClientToServer
-        } else if S2CMessage::try_from(opcode).is_ok() {
-            Direction::ServerToClient
-        } else {
-            // Default to server to client for unknown opcodes
-            Direction::ServerToClient
-        }
-    }
 }
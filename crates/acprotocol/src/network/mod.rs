@@ -1,13 +1,55 @@
+pub mod anonymize;
+pub mod bandwidth;
+pub mod checksum;
 pub mod fragment_impl;
+pub mod hooks;
+pub mod interceptor;
 pub mod message;
 pub mod packet;
+pub mod packet_builder;
 pub mod packet_parser;
 pub mod packet_reader;
+pub mod packet_record;
 pub mod pcap;
+pub mod pcapng;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod proxy;
 pub mod raw_message;
+pub mod reassembly;
+pub mod reliability;
+mod session_table;
+pub mod session_tracker;
+pub mod stream;
+pub mod timing;
+pub mod udp;
 
 pub use crate::generated::network::{Fragment, FragmentHeader};
-pub use fragment_impl::FRAGMENT_CHUNK_SIZE;
+pub use anonymize::{AnonymizeStats, AnonymizedData, Anonymizer, CaptureAnonymizer};
+pub use bandwidth::{
+    BandwidthAnalyzer, BandwidthReport, DirectionBandwidth, FlowReports, SessionBandwidth,
+    TrafficCount,
+};
+pub use checksum::{ChecksumStatus, Isaac, KeyWindow, PacketChecksum, hash32, seal_packet};
+pub use fragment_impl::{ChunkStatus, FRAGMENT_CHUNK_SIZE, PendingFragment};
+pub use hooks::{HookAction, HookOutcome, HookStats, MessageHooks};
+pub use interceptor::Interceptor;
 pub use message::Message;
-pub use packet_parser::FragmentAssembler;
-pub use raw_message::RawMessage;
+pub use packet_builder::{FRAGMENT_HEADER_SIZE, MAX_PACKET_PAYLOAD, PacketBuilder};
+pub use packet_parser::{
+    ExtractedFragment, FragmentAssembler, FragmentAssemblerOptions, FragmentKey,
+};
+pub use packet_record::{
+    OptionalHeaders, PacketRecord, ParsedDatagram, REDACTED, redact_credentials,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use proxy::{LiveStream, ProxiedDatagram, UdpProxy};
+pub(crate) use raw_message::serialize_capture_time;
+pub use raw_message::{RawMessage, format_capture_time};
+pub use reassembly::{EvictionReason, IncompleteMessage, ReassemblyDiagnostics};
+pub use reliability::{
+    FlowReliability, LatencyStats, ReliabilityAnalyzer, ReliabilityReport, SessionReliability,
+};
+pub use session_tracker::{Connection, ConnectionOrigin, LogicalSession, SessionTracker};
+pub use stream::{DatagramStream, MessageStream};
+pub use timing::{ClockDrift, HeaderClock, RttSample, SessionTiming, TimingAnalyzer, TimingReport};
+pub use udp::UdpDatagram;
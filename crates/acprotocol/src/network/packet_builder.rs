@@ -0,0 +1,422 @@
+use std::collections::VecDeque;
+use std::io::{self, Cursor};
+
+use crate::enums::{FragmentGroup, PacketHeaderFlags};
+use crate::types::BlobFragments;
+use crate::writers::ACWritable;
+
+use super::checksum::{Isaac, seal_packet};
+use super::fragment_impl::FRAGMENT_CHUNK_SIZE;
+use super::packet::PacketHeader;
+
+/// Size of the 16-byte header in front of every fragment chunk
+pub const FRAGMENT_HEADER_SIZE: usize = 16;
+
+/// Maximum number of bytes that can follow a packet's 20-byte header
+pub const MAX_PACKET_PAYLOAD: usize = FRAGMENT_HEADER_SIZE + FRAGMENT_CHUNK_SIZE;
+
+/// Splits messages into fragments and packs them into wire-ready packets
+///
+/// This is the inverse of `FragmentAssembler`: each queued message is cut
+/// into `FRAGMENT_CHUNK_SIZE` chunks, and `build_packet` packs as many of
+/// the queued chunks as fit into the next packet, filling in the header's
+/// sequence, size, iteration and checksum. Every packet is returned as its
+/// own datagram.
+#[derive(Debug, Clone)]
+pub struct PacketBuilder {
+    /// Sequence of the next packet
+    sequence: u32,
+    /// Sequence of the next fragment
+    fragment_sequence: u32,
+    /// Id of the next fragment
+    fragment_id: u32,
+    /// Connection id the server assigned this client
+    connection_id: u16,
+    iteration: u16,
+    /// Key stream for encrypted checksums, if the session has been seeded
+    keys: Option<Isaac>,
+    /// Ack to include in the next packet
+    ack_sequence: Option<u32>,
+    queue: VecDeque<BlobFragments>,
+}
+
+impl PacketBuilder {
+    pub fn new() -> Self {
+        Self {
+            sequence: 1,
+            fragment_sequence: 1,
+            fragment_id: 0x8000_0000,
+            connection_id: 0,
+            iteration: 0,
+            keys: None,
+            ack_sequence: None,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Encrypt checksums with the ISAAC stream for `seed`, i.e. the
+    /// `ConnectRequestHeader` outgoing seed when acting as a server or the
+    /// incoming seed when acting as a client
+    pub fn with_isaac_seed(mut self, seed: u32) -> Self {
+        self.keys = Some(Isaac::new(seed));
+        self
+    }
+
+    /// Set the connection id and iteration written into every packet header
+    pub fn with_connection(mut self, connection_id: u16, iteration: u16) -> Self {
+        self.connection_id = connection_id;
+        self.iteration = iteration;
+        self
+    }
+
+    /// Set the sequences the next packet and fragment will use
+    pub fn with_sequences(mut self, sequence: u32, fragment_sequence: u32) -> Self {
+        self.sequence = sequence;
+        self.fragment_sequence = fragment_sequence;
+        self
+    }
+
+    /// Sequence the next packet will use
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Whether any fragments are still waiting to be packed
+    pub fn has_pending(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    /// Acknowledge `sequence` in the next packet
+    pub fn ack(&mut self, sequence: u32) {
+        self.ack_sequence = Some(sequence);
+    }
+
+    /// Serialize a message, e.g. a `C2SMessage` or `S2CMessage`, and queue
+    /// its fragments. Returns the fragment sequence it was given.
+    pub fn queue_message<T: ACWritable>(
+        &mut self,
+        group: FragmentGroup,
+        message: &T,
+    ) -> io::Result<u32> {
+        let mut data = Vec::new();
+        message
+            .write(&mut Cursor::new(&mut data))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.queue_bytes(group, &data)
+    }
+
+    /// Queue the fragments of an already serialized message. Returns the
+    /// fragment sequence it was given.
+    ///
+    /// Fails if the message needs more fragments than a fragment header can
+    /// count.
+    pub fn queue_bytes(&mut self, group: FragmentGroup, data: &[u8]) -> io::Result<u32> {
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(FRAGMENT_CHUNK_SIZE).collect()
+        };
+        let count = u16::try_from(chunks.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "message of {} bytes needs {} fragments, more than {}",
+                    data.len(),
+                    chunks.len(),
+                    u16::MAX
+                ),
+            )
+        })?;
+
+        let sequence = self.fragment_sequence;
+        let id = self.fragment_id;
+        self.fragment_sequence = self.fragment_sequence.wrapping_add(1);
+        self.fragment_id = self.fragment_id.wrapping_add(1);
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            self.queue.push_back(BlobFragments {
+                sequence,
+                id,
+                count,
+                size: (FRAGMENT_HEADER_SIZE + chunk.len()) as u16,
+                index: index as u16,
+                group: group.clone(),
+                data: chunk.to_vec(),
+            });
+        }
+
+        Ok(sequence)
+    }
+
+    /// Build the next packet from the queued fragments and any pending ack
+    ///
+    /// A bare ACK reuses the sequence of the packet before it and a plain
+    /// checksum, like the client does, so only packets carrying fragments
+    /// use up a sequence and a key. When the next fragment doesn't fit
+    /// alongside the pending ack, the ack goes out on its own first.
+    /// Returns `None` once there's nothing left to send.
+    pub fn build_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.queue.is_empty() && self.ack_sequence.is_none() {
+            return Ok(None);
+        }
+
+        let mut header = PacketHeader::with_flags(PacketHeaderFlags::NONE);
+        header.id = self.connection_id;
+        header.iteration = self.iteration;
+
+        let mut body = Vec::new();
+        if let Some(ack) = self.ack_sequence.take() {
+            header.flags |= PacketHeaderFlags::ACK_SEQUENCE;
+            body.extend_from_slice(&ack.to_le_bytes());
+        }
+
+        while let Some(fragment) = self.queue.front() {
+            if body.len() + fragment.size as usize > MAX_PACKET_PAYLOAD {
+                break;
+            }
+            let fragment = self.queue.pop_front().expect("front was just checked");
+            header.flags |= PacketHeaderFlags::BLOB_FRAGMENTS;
+            let mut cursor = Cursor::new(&mut body);
+            cursor.set_position(cursor.get_ref().len() as u64);
+            fragment
+                .write(&mut cursor)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+        }
+        header.size = body.len() as u16;
+
+        let bare_ack = !header.flags.contains(PacketHeaderFlags::BLOB_FRAGMENTS);
+        header.sequence = if bare_ack {
+            self.sequence.wrapping_sub(1)
+        } else {
+            self.sequence
+        };
+        if self.keys.is_some() && !bare_ack {
+            header.flags |= PacketHeaderFlags::ENCRYPTED_CHECKSUM;
+        }
+
+        let mut packet = Vec::with_capacity(PacketHeader::BASE_SIZE + body.len());
+        header
+            .write(&mut Cursor::new(&mut packet))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        packet.extend_from_slice(&body);
+
+        if bare_ack {
+            seal_packet(&mut packet, None)?;
+            return Ok(Some(packet));
+        }
+        let key = self.keys.as_mut().map(Isaac::next_key);
+        seal_packet(&mut packet, key)?;
+
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(Some(packet))
+    }
+
+    /// Build packets until nothing is left to send
+    pub fn build_all(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut packets = Vec::new();
+        while let Some(packet) = self.build_packet()? {
+            packets.push(packet);
+        }
+        Ok(packets)
+    }
+}
+
+impl Default for PacketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::checksum::{ChecksumStatus, PacketChecksum};
+    use crate::network::{FragmentAssembler, UdpDatagram};
+
+    fn turbine_chat(len: usize) -> Vec<u8> {
+        let mut message = vec![0xDE, 0xF7, 0x00, 0x00];
+        message.extend((0..len - 4).map(|i| i as u8));
+        message
+    }
+
+    fn parse(assembler: &mut FragmentAssembler, packet: &[u8]) -> crate::network::ParsedDatagram {
+        let datagram = UdpDatagram {
+            source: "10.0.0.100:9000".parse().unwrap(),
+            destination: "10.0.0.1:50000".parse().unwrap(),
+            payload: packet,
+        };
+        assembler.parse_datagram_packets(&datagram).unwrap()
+    }
+
+    #[test]
+    fn test_large_message_round_trip() {
+        let message = turbine_chat(2 * FRAGMENT_CHUNK_SIZE + 100);
+        let mut builder = PacketBuilder::new().with_connection(0x0B, 3);
+        assert_eq!(
+            builder
+                .queue_bytes(FragmentGroup::Private, &message)
+                .unwrap(),
+            1
+        );
+
+        let packets = builder.build_all().unwrap();
+        assert_eq!(packets.len(), 3);
+        assert!(!builder.has_pending());
+
+        let mut assembler = FragmentAssembler::new();
+        let mut messages = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            assert!(packet.len() <= PacketHeader::BASE_SIZE + MAX_PACKET_PAYLOAD);
+            let parsed = parse(&mut assembler, packet);
+            let record = &parsed.packets[0];
+            assert_eq!(record.header.sequence, 1 + i as u32);
+            assert_eq!(record.header.id, 0x0B);
+            assert_eq!(record.header.iteration, 3);
+            assert_eq!(record.checksum, ChecksumStatus::Valid);
+            messages.extend(parsed.messages);
+        }
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, message);
+        assert_eq!(messages[0].sequence, 1);
+        assert!(assembler.diagnostics().is_clean());
+    }
+
+    #[test]
+    fn test_small_messages_share_a_packet() {
+        let mut builder = PacketBuilder::new();
+        builder.ack(41);
+        for len in [8, 12, 20] {
+            builder
+                .queue_bytes(FragmentGroup::Event, &turbine_chat(len))
+                .unwrap();
+        }
+
+        let packets = builder.build_all().unwrap();
+        assert_eq!(packets.len(), 1);
+
+        let mut assembler = FragmentAssembler::new();
+        let parsed = parse(&mut assembler, &packets[0]);
+        assert_eq!(parsed.packets[0].optional.ack_sequence, Some(41));
+        assert_eq!(parsed.packets[0].fragments.len(), 3);
+        let sizes: Vec<usize> = parsed.messages.iter().map(|m| m.data.len()).collect();
+        assert_eq!(sizes, vec![8, 12, 20]);
+    }
+
+    #[test]
+    fn test_ack_only_packet() {
+        let mut builder = PacketBuilder::new();
+        assert!(builder.build_packet().unwrap().is_none());
+
+        builder.ack(7);
+        let packet = builder.build_packet().unwrap().unwrap();
+        assert_eq!(packet.len(), PacketHeader::BASE_SIZE + 4);
+        assert!(builder.build_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ack_only_packet_reuses_sequence() {
+        let mut builder = PacketBuilder::new().with_isaac_seed(0x5EED);
+        builder
+            .queue_bytes(FragmentGroup::Private, &turbine_chat(8))
+            .unwrap();
+        builder.build_packet().unwrap().unwrap();
+
+        builder.ack(7);
+        let ack = builder.build_packet().unwrap().unwrap();
+        builder
+            .queue_bytes(FragmentGroup::Private, &turbine_chat(8))
+            .unwrap();
+        let next = builder.build_packet().unwrap().unwrap();
+
+        let mut assembler = FragmentAssembler::new();
+        let ack = parse(&mut assembler, &ack).packets.remove(0);
+        assert_eq!(ack.header.sequence, 1);
+        assert!(
+            !ack.header
+                .flags
+                .contains(PacketHeaderFlags::ENCRYPTED_CHECKSUM)
+        );
+        assert_eq!(ack.checksum, ChecksumStatus::Valid);
+
+        // The ack used up neither a sequence nor a key
+        let record = parse(&mut assembler, &next).packets.remove(0);
+        assert_eq!(record.header.sequence, 2);
+        let mut keys = Isaac::new(0x5EED);
+        keys.next_key();
+        let checksum = PacketChecksum::compute(&next).unwrap();
+        assert_eq!(
+            checksum.recover_key(record.header.checksum),
+            keys.next_key()
+        );
+    }
+
+    #[test]
+    fn test_ack_goes_out_alone_when_a_full_chunk_does_not_fit() {
+        let mut builder = PacketBuilder::new().with_isaac_seed(0x5EED);
+        builder.ack(7);
+        builder
+            .queue_bytes(FragmentGroup::Private, &turbine_chat(FRAGMENT_CHUNK_SIZE))
+            .unwrap();
+
+        let packets = builder.build_all().unwrap();
+        assert_eq!(packets.len(), 2);
+
+        let mut assembler = FragmentAssembler::new();
+        let ack = parse(&mut assembler, &packets[0]).packets.remove(0);
+        assert_eq!(ack.header.flags, PacketHeaderFlags::ACK_SEQUENCE);
+        assert_eq!(ack.header.sequence, 0);
+        assert_eq!(ack.optional.ack_sequence, Some(7));
+        assert_eq!(ack.checksum, ChecksumStatus::Valid);
+
+        let parsed = parse(&mut assembler, &packets[1]);
+        let record = &parsed.packets[0];
+        assert_eq!(
+            record.header.flags,
+            PacketHeaderFlags::BLOB_FRAGMENTS | PacketHeaderFlags::ENCRYPTED_CHECKSUM
+        );
+        assert_eq!(record.header.sequence, 1);
+        assert_eq!(parsed.messages[0].data.len(), FRAGMENT_CHUNK_SIZE);
+        let checksum = PacketChecksum::compute(&packets[1]).unwrap();
+        assert_eq!(
+            checksum.recover_key(record.header.checksum),
+            Isaac::new(0x5EED).next_key()
+        );
+    }
+
+    #[test]
+    fn test_encrypted_checksums_follow_key_stream() {
+        let mut builder = PacketBuilder::new().with_isaac_seed(0x5EED);
+        builder
+            .queue_bytes(
+                FragmentGroup::Private,
+                &turbine_chat(2 * FRAGMENT_CHUNK_SIZE),
+            )
+            .unwrap();
+
+        let mut keys = Isaac::new(0x5EED);
+        for packet in builder.build_all().unwrap() {
+            let checksum = PacketChecksum::compute(&packet).unwrap();
+            let sealed = u32::from_le_bytes(packet[8..12].try_into().unwrap());
+            assert_eq!(checksum.recover_key(sealed), keys.next_key());
+        }
+    }
+
+    #[test]
+    fn test_message_with_too_many_fragments() {
+        let mut builder = PacketBuilder::new();
+        let message = vec![0u8; (u16::MAX as usize + 1) * FRAGMENT_CHUNK_SIZE];
+        let error = builder
+            .queue_bytes(FragmentGroup::Private, &message)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        // Nothing was queued and no sequence was used up
+        assert!(!builder.has_pending());
+        assert_eq!(
+            builder
+                .queue_bytes(FragmentGroup::Private, &turbine_chat(8))
+                .unwrap(),
+            1
+        );
+    }
+}
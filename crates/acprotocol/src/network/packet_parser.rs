@@ -1,36 +1,199 @@
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
 
-use crate::generated::network::Fragment;
+use crate::message::Direction;
 use crate::readers::ACDataType;
 
+use super::checksum::{ChecksumStatus, KeyWindow, PacketChecksum};
+use super::fragment_impl::{ChunkStatus, FRAGMENT_CHUNK_SIZE, PendingFragment};
 use super::packet::PacketHeader;
 use super::packet_reader::PacketReader;
-use super::raw_message::RawMessage;
+use super::packet_record::{OptionalHeaders, PacketRecord, ParsedDatagram};
+use super::raw_message::{RawMessage, guess_direction};
+use super::reassembly::{EvictionReason, IncompleteMessage, ReassemblyDiagnostics};
+use super::udp::{UdpDatagram, is_server_port};
 use crate::enums::PacketHeaderFlags;
 
 /// Information about a fragment extracted from a packet
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ExtractedFragment {
     pub sequence: u32,
     pub id: u32,
     pub index: u16,
     pub count: u16,
+    /// Size of the fragment including its 16-byte header
+    pub size: u16,
+    pub group: u16,
+    #[serde(serialize_with = "serialize_hex")]
     pub data: Vec<u8>,
+    /// Whether this chunk completed its message
     pub is_complete: bool,
 }
 
+fn serialize_hex<S: serde::Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(data))
+}
+
+/// Identifies a message being reassembled
+///
+/// Fragment sequences are only unique within one direction of one
+/// connection, so the UDP endpoints are part of the key. Their order is what
+/// separates the two directions of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FragmentKey {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub sequence: u32,
+    pub id: u32,
+}
+
+impl FragmentKey {
+    pub fn new(source: SocketAddr, destination: SocketAddr, sequence: u32, id: u32) -> Self {
+        Self {
+            source,
+            destination,
+            sequence,
+            id,
+        }
+    }
+}
+
+impl From<&IncompleteMessage> for FragmentKey {
+    fn from(message: &IncompleteMessage) -> Self {
+        Self::new(
+            message.source,
+            message.destination,
+            message.sequence,
+            message.id,
+        )
+    }
+}
+
+/// A pending fragment along with when it was first seen
+struct PendingEntry {
+    pending: PendingFragment,
+    /// Value of the assembler's datagram counter when the first chunk arrived
+    first_datagram: u64,
+    /// Capture time when the first chunk arrived, if the caller provides times
+    first_time: Option<Duration>,
+}
+
+/// How many recently completed messages are remembered so chunks resent in
+/// retransmitted packets aren't reassembled a second time
+const COMPLETED_HISTORY: usize = 8192;
+
+/// Limits on how long incomplete messages are kept around
+///
+/// Every limit is optional. Messages that hit a limit are dropped and
+/// reported through `FragmentAssembler::diagnostics`.
+#[derive(Debug, Clone)]
+pub struct FragmentAssemblerOptions {
+    /// Drop a message once this many datagrams have gone by since its first chunk
+    pub max_age_datagrams: Option<u64>,
+    /// Drop a message once this much capture time has gone by since its first
+    /// chunk. Only applies when the caller calls `set_capture_time`.
+    pub max_age: Option<Duration>,
+    /// Drop the oldest messages while pending buffers use more than this many bytes
+    pub max_pending_bytes: Option<usize>,
+    /// Keep passwords and GLS tickets in decoded login requests instead of
+    /// redacting them
+    pub reveal_credentials: bool,
+}
+
+impl Default for FragmentAssemblerOptions {
+    fn default() -> Self {
+        Self {
+            max_age_datagrams: Some(4096),
+            max_age: Some(Duration::from_secs(60)),
+            max_pending_bytes: Some(16 * 1024 * 1024),
+            reveal_credentials: false,
+        }
+    }
+}
+
 /// Parses packets and assembles fragments into complete messages
 pub struct FragmentAssembler {
-    pending_fragments: HashMap<u32, Fragment>,
+    pending_fragments: HashMap<FragmentKey, PendingEntry>,
+    /// Pending messages oldest first, by the datagram their first chunk came in
+    pending_order: BTreeSet<(u64, FragmentKey)>,
+    /// Recently completed messages, oldest first in `completed_order`
+    completed: HashSet<FragmentKey>,
+    completed_order: VecDeque<FragmentKey>,
     next_message_id: u32,
+    /// Server endpoints learned so far, used to tell which way a datagram is going
+    servers: HashSet<SocketAddr>,
+    options: FragmentAssemblerOptions,
+    diagnostics: ReassemblyDiagnostics,
+    /// Number of datagrams parsed so far
+    datagrams: u64,
+    /// Latest capture time passed to `set_capture_time`
+    capture_time: Option<Duration>,
+    /// Total size of all pending fragment buffers
+    pending_bytes: usize,
+    /// ISAAC keys each session's client and server may still use, keyed by
+    /// client endpoint and direction
+    key_windows: HashMap<(SocketAddr, Direction), KeyWindow>,
 }
 
 impl FragmentAssembler {
     pub fn new() -> Self {
+        Self::with_options(FragmentAssemblerOptions::default())
+    }
+
+    pub fn with_options(options: FragmentAssemblerOptions) -> Self {
         Self {
             pending_fragments: HashMap::new(),
+            pending_order: BTreeSet::new(),
+            completed: HashSet::new(),
+            completed_order: VecDeque::new(),
             next_message_id: 0,
+            servers: HashSet::new(),
+            options,
+            diagnostics: ReassemblyDiagnostics::default(),
+            datagrams: 0,
+            capture_time: None,
+            pending_bytes: 0,
+            key_windows: HashMap::new(),
+        }
+    }
+
+    /// Counters and dropped messages collected so far
+    pub fn diagnostics(&self) -> &ReassemblyDiagnostics {
+        &self.diagnostics
+    }
+
+    /// Whether the message `key` identifies is still waiting on fragments
+    pub fn is_pending(&self, key: &FragmentKey) -> bool {
+        self.pending_fragments.contains_key(key)
+    }
+
+    /// Messages that are still waiting on fragments
+    pub fn incomplete_messages(&self) -> Vec<IncompleteMessage> {
+        self.pending_fragments
+            .iter()
+            .map(|(key, entry)| {
+                incomplete_message(key, &entry.pending, EvictionReason::EndOfCapture)
+            })
+            .collect()
+    }
+
+    /// Set the capture time of the datagram about to be parsed
+    ///
+    /// The assembler has no clock of its own, so time-based eviction only
+    /// happens when callers provide capture times.
+    pub fn set_capture_time(&mut self, time: Duration) {
+        self.capture_time = Some(time);
+    }
+
+    /// Give up on every message that's still incomplete, e.g. at the end of a
+    /// capture, recording them as dropped
+    pub fn finish(&mut self) {
+        let keys: Vec<FragmentKey> = self.pending_fragments.keys().copied().collect();
+        for key in keys {
+            self.evict(&key, EvictionReason::EndOfCapture);
         }
     }
 
@@ -42,7 +205,9 @@ impl FragmentAssembler {
     /// (https://github.com/tfarley/aclog).
     ///
     /// The difference between these two is in the number of leading bytes
-    /// before the embedded payload starts.
+    /// before the embedded payload starts. Frames are assumed to be
+    /// Ethernet-framed; use `parse_link_frame` when the capture's link type
+    /// is known.
     ///
     ///
     /// Standard PCAP (libpcap)             aclog PCAP
@@ -65,131 +230,128 @@ impl FragmentAssembler {
         // PCAP packets include network stack headers that we need to skip to get to AC protocol data
         // Standard PCAP: Ethernet (14) + IP (20) + UDP (8) = 42 bytes
         // Some custom PCAP exports prepend 4 bytes before Ethernet
+        match UdpDatagram::from_frame(payload) {
+            Some(datagram) => self.parse_datagram(&datagram),
+            None => Ok(Vec::new()),
+        }
+    }
 
-        // Detect 4-byte prefix by checking if IPv4 header is at offset 4
-        let has_4byte_prefix = payload.len() > 4 && payload[4] == 0x45;
-        let ip_offset = if has_4byte_prefix { 4 } else { 14 };
+    /// Parse a frame captured with the given `LINKTYPE_*` (see
+    /// `network::udp`), returning any completed messages. Frames that aren't
+    /// UDP are skipped.
+    ///
+    /// Unlike `parse_packet_payload`, nothing about the framing is guessed.
+    pub fn parse_link_frame(
+        &mut self,
+        link_type: u32,
+        frame: &[u8],
+    ) -> io::Result<Vec<RawMessage>> {
+        match UdpDatagram::from_link_frame(link_type, frame) {
+            Some(datagram) => self.parse_datagram(&datagram),
+            None => Ok(Vec::new()),
+        }
+    }
 
-        // Parse IP header to find UDP payload offset
-        let ac_payload = if payload.len() > ip_offset + 2 {
-            // Extract IP IHL (Internet Header Length) from first byte's lower 4 bits
-            let ihl_words = (payload[ip_offset] & 0x0f) as usize;
-            let ihl_bytes = ihl_words * 4;
+    /// Parse the AC packets in a UDP datagram, returning any completed
+    /// messages.
+    ///
+    /// Messages are stamped with the direction of the datagram they completed
+    /// in. When the server endpoint can't be determined, the direction is
+    /// guessed from each message's opcode instead.
+    pub fn parse_datagram(&mut self, datagram: &UdpDatagram) -> io::Result<Vec<RawMessage>> {
+        self.parse_datagram_packets(datagram)
+            .map(|parsed| parsed.messages)
+    }
 
-            // UDP header is 8 bytes (src port, dst port, length, checksum)
-            let udp_offset = ip_offset + ihl_bytes;
-            let ac_start = udp_offset + 8;
+    /// Parse a captured frame into typed packet records as well as any
+    /// completed messages. See `parse_packet_payload` for supported framing.
+    pub fn parse_packets(&mut self, frame: &[u8]) -> io::Result<ParsedDatagram> {
+        match UdpDatagram::from_frame(frame) {
+            Some(datagram) => self.parse_datagram_packets(&datagram),
+            None => Ok(ParsedDatagram::default()),
+        }
+    }
 
-            if payload.len() > ac_start {
-                &payload[ac_start..]
-            } else {
-                &[]
-            }
-        } else {
-            &[]
+    /// Parse a frame captured with the given `LINKTYPE_*` into typed packet
+    /// records as well as any completed messages
+    pub fn parse_link_packets(
+        &mut self,
+        link_type: u32,
+        frame: &[u8],
+    ) -> io::Result<ParsedDatagram> {
+        match UdpDatagram::from_link_frame(link_type, frame) {
+            Some(datagram) => self.parse_datagram_packets(&datagram),
+            None => Ok(ParsedDatagram::default()),
+        }
+    }
+
+    /// Parse the AC packets in a UDP datagram into typed packet records as
+    /// well as any completed messages
+    ///
+    /// Datagrams whose packet headers can't be read are counted in
+    /// `ReassemblyDiagnostics::malformed_datagrams`. A bad packet after good
+    /// ones in the same datagram is skipped, so the messages those completed
+    /// are still returned; a datagram with no readable packet at all is
+    /// returned as an error.
+    pub fn parse_datagram_packets(&mut self, datagram: &UdpDatagram) -> io::Result<ParsedDatagram> {
+        let (parsed, error) = self.read_datagram_packets(datagram);
+        let Some(error) = error else {
+            return Ok(parsed);
         };
+        self.diagnostics.malformed_datagrams += 1;
+        if parsed.packets.is_empty() {
+            Err(error)
+        } else {
+            Ok(parsed)
+        }
+    }
 
-        let mut completed_messages = Vec::new();
-        let mut reader = PacketReader::new(ac_payload);
+    /// Read every packet in a datagram, along with the last error from a
+    /// packet that had to be skipped
+    fn read_datagram_packets(
+        &mut self,
+        datagram: &UdpDatagram,
+    ) -> (ParsedDatagram, Option<io::Error>) {
+        self.datagrams += 1;
+        self.evict_stale();
+
+        let direction = self.resolve_direction(datagram);
+        let mut parsed = ParsedDatagram::default();
+        let mut error = None;
+        let mut reader = PacketReader::new(datagram.payload);
 
         while reader.remaining() > 0 {
             let start_pos = reader.position();
 
-            // Parse packet header
-            let header =
-                PacketHeader::read(&mut reader).map_err(|e| io::Error::other(e.to_string()))?;
+            // Parse packet header. Without one there's no telling where the
+            // next packet starts.
+            let header = match PacketHeader::read(&mut reader) {
+                Ok(header) => header,
+                Err(e) => {
+                    error = Some(io::Error::other(e.to_string()));
+                    break;
+                }
+            };
 
             // Calculate packet boundaries (header is always 20 bytes + variable size payload)
             let packet_end = start_pos + PacketHeader::BASE_SIZE + header.size as usize;
 
             // Parse optional headers based on flags
             // NOTE: We must parse ALL optional headers to advance reader correctly!
-            if header.flags.contains(PacketHeaderFlags::SERVER_SWITCH) {
-                reader.read_u32()?; // SeqNo
-                reader.read_u32()?; // Type
-            }
-            if header.flags.contains(PacketHeaderFlags::LOGON_SERVER_ADDR) {
-                reader.read_u16()?; // Family
-                reader.read_u16()?; // Port
-                reader.read_bytes(4)?; // Address (IPv4)
-                reader.read_bytes(8)?; // Zero padding
-            }
-            if header.flags.contains(PacketHeaderFlags::REQUEST_RETRANSMIT) {
-                let num = reader.read_u32()?;
-                for _ in 0..num {
-                    reader.read_u32()?; // sequence id
+            let optional = match OptionalHeaders::read(
+                &mut reader,
+                header.flags,
+                packet_end,
+                self.options.reveal_credentials,
+            ) {
+                Ok(optional) => optional,
+                Err(e) => {
+                    error = Some(e);
+                    reader.set_position(packet_end);
+                    continue;
                 }
-            }
-            if header.flags.contains(PacketHeaderFlags::REFERRAL) {
-                reader.read_bytes(8)?; // Cookie (u64)
-                // SocketAddr: Family (i16), Port (u16), Address (4 bytes), Zero (8 bytes)
-                reader.read_u16()?; // Family
-                reader.read_u16()?; // Port
-                reader.read_bytes(4)?; // Address
-                reader.read_bytes(8)?; // Zero padding
-                reader.read_u16()?; // IdServer
-                reader.read_u16()?; // Padding
-                reader.read_u32()?; // Unknown
-            }
-            if header.flags.contains(PacketHeaderFlags::ACK_SEQUENCE) {
-                reader.read_u32()?; // sequence
-            }
-            if header.flags.contains(PacketHeaderFlags::LOGIN_REQUEST) {
-                // LoginRequest has variable-length strings - skip the entire payload
-                // as we can't reliably parse it without proper error recovery.
-                // C# implementation parses these fields but we don't need them for fragment assembly.
-                // Jump to the end of this packet's data.
-                reader.set_position(packet_end);
-                // No fragments can follow LOGIN_REQUEST in the same packet
-                continue;
-            }
-            if header
-                .flags
-                .contains(PacketHeaderFlags::WORLD_LOGIN_REQUEST)
-            {
-                reader.read_bytes(8)?; // Prim (u64)
-            }
-            if header.flags.contains(PacketHeaderFlags::CONNECT_REQUEST) {
-                reader.read_bytes(8)?; // ServerTime (u64)
-                reader.read_bytes(8)?; // Cookie (u64)
-                reader.read_u32()?; // NetID
-                reader.read_u32()?; // OutgoingSeed
-                reader.read_u32()?; // IncomingSeed
-                reader.read_u32()?; // Unknown
-            }
-            if header.flags.contains(PacketHeaderFlags::CONNECT_RESPONSE) {
-                reader.read_bytes(8)?; // Prim (u64)
-            }
-            if header.flags.contains(PacketHeaderFlags::NET_ERROR) {
-                reader.read_u32()?; // StringId
-                reader.read_u32()?; // TableId
-            }
-            if header
-                .flags
-                .contains(PacketHeaderFlags::NET_ERROR_DISCONNECT)
-            {
-                reader.read_u32()?; // StringId
-                reader.read_u32()?; // TableId
-            }
-            if header.flags.contains(PacketHeaderFlags::CICMDCOMMAND) {
-                // CICMDCommand: Command (u32) + Param (u32)
-                reader.read_u32()?; // Command
-                reader.read_u32()?; // Param
-            }
-            if header.flags.contains(PacketHeaderFlags::TIME_SYNC) {
-                reader.read_bytes(8)?; // Time (f64)
-            }
-            if header.flags.contains(PacketHeaderFlags::ECHO_REQUEST) {
-                reader.read_bytes(4)?; // LocalTime (f32)
-            }
-            if header.flags.contains(PacketHeaderFlags::ECHO_RESPONSE) {
-                reader.read_bytes(4)?; // LocalTime (f32)
-                reader.read_bytes(4)?; // HoldingTime (f32)
-            }
-            if header.flags.contains(PacketHeaderFlags::FLOW) {
-                reader.read_u32()?; // DataReceived
-                reader.read_u16()?; // Interval
-            }
+            };
+            let mut fragments = Vec::new();
 
             // If this packet has fragments, parse them
             if header.flags.contains(PacketHeaderFlags::BLOB_FRAGMENTS) {
@@ -197,16 +359,18 @@ impl FragmentAssembler {
                 while reader.position() < packet_end && reader.remaining() > 0 {
                     match self.parse_fragment_internal(
                         &mut reader,
+                        datagram,
+                        direction,
                         Some(header.iteration),
                         Some(header_flags),
                     ) {
-                        Ok(Some(msg)) => {
-                            completed_messages.push(msg);
-                        }
-                        Ok(None) => {
-                            // Fragment received but not complete yet
+                        Ok((fragment, msg)) => {
+                            fragments.push(fragment);
+                            // None means the fragment was received but isn't complete yet
+                            parsed.messages.extend(msg);
                         }
                         Err(_e) => {
+                            self.diagnostics.malformed_fragments += 1;
                             // Fragment parsing failed - skip to end of packet like C# does
                             // C# catches exceptions in ParseFragment and returns null,
                             // then the outer loop advances to the next packet
@@ -216,23 +380,224 @@ impl FragmentAssembler {
                 }
             }
 
+            let checksum = self.verify_checksum(
+                datagram,
+                direction,
+                &header,
+                &optional,
+                &datagram.payload[start_pos..packet_end.min(datagram.payload.len())],
+            );
+
+            parsed.packets.push(PacketRecord {
+                direction,
+                checksum,
+                source: datagram.source,
+                destination: datagram.destination,
+                header,
+                optional,
+                fragments,
+            });
+
             // Move to next packet
             if reader.position() < packet_end {
                 reader.set_position(packet_end);
             }
         }
 
-        Ok(completed_messages)
+        (parsed, error)
+    }
+
+    /// Check a packet's checksum, seeding the session's ISAAC streams when
+    /// it's a ConnectRequest
+    fn verify_checksum(
+        &mut self,
+        datagram: &UdpDatagram,
+        direction: Option<Direction>,
+        header: &PacketHeader,
+        optional: &OptionalHeaders,
+        packet: &[u8],
+    ) -> ChecksumStatus {
+        let client = match direction {
+            Some(Direction::ServerToClient) => Some(datagram.destination),
+            Some(Direction::ClientToServer) => Some(datagram.source),
+            None => None,
+        };
+
+        if let (Some(client), Some(connect)) = (client, &optional.connect_request) {
+            // A new connection starts its fragment sequences over
+            self.completed_order
+                .retain(|key| key.source != client && key.destination != client);
+            self.completed
+                .retain(|key| key.source != client && key.destination != client);
+            self.key_windows.insert(
+                (client, Direction::ServerToClient),
+                KeyWindow::new(connect.outgoing_seed),
+            );
+            self.key_windows.insert(
+                (client, Direction::ClientToServer),
+                KeyWindow::new(connect.incoming_seed),
+            );
+        }
+
+        let status = match PacketChecksum::compute(packet) {
+            Err(_) => ChecksumStatus::Invalid,
+            Ok(checksum) if !header.flags.contains(PacketHeaderFlags::ENCRYPTED_CHECKSUM) => {
+                if checksum.plain() == header.checksum {
+                    ChecksumStatus::Valid
+                } else {
+                    ChecksumStatus::Invalid
+                }
+            }
+            Ok(checksum) => match client
+                .zip(direction)
+                .and_then(|key| self.key_windows.get_mut(&key))
+            {
+                Some(window) => {
+                    if window.accept(checksum.recover_key(header.checksum)) {
+                        ChecksumStatus::Valid
+                    } else {
+                        ChecksumStatus::Invalid
+                    }
+                }
+                None => ChecksumStatus::Unverified,
+            },
+        };
+
+        if status == ChecksumStatus::Invalid {
+            self.diagnostics.invalid_checksums += 1;
+        }
+        status
+    }
+
+    /// Remember a completed message, forgetting the oldest beyond
+    /// `COMPLETED_HISTORY`
+    fn remember_completed(&mut self, key: FragmentKey) {
+        if self.completed.insert(key) {
+            self.completed_order.push_back(key);
+        }
+        if self.completed_order.len() > COMPLETED_HISTORY
+            && let Some(oldest) = self.completed_order.pop_front()
+        {
+            self.completed.remove(&oldest);
+        }
+    }
+
+    /// Drop pending messages that have exceeded the age limits, oldest first
+    /// until one that hasn't
+    fn evict_stale(&mut self) {
+        while let Some(&(first_datagram, key)) = self.pending_order.first() {
+            let first_time = self.pending_fragments.get(&key).and_then(|e| e.first_time);
+            let reason = if let Some(max) = self.options.max_age_datagrams
+                && self.datagrams - first_datagram > max
+            {
+                EvictionReason::PacketAge
+            } else if let (Some(max), Some(now), Some(first)) =
+                (self.options.max_age, self.capture_time, first_time)
+                && now.saturating_sub(first) > max
+            {
+                EvictionReason::Time
+            } else {
+                break;
+            };
+            self.evict(&key, reason);
+        }
+    }
+
+    /// Drop the oldest pending messages until `incoming` more bytes fit in
+    /// the memory cap. Returns false if they wouldn't fit even with nothing
+    /// pending.
+    fn make_room(&mut self, incoming: usize) -> bool {
+        let Some(max) = self.options.max_pending_bytes else {
+            return true;
+        };
+        if incoming > max {
+            return false;
+        }
+
+        while self.pending_bytes + incoming > max {
+            let Some(&(_, oldest)) = self.pending_order.first() else {
+                break;
+            };
+            self.evict(&oldest, EvictionReason::MemoryCap);
+        }
+        true
+    }
+
+    fn evict(&mut self, key: &FragmentKey, reason: EvictionReason) {
+        if let Some(entry) = self.remove_pending(key) {
+            self.diagnostics
+                .record_dropped(incomplete_message(key, &entry.pending, reason));
+        }
+    }
+
+    /// Stop tracking a pending message, releasing its share of the memory cap
+    fn remove_pending(&mut self, key: &FragmentKey) -> Option<PendingEntry> {
+        let entry = self.pending_fragments.remove(key)?;
+        self.pending_order.remove(&(entry.first_datagram, *key));
+        self.pending_bytes -= entry.pending.fragment.data.len();
+        Some(entry)
+    }
+
+    /// Treat `server` as a server endpoint, for callers that already know
+    /// which side of a connection is which
+    pub fn add_server(&mut self, server: SocketAddr) {
+        self.servers.insert(server);
+    }
+
+    /// Work out which way a datagram is going, learning server endpoints as we go
+    ///
+    /// A server is the side that sends a ConnectRequest or receives a
+    /// LoginRequest, or failing that, the side using one of the well-known
+    /// server ports.
+    fn resolve_direction(&mut self, datagram: &UdpDatagram) -> Option<Direction> {
+        if datagram.payload.len() >= 8 {
+            let flags = PacketHeaderFlags::from_bits_retain(u32::from_le_bytes([
+                datagram.payload[4],
+                datagram.payload[5],
+                datagram.payload[6],
+                datagram.payload[7],
+            ]));
+            if flags.contains(PacketHeaderFlags::CONNECT_REQUEST) {
+                self.servers.insert(datagram.source);
+            }
+            if flags.contains(PacketHeaderFlags::LOGIN_REQUEST) {
+                self.servers.insert(datagram.destination);
+            }
+        }
+
+        if self.servers.contains(&datagram.source) {
+            return Some(Direction::ServerToClient);
+        }
+        if self.servers.contains(&datagram.destination) {
+            return Some(Direction::ClientToServer);
+        }
+
+        match (
+            is_server_port(datagram.source.port()),
+            is_server_port(datagram.destination.port()),
+        ) {
+            (true, false) => {
+                self.servers.insert(datagram.source);
+                Some(Direction::ServerToClient)
+            }
+            (false, true) => {
+                self.servers.insert(datagram.destination);
+                Some(Direction::ClientToServer)
+            }
+            _ => None,
+        }
     }
 
     /// Parse a single fragment from the reader
-    /// Returns Some(RawMessage) if the fragment completes a message, None otherwise
+    /// Returns the fragment along with Some(RawMessage) if it completes a message, None otherwise
     fn parse_fragment_internal(
         &mut self,
         reader: &mut PacketReader,
+        datagram: &UdpDatagram,
+        direction: Option<Direction>,
         packet_iteration: Option<u16>,
         header_flags: Option<u32>,
-    ) -> io::Result<Option<RawMessage>> {
+    ) -> io::Result<(ExtractedFragment, Option<RawMessage>)> {
         let sequence = reader.read_u32()?;
         let id = reader.read_u32()?;
         let count = reader.read_u16()?;
@@ -251,46 +616,124 @@ impl FragmentAssembler {
         }
 
         let data = reader.read_bytes(frag_length)?;
+        let mut fragment = ExtractedFragment {
+            sequence,
+            id,
+            index,
+            count,
+            size,
+            group,
+            data: data.clone(),
+            is_complete: false,
+        };
 
         // Update or create fragment entry
-        let fragment = self
-            .pending_fragments
-            .entry(sequence)
-            .or_insert_with(|| Fragment::new(sequence, count));
+        let key = FragmentKey {
+            source: datagram.source,
+            destination: datagram.destination,
+            sequence,
+            id,
+        };
+        if self.completed.contains(&key) {
+            self.diagnostics.retransmitted_chunks += 1;
+            return Ok((fragment, None));
+        }
+        if !self.pending_fragments.contains_key(&key) {
+            // The buffer is sized by the count off the wire, so it has to fit
+            // the cap before it's allocated
+            let bytes = count as usize * FRAGMENT_CHUNK_SIZE;
+            if !self.make_room(bytes) {
+                self.diagnostics.record_dropped(IncompleteMessage {
+                    source: key.source,
+                    destination: key.destination,
+                    sequence,
+                    id,
+                    group,
+                    count,
+                    received: 1,
+                    reason: EvictionReason::MemoryCap,
+                });
+                return Ok((fragment, None));
+            }
+            self.pending_bytes += bytes;
+            self.pending_order.insert((self.datagrams, key));
+            self.pending_fragments.insert(
+                key,
+                PendingEntry {
+                    pending: PendingFragment::new(sequence, count),
+                    first_datagram: self.datagrams,
+                    first_time: self.capture_time,
+                },
+            );
+        }
+        let Some(entry) = self.pending_fragments.get_mut(&key) else {
+            return Ok((fragment, None));
+        };
+        let pending = &mut entry.pending;
 
-        fragment.add_chunk(&data, index as usize, frag_length); // Pass chunk size
-        fragment.header.id = id;
-        fragment.header.index = index;
-        fragment.set_fragment_info(size, group);
+        match pending.add_chunk(&data, index as usize, frag_length) {
+            ChunkStatus::Added => {}
+            ChunkStatus::Duplicate => self.diagnostics.duplicate_chunks += 1,
+            ChunkStatus::OutOfRange => self.diagnostics.out_of_range_chunks += 1,
+        }
+        pending.fragment.header.id = id;
+        pending.fragment.header.index = index;
+        pending.set_fragment_info(size, group);
 
         // Check if this completes the fragment assembly
-        if fragment.is_complete() {
-            let assembled_data = fragment.get_data().to_vec();
-            fragment.cleanup();
-            self.pending_fragments.remove(&sequence);
+        if !pending.is_complete() {
+            return Ok((fragment, None));
+        }
 
-            // Try to parse as a message
-            let msg_id = self.next_message_id;
-            self.next_message_id += 1;
+        let Some(entry) = self.remove_pending(&key) else {
+            return Ok((fragment, None));
+        };
+        fragment.is_complete = true;
+        self.diagnostics.completed += 1;
+        self.remember_completed(key);
+        let first_time = entry.first_time;
+        let assembled_data = entry.pending.into_data();
 
-            let parsed_msg = RawMessage::from_fragment_with_iteration(
-                assembled_data,
-                sequence,
-                msg_id,
-                packet_iteration,
-                header_flags,
-            )?;
-            Ok(Some(parsed_msg))
-        } else {
-            Ok(None)
-        }
+        // Try to parse as a message
+        let msg_id = self.next_message_id;
+        self.next_message_id += 1;
+
+        // Fall back to guessing from the opcode if the transport didn't tell us
+        let direction = direction.unwrap_or_else(|| {
+            let opcode = assembled_data
+                .get(..4)
+                .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            guess_direction(opcode)
+        });
+
+        let mut parsed_msg = RawMessage::from_fragment_with_iteration(
+            assembled_data,
+            sequence,
+            msg_id,
+            direction,
+            packet_iteration,
+            header_flags,
+        )?;
+        parsed_msg.first_time = first_time;
+        parsed_msg.last_time = self.capture_time;
+        Ok((fragment, Some(parsed_msg)))
     }
+}
 
-    /// Parse a single fragment from the reader
-    /// Returns Some(RawMessage) if the fragment completes a message, None otherwise
-    #[allow(dead_code)]
-    fn parse_fragment(&mut self, reader: &mut PacketReader) -> io::Result<Option<RawMessage>> {
-        self.parse_fragment_internal(reader, None, None)
+fn incomplete_message(
+    key: &FragmentKey,
+    pending: &PendingFragment,
+    reason: EvictionReason,
+) -> IncompleteMessage {
+    IncompleteMessage {
+        source: key.source,
+        destination: key.destination,
+        sequence: key.sequence,
+        id: key.id,
+        group: pending.get_group(),
+        count: pending.fragment.header.count,
+        received: pending.metadata.received_chunks as u16,
+        reason,
     }
 }
 
@@ -299,3 +742,498 @@ impl Default for FragmentAssembler {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::reassembly::MAX_DROPPED_DETAILS;
+
+    /// Build an Ethernet/IPv4/UDP frame around a single AC packet carrying one
+    /// complete fragment
+    fn frame(src_port: u16, dst_port: u16, flags: PacketHeaderFlags, message: &[u8]) -> Vec<u8> {
+        chunk_frame(src_port, dst_port, flags, 1, 1, 0, message)
+    }
+
+    /// Build an Ethernet/IPv4/UDP frame around a single AC packet carrying one
+    /// chunk of a fragment
+    fn chunk_frame(
+        src_port: u16,
+        dst_port: u16,
+        flags: PacketHeaderFlags,
+        sequence: u32,
+        count: u16,
+        index: u16,
+        chunk: &[u8],
+    ) -> Vec<u8> {
+        let mut fragment = Vec::new();
+        fragment.extend_from_slice(&sequence.to_le_bytes()); // sequence
+        fragment.extend_from_slice(&1u32.to_le_bytes()); // id
+        fragment.extend_from_slice(&count.to_le_bytes()); // count
+        fragment.extend_from_slice(&(16 + chunk.len() as u16).to_le_bytes()); // size
+        fragment.extend_from_slice(&index.to_le_bytes()); // index
+        fragment.extend_from_slice(&9u16.to_le_bytes()); // group
+        fragment.extend_from_slice(chunk);
+
+        let mut frame = vec![0u8; 14];
+        frame[12] = 0x08; // EtherType: IPv4
+        frame.extend_from_slice(&[
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, // IPv4
+            10, 0, 0, 1, // source
+            10, 0, 0, 2, // destination
+        ]);
+        frame.extend_from_slice(&src_port.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        frame.extend_from_slice(&1u32.to_le_bytes()); // sequence
+        frame.extend_from_slice(
+            &(flags | PacketHeaderFlags::BLOB_FRAGMENTS)
+                .bits()
+                .to_le_bytes(),
+        );
+        frame.extend_from_slice(&0u32.to_le_bytes()); // checksum
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]); // id, time
+        frame.extend_from_slice(&(fragment.len() as u16).to_le_bytes()); // size
+        frame.extend_from_slice(&0u16.to_le_bytes()); // iteration
+        frame.extend_from_slice(&fragment);
+        frame
+    }
+
+    // CommunicationTurbineChat exists in both directions
+    const TURBINE_CHAT: [u8; 4] = [0xDE, 0xF7, 0x00, 0x00];
+
+    #[test]
+    fn test_assembler_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<FragmentAssembler>();
+    }
+
+    #[test]
+    fn test_direction_from_server_port() {
+        let mut assembler = FragmentAssembler::new();
+
+        let messages = assembler
+            .parse_packet_payload(&frame(9000, 50000, PacketHeaderFlags::NONE, &TURBINE_CHAT))
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].direction, Direction::ServerToClient);
+        assert_eq!(messages[0].direction(), "Recv");
+
+        let messages = assembler
+            .parse_packet_payload(&frame(50000, 9000, PacketHeaderFlags::NONE, &TURBINE_CHAT))
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].direction, Direction::ClientToServer);
+        assert_eq!(messages[0].direction(), "Send");
+    }
+
+    #[test]
+    fn test_parse_packets_returns_records() {
+        let mut assembler = FragmentAssembler::new();
+
+        let parsed = assembler
+            .parse_packets(&frame(9000, 50000, PacketHeaderFlags::NONE, &TURBINE_CHAT))
+            .unwrap();
+        assert_eq!(parsed.messages.len(), 1);
+        assert_eq!(parsed.packets.len(), 1);
+
+        let packet = &parsed.packets[0];
+        assert_eq!(packet.direction, Some(Direction::ServerToClient));
+        assert_eq!(packet.source.port(), 9000);
+        assert!(
+            packet
+                .header
+                .flags
+                .contains(PacketHeaderFlags::BLOB_FRAGMENTS)
+        );
+        assert_eq!(packet.optional, OptionalHeaders::default());
+        assert_eq!(packet.fragments.len(), 1);
+        assert_eq!(packet.fragments[0].group, 9);
+        assert_eq!(packet.fragments[0].size, 16 + TURBINE_CHAT.len() as u16);
+        assert_eq!(packet.fragments[0].data, TURBINE_CHAT);
+        assert!(packet.fragments[0].is_complete);
+    }
+
+    #[test]
+    fn test_direction_learned_from_connect_request() {
+        let mut assembler = FragmentAssembler::new();
+
+        // Neither port is a well-known server port, so the ConnectRequest
+        // sender is what identifies the server
+        let mut connect = frame(20000, 30000, PacketHeaderFlags::NONE, &[]);
+        connect.truncate(42 + 20);
+        connect[46..50].copy_from_slice(&PacketHeaderFlags::CONNECT_REQUEST.bits().to_le_bytes());
+        connect[58..60].copy_from_slice(&32u16.to_le_bytes());
+        connect.extend_from_slice(&[0u8; 32]);
+        let parsed = assembler.parse_packets(&connect).unwrap();
+        assert!(parsed.messages.is_empty());
+        assert_eq!(parsed.packets[0].direction, Some(Direction::ServerToClient));
+        assert_eq!(
+            parsed.packets[0]
+                .optional
+                .connect_request
+                .as_ref()
+                .map(|c| c.cookie),
+            Some(0)
+        );
+
+        let messages = assembler
+            .parse_packet_payload(&frame(30000, 20000, PacketHeaderFlags::NONE, &TURBINE_CHAT))
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].direction, Direction::ClientToServer);
+    }
+
+    #[test]
+    fn test_checksums_verified_with_connect_request_seeds() {
+        use crate::network::checksum::{Isaac, seal_packet};
+
+        let mut assembler = FragmentAssembler::new();
+
+        let mut connect = frame(9000, 50000, PacketHeaderFlags::NONE, &[]);
+        connect.truncate(42 + 20);
+        connect[46..50].copy_from_slice(&PacketHeaderFlags::CONNECT_REQUEST.bits().to_le_bytes());
+        connect[58..60].copy_from_slice(&32u16.to_le_bytes());
+        let mut connect_request = [0u8; 32];
+        connect_request[20..24].copy_from_slice(&111u32.to_le_bytes()); // outgoing seed
+        connect_request[24..28].copy_from_slice(&222u32.to_le_bytes()); // incoming seed
+        connect.extend_from_slice(&connect_request);
+        seal_packet(&mut connect[42..], None).unwrap();
+        let parsed = assembler.parse_packets(&connect).unwrap();
+        assert_eq!(parsed.packets[0].checksum, ChecksumStatus::Valid);
+
+        let mut server_keys = Isaac::new(111);
+        let mut client_keys = Isaac::new(222);
+        let send = |src_port, dst_port, key| {
+            let mut frame = frame(
+                src_port,
+                dst_port,
+                PacketHeaderFlags::ENCRYPTED_CHECKSUM,
+                &TURBINE_CHAT,
+            );
+            if src_port != 9000 {
+                // The helper always sends from 10.0.0.1, so swap the
+                // addresses to keep the client at 10.0.0.2
+                frame[26..34].rotate_left(4);
+            }
+            seal_packet(&mut frame[42..], Some(key)).unwrap();
+            frame
+        };
+
+        let recv = send(9000, 50000, server_keys.next_key());
+        let parsed = assembler.parse_packets(&recv).unwrap();
+        assert_eq!(parsed.packets[0].checksum, ChecksumStatus::Valid);
+
+        // Keyed with the wrong direction's stream
+        let spoofed = send(50000, 9000, server_keys.next_key());
+        let parsed = assembler.parse_packets(&spoofed).unwrap();
+        assert_eq!(parsed.packets[0].checksum, ChecksumStatus::Invalid);
+
+        let mut corrupted = send(50000, 9000, client_keys.next_key());
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        let parsed = assembler.parse_packets(&corrupted).unwrap();
+        assert_eq!(parsed.packets[0].checksum, ChecksumStatus::Invalid);
+        assert_eq!(assembler.diagnostics().invalid_checksums, 2);
+
+        // A session we never saw the ConnectRequest for
+        let unknown = send(9000, 50001, 0x1234);
+        let parsed = assembler.parse_packets(&unknown).unwrap();
+        assert_eq!(parsed.packets[0].checksum, ChecksumStatus::Unverified);
+    }
+
+    #[test]
+    fn test_direction_falls_back_to_opcode() {
+        let mut assembler = FragmentAssembler::new();
+
+        // Ordered_GameAction is only ever sent by the client
+        let messages = assembler
+            .parse_packet_payload(&frame(
+                20000,
+                30000,
+                PacketHeaderFlags::NONE,
+                &[0xB1, 0xF7, 0x00, 0x00],
+            ))
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].direction, Direction::ClientToServer);
+    }
+
+    fn no_limits() -> FragmentAssemblerOptions {
+        FragmentAssemblerOptions {
+            max_age_datagrams: None,
+            max_age: None,
+            max_pending_bytes: None,
+            reveal_credentials: false,
+        }
+    }
+
+    #[test]
+    fn test_evicts_by_datagram_age() {
+        let mut assembler = FragmentAssembler::with_options(FragmentAssemblerOptions {
+            max_age_datagrams: Some(2),
+            ..no_limits()
+        });
+
+        let first_chunk = chunk_frame(9000, 50000, PacketHeaderFlags::NONE, 5, 2, 0, &TURBINE_CHAT);
+        assert!(
+            assembler
+                .parse_packet_payload(&first_chunk)
+                .unwrap()
+                .is_empty()
+        );
+
+        for sequence in 10..12 {
+            let complete = chunk_frame(
+                9000,
+                50000,
+                PacketHeaderFlags::NONE,
+                sequence,
+                1,
+                0,
+                &TURBINE_CHAT,
+            );
+            assembler.parse_packet_payload(&complete).unwrap();
+        }
+        assert!(assembler.diagnostics().dropped.is_empty());
+
+        let complete = chunk_frame(
+            9000,
+            50000,
+            PacketHeaderFlags::NONE,
+            12,
+            1,
+            0,
+            &TURBINE_CHAT,
+        );
+        assembler.parse_packet_payload(&complete).unwrap();
+
+        let diagnostics = assembler.diagnostics();
+        assert_eq!(diagnostics.completed, 3);
+        assert_eq!(diagnostics.dropped.len(), 1);
+        assert_eq!(diagnostics.dropped[0].sequence, 5);
+        assert_eq!(diagnostics.dropped[0].count, 2);
+        assert_eq!(diagnostics.dropped[0].received, 1);
+        assert_eq!(diagnostics.dropped[0].reason, EvictionReason::PacketAge);
+        assert!(assembler.incomplete_messages().is_empty());
+    }
+
+    #[test]
+    fn test_evicts_by_capture_time() {
+        let mut assembler = FragmentAssembler::with_options(FragmentAssemblerOptions {
+            max_age: Some(Duration::from_secs(10)),
+            ..no_limits()
+        });
+
+        assembler.set_capture_time(Duration::from_secs(100));
+        let first_chunk = chunk_frame(9000, 50000, PacketHeaderFlags::NONE, 5, 2, 0, &TURBINE_CHAT);
+        assembler.parse_packet_payload(&first_chunk).unwrap();
+
+        assembler.set_capture_time(Duration::from_secs(111));
+        let complete = frame(9000, 50000, PacketHeaderFlags::NONE, &TURBINE_CHAT);
+        assembler.parse_packet_payload(&complete).unwrap();
+
+        assert_eq!(assembler.diagnostics().dropped_for(EvictionReason::Time), 1);
+    }
+
+    #[test]
+    fn test_messages_stamped_with_first_and_last_capture_time() {
+        let mut assembler = FragmentAssembler::with_options(no_limits());
+
+        assembler.set_capture_time(Duration::from_millis(100_500));
+        let first_chunk = chunk_frame(9000, 50000, PacketHeaderFlags::NONE, 5, 2, 0, &TURBINE_CHAT);
+        assert!(
+            assembler
+                .parse_packet_payload(&first_chunk)
+                .unwrap()
+                .is_empty()
+        );
+
+        assembler.set_capture_time(Duration::from_millis(102_250));
+        let second_chunk =
+            chunk_frame(9000, 50000, PacketHeaderFlags::NONE, 5, 2, 1, &TURBINE_CHAT);
+        let messages = assembler.parse_packet_payload(&second_chunk).unwrap();
+        assert_eq!(messages[0].first_time, Some(Duration::from_millis(100_500)));
+        assert_eq!(messages[0].last_time, Some(Duration::from_millis(102_250)));
+    }
+
+    #[test]
+    fn test_evicts_oldest_over_memory_cap() {
+        let mut assembler = FragmentAssembler::with_options(FragmentAssemblerOptions {
+            max_pending_bytes: Some(2 * FRAGMENT_CHUNK_SIZE),
+            ..no_limits()
+        });
+
+        for sequence in [5, 6] {
+            let chunk = chunk_frame(
+                9000,
+                50000,
+                PacketHeaderFlags::NONE,
+                sequence,
+                2,
+                0,
+                &TURBINE_CHAT,
+            );
+            assembler.parse_packet_payload(&chunk).unwrap();
+        }
+
+        let diagnostics = assembler.diagnostics();
+        assert_eq!(diagnostics.dropped.len(), 1);
+        assert_eq!(diagnostics.dropped[0].sequence, 5);
+        assert_eq!(diagnostics.dropped[0].reason, EvictionReason::MemoryCap);
+        assert_eq!(assembler.incomplete_messages().len(), 1);
+    }
+
+    #[test]
+    fn test_completed_messages_leave_the_eviction_order() {
+        let mut assembler = FragmentAssembler::with_options(FragmentAssemblerOptions {
+            max_pending_bytes: Some(4 * FRAGMENT_CHUNK_SIZE),
+            ..no_limits()
+        });
+        let chunk = |sequence, index| {
+            chunk_frame(
+                9000,
+                50000,
+                PacketHeaderFlags::NONE,
+                sequence,
+                2,
+                index,
+                &TURBINE_CHAT,
+            )
+        };
+
+        for (sequence, index) in [(5, 0), (6, 0), (5, 1), (7, 0), (8, 0)] {
+            assembler
+                .parse_packet_payload(&chunk(sequence, index))
+                .unwrap();
+        }
+
+        let diagnostics = assembler.diagnostics();
+        assert_eq!(diagnostics.completed, 1);
+        assert_eq!(diagnostics.dropped.len(), 1);
+        assert_eq!(diagnostics.dropped[0].sequence, 6);
+        assert_eq!(diagnostics.dropped[0].reason, EvictionReason::MemoryCap);
+        assert_eq!(assembler.pending_order.len(), 2);
+        assert_eq!(assembler.pending_fragments.len(), 2);
+    }
+
+    #[test]
+    fn test_reports_duplicate_and_out_of_range_chunks() {
+        let mut assembler = FragmentAssembler::with_options(no_limits());
+
+        let first_chunk = chunk_frame(9000, 50000, PacketHeaderFlags::NONE, 5, 2, 0, &TURBINE_CHAT);
+        assembler.parse_packet_payload(&first_chunk).unwrap();
+        assembler.parse_packet_payload(&first_chunk).unwrap();
+        let bad_index = chunk_frame(9000, 50000, PacketHeaderFlags::NONE, 5, 2, 7, &[0xFF; 4]);
+        assembler.parse_packet_payload(&bad_index).unwrap();
+
+        let diagnostics = assembler.diagnostics();
+        assert_eq!(diagnostics.duplicate_chunks, 1);
+        assert_eq!(diagnostics.out_of_range_chunks, 1);
+        assert!(!diagnostics.is_clean());
+
+        let incomplete = assembler.incomplete_messages();
+        assert_eq!(incomplete.len(), 1);
+        assert_eq!(incomplete[0].received, 1);
+
+        assembler.finish();
+        assert!(assembler.incomplete_messages().is_empty());
+        assert_eq!(
+            assembler
+                .diagnostics()
+                .dropped_for(EvictionReason::EndOfCapture),
+            1
+        );
+    }
+
+    #[test]
+    fn test_retransmitted_message_not_emitted_twice() {
+        let mut assembler = FragmentAssembler::with_options(no_limits());
+        let message = frame(9000, 50000, PacketHeaderFlags::NONE, &TURBINE_CHAT);
+        let resent = frame(
+            9000,
+            50000,
+            PacketHeaderFlags::RETRANSMISSION,
+            &TURBINE_CHAT,
+        );
+
+        assert_eq!(assembler.parse_packet_payload(&message).unwrap().len(), 1);
+        assert!(assembler.parse_packet_payload(&resent).unwrap().is_empty());
+
+        let diagnostics = assembler.diagnostics();
+        assert_eq!(diagnostics.completed, 1);
+        assert_eq!(diagnostics.retransmitted_chunks, 1);
+        assert_eq!(diagnostics.duplicate_chunks, 0);
+    }
+
+    #[test]
+    fn test_bad_packet_keeps_messages_completed_before_it() {
+        let mut assembler = FragmentAssembler::new();
+        let mut datagram = frame(9000, 50000, PacketHeaderFlags::NONE, &TURBINE_CHAT);
+        // A second packet too short to hold a header
+        datagram.extend_from_slice(&[0xFF; 6]);
+
+        let parsed = assembler.parse_packets(&datagram).unwrap();
+        assert_eq!(parsed.packets.len(), 1);
+        assert_eq!(parsed.messages.len(), 1);
+        assert_eq!(assembler.diagnostics().malformed_datagrams, 1);
+
+        let garbage = frame(9000, 50000, PacketHeaderFlags::NONE, &[]);
+        assert!(assembler.parse_packets(&garbage[..42 + 6]).is_err());
+        assert_eq!(assembler.diagnostics().malformed_datagrams, 2);
+    }
+
+    #[test]
+    fn test_oversized_message_dropped_before_allocating() {
+        let mut assembler = FragmentAssembler::with_options(FragmentAssemblerOptions {
+            max_pending_bytes: Some(2 * FRAGMENT_CHUNK_SIZE),
+            ..no_limits()
+        });
+        let pending = chunk_frame(9000, 50000, PacketHeaderFlags::NONE, 5, 2, 0, &TURBINE_CHAT);
+        assembler.parse_packet_payload(&pending).unwrap();
+        let huge = chunk_frame(
+            9000,
+            50000,
+            PacketHeaderFlags::NONE,
+            6,
+            u16::MAX,
+            0,
+            &TURBINE_CHAT,
+        );
+        assembler.parse_packet_payload(&huge).unwrap();
+
+        // The message that could never fit is dropped without evicting the
+        // one that could
+        let diagnostics = assembler.diagnostics();
+        assert_eq!(diagnostics.dropped_for(EvictionReason::MemoryCap), 1);
+        assert_eq!(diagnostics.dropped[0].sequence, 6);
+        assert_eq!(diagnostics.dropped[0].count, u16::MAX);
+        assert_eq!(assembler.incomplete_messages().len(), 1);
+        assert_eq!(assembler.pending_bytes, 2 * FRAGMENT_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_dropped_details_are_capped() {
+        let mut assembler = FragmentAssembler::with_options(no_limits());
+        let total = MAX_DROPPED_DETAILS as u32 + 5;
+        for sequence in 0..total {
+            let chunk = chunk_frame(
+                9000,
+                50000,
+                PacketHeaderFlags::NONE,
+                sequence,
+                2,
+                0,
+                &TURBINE_CHAT,
+            );
+            assembler.parse_packet_payload(&chunk).unwrap();
+        }
+        assembler.finish();
+
+        let diagnostics = assembler.diagnostics();
+        assert_eq!(diagnostics.dropped.len(), MAX_DROPPED_DETAILS);
+        assert_eq!(diagnostics.dropped_total(), total as u64);
+        assert_eq!(
+            diagnostics.dropped_for(EvictionReason::EndOfCapture),
+            total as u64
+        );
+    }
+}
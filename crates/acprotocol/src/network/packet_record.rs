@@ -0,0 +1,473 @@
+use serde::Serialize;
+use std::io;
+use std::net::SocketAddr;
+
+use crate::enums::PacketHeaderFlags;
+use crate::message::Direction;
+use crate::readers::{ACDataType, read_f32, read_f64, read_packable_list, read_u64};
+use crate::types::{
+    CICMDCommandHeader, ConnectRequestHeader, EchoResponseHeader, FlowHeader, LoginRequestHeader,
+    NetError, PackableList, ReferralHeader, ServerSwitchHeader, SocketAddress, WString,
+};
+use crate::writers::{
+    ACWritable, ACWriter, write_f32, write_f64, write_packable_list, write_u32, write_u64,
+};
+
+use super::checksum::ChecksumStatus;
+use super::packet::PacketHeader;
+use super::packet_parser::ExtractedFragment;
+use super::packet_reader::PacketReader;
+use super::raw_message::RawMessage;
+
+/// A single AC packet: its header, optional headers and fragment chunks
+///
+/// The generated `C2SPacket`/`S2CPacket` types can't be read straight off the
+/// wire: they hold at most one fragment while real packets often carry
+/// several, `BlobFragments` rejects fragment groups missing from
+/// `FragmentGroup`, and each direction's struct leaves out optional headers
+/// the other side is still seen sending. This record reuses the generated
+/// header types for everything else.
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketRecord {
+    /// Which way the packet was going, if the server endpoint is known
+    pub direction: Option<Direction>,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub header: PacketHeader,
+    /// Whether `header.checksum` matched the packet's contents
+    pub checksum: ChecksumStatus,
+    pub optional: OptionalHeaders,
+    pub fragments: Vec<ExtractedFragment>,
+}
+
+impl PacketRecord {
+    /// The client and server endpoints of the packet's connection, if its
+    /// direction is known
+    pub fn client_and_server(&self) -> Option<(SocketAddr, SocketAddr)> {
+        match self.direction? {
+            Direction::ClientToServer => Some((self.source, self.destination)),
+            Direction::ServerToClient => Some((self.destination, self.source)),
+        }
+    }
+
+    /// A packet with a valid checksum going `direction` between `client` and
+    /// `server`, carrying no optional headers or fragments
+    #[cfg(test)]
+    pub(crate) fn between(
+        client: &str,
+        server: &str,
+        direction: Direction,
+        flags: PacketHeaderFlags,
+    ) -> Self {
+        let (source, destination) = match direction {
+            Direction::ClientToServer => (client, server),
+            Direction::ServerToClient => (server, client),
+        };
+        Self {
+            direction: Some(direction),
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            header: PacketHeader::with_flags(flags),
+            checksum: ChecksumStatus::Valid,
+            optional: OptionalHeaders::default(),
+            fragments: Vec::new(),
+        }
+    }
+}
+
+/// The optional headers present in a packet, as indicated by its flags
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OptionalHeaders {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_switch: Option<ServerSwitchHeader>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logon_server_addr: Option<SocketAddress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referral: Option<ReferralHeader>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retransmit_sequences: Option<PackableList<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reject_sequences: Option<PackableList<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack_sequence: Option<u32>,
+    /// Client version, auth type, account and timestamp of a login attempt.
+    /// The password or GLS ticket is redacted unless the assembler was told
+    /// to reveal credentials.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub login_request: Option<LoginRequestHeader>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub world_login_request: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_request: Option<ConnectRequestHeader>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_response: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_error: Option<NetError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_error_disconnect: Option<NetError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cicmd_command: Option<CICMDCommandHeader>,
+    /// Server time in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_sync: Option<f64>,
+    /// Client time the echo was sent at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub echo_request: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub echo_response: Option<EchoResponseHeader>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow: Option<FlowHeader>,
+}
+
+impl OptionalHeaders {
+    /// Read the optional headers indicated by `flags`
+    ///
+    /// Nothing can follow a LoginRequest in the same packet, so the reader is
+    /// left at `packet_end` after one. Credentials in it are replaced with
+    /// `REDACTED` unless `reveal_credentials` is set.
+    pub(crate) fn read(
+        reader: &mut PacketReader,
+        flags: PacketHeaderFlags,
+        packet_end: usize,
+        reveal_credentials: bool,
+    ) -> io::Result<Self> {
+        let mut optional = Self::default();
+
+        if flags.contains(PacketHeaderFlags::SERVER_SWITCH) {
+            optional.server_switch = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::LOGON_SERVER_ADDR) {
+            optional.logon_server_addr = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::REQUEST_RETRANSMIT) {
+            optional.retransmit_sequences =
+                Some(read_packable_list(reader).map_err(|e| io::Error::other(e.to_string()))?);
+        }
+        if flags.contains(PacketHeaderFlags::REJECT_RETRANSMIT) {
+            optional.reject_sequences =
+                Some(read_packable_list(reader).map_err(|e| io::Error::other(e.to_string()))?);
+        }
+        if flags.contains(PacketHeaderFlags::REFERRAL) {
+            optional.referral = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::ACK_SEQUENCE) {
+            optional.ack_sequence = Some(reader.read_u32()?);
+        }
+        if flags.contains(PacketHeaderFlags::LOGIN_REQUEST) {
+            // A login request we can't decode (e.g. an unknown auth type) is
+            // left out rather than failing the whole packet
+            let mut login_request = read_typed::<LoginRequestHeader>(reader).ok();
+            if !reveal_credentials && let Some(login_request) = &mut login_request {
+                redact_credentials(login_request);
+            }
+            optional.login_request = login_request;
+
+            // No fragments can follow LOGIN_REQUEST in the same packet
+            reader.set_position(packet_end);
+            return Ok(optional);
+        }
+        if flags.contains(PacketHeaderFlags::WORLD_LOGIN_REQUEST) {
+            optional.world_login_request =
+                Some(read_u64(reader).map_err(|e| io::Error::other(e.to_string()))?);
+        }
+        if flags.contains(PacketHeaderFlags::CONNECT_REQUEST) {
+            optional.connect_request = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::CONNECT_RESPONSE) {
+            optional.connect_response =
+                Some(read_u64(reader).map_err(|e| io::Error::other(e.to_string()))?);
+        }
+        if flags.contains(PacketHeaderFlags::NET_ERROR) {
+            optional.net_error = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::NET_ERROR_DISCONNECT) {
+            optional.net_error_disconnect = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::CICMDCOMMAND) {
+            optional.cicmd_command = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::TIME_SYNC) {
+            optional.time_sync =
+                Some(read_f64(reader).map_err(|e| io::Error::other(e.to_string()))?);
+        }
+        if flags.contains(PacketHeaderFlags::ECHO_REQUEST) {
+            optional.echo_request =
+                Some(read_f32(reader).map_err(|e| io::Error::other(e.to_string()))?);
+        }
+        if flags.contains(PacketHeaderFlags::ECHO_RESPONSE) {
+            optional.echo_response = Some(read_typed(reader)?);
+        }
+        if flags.contains(PacketHeaderFlags::FLOW) {
+            optional.flow = Some(read_typed(reader)?);
+        }
+
+        Ok(optional)
+    }
+
+    /// Write the headers that are present back out in wire order
+    ///
+    /// The inverse of `read`: the packet's flags must name exactly the
+    /// headers that are present.
+    pub(crate) fn write(&self, writer: &mut dyn ACWriter) -> io::Result<()> {
+        self.write_present(writer)
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn write_present(&self, writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(server_switch) = &self.server_switch {
+            server_switch.write(writer)?;
+        }
+        if let Some(logon_server_addr) = &self.logon_server_addr {
+            logon_server_addr.write(writer)?;
+        }
+        if let Some(sequences) = &self.retransmit_sequences {
+            write_packable_list(writer, sequences)?;
+        }
+        if let Some(sequences) = &self.reject_sequences {
+            write_packable_list(writer, sequences)?;
+        }
+        if let Some(referral) = &self.referral {
+            referral.write(writer)?;
+        }
+        if let Some(ack_sequence) = self.ack_sequence {
+            write_u32(writer, ack_sequence)?;
+        }
+        if let Some(login_request) = &self.login_request {
+            login_request.write(writer)?;
+        }
+        if let Some(cookie) = self.world_login_request {
+            write_u64(writer, cookie)?;
+        }
+        if let Some(connect_request) = &self.connect_request {
+            connect_request.write(writer)?;
+        }
+        if let Some(cookie) = self.connect_response {
+            write_u64(writer, cookie)?;
+        }
+        if let Some(net_error) = &self.net_error {
+            net_error.write(writer)?;
+        }
+        if let Some(net_error) = &self.net_error_disconnect {
+            net_error.write(writer)?;
+        }
+        if let Some(command) = &self.cicmd_command {
+            command.write(writer)?;
+        }
+        if let Some(time) = self.time_sync {
+            write_f64(writer, time)?;
+        }
+        if let Some(time) = self.echo_request {
+            write_f32(writer, time)?;
+        }
+        if let Some(echo_response) = &self.echo_response {
+            echo_response.write(writer)?;
+        }
+        if let Some(flow) = &self.flow {
+            flow.write(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Placeholder for credentials that have been redacted
+pub const REDACTED: &str = "<redacted>";
+
+/// Replace the password or GLS ticket in a login request with `REDACTED`
+pub fn redact_credentials(login_request: &mut LoginRequestHeader) {
+    match login_request {
+        LoginRequestHeader::Type2(request) => request.password = WString(REDACTED.to_string()),
+        LoginRequestHeader::Type40000002(request) => request.gls_ticket = REDACTED.to_string(),
+    }
+}
+
+/// Read a generated type, converting its error into an `io::Error`
+fn read_typed<T: ACDataType>(reader: &mut PacketReader) -> io::Result<T> {
+    T::read(reader).map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Everything decoded from one UDP datagram
+#[derive(Debug, Clone, Default)]
+pub struct ParsedDatagram {
+    /// Every AC packet in the datagram, in order
+    pub packets: Vec<PacketRecord>,
+    /// Messages completed by fragments in this datagram
+    pub messages: Vec<RawMessage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::AuthFlags;
+    use crate::types::{LoginRequestHeaderType2, LoginRequestHeaderType40000002};
+
+    #[test]
+    fn test_read_optional_headers() {
+        let flags = PacketHeaderFlags::ACK_SEQUENCE
+            | PacketHeaderFlags::TIME_SYNC
+            | PacketHeaderFlags::ECHO_RESPONSE
+            | PacketHeaderFlags::FLOW;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x1234u32.to_le_bytes()); // ack sequence
+        data.extend_from_slice(&1.5f64.to_le_bytes()); // time sync
+        data.extend_from_slice(&2.0f32.to_le_bytes()); // echo local time
+        data.extend_from_slice(&0.25f32.to_le_bytes()); // echo holding time
+        data.extend_from_slice(&1000u32.to_le_bytes()); // flow bytes
+        data.extend_from_slice(&3u16.to_le_bytes()); // flow interval
+
+        let mut reader = PacketReader::new(&data);
+        let optional = OptionalHeaders::read(&mut reader, flags, data.len(), false).unwrap();
+
+        assert_eq!(reader.remaining(), 0);
+        assert_eq!(optional.ack_sequence, Some(0x1234));
+        assert_eq!(optional.time_sync, Some(1.5));
+        let echo = optional.echo_response.unwrap();
+        assert_eq!(echo.local_time, 2.0);
+        assert_eq!(echo.holding_time, 0.25);
+        let flow = optional.flow.unwrap();
+        assert_eq!(flow.bytes, 1000);
+        assert_eq!(flow.interval, 3);
+        assert!(optional.connect_request.is_none());
+    }
+
+    #[test]
+    fn test_write_optional_headers_round_trip() {
+        let flags = PacketHeaderFlags::REQUEST_RETRANSMIT
+            | PacketHeaderFlags::ACK_SEQUENCE
+            | PacketHeaderFlags::TIME_SYNC
+            | PacketHeaderFlags::FLOW;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u32.to_le_bytes()); // retransmit count
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(&0x1234u32.to_le_bytes()); // ack sequence
+        data.extend_from_slice(&1.5f64.to_le_bytes()); // time sync
+        data.extend_from_slice(&1000u32.to_le_bytes()); // flow bytes
+        data.extend_from_slice(&3u16.to_le_bytes()); // flow interval
+
+        let mut reader = PacketReader::new(&data);
+        let optional = OptionalHeaders::read(&mut reader, flags, data.len(), false).unwrap();
+
+        let mut written = Vec::new();
+        optional
+            .write(&mut std::io::Cursor::new(&mut written))
+            .unwrap();
+        assert_eq!(written, data);
+    }
+
+    #[test]
+    fn test_read_retransmit_requests() {
+        let flags = PacketHeaderFlags::REQUEST_RETRANSMIT;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(&9u32.to_le_bytes());
+
+        let mut reader = PacketReader::new(&data);
+        let optional = OptionalHeaders::read(&mut reader, flags, data.len(), false).unwrap();
+
+        assert_eq!(optional.retransmit_sequences.unwrap().list, vec![7, 9]);
+    }
+
+    #[test]
+    fn test_read_truncated_optional_header() {
+        let mut reader = PacketReader::new(&[0x01, 0x02]);
+        let result = OptionalHeaders::read(&mut reader, PacketHeaderFlags::ACK_SEQUENCE, 2, false);
+        assert!(result.is_err());
+    }
+
+    fn login_request_bytes(auth: &LoginRequestHeader) -> Vec<u8> {
+        let mut data = Vec::new();
+        auth.write(&mut std::io::Cursor::new(&mut data)).unwrap();
+        data
+    }
+
+    fn password_login() -> LoginRequestHeader {
+        LoginRequestHeader::Type2(LoginRequestHeaderType2 {
+            client_version: "1802".to_string(),
+            length: 0,
+            flags: AuthFlags::None,
+            sequence: 1763490291,
+            account: "player".to_string(),
+            account_to_login_as: String::new(),
+            password: WString("hunter2".to_string()),
+        })
+    }
+
+    #[test]
+    fn test_read_login_request_redacted() {
+        let data = login_request_bytes(&password_login());
+
+        let mut reader = PacketReader::new(&data);
+        let optional = OptionalHeaders::read(
+            &mut reader,
+            PacketHeaderFlags::LOGIN_REQUEST,
+            data.len(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(reader.remaining(), 0);
+        let Some(LoginRequestHeader::Type2(request)) = optional.login_request else {
+            panic!("expected a password login request");
+        };
+        assert_eq!(request.client_version, "1802");
+        assert_eq!(request.account, "player");
+        assert_eq!(request.sequence, 1763490291);
+        assert_eq!(request.password.0, REDACTED);
+    }
+
+    #[test]
+    fn test_read_login_request_revealed() {
+        let data = login_request_bytes(&password_login());
+
+        let mut reader = PacketReader::new(&data);
+        let optional = OptionalHeaders::read(
+            &mut reader,
+            PacketHeaderFlags::LOGIN_REQUEST,
+            data.len(),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(optional.login_request, Some(password_login()));
+    }
+
+    #[test]
+    fn test_redact_gls_ticket() {
+        let mut request = LoginRequestHeader::Type40000002(LoginRequestHeaderType40000002 {
+            client_version: "1802".to_string(),
+            length: 0,
+            flags: AuthFlags::None,
+            sequence: 0,
+            account: "player".to_string(),
+            account_to_login_as: String::new(),
+            gls_ticket: "ticket".to_string(),
+        });
+
+        redact_credentials(&mut request);
+
+        let LoginRequestHeader::Type40000002(request) = request else {
+            unreachable!();
+        };
+        assert_eq!(request.gls_ticket, REDACTED);
+    }
+
+    #[test]
+    fn test_undecodable_login_request_is_skipped() {
+        let data = [0xFFu8; 12];
+
+        let mut reader = PacketReader::new(&data);
+        let optional = OptionalHeaders::read(
+            &mut reader,
+            PacketHeaderFlags::LOGIN_REQUEST,
+            data.len(),
+            false,
+        )
+        .unwrap();
+
+        assert!(optional.login_request.is_none());
+        assert_eq!(reader.remaining(), 0);
+    }
+}
@@ -1,9 +1,12 @@
-use std::io::{Cursor, Read};
+use std::io::{self, Cursor, Read, Write};
+use std::time::Duration;
+
+use super::pcapng::{PcapNgReader, SECTION_HEADER_MAGIC};
 
 #[cfg(not(target_arch = "wasm32"))]
 use std::fs::File;
 #[cfg(not(target_arch = "wasm32"))]
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter};
 #[cfg(not(target_arch = "wasm32"))]
 use std::path::Path;
 
@@ -16,40 +19,81 @@ pub struct Packet {
     pub ts_usec: u32,
     /// Captured packet data
     pub data: Vec<u8>,
+    /// Link-layer header type of the interface the packet was captured on
+    /// (LINKTYPE_ETHERNET is 1)
+    pub link_type: u32,
+}
+
+impl Packet {
+    /// Capture time as a duration since the Unix epoch
+    pub fn time(&self) -> Duration {
+        Duration::new(self.ts_sec as u64, self.ts_usec.saturating_mul(1000))
+    }
 }
 
-/// Iterator over packets in a pcap file
+/// Which kind of capture file is being read
+enum Format {
+    /// Classic libpcap, with a single link type for the whole file
+    Pcap {
+        is_big_endian: bool,
+        /// Record timestamps hold nanoseconds rather than microseconds
+        nanosecond: bool,
+        link_type: u32,
+    },
+    PcapNg(PcapNgReader),
+}
+
+/// Iterator over packets in a pcap or pcapng file
 pub struct PcapIterator<R: Read> {
     reader: R,
-    is_big_endian: bool,
+    format: Format,
 }
 
 impl<R: Read> PcapIterator<R> {
     /// Create a new pcap iterator from a reader
+    ///
+    /// Both classic libpcap files and pcapng files are accepted; the format
+    /// is detected from the first four bytes.
     pub fn new(mut reader: R) -> std::io::Result<Self> {
-        // Read and parse pcap file header (24 bytes)
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if u32::from_le_bytes(magic) == SECTION_HEADER_MAGIC {
+            let format = Format::PcapNg(PcapNgReader::new(&mut reader)?);
+            return Ok(PcapIterator { reader, format });
+        }
+
+        // Read and parse the rest of the pcap file header (24 bytes)
         let mut header = [0u8; 24];
-        reader.read_exact(&mut header)?;
+        header[..4].copy_from_slice(&magic);
+        reader.read_exact(&mut header[4..])?;
 
         // Check magic number to determine endianness
         // The magic bytes are always stored in the file's native endianness
         // 0xa1b2c3d4 = little-endian PCAP file
         // 0xd4c3b2a1 = big-endian PCAP file
+        // 0xa1b23c4d / 0x4d3cb2a1 = the same with nanosecond timestamps
         let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
-        let is_big_endian = if magic == 0xa1b2c3d4 {
-            false // little-endian PCAP file - the bytes read as LE give this magic
-        } else if magic == 0xd4c3b2a1 {
-            true // big-endian PCAP file - the bytes read as LE give this magic
-        } else {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid pcap magic number",
-            ));
+        let (is_big_endian, nanosecond) = match magic {
+            0xa1b2c3d4 => (false, false),
+            0xd4c3b2a1 => (true, false),
+            0xa1b23c4d => (false, true),
+            0x4d3cb2a1 => (true, true),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid pcap magic number",
+                ));
+            }
         };
 
+        let link_type = read_u32(is_big_endian, &header[20..24]);
         Ok(PcapIterator {
             reader,
-            is_big_endian,
+            format: Format::Pcap {
+                is_big_endian,
+                nanosecond,
+                link_type,
+            },
         })
     }
 
@@ -58,27 +102,35 @@ impl<R: Read> PcapIterator<R> {
         let cursor = Cursor::new(bytes);
         PcapIterator::new(cursor)
     }
+}
 
-    fn read_u32(&self, bytes: &[u8]) -> u32 {
-        let val = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-        if self.is_big_endian {
-            val.swap_bytes()
-        } else {
-            val
-        }
-    }
+fn read_u32(is_big_endian: bool, bytes: &[u8]) -> u32 {
+    let val = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if is_big_endian { val.swap_bytes() } else { val }
 }
 
 impl<R: Read> Iterator for PcapIterator<R> {
     type Item = std::io::Result<Packet>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let (is_big_endian, nanosecond, link_type) = match &mut self.format {
+            Format::Pcap {
+                is_big_endian,
+                nanosecond,
+                link_type,
+            } => (*is_big_endian, *nanosecond, *link_type),
+            Format::PcapNg(pcapng) => return pcapng.next_packet(&mut self.reader),
+        };
+
         let mut packet_header = [0u8; 16];
         match self.reader.read_exact(&mut packet_header) {
             Ok(()) => {
-                let ts_sec = self.read_u32(&packet_header[0..4]);
-                let ts_usec = self.read_u32(&packet_header[4..8]);
-                let incl_len = self.read_u32(&packet_header[8..12]);
+                let ts_sec = read_u32(is_big_endian, &packet_header[0..4]);
+                let mut ts_usec = read_u32(is_big_endian, &packet_header[4..8]);
+                if nanosecond {
+                    ts_usec /= 1000;
+                }
+                let incl_len = read_u32(is_big_endian, &packet_header[8..12]);
 
                 // Read packet data
                 let mut data = vec![0u8; incl_len as usize];
@@ -87,6 +139,7 @@ impl<R: Read> Iterator for PcapIterator<R> {
                         ts_sec,
                         ts_usec,
                         data,
+                        link_type,
                     })),
                     Err(e) => Some(Err(e)),
                 }
@@ -97,7 +150,7 @@ impl<R: Read> Iterator for PcapIterator<R> {
     }
 }
 
-/// Open a pcap file and return an iterator over its packets
+/// Open a pcap or pcapng file and return an iterator over its packets
 ///
 /// This function is only available on non-WASM targets.
 /// For WASM compatibility, use `PcapIterator::from_bytes()` instead.
@@ -107,3 +160,131 @@ pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<PcapIterator<BufReader<F
     let reader = BufReader::new(file);
     PcapIterator::new(reader)
 }
+
+/// Writes packets to a classic little-endian libpcap file
+///
+/// Every packet must have been captured with the link type the file was
+/// created with; pcapng input mixing link types can't be written out as one
+/// pcap.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    link_type: u32,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Largest packet a reader should expect, as written by tcpdump
+    pub const SNAP_LEN: u32 = 262_144;
+
+    /// Write the file header for a capture with the given link type
+    pub fn new(mut writer: W, link_type: u32) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes()); // version major
+        header.extend_from_slice(&4u16.to_le_bytes()); // version minor
+        header.extend_from_slice(&0i32.to_le_bytes()); // timezone offset
+        header.extend_from_slice(&0u32.to_le_bytes()); // timestamp accuracy
+        header.extend_from_slice(&Self::SNAP_LEN.to_le_bytes());
+        header.extend_from_slice(&link_type.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(Self { writer, link_type })
+    }
+
+    /// Append a packet record
+    pub fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        if packet.link_type != self.link_type {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Packet has link type {} but the capture was created with {}",
+                    packet.link_type, self.link_type
+                ),
+            ));
+        }
+
+        let mut record = Vec::with_capacity(16);
+        record.extend_from_slice(&packet.ts_sec.to_le_bytes());
+        record.extend_from_slice(&packet.ts_usec.to_le_bytes());
+        record.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+        self.writer.write_all(&record)?;
+        self.writer.write_all(&packet.data)
+    }
+
+    /// Flush any buffered packets to the underlying writer
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Take back the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Create a pcap file, replacing any existing one, and return a writer for it
+///
+/// This function is only available on non-WASM targets.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn create<P: AsRef<Path>>(
+    path: P,
+    link_type: u32,
+) -> std::io::Result<PcapWriter<BufWriter<File>>> {
+    let file = File::create(path)?;
+    PcapWriter::new(BufWriter::new(file), link_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_round_trip() {
+        let packets = vec![
+            Packet {
+                ts_sec: 1_763_490_291,
+                ts_usec: 452_755,
+                data: vec![0xAA; 60],
+                link_type: 113,
+            },
+            Packet {
+                ts_sec: 1_763_490_292,
+                ts_usec: 1,
+                data: vec![],
+                link_type: 113,
+            },
+        ];
+
+        let mut writer = PcapWriter::new(Vec::new(), 113).unwrap();
+        for packet in &packets {
+            writer.write_packet(packet).unwrap();
+        }
+        let file = writer.into_inner();
+        assert_eq!(file.len(), 24 + 16 + 60 + 16);
+
+        let read: Vec<Packet> = PcapIterator::<&[u8]>::from_bytes(&file)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read.len(), 2);
+        for (a, b) in packets.iter().zip(&read) {
+            assert_eq!(
+                (a.ts_sec, a.ts_usec, a.link_type, &a.data),
+                (b.ts_sec, b.ts_usec, b.link_type, &b.data)
+            );
+        }
+    }
+
+    #[test]
+    fn test_writer_rejects_other_link_types() {
+        let mut writer = PcapWriter::new(Vec::new(), 1).unwrap();
+        let packet = Packet {
+            ts_sec: 0,
+            ts_usec: 0,
+            data: vec![0; 4],
+            link_type: 0,
+        };
+        assert!(writer.write_packet(&packet).is_err());
+        assert_eq!(writer.into_inner().len(), 24);
+    }
+}
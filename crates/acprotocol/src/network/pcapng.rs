@@ -0,0 +1,523 @@
+//! Reading pcapng captures, the default format of Wireshark and dumpcap
+//!
+//! A pcapng file is a sequence of blocks. Each section starts with a Section
+//! Header Block, which sets the byte order for the rest of the section, and
+//! packets refer to the Interface Description Blocks of their section for
+//! their link type and timestamp resolution.
+
+use std::io::{self, Read};
+
+use super::pcap::Packet;
+
+/// Magic number starting every pcapng section, the same in either byte order
+pub const SECTION_HEADER_MAGIC: u32 = 0x0A0D_0D0A;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const OBSOLETE_PACKET_BLOCK: u32 = 0x0000_0002;
+const SIMPLE_PACKET_BLOCK: u32 = 0x0000_0003;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+
+const OPTION_END: u16 = 0;
+const OPTION_IF_TSRESOL: u16 = 9;
+const OPTION_IF_TSOFFSET: u16 = 14;
+
+/// Largest block we'll allocate a buffer for, to fail fast on corrupt files
+const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+/// An interface described in the current section
+#[derive(Debug, Clone)]
+struct Interface {
+    link_type: u32,
+    snap_len: u32,
+    /// Timestamp units per second, from `if_tsresol`
+    units_per_second: u64,
+    /// Seconds to add to every timestamp, from `if_tsoffset`
+    offset_seconds: i64,
+}
+
+/// State for reading the blocks of a pcapng file
+#[derive(Debug)]
+pub(crate) struct PcapNgReader {
+    is_big_endian: bool,
+    interfaces: Vec<Interface>,
+}
+
+impl PcapNgReader {
+    /// Start reading a file whose first four bytes, the section header's
+    /// block type, have already been consumed
+    pub(crate) fn new<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut state = Self {
+            is_big_endian: false,
+            interfaces: Vec::new(),
+        };
+        state.read_section_header(reader)?;
+        Ok(state)
+    }
+
+    /// Read blocks until the next packet, returning `None` at the end of the file
+    pub(crate) fn next_packet<R: Read>(&mut self, reader: &mut R) -> Option<io::Result<Packet>> {
+        loop {
+            let mut block_type = [0u8; 4];
+            match reader.read_exact(&mut block_type) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+                Err(e) => return Some(Err(e)),
+            }
+
+            let result = if u32::from_le_bytes(block_type) == SECTION_HEADER_MAGIC {
+                self.read_section_header(reader).map(|()| None)
+            } else {
+                let block_type = self.u32(&block_type);
+                self.read_block_body(reader)
+                    .and_then(|body| self.parse_block(block_type, &body))
+            };
+
+            match result {
+                Ok(Some(packet)) => return Some(Ok(packet)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Read a Section Header Block, which resets the byte order and interfaces
+    fn read_section_header<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let byte_order = &header[4..8];
+        self.is_big_endian =
+            if u32::from_le_bytes(byte_order.try_into().unwrap()) == BYTE_ORDER_MAGIC {
+                false
+            } else if u32::from_be_bytes(byte_order.try_into().unwrap()) == BYTE_ORDER_MAGIC {
+                true
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid pcapng byte-order magic",
+                ));
+            };
+        self.interfaces.clear();
+
+        // The rest of the block is the version, section length and options,
+        // none of which affect how packets are read
+        let total_length = self.block_length(&header[0..4])?;
+        let mut rest = vec![0u8; total_length - 12];
+        reader.read_exact(&mut rest)?;
+        Ok(())
+    }
+
+    /// Read the body of a block whose type has been consumed, checking and
+    /// dropping the trailing length
+    fn read_block_body<R: Read>(&self, reader: &mut R) -> io::Result<Vec<u8>> {
+        let mut length = [0u8; 4];
+        reader.read_exact(&mut length)?;
+        let total_length = self.block_length(&length)?;
+
+        let mut body = vec![0u8; total_length - 8];
+        reader.read_exact(&mut body)?;
+        let trailer = body.split_off(total_length - 12);
+        if self.u32(&trailer) as usize != total_length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "pcapng block lengths don't match",
+            ));
+        }
+        Ok(body)
+    }
+
+    fn block_length(&self, bytes: &[u8]) -> io::Result<usize> {
+        let length = self.u32(bytes) as usize;
+        if length < 12 || !length.is_multiple_of(4) || length > MAX_BLOCK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid pcapng block length {length}"),
+            ));
+        }
+        Ok(length)
+    }
+
+    fn parse_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<Option<Packet>> {
+        match block_type {
+            INTERFACE_DESCRIPTION_BLOCK => {
+                self.parse_interface(body)?;
+                Ok(None)
+            }
+            ENHANCED_PACKET_BLOCK => {
+                let fields = self.fields(body, 20)?;
+                let captured = fields[3] as usize;
+                self.packet(fields[0], fields[1], fields[2], body, 20, captured)
+                    .map(Some)
+            }
+            OBSOLETE_PACKET_BLOCK => {
+                let fields = self.fields(body, 20)?;
+                let interface_id = if self.is_big_endian {
+                    fields[0] >> 16
+                } else {
+                    fields[0] & 0xFFFF
+                };
+                let captured = fields[3] as usize;
+                self.packet(interface_id, fields[1], fields[2], body, 20, captured)
+                    .map(Some)
+            }
+            SIMPLE_PACKET_BLOCK => {
+                let original = self.fields(body, 4)?[0] as usize;
+                let snap_len = self.interface(0)?.snap_len as usize;
+                let mut captured = original.min(body.len() - 4);
+                if snap_len > 0 {
+                    captured = captured.min(snap_len);
+                }
+                // Simple packets carry no timestamp
+                self.packet(0, 0, 0, body, 4, captured).map(Some)
+            }
+            // Name resolution, statistics, custom blocks and so on
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        let fields = self.fields(body, 8)?;
+        let link_type = if self.is_big_endian {
+            fields[0] >> 16
+        } else {
+            fields[0] & 0xFFFF
+        };
+        let mut interface = Interface {
+            link_type,
+            snap_len: fields[1],
+            units_per_second: 1_000_000,
+            offset_seconds: 0,
+        };
+
+        let mut position = 8;
+        while position + 4 <= body.len() {
+            let code = self.u16(&body[position..]);
+            let length = self.u16(&body[position + 2..]) as usize;
+            let value = body
+                .get(position + 4..position + 4 + length)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Truncated pcapng option")
+                })?;
+            match code {
+                OPTION_END => break,
+                OPTION_IF_TSRESOL if length >= 1 => {
+                    interface.units_per_second = units_per_second(value[0])?;
+                }
+                OPTION_IF_TSOFFSET if length >= 8 => {
+                    let bytes = value[..8].try_into().unwrap();
+                    interface.offset_seconds = if self.is_big_endian {
+                        i64::from_be_bytes(bytes)
+                    } else {
+                        i64::from_le_bytes(bytes)
+                    };
+                }
+                _ => {}
+            }
+            position += 4 + length.next_multiple_of(4);
+        }
+
+        self.interfaces.push(interface);
+        Ok(())
+    }
+
+    /// Build a packet from a block's timestamp and captured data
+    fn packet(
+        &self,
+        interface_id: u32,
+        ts_high: u32,
+        ts_low: u32,
+        body: &[u8],
+        data_offset: usize,
+        captured: usize,
+    ) -> io::Result<Packet> {
+        let interface = self.interface(interface_id)?;
+        let data = body
+            .get(data_offset..data_offset + captured)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "pcapng packet longer than its block",
+                )
+            })?
+            .to_vec();
+
+        let timestamp = ((ts_high as u64) << 32) | ts_low as u64;
+        let units = interface.units_per_second;
+        let seconds = i64::try_from(timestamp / units)
+            .ok()
+            .and_then(|seconds| seconds.checked_add(interface.offset_seconds))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "pcapng timestamp out of range after its interface's offset",
+                )
+            })?;
+        let fraction = timestamp % units;
+
+        Ok(Packet {
+            ts_sec: seconds.clamp(0, u32::MAX as i64) as u32,
+            ts_usec: (fraction as u128 * 1_000_000 / units as u128) as u32,
+            data,
+            link_type: interface.link_type,
+        })
+    }
+
+    fn interface(&self, id: u32) -> io::Result<&Interface> {
+        self.interfaces.get(id as usize).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("pcapng packet refers to unknown interface {id}"),
+            )
+        })
+    }
+
+    /// Read the fixed `u32` fields at the start of a block body
+    fn fields(&self, body: &[u8], length: usize) -> io::Result<Vec<u32>> {
+        if body.len() < length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "pcapng block too short",
+            ));
+        }
+        Ok(body[..length].chunks(4).map(|b| self.u32(b)).collect())
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.is_big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.is_big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+}
+
+/// Decode an `if_tsresol` value: a negative power of ten, or of two when the
+/// high bit is set
+fn units_per_second(resolution: u8) -> io::Result<u64> {
+    let exponent = (resolution & 0x7F) as u32;
+    let units = if resolution & 0x80 == 0 {
+        10u64.checked_pow(exponent)
+    } else {
+        1u64.checked_shl(exponent)
+    };
+    units.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported pcapng timestamp resolution {resolution:#04x}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::pcap::PcapIterator;
+
+    /// Writes pcapng blocks in either byte order
+    struct Writer {
+        big_endian: bool,
+        bytes: Vec<u8>,
+    }
+
+    impl Writer {
+        fn u16(&self, value: u16) -> [u8; 2] {
+            if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        }
+
+        fn u32(&self, value: u32) -> [u8; 4] {
+            if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        }
+
+        fn block(&mut self, block_type: u32, body: &[u8]) {
+            let mut body = body.to_vec();
+            body.resize(body.len().next_multiple_of(4), 0);
+            let length = self.u32(12 + body.len() as u32);
+            let block_type = self.u32(block_type);
+            self.bytes.extend_from_slice(&block_type);
+            self.bytes.extend_from_slice(&length);
+            self.bytes.extend_from_slice(&body);
+            self.bytes.extend_from_slice(&length);
+        }
+
+        fn section(&mut self) {
+            let mut body = self.u32(BYTE_ORDER_MAGIC).to_vec();
+            body.extend_from_slice(&self.u16(1));
+            body.extend_from_slice(&self.u16(0));
+            body.extend_from_slice(&[0xFF; 8]); // unspecified section length
+            self.block(SECTION_HEADER_MAGIC, &body);
+        }
+
+        fn interface(&mut self, link_type: u16, tsresol: Option<u8>) {
+            let mut body = self.u16(link_type).to_vec();
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&self.u32(0));
+            if let Some(resolution) = tsresol {
+                body.extend_from_slice(&self.u16(OPTION_IF_TSRESOL));
+                body.extend_from_slice(&self.u16(1));
+                body.extend_from_slice(&[resolution, 0, 0, 0]);
+            }
+            body.extend_from_slice(&[0; 4]); // opt_endofopt
+            self.block(INTERFACE_DESCRIPTION_BLOCK, &body);
+        }
+
+        fn enhanced(&mut self, interface: u32, timestamp: u64, data: &[u8]) {
+            let mut body = self.u32(interface).to_vec();
+            body.extend_from_slice(&self.u32((timestamp >> 32) as u32));
+            body.extend_from_slice(&self.u32(timestamp as u32));
+            body.extend_from_slice(&self.u32(data.len() as u32));
+            body.extend_from_slice(&self.u32(data.len() as u32));
+            body.extend_from_slice(data);
+            self.block(ENHANCED_PACKET_BLOCK, &body);
+        }
+    }
+
+    fn read_all(bytes: &[u8]) -> Vec<Packet> {
+        PcapIterator::<&[u8]>::from_bytes(bytes)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_enhanced_packets_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            let mut writer = Writer {
+                big_endian,
+                bytes: Vec::new(),
+            };
+            writer.section();
+            writer.interface(1, None);
+            writer.enhanced(0, 1_700_000_000_123_456, &[1, 2, 3, 4, 5]);
+            writer.block(0x0000_0005, &[0; 8]); // statistics block is skipped
+
+            let packets = read_all(&writer.bytes);
+            assert_eq!(packets.len(), 1);
+            assert_eq!(packets[0].ts_sec, 1_700_000_000);
+            assert_eq!(packets[0].ts_usec, 123_456);
+            assert_eq!(packets[0].data, [1, 2, 3, 4, 5]);
+            assert_eq!(packets[0].link_type, 1);
+        }
+    }
+
+    #[test]
+    fn test_interfaces_timestamp_resolution() {
+        let mut writer = Writer {
+            big_endian: false,
+            bytes: Vec::new(),
+        };
+        writer.section();
+        writer.interface(1, Some(9)); // nanoseconds
+        writer.interface(113, Some(0x80 | 10)); // 1/1024ths of a second
+        writer.enhanced(0, 5_250_000_999, &[0xAA]);
+        writer.enhanced(1, 3 * 1024 + 512, &[0xBB]);
+
+        let packets = read_all(&writer.bytes);
+        assert_eq!((packets[0].ts_sec, packets[0].ts_usec), (5, 250_000));
+        assert_eq!(packets[0].link_type, 1);
+        assert_eq!((packets[1].ts_sec, packets[1].ts_usec), (3, 500_000));
+        assert_eq!(packets[1].link_type, 113);
+    }
+
+    #[test]
+    fn test_timestamp_offset() {
+        let mut writer = Writer {
+            big_endian: false,
+            bytes: Vec::new(),
+        };
+        writer.section();
+        for offset in [3_600, i64::MAX] {
+            let mut body = writer.u16(1).to_vec();
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&writer.u32(0));
+            body.extend_from_slice(&writer.u16(OPTION_IF_TSOFFSET));
+            body.extend_from_slice(&writer.u16(8));
+            body.extend_from_slice(&offset.to_le_bytes());
+            body.extend_from_slice(&[0; 4]); // opt_endofopt
+            writer.block(INTERFACE_DESCRIPTION_BLOCK, &body);
+        }
+        writer.enhanced(0, 5_000_000, &[1]);
+        writer.enhanced(1, 5_000_000, &[2]);
+
+        let mut packets = PcapIterator::<&[u8]>::from_bytes(&writer.bytes).unwrap();
+        assert_eq!(packets.next().unwrap().unwrap().ts_sec, 3_605);
+        // Overflows rather than wrapping
+        let error = packets.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_multiple_sections_reset_interfaces() {
+        let mut writer = Writer {
+            big_endian: false,
+            bytes: Vec::new(),
+        };
+        writer.section();
+        writer.interface(1, None);
+        writer.enhanced(0, 0, &[1]);
+        writer.big_endian = true;
+        writer.section();
+        writer.interface(0, None);
+        writer.enhanced(0, 0, &[2]);
+        let mut simple = writer.u32(3).to_vec();
+        simple.extend_from_slice(&[3, 3, 3]);
+        writer.block(SIMPLE_PACKET_BLOCK, &simple);
+
+        let packets = read_all(&writer.bytes);
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|p| (p.link_type, p.data.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(1, vec![1]), (0, vec![2]), (0, vec![3, 3, 3])]
+        );
+    }
+
+    #[test]
+    fn test_unknown_interface_is_an_error() {
+        let mut writer = Writer {
+            big_endian: false,
+            bytes: Vec::new(),
+        };
+        writer.section();
+        writer.interface(1, None);
+        writer.enhanced(1, 0, &[1]);
+
+        let mut packets = PcapIterator::<&[u8]>::from_bytes(&writer.bytes).unwrap();
+        assert!(packets.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_mismatched_block_length_is_an_error() {
+        let mut writer = Writer {
+            big_endian: false,
+            bytes: Vec::new(),
+        };
+        writer.section();
+        writer.interface(1, None);
+        let len = writer.bytes.len();
+        writer.bytes[len - 4] ^= 0x04;
+
+        let mut packets = PcapIterator::<&[u8]>::from_bytes(&writer.bytes).unwrap();
+        assert!(packets.next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::message::Direction;
+
+use super::hooks::{HookStats, MessageHooks};
+use super::interceptor::Interceptor;
+use super::packet_parser::FragmentAssembler;
+use super::pcap::{Packet, PcapWriter};
+use super::raw_message::RawMessage;
+use super::reassembly::ReassemblyDiagnostics;
+use super::udp::{LINKTYPE_ETHERNET, UdpDatagram};
+
+/// How often relay threads wake up to check whether the proxy was shut down
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Largest datagram a relay will read. AC packets are far smaller.
+const MAX_DATAGRAM: usize = 65_535;
+
+/// A datagram the proxy relayed, addressed as if it had gone straight
+/// between the client and the server
+///
+/// The payload is what the proxy received, before any hooks rewrote it.
+#[derive(Debug, Clone)]
+pub struct ProxiedDatagram {
+    /// When the proxy received it, since the Unix epoch
+    pub time: Duration,
+    pub direction: Direction,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    /// The UDP payload, i.e. one or more AC packets
+    pub payload: Vec<u8>,
+}
+
+impl ProxiedDatagram {
+    pub fn udp(&self) -> UdpDatagram<'_> {
+        UdpDatagram {
+            source: self.source,
+            destination: self.destination,
+            payload: &self.payload,
+        }
+    }
+
+    /// The datagram as an Ethernet capture record, for writing to a pcap
+    pub fn to_packet(&self) -> io::Result<Packet> {
+        Ok(Packet {
+            ts_sec: self.time.as_secs() as u32,
+            ts_usec: self.time.subsec_micros(),
+            data: self.udp().to_ethernet_frame()?,
+            link_type: LINKTYPE_ETHERNET,
+        })
+    }
+}
+
+/// A UDP relay between a client and a server, reporting every datagram it
+/// passes along
+///
+/// Each route listens on a local address and forwards what arrives to its
+/// server from a socket of its own, sending the server's replies back to
+/// whichever client last sent something. AC servers take the ConnectResponse
+/// on the port after their main one, so a proxy for a real server wants a
+/// route for each. Only one client is relayed per route, and referrals to
+/// other servers aren't rewritten, so a client that gets handed on connects
+/// to the next server directly.
+///
+/// Relaying happens on background threads, which stop when the proxy is
+/// dropped.
+pub struct UdpProxy {
+    local_addrs: Vec<SocketAddr>,
+    servers: Vec<SocketAddr>,
+    datagrams: Receiver<io::Result<ProxiedDatagram>>,
+    hooks: Option<Arc<Mutex<MessageHooks>>>,
+    shutdown: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl UdpProxy {
+    /// Listen on each route's local address and relay to its server
+    pub fn bind(routes: &[(SocketAddr, SocketAddr)]) -> io::Result<Self> {
+        Self::bind_routes(routes, None)
+    }
+
+    /// Like `bind`, but pass every message through `hooks` before relaying
+    /// it, as an `Interceptor` for each direction of each route does
+    ///
+    /// Datagrams that can't be parsed are relayed unchanged.
+    pub fn bind_with_hooks(
+        routes: &[(SocketAddr, SocketAddr)],
+        hooks: MessageHooks,
+    ) -> io::Result<Self> {
+        Self::bind_routes(routes, Some(Arc::new(Mutex::new(hooks))))
+    }
+
+    fn bind_routes(
+        routes: &[(SocketAddr, SocketAddr)],
+        hooks: Option<Arc<Mutex<MessageHooks>>>,
+    ) -> io::Result<Self> {
+        let (sender, datagrams) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let mut proxy = Self {
+            local_addrs: Vec::new(),
+            servers: Vec::new(),
+            datagrams,
+            hooks,
+            shutdown,
+            threads: Vec::new(),
+        };
+
+        for &(listen, server) in routes {
+            let downstream = UdpSocket::bind(listen)?;
+            let unspecified: SocketAddr = match server {
+                SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+            };
+            let upstream = UdpSocket::bind(unspecified)?;
+            downstream.set_read_timeout(Some(POLL_INTERVAL))?;
+            upstream.set_read_timeout(Some(POLL_INTERVAL))?;
+            proxy.local_addrs.push(downstream.local_addr()?);
+            proxy.servers.push(server);
+
+            let client = Arc::new(Mutex::new(None));
+            let relay = Relay {
+                server,
+                client,
+                sender: sender.clone(),
+                hooks: proxy.hooks.clone(),
+                shutdown: proxy.shutdown.clone(),
+            };
+            let from_client = (downstream.try_clone()?, upstream.try_clone()?);
+            let from_server = (upstream, downstream);
+            let to_server = relay.clone();
+            proxy.threads.push(thread::spawn(move || {
+                to_server.run(Direction::ClientToServer, from_client.0, from_client.1)
+            }));
+            proxy.threads.push(thread::spawn(move || {
+                relay.run(Direction::ServerToClient, from_server.0, from_server.1)
+            }));
+        }
+        Ok(proxy)
+    }
+
+    /// The address each route is listening on, in the order they were given
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// The server each route relays to, in the order they were given
+    pub fn servers(&self) -> &[SocketAddr] {
+        &self.servers
+    }
+
+    /// What the hooks have done so far, if the proxy has any
+    pub fn hook_stats(&self) -> Option<HookStats> {
+        let hooks = self.hooks.as_ref()?;
+        Some(hooks.lock().unwrap().stats().clone())
+    }
+
+    /// Wait for the next relayed datagram. `None` once every relay has
+    /// stopped.
+    pub fn recv(&self) -> Option<io::Result<ProxiedDatagram>> {
+        self.datagrams.recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next relayed datagram
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<io::Result<ProxiedDatagram>> {
+        self.datagrams.recv_timeout(timeout).ok()
+    }
+
+    /// The next relayed datagram if there is one, without waiting
+    pub fn try_recv(&self) -> Option<io::Result<ProxiedDatagram>> {
+        self.datagrams.try_recv().ok()
+    }
+}
+
+impl Drop for UdpProxy {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// State shared by the two threads relaying one route
+#[derive(Clone)]
+struct Relay {
+    server: SocketAddr,
+    /// The client replies go to, learned from what arrives on the route's
+    /// local address
+    client: Arc<Mutex<Option<SocketAddr>>>,
+    sender: Sender<io::Result<ProxiedDatagram>>,
+    hooks: Option<Arc<Mutex<MessageHooks>>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Relay {
+    /// Read datagrams going one way off `from` and send them on with `to`
+    /// until the proxy shuts down
+    fn run(&self, direction: Direction, from: UdpSocket, to: UdpSocket) {
+        let mut buffer = vec![0u8; MAX_DATAGRAM];
+        let mut interceptor = Interceptor::new(direction);
+        while !self.shutdown.load(Ordering::Relaxed) {
+            let (length, peer) = match from.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if is_transient(&e) => continue,
+                Err(e) => {
+                    let _ = self.sender.send(Err(e));
+                    return;
+                }
+            };
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+
+            let (source, destination) = match direction {
+                Direction::ClientToServer => {
+                    *self.client.lock().unwrap() = Some(peer);
+                    (peer, self.server)
+                }
+                Direction::ServerToClient => {
+                    let client = *self.client.lock().unwrap();
+                    match client {
+                        Some(client) if peer == self.server => (peer, client),
+                        // Nothing to relay to, or not from our server
+                        _ => continue,
+                    }
+                }
+            };
+
+            let payload = buffer[..length].to_vec();
+            let rewritten = self.hooks.as_ref().and_then(|hooks| {
+                interceptor
+                    .rewrite(&payload, &mut hooks.lock().unwrap())
+                    .ok()
+            });
+            if let Err(e) = to.send_to(rewritten.as_deref().unwrap_or(&payload), destination)
+                && !is_transient(&e)
+            {
+                let _ = self.sender.send(Err(e));
+                return;
+            }
+            let datagram = ProxiedDatagram {
+                time,
+                direction,
+                source,
+                destination,
+                payload,
+            };
+            if self.sender.send(Ok(datagram)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Errors a relay can carry on after: read timeouts, and on Windows the ICMP
+/// port unreachable reported on the next read after a send to a closed port
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+    )
+}
+
+/// Reassembles messages from a proxy's traffic as it's relayed, optionally
+/// recording every datagram to a pcap
+///
+/// The live counterpart to `MessageStream`: iterating blocks until the next
+/// message completes, while `try_next` returns straight away for callers
+/// with something else to do, like redrawing a UI.
+pub struct LiveStream<W: Write = io::Sink> {
+    proxy: UdpProxy,
+    assembler: FragmentAssembler,
+    recorder: Option<PcapWriter<W>>,
+    /// Messages completed by the last datagram that haven't been yielded yet
+    ready: VecDeque<RawMessage>,
+}
+
+impl LiveStream {
+    pub fn new(proxy: UdpProxy) -> Self {
+        Self::with_recorder(proxy, None)
+    }
+}
+
+impl<W: Write> LiveStream<W> {
+    /// Stream messages while writing every relayed datagram to `recorder`.
+    /// The recorder must have been created with `LINKTYPE_ETHERNET`.
+    pub fn recording(proxy: UdpProxy, mut recorder: PcapWriter<W>) -> io::Result<Self> {
+        // Leave a valid, empty capture even if nothing is ever relayed
+        recorder.flush()?;
+        Ok(Self::with_recorder(proxy, Some(recorder)))
+    }
+
+    fn with_recorder(proxy: UdpProxy, recorder: Option<PcapWriter<W>>) -> Self {
+        let mut assembler = FragmentAssembler::new();
+        for &server in proxy.servers() {
+            assembler.add_server(server);
+        }
+        Self {
+            proxy,
+            assembler,
+            recorder,
+            ready: VecDeque::new(),
+        }
+    }
+
+    pub fn proxy(&self) -> &UdpProxy {
+        &self.proxy
+    }
+
+    /// Reassembly diagnostics for the datagrams relayed so far, in both
+    /// directions
+    pub fn diagnostics(&self) -> &ReassemblyDiagnostics {
+        self.assembler.diagnostics()
+    }
+
+    /// The next completed message if the datagrams relayed so far hold one,
+    /// without waiting for more
+    pub fn try_next(&mut self) -> Option<io::Result<RawMessage>> {
+        loop {
+            if let Some(message) = self.ready.pop_front() {
+                return Some(Ok(message));
+            }
+            match self.proxy.try_recv()? {
+                Ok(datagram) => {
+                    if let Err(e) = self.add(&datagram) {
+                        return Some(Err(e));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    fn add(&mut self, datagram: &ProxiedDatagram) -> io::Result<()> {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write_packet(&datagram.to_packet()?)?;
+            // Keep the file whole in case the process is killed
+            recorder.flush()?;
+        }
+        self.assembler.set_capture_time(datagram.time);
+        // A datagram that can't be parsed is counted in the diagnostics and
+        // mustn't end the stream; only socket and recording errors do
+        if let Ok(messages) = self.assembler.parse_datagram(&datagram.udp()) {
+            self.ready.extend(messages);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Iterator for LiveStream<W> {
+    type Item = io::Result<RawMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.ready.pop_front() {
+                return Some(Ok(message));
+            }
+            match self.proxy.recv()? {
+                Ok(datagram) => {
+                    if let Err(e) = self.add(&datagram) {
+                        return Some(Err(e));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::FragmentGroup;
+    use crate::network::PacketBuilder;
+    use crate::network::pcap::PcapIterator;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn loopback() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    #[test]
+    fn test_relays_both_directions() {
+        let server = UdpSocket::bind(loopback()).unwrap();
+        server.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let proxy = UdpProxy::bind(&[(loopback(), server.local_addr().unwrap())]).unwrap();
+        let client = UdpSocket::bind(loopback()).unwrap();
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        client.send_to(b"hello", proxy.local_addrs()[0]).unwrap();
+        let mut buffer = [0u8; 64];
+        let (length, upstream) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"hello");
+
+        server.send_to(b"welcome", upstream).unwrap();
+        let (length, from) = client.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"welcome");
+        assert_eq!(from, proxy.local_addrs()[0]);
+
+        let sent = proxy.recv_timeout(TIMEOUT).unwrap().unwrap();
+        assert_eq!(sent.direction, Direction::ClientToServer);
+        assert_eq!(sent.source, client.local_addr().unwrap());
+        assert_eq!(sent.destination, server.local_addr().unwrap());
+        assert_eq!(sent.payload, b"hello");
+
+        let received = proxy.recv_timeout(TIMEOUT).unwrap().unwrap();
+        assert_eq!(received.direction, Direction::ServerToClient);
+        assert_eq!(received.source, server.local_addr().unwrap());
+        assert_eq!(received.destination, client.local_addr().unwrap());
+        assert_eq!(received.payload, b"welcome");
+    }
+
+    #[test]
+    fn test_live_stream_reassembles_and_records() {
+        let server = UdpSocket::bind(loopback()).unwrap();
+        server.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let proxy = UdpProxy::bind(&[(loopback(), server.local_addr().unwrap())]).unwrap();
+        let listen = proxy.local_addrs()[0];
+        let recorder = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        let mut stream = LiveStream::recording(proxy, recorder).unwrap();
+        let client = UdpSocket::bind(loopback()).unwrap();
+
+        // Nothing relayed yet
+        assert!(stream.try_next().is_none());
+
+        // CommunicationTurbineChat, split over more packets than fit in one
+        let mut message = 0xF7DEu32.to_le_bytes().to_vec();
+        message.resize(1200, 0);
+        let mut builder = PacketBuilder::new();
+        builder
+            .queue_bytes(FragmentGroup::Private, &message)
+            .unwrap();
+        let packets = builder.build_all().unwrap();
+        assert!(packets.len() > 1);
+        for packet in &packets {
+            client.send_to(packet, listen).unwrap();
+            server.recv_from(&mut [0u8; 1024]).unwrap();
+        }
+
+        let message = stream.next().unwrap().unwrap();
+        assert_eq!(message.direction, Direction::ClientToServer);
+        assert_eq!(message.data.len(), 1200);
+        assert!(message.first_time.is_some());
+
+        let recording = stream.recorder.take().unwrap().into_inner();
+        let recorded: Vec<_> = PcapIterator::<&[u8]>::from_bytes(&recording)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(recorded.len(), packets.len());
+        let frame = UdpDatagram::from_frame(&recorded[0].data).unwrap();
+        assert_eq!(frame.source, client.local_addr().unwrap());
+        assert_eq!(frame.payload, &packets[0][..]);
+    }
+
+    #[test]
+    fn test_live_stream_skips_malformed_datagrams() {
+        let server = UdpSocket::bind(loopback()).unwrap();
+        server.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let proxy = UdpProxy::bind(&[(loopback(), server.local_addr().unwrap())]).unwrap();
+        let listen = proxy.local_addrs()[0];
+        let mut stream = LiveStream::new(proxy);
+        let client = UdpSocket::bind(loopback()).unwrap();
+
+        // Too short for a packet header
+        client.send_to(b"bad", listen).unwrap();
+        server.recv_from(&mut [0u8; 1024]).unwrap();
+
+        let mut builder = PacketBuilder::new();
+        builder
+            .queue_bytes(FragmentGroup::Private, &0xF7DEu32.to_le_bytes())
+            .unwrap();
+        for packet in builder.build_all().unwrap() {
+            client.send_to(&packet, listen).unwrap();
+            server.recv_from(&mut [0u8; 1024]).unwrap();
+        }
+
+        let message = stream.next().unwrap().unwrap();
+        assert_eq!(message.data, 0xF7DEu32.to_le_bytes());
+        assert_eq!(stream.diagnostics().malformed_datagrams, 1);
+    }
+}
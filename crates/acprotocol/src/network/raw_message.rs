@@ -1,10 +1,12 @@
 use crate::enums::MessageQueue;
 use crate::message::{Direction, MessageKind};
-use serde::Serialize;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 use std::io::{self, Cursor};
+use std::time::Duration;
 
 /// A raw message extracted from assembled fragments
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone)]
 pub struct RawMessage {
     /// Unique message ID
     pub id: u32,
@@ -12,62 +14,158 @@ pub struct RawMessage {
     pub opcode: u32,
     /// Human-readable message type name
     pub message_type: String,
-    /// Message direction (Send/Recv)
-    pub direction: String,
+    /// Message direction (serialized as Send/Recv)
+    pub direction: Direction,
     /// Queue this message belongs to
-    #[serde(skip_serializing_if = "Option::is_none")]
     pub queue: Option<MessageQueue>,
     /// Parsed message data as JSON, or raw hex if parsing fails
-    #[serde(serialize_with = "serialize_parsed_data")]
     pub data: Vec<u8>,
     /// Position in the message stream
     pub sequence: u32,
     /// Packet iteration counter (from AC packet header)
-    #[serde(skip_serializing_if = "Option::is_none")]
     pub iteration: Option<u16>,
     /// Packet header flags (Flow, ACK, etc.)
-    #[serde(skip_serializing_if = "Option::is_none")]
     pub header_flags: Option<u32>,
+    /// Capture time of the packet holding the first fragment to arrive, since
+    /// the Unix epoch
+    pub first_time: Option<Duration>,
+    /// Capture time of the packet that completed the message
+    pub last_time: Option<Duration>,
 }
 
-fn serialize_parsed_data<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    // Extract opcode from the data
-    if data.len() < 4 {
-        return serializer.serialize_str("invalid");
+impl Serialize for RawMessage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("RawMessage", 11)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("opcode", &self.opcode)?;
+        state.serialize_field("message_type", &self.message_type)?;
+        state.serialize_field("direction", self.direction())?;
+        match &self.queue {
+            Some(queue) => state.serialize_field("queue", queue)?,
+            None => state.skip_field("queue")?,
+        }
+        state.serialize_field(
+            "data",
+            &ParsedData {
+                data: &self.data,
+                direction: self.direction,
+            },
+        )?;
+        state.serialize_field("sequence", &self.sequence)?;
+        match &self.iteration {
+            Some(iteration) => state.serialize_field("iteration", iteration)?,
+            None => state.skip_field("iteration")?,
+        }
+        match &self.header_flags {
+            Some(header_flags) => state.serialize_field("header_flags", header_flags)?,
+            None => state.skip_field("header_flags")?,
+        }
+        match &self.first_time {
+            Some(time) => state.serialize_field("first_time", &format_capture_time(*time))?,
+            None => state.skip_field("first_time")?,
+        }
+        match &self.last_time {
+            Some(time) => state.serialize_field("last_time", &format_capture_time(*time))?,
+            None => state.skip_field("last_time")?,
+        }
+        state.end()
     }
+}
 
-    let opcode = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+/// Serializes message data as the parsed message, or error details if parsing fails
+struct ParsedData<'a> {
+    data: &'a [u8],
+    direction: Direction,
+}
 
-    // Determine direction based on opcode
-    let direction = determine_direction(opcode);
+impl Serialize for ParsedData<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let data = self.data;
 
-    // Try to parse the data as a structured message
-    // Note: MessageKind::read will read the opcode from the cursor, so start from beginning
-    let mut cursor = Cursor::new(data);
+        // Extract opcode from the data
+        if data.len() < 4 {
+            return serializer.serialize_str("invalid");
+        }
 
-    match MessageKind::read(&mut cursor, direction) {
-        Ok(message) => message.serialize(serializer),
-        Err(e) => {
-            // Serialize error information instead of panicking
-            use serde::ser::SerializeMap;
-            let mut map = serializer.serialize_map(Some(4))?;
-            map.serialize_entry("error", &format!("{}", e))?;
-            map.serialize_entry("opcode", &format!("0x{:04x}", opcode))?;
+        let opcode = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+
+        // Try to parse the data as a structured message
+        // Note: MessageKind::read will read the opcode from the cursor, so start from beginning
+        let mut cursor = Cursor::new(data);
 
-            // Add debug info
-            let pos = cursor.position() as usize;
-            map.serialize_entry("buffer_position", &pos)?;
-            map.serialize_entry("total_size", &data.len())?;
+        match MessageKind::read(&mut cursor, self.direction) {
+            Ok(message) => message.serialize(serializer),
+            Err(e) => {
+                // Serialize error information instead of panicking
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(Some(4))?;
+                map.serialize_entry("error", &format!("{}", e))?;
+                map.serialize_entry("opcode", &format!("0x{:04x}", opcode))?;
 
-            map.end()
+                // Add debug info
+                let pos = cursor.position() as usize;
+                map.serialize_entry("buffer_position", &pos)?;
+                map.serialize_entry("total_size", &data.len())?;
+
+                map.end()
+            }
         }
     }
 }
 
-fn determine_direction(opcode: u32) -> Direction {
+/// Serialize an optional capture time with `format_capture_time`, for use
+/// with `#[serde(serialize_with)]`
+pub(crate) fn serialize_capture_time<S: Serializer>(
+    time: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => serializer.serialize_str(&format_capture_time(*time)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Format a capture time as an RFC 3339 UTC timestamp with microseconds,
+/// e.g. `2025-11-18T18:24:51.123456Z`
+pub fn format_capture_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // Civil date from days since the epoch, counting years from March so
+    // leap days fall at the end (Howard Hinnant's civil_from_days)
+    let days = secs / 86400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{:06}Z",
+        time.subsec_micros()
+    )
+}
+
+/// Guess a message's direction from its opcode alone
+///
+/// This is only a fallback for when the transport doesn't tell us which side
+/// sent the message: several opcodes exist in both directions and unknown
+/// opcodes are assumed to be server to client.
+pub fn guess_direction(opcode: u32) -> Direction {
     use crate::enums::{C2SMessage, S2CMessage};
 
     if C2SMessage::try_from(opcode).is_ok() {
@@ -81,19 +179,14 @@ fn determine_direction(opcode: u32) -> Direction {
 }
 
 impl RawMessage {
-    /// Determine the direction enum for this message
-    fn determine_direction_enum(&self) -> Direction {
-        use crate::enums::C2SMessage;
-        if C2SMessage::try_from(self.opcode).is_ok() {
-            Direction::ClientToServer
-        } else {
-            Direction::ServerToClient
-        }
-    }
-
     /// Parse a message from assembled fragment data
-    pub fn from_fragment(data: Vec<u8>, sequence: u32, id: u32) -> io::Result<Self> {
-        Self::from_fragment_with_iteration(data, sequence, id, None, None)
+    pub fn from_fragment(
+        data: Vec<u8>,
+        sequence: u32,
+        id: u32,
+        direction: Direction,
+    ) -> io::Result<Self> {
+        Self::from_fragment_with_iteration(data, sequence, id, direction, None, None)
     }
 
     /// Parse a message from assembled fragment data with packet header info
@@ -101,6 +194,7 @@ impl RawMessage {
         data: Vec<u8>,
         sequence: u32,
         id: u32,
+        direction: Direction,
         iteration: Option<u16>,
         header_flags: Option<u32>,
     ) -> io::Result<Self> {
@@ -113,25 +207,26 @@ impl RawMessage {
 
         let opcode = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
 
-        // Create a temporary message to get type name and direction
+        // Create a temporary message to get the type name
         let message = Self {
             id,
             opcode,
             message_type: String::new(),
-            direction: String::new(),
+            direction,
             queue: None,
             data,
             sequence,
             iteration,
             header_flags,
+            first_time: None,
+            last_time: None,
         };
 
         let message_type = message.message_type_name();
-        let direction = message.direction().to_string();
 
         // Try to parse and get queue from the actual message
         let mut cursor = Cursor::new(&message.data);
-        let queue = MessageKind::read(&mut cursor, message.determine_direction_enum())
+        let queue = MessageKind::read(&mut cursor, direction)
             .ok()
             .and_then(|msg| msg.queue());
 
@@ -145,6 +240,8 @@ impl RawMessage {
             sequence: message.sequence,
             iteration: message.iteration,
             header_flags,
+            first_time: None,
+            last_time: None,
         })
     }
 
@@ -165,8 +262,10 @@ impl RawMessage {
 
         let payload = &self.data[4..]; // Skip the outer opcode
 
-        // Based on the outer opcode, interpret the inner payload
-        if let Ok(msg_type) = C2SMessage::try_from(self.opcode) {
+        // Based on the direction and outer opcode, interpret the inner payload
+        if self.direction == Direction::ClientToServer
+            && let Ok(msg_type) = C2SMessage::try_from(self.opcode)
+        {
             if msg_type == C2SMessage::OrderedGameAction && payload.len() >= 8 {
                 // For OrderedGameAction: [sequence (4)] [action_type (4)] [payload...]
                 let action_type_val =
@@ -180,7 +279,9 @@ impl RawMessage {
             return format!("{:?}", msg_type);
         }
 
-        if let Ok(msg_type) = S2CMessage::try_from(self.opcode) {
+        if self.direction == Direction::ServerToClient
+            && let Ok(msg_type) = S2CMessage::try_from(self.opcode)
+        {
             if msg_type == S2CMessage::OrderedGameEvent && payload.len() >= 12 {
                 // For OrderedGameEvent: [object_id (4)] [sequence (4)] [event_type (4)] [payload...]
                 let event_type_val =
@@ -199,14 +300,35 @@ impl RawMessage {
 
     /// Get the message direction (Send/Recv)
     pub fn direction(&self) -> &'static str {
-        use crate::generated::enums::{C2SMessage, S2CMessage};
-
-        if C2SMessage::try_from(self.opcode).is_ok() {
-            "Send"
-        } else if S2CMessage::try_from(self.opcode).is_ok() {
-            "Recv"
-        } else {
-            "Unknown"
+        match self.direction {
+            Direction::ClientToServer => "Send",
+            Direction::ServerToClient => "Recv",
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_capture_time() {
+        assert_eq!(
+            format_capture_time(Duration::ZERO),
+            "1970-01-01T00:00:00.000000Z"
+        );
+        assert_eq!(
+            format_capture_time(Duration::new(1_763_490_291, 123_456_789)),
+            "2025-11-18T18:24:51.123456Z"
+        );
+        // Leap day, and a century that isn't a leap year
+        assert_eq!(
+            format_capture_time(Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000000Z"
+        );
+        assert_eq!(
+            format_capture_time(Duration::from_secs(4_107_542_399)),
+            "2100-02-28T23:59:59.000000Z"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// How many dropped messages `ReassemblyDiagnostics` keeps the details of.
+/// Later ones are only counted.
+pub const MAX_DROPPED_DETAILS: usize = 1000;
+
+/// Why a message was given up on before all of its fragments arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum EvictionReason {
+    /// Too many packets went by without it completing
+    PacketAge,
+    /// Too much capture time went by without it completing
+    Time,
+    /// Pending fragments were using more memory than allowed
+    MemoryCap,
+    /// Still incomplete when the assembler was finished
+    EndOfCapture,
+}
+
+/// A message that was dropped before it could be reassembled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IncompleteMessage {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub sequence: u32,
+    pub id: u32,
+    pub group: u16,
+    /// Number of chunks the message was split into
+    pub count: u16,
+    /// Number of distinct chunks that actually arrived
+    pub received: u16,
+    pub reason: EvictionReason,
+}
+
+/// Capture-quality counters collected while reassembling fragments
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReassemblyDiagnostics {
+    /// Messages successfully reassembled
+    pub completed: u64,
+    /// Chunks received for an index that was already filled
+    pub duplicate_chunks: u64,
+    /// Chunks of messages that had already been reassembled, e.g. resent in a
+    /// retransmitted packet. These are expected on lossy links and ignored.
+    pub retransmitted_chunks: u64,
+    /// Chunks whose index or length didn't fit the message they claimed to be part of
+    pub out_of_range_chunks: u64,
+    /// Fragments that couldn't be read at all, e.g. truncated packets
+    pub malformed_fragments: u64,
+    /// Datagrams whose packet headers couldn't be read
+    pub malformed_datagrams: u64,
+    /// Packets whose checksum didn't match, i.e. corrupted or spoofed packets
+    pub invalid_checksums: u64,
+    /// Number of messages evicted before they were complete, by reason
+    pub dropped_counts: HashMap<EvictionReason, u64>,
+    /// The first `MAX_DROPPED_DETAILS` messages evicted before they were
+    /// complete
+    pub dropped: Vec<IncompleteMessage>,
+}
+
+impl ReassemblyDiagnostics {
+    /// Number of dropped messages for a given reason
+    pub fn dropped_for(&self, reason: EvictionReason) -> u64 {
+        self.dropped_counts.get(&reason).copied().unwrap_or(0)
+    }
+
+    /// Number of dropped messages for any reason
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped_counts.values().sum()
+    }
+
+    pub(crate) fn record_dropped(&mut self, message: IncompleteMessage) {
+        *self.dropped_counts.entry(message.reason).or_default() += 1;
+        if self.dropped.len() < MAX_DROPPED_DETAILS {
+            self.dropped.push(message);
+        }
+    }
+
+    /// Whether anything went wrong during reassembly
+    pub fn is_clean(&self) -> bool {
+        self.duplicate_chunks == 0
+            && self.out_of_range_chunks == 0
+            && self.malformed_fragments == 0
+            && self.malformed_datagrams == 0
+            && self.invalid_checksums == 0
+            && self.dropped_total() == 0
+    }
+}
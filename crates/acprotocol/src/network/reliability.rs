@@ -0,0 +1,416 @@
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::enums::PacketHeaderFlags;
+use crate::message::Direction;
+
+use super::checksum::ChecksumStatus;
+use super::packet_record::PacketRecord;
+use super::session_table::SessionTable;
+
+/// Gaps in a packet sequence wider than this are counted as lost without
+/// tracking each missing sequence, so a corrupt sequence number can't make
+/// the analyzer allocate without bound
+const MAX_TRACKED_GAP: u32 = 4096;
+
+/// Summary of how long something took, over a number of samples
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyStats {
+    pub samples: u64,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    pub total: Duration,
+}
+
+impl LatencyStats {
+    pub fn add(&mut self, sample: Duration) {
+        self.samples += 1;
+        self.min = Some(self.min.map_or(sample, |min| min.min(sample)));
+        self.max = Some(self.max.map_or(sample, |max| max.max(sample)));
+        self.total += sample;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.samples > 0).then(|| self.total / self.samples as u32)
+    }
+}
+
+/// Serialized as milliseconds, which is what anyone reading a report wants
+impl Serialize for LatencyStats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = |d: Option<Duration>| d.map(|d| d.as_secs_f64() * 1000.0);
+        let mut state = serializer.serialize_struct("LatencyStats", 4)?;
+        state.serialize_field("samples", &self.samples)?;
+        state.serialize_field("min_ms", &millis(self.min))?;
+        state.serialize_field("mean_ms", &millis(self.mean()))?;
+        state.serialize_field("max_ms", &millis(self.max))?;
+        state.end()
+    }
+}
+
+/// Reliability of the packets one side of a session sent
+///
+/// Only packets with an encrypted checksum take a sequence number of their
+/// own; bare ACKs and connection setup reuse the last one, so they aren't
+/// counted here. Requests and ACKs are the ones the other side sent back.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FlowReliability {
+    /// Sequenced packets, each counted once however often it was seen
+    pub packets: u64,
+    /// Packets resent with the RETRANSMISSION flag
+    pub retransmissions: u64,
+    /// Packets seen again without being flagged as retransmissions
+    pub duplicates: u64,
+    /// Packets that arrived after a later sequence without being resent
+    pub out_of_order: u64,
+    /// Sequences missing from the capture that a retransmission filled in
+    pub recovered: u64,
+    /// Sequences never seen in the capture, even as retransmissions
+    pub lost: u64,
+    /// Sequences the other side asked to have resent
+    pub retransmit_requests: u64,
+    /// Requested sequences that the capture had seen, i.e. lost somewhere
+    /// between the capture point and the other side
+    pub lost_after_capture: u64,
+    /// Requests this side answered with REJECT_RETRANSMIT
+    pub rejected_requests: u64,
+    /// Requests answered by neither a retransmission nor a rejection
+    pub unanswered_requests: u64,
+    /// ACKs the other side sent for this side's packets
+    pub acks: u64,
+    /// Time from a packet being captured to the ACK naming its sequence
+    pub ack_latency: LatencyStats,
+}
+
+/// Reliability of both directions of one session
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionReliability {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub client_to_server: FlowReliability,
+    pub server_to_client: FlowReliability,
+}
+
+/// Everything `ReliabilityAnalyzer` found in a capture
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReliabilityReport {
+    /// One entry per session, in the order they were first seen. A client
+    /// reconnecting from the same endpoint starts a new session.
+    pub sessions: Vec<SessionReliability>,
+    /// Packets left out because their direction couldn't be worked out
+    pub unknown_direction: u64,
+    /// Packets left out because their checksum didn't match
+    pub invalid_checksums: u64,
+}
+
+/// Tracking state for the packets one side sent
+#[derive(Debug, Default)]
+struct FlowState {
+    stats: FlowReliability,
+    highest: Option<u32>,
+    /// Sequences below `highest` that haven't been seen yet
+    missing: BTreeSet<u32>,
+    /// When each sequence not yet covered by an ACK was first captured
+    unacked: BTreeMap<u32, Option<Duration>>,
+    /// Sequences asked for that haven't been resent or rejected yet
+    requested: BTreeSet<u32>,
+}
+
+impl FlowState {
+    fn sent(&mut self, sequence: u32, retransmission: bool, time: Option<Duration>) {
+        if retransmission {
+            self.stats.retransmissions += 1;
+            self.requested.remove(&sequence);
+        }
+
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence);
+            self.stats.packets += 1;
+            self.unacked.insert(sequence, time);
+            return;
+        };
+
+        if sequence > highest {
+            let gap = sequence - highest - 1;
+            if gap > MAX_TRACKED_GAP {
+                self.stats.lost += gap as u64;
+            } else {
+                self.missing.extend(highest + 1..sequence);
+            }
+            self.highest = Some(sequence);
+        } else if self.missing.remove(&sequence) {
+            if retransmission {
+                self.stats.recovered += 1;
+            } else {
+                self.stats.out_of_order += 1;
+            }
+        } else {
+            if !retransmission {
+                self.stats.duplicates += 1;
+            }
+            return;
+        }
+
+        self.stats.packets += 1;
+        self.unacked.insert(sequence, time);
+    }
+
+    fn acked(&mut self, sequence: u32, time: Option<Duration>) {
+        self.stats.acks += 1;
+        if let (Some(Some(sent)), Some(time)) = (self.unacked.get(&sequence), time) {
+            self.stats.ack_latency.add(time.saturating_sub(*sent));
+        }
+        // ACKs are cumulative
+        self.unacked = self.unacked.split_off(&sequence.saturating_add(1));
+    }
+
+    fn retransmit_requested(&mut self, sequence: u32) {
+        self.stats.retransmit_requests += 1;
+        if self.highest.is_some_and(|highest| sequence <= highest)
+            && !self.missing.contains(&sequence)
+        {
+            self.stats.lost_after_capture += 1;
+        }
+        self.requested.insert(sequence);
+    }
+
+    fn rejected(&mut self, sequence: u32) {
+        if self.requested.remove(&sequence) {
+            self.stats.rejected_requests += 1;
+        }
+    }
+
+    fn finish(mut self) -> FlowReliability {
+        self.stats.lost += self.missing.len() as u64;
+        self.stats.unanswered_requests = self.requested.len() as u64;
+        self.stats
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    client_to_server: FlowState,
+    server_to_client: FlowState,
+}
+
+impl SessionState {
+    fn flows(&mut self, direction: Direction) -> (&mut FlowState, &mut FlowState) {
+        match direction {
+            Direction::ClientToServer => (&mut self.client_to_server, &mut self.server_to_client),
+            Direction::ServerToClient => (&mut self.server_to_client, &mut self.client_to_server),
+        }
+    }
+}
+
+/// Follows packet sequence numbers, ACKs and retransmissions per session
+///
+/// Feed it every packet record of a capture in order, along with the capture
+/// time, then call `finish` for the report. Packets whose direction isn't
+/// known or whose checksum is invalid can't be trusted and are only counted.
+#[derive(Debug, Default)]
+pub struct ReliabilityAnalyzer {
+    sessions: SessionTable<SessionState>,
+    unknown_direction: u64,
+    invalid_checksums: u64,
+}
+
+impl ReliabilityAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_packet(&mut self, packet: &PacketRecord, time: Option<Duration>) {
+        if packet.checksum == ChecksumStatus::Invalid {
+            self.invalid_checksums += 1;
+            return;
+        }
+        let (Some(direction), Some(session)) = (
+            packet.direction,
+            self.sessions.session(packet, SessionState::default),
+        ) else {
+            self.unknown_direction += 1;
+            return;
+        };
+
+        let flags = packet.header.flags;
+        let (sent, received) = session.flows(direction);
+
+        if flags.contains(PacketHeaderFlags::ENCRYPTED_CHECKSUM) {
+            sent.sent(
+                packet.header.sequence,
+                flags.contains(PacketHeaderFlags::RETRANSMISSION),
+                time,
+            );
+        }
+        if let Some(sequences) = &packet.optional.reject_sequences {
+            for &sequence in &sequences.list {
+                sent.rejected(sequence);
+            }
+        }
+        if let Some(sequence) = packet.optional.ack_sequence {
+            received.acked(sequence, time);
+        }
+        if let Some(sequences) = &packet.optional.retransmit_sequences {
+            for &sequence in &sequences.list {
+                received.retransmit_requested(sequence);
+            }
+        }
+    }
+
+    /// Close out every session and report on them
+    pub fn finish(self) -> ReliabilityReport {
+        ReliabilityReport {
+            sessions: self
+                .sessions
+                .into_sessions()
+                .map(|(client, server, session)| SessionReliability {
+                    client,
+                    server,
+                    client_to_server: session.client_to_server.finish(),
+                    server_to_client: session.server_to_client.finish(),
+                })
+                .collect(),
+            unknown_direction: self.unknown_direction,
+            invalid_checksums: self.invalid_checksums,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::PackableList;
+
+    const CLIENT: &str = "192.0.2.33:50000";
+    const SERVER: &str = "198.51.100.7:9000";
+
+    fn packet(direction: Direction, sequence: u32, flags: PacketHeaderFlags) -> PacketRecord {
+        let mut packet = PacketRecord::between(CLIENT, SERVER, direction, flags);
+        packet.header.sequence = sequence;
+        packet
+    }
+
+    fn sequenced(direction: Direction, sequence: u32) -> PacketRecord {
+        packet(
+            direction,
+            sequence,
+            PacketHeaderFlags::ENCRYPTED_CHECKSUM | PacketHeaderFlags::BLOB_FRAGMENTS,
+        )
+    }
+
+    fn ms(millis: u64) -> Option<Duration> {
+        Some(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn test_loss_and_recovery() {
+        let mut analyzer = ReliabilityAnalyzer::new();
+        for sequence in [10, 11, 13, 14, 17, 16] {
+            analyzer.add_packet(&sequenced(Direction::ServerToClient, sequence), None);
+        }
+        // Seen twice by the capture, e.g. on two interfaces
+        analyzer.add_packet(&sequenced(Direction::ServerToClient, 14), None);
+
+        let mut request = packet(
+            Direction::ClientToServer,
+            3,
+            PacketHeaderFlags::REQUEST_RETRANSMIT,
+        );
+        request.optional.retransmit_sequences = Some(PackableList {
+            count: 3,
+            list: vec![12, 13, 15],
+        });
+        analyzer.add_packet(&request, None);
+
+        let mut resent = sequenced(Direction::ServerToClient, 12);
+        resent.header.flags |= PacketHeaderFlags::RETRANSMISSION;
+        analyzer.add_packet(&resent, None);
+
+        let mut reject = packet(
+            Direction::ServerToClient,
+            17,
+            PacketHeaderFlags::REJECT_RETRANSMIT,
+        );
+        reject.optional.reject_sequences = Some(PackableList {
+            count: 1,
+            list: vec![13],
+        });
+        analyzer.add_packet(&reject, None);
+
+        let report = analyzer.finish();
+        assert_eq!(report.sessions.len(), 1);
+        let server = &report.sessions[0].server_to_client;
+        assert_eq!(server.packets, 7);
+        assert_eq!(server.duplicates, 1);
+        assert_eq!(server.out_of_order, 1);
+        assert_eq!(server.retransmissions, 1);
+        assert_eq!(server.recovered, 1);
+        assert_eq!(server.lost, 1);
+        assert_eq!(server.retransmit_requests, 3);
+        assert_eq!(server.lost_after_capture, 1);
+        assert_eq!(server.rejected_requests, 1);
+        assert_eq!(server.unanswered_requests, 1);
+        assert_eq!(report.sessions[0].client_to_server.packets, 0);
+    }
+
+    #[test]
+    fn test_ack_latency() {
+        let mut analyzer = ReliabilityAnalyzer::new();
+        analyzer.add_packet(&sequenced(Direction::ClientToServer, 1), ms(0));
+        analyzer.add_packet(&sequenced(Direction::ClientToServer, 2), ms(10));
+        analyzer.add_packet(&sequenced(Direction::ClientToServer, 3), ms(20));
+
+        let ack = |sequence| {
+            let mut ack = packet(
+                Direction::ServerToClient,
+                5,
+                PacketHeaderFlags::ACK_SEQUENCE,
+            );
+            ack.optional.ack_sequence = Some(sequence);
+            ack
+        };
+        analyzer.add_packet(&ack(2), ms(50));
+        // Already covered by the first ACK
+        analyzer.add_packet(&ack(1), ms(60));
+        analyzer.add_packet(&ack(3), ms(100));
+
+        let report = analyzer.finish();
+        let client = &report.sessions[0].client_to_server;
+        assert_eq!(client.acks, 3);
+        assert_eq!(client.ack_latency.samples, 2);
+        assert_eq!(client.ack_latency.min, ms(40));
+        assert_eq!(client.ack_latency.max, ms(80));
+        assert_eq!(client.ack_latency.mean(), ms(60));
+    }
+
+    #[test]
+    fn test_sessions_split_on_connect_and_untrusted_packets_skipped() {
+        let mut analyzer = ReliabilityAnalyzer::new();
+        analyzer.add_packet(&sequenced(Direction::ServerToClient, 100), None);
+        analyzer.add_packet(
+            &packet(
+                Direction::ServerToClient,
+                0,
+                PacketHeaderFlags::CONNECT_REQUEST,
+            ),
+            None,
+        );
+        analyzer.add_packet(&sequenced(Direction::ServerToClient, 1), None);
+
+        let mut unknown = sequenced(Direction::ServerToClient, 2);
+        unknown.direction = None;
+        analyzer.add_packet(&unknown, None);
+        let mut corrupt = sequenced(Direction::ServerToClient, 3);
+        corrupt.checksum = ChecksumStatus::Invalid;
+        analyzer.add_packet(&corrupt, None);
+
+        let report = analyzer.finish();
+        assert_eq!(report.sessions.len(), 2);
+        assert_eq!(report.sessions[1].server_to_client.packets, 1);
+        assert_eq!(report.sessions[1].server_to_client.lost, 0);
+        assert_eq!(report.unknown_direction, 1);
+        assert_eq!(report.invalid_checksums, 1);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::enums::PacketHeaderFlags;
+
+use super::packet_record::PacketRecord;
+
+/// Per-session state kept by the capture analyzers
+///
+/// Sessions are told apart by their client and server endpoints. A
+/// ConnectRequest starts a new session even on endpoints already seen, since
+/// clients often reconnect from the same port.
+#[derive(Debug)]
+pub(crate) struct SessionTable<T> {
+    sessions: Vec<(SocketAddr, SocketAddr, T)>,
+    /// Index into `sessions` of each client and server's current session
+    active: HashMap<(SocketAddr, SocketAddr), usize>,
+}
+
+impl<T> Default for SessionTable<T> {
+    fn default() -> Self {
+        Self {
+            sessions: Vec::new(),
+            active: HashMap::new(),
+        }
+    }
+}
+
+impl<T> SessionTable<T> {
+    /// State of the session `packet` belongs to, created with `new` if this
+    /// is the session's first packet. `None` if the packet's direction isn't
+    /// known.
+    pub(crate) fn session(
+        &mut self,
+        packet: &PacketRecord,
+        new: impl FnOnce() -> T,
+    ) -> Option<&mut T> {
+        let index = self.session_index(packet, new)?;
+        Some(&mut self.sessions[index].2)
+    }
+
+    /// Like `session`, but returns the session's index for `get_mut`, so
+    /// callers can come back to it without the packet starting another one
+    pub(crate) fn session_index(
+        &mut self,
+        packet: &PacketRecord,
+        new: impl FnOnce() -> T,
+    ) -> Option<usize> {
+        let key = packet.client_and_server()?;
+        let reconnect = packet
+            .header
+            .flags
+            .contains(PacketHeaderFlags::CONNECT_REQUEST);
+
+        let index = match self.active.get(&key) {
+            Some(&index) if !reconnect => index,
+            _ => {
+                self.sessions.push((key.0, key.1, new()));
+                self.active.insert(key, self.sessions.len() - 1);
+                self.sessions.len() - 1
+            }
+        };
+        Some(index)
+    }
+
+    /// State of the session at `index`, as returned by `session_index`
+    pub(crate) fn get_mut(&mut self, index: usize) -> &mut T {
+        &mut self.sessions[index].2
+    }
+
+    /// Every session's client, server and state, in the order they started
+    pub(crate) fn into_sessions(self) -> impl Iterator<Item = (SocketAddr, SocketAddr, T)> {
+        self.sessions.into_iter()
+    }
+}
@@ -0,0 +1,529 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::enums::PacketHeaderFlags;
+use crate::message::{C2SMessage, Direction, MessageKind, S2CMessage};
+use crate::types::{CharacterIdentity, LoginRequestHeader, SocketAddress};
+
+use super::checksum::ChecksumStatus;
+use super::packet_record::{PacketRecord, ParsedDatagram};
+use super::raw_message::{RawMessage, serialize_capture_time};
+
+/// Referrals not yet followed by a connection to the server they point at.
+/// Older ones are dropped past this many.
+const MAX_PENDING_REFERRALS: usize = 64;
+
+/// `AF_INET` as it appears in a `SocketAddress`
+const AF_INET: i16 = 2;
+
+/// How a connection came to belong to its logical session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ConnectionOrigin {
+    /// Opened by the client logging in
+    Login,
+    /// Opened with a WorldLoginRequest carrying a cookie a previous server
+    /// handed out in a referral
+    Referral,
+    /// Opened with a ServerSwitch to a server a previous one referred the
+    /// client to
+    ServerSwitch,
+    /// Already open when the capture started, or opened without anything
+    /// tying it to an earlier connection
+    Unknown,
+}
+
+/// One client/server connection within a logical session
+#[derive(Debug, Clone, Serialize)]
+pub struct Connection {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub origin: ConnectionOrigin,
+    /// Cookie from the referral that led here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<u64>,
+    /// IdServer from the referral that led here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_server: Option<u16>,
+    pub packets: u64,
+    pub messages: u64,
+    /// Capture time of the connection's first packet
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_capture_time"
+    )]
+    pub first_time: Option<Duration>,
+    /// Capture time of the connection's last packet
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_capture_time"
+    )]
+    pub last_time: Option<Duration>,
+}
+
+/// A client's time in the game, across every server it was handed between
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LogicalSession {
+    /// Account from the login request or character list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Character the client entered the world with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub character_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub character_name: Option<String>,
+    /// Every connection in the session, in the order they were opened
+    pub connections: Vec<Connection>,
+    /// Id of the session's first message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_message_id: Option<u32>,
+    /// Id of the session's last message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_message_id: Option<u32>,
+    /// Characters on the account, from the character list
+    #[serde(skip)]
+    characters: Vec<CharacterIdentity>,
+}
+
+impl LogicalSession {
+    /// The connection currently in use, which is the last one opened
+    pub fn current_connection(&self) -> Option<&Connection> {
+        self.connections.last()
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.connections.iter().map(|c| c.messages).sum()
+    }
+
+    fn connection(&mut self, client: SocketAddr, server: SocketAddr) -> Option<&mut Connection> {
+        self.connections
+            .iter_mut()
+            .rev()
+            .find(|c| c.client == client && c.server == server)
+    }
+
+    fn entered_world(&mut self, character_id: u32) {
+        self.character_id = Some(character_id);
+        self.character_name = self
+            .characters
+            .iter()
+            .find(|c| c.character_id.0 == character_id)
+            .map(|c| c.name.clone());
+    }
+}
+
+/// A referral a server sent that no connection has followed yet
+#[derive(Debug)]
+struct PendingReferral {
+    session: usize,
+    client: IpAddr,
+    cookie: u64,
+    server: Option<SocketAddr>,
+    id_server: u16,
+}
+
+/// Follows clients as they're handed from the login server to world servers
+/// and between world servers, stitching each client's connections into one
+/// `LogicalSession`
+///
+/// A LoginRequest starts a new logical session, unless it's resent on a
+/// connection whose login is still in progress. Servers pass clients on with
+/// a Referral header holding a cookie and the next server's address; the
+/// client's WorldLoginRequest to that server quotes the cookie, and a
+/// ServerSwitch to it names the server, either of which joins the new
+/// connection to the session that was referred. Connections nothing ties to
+/// an earlier one start their own session.
+///
+/// Feed it every parsed datagram of a capture in order, along with its
+/// capture time. Packets of unknown direction or with invalid checksums are
+/// left out.
+#[derive(Debug, Default)]
+pub struct SessionTracker {
+    sessions: Vec<LogicalSession>,
+    /// Index into `sessions` of each connection's session
+    connections: HashMap<(SocketAddr, SocketAddr), usize>,
+    /// Sessions started by a LoginRequest on connections that haven't been
+    /// disconnected or referred elsewhere yet, which a resent request joins
+    logins: HashMap<(SocketAddr, SocketAddr), usize>,
+    referrals: VecDeque<PendingReferral>,
+}
+
+impl SessionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a datagram's packets and the messages they completed, returning
+    /// the index of the logical session they belong to
+    pub fn add_datagram(
+        &mut self,
+        datagram: &ParsedDatagram,
+        time: Option<Duration>,
+    ) -> Option<usize> {
+        let mut index = None;
+        for packet in &datagram.packets {
+            index = self.add_packet(packet, time).or(index);
+        }
+
+        // Every packet in a datagram shares its endpoints, so the messages
+        // completed in it belong to the same session
+        let (client, server) = datagram.packets.first()?.client_and_server()?;
+        let index = index.or_else(|| self.connections.get(&(client, server)).copied())?;
+        let session = &mut self.sessions[index];
+        for message in &datagram.messages {
+            session.first_message_id.get_or_insert(message.id);
+            session.last_message_id = Some(message.id);
+            if let Some(connection) = session.connection(client, server) {
+                connection.messages += 1;
+            }
+            Self::add_message(session, message);
+        }
+        Some(index)
+    }
+
+    /// Track a single packet, returning the index of the logical session it
+    /// belongs to
+    pub fn add_packet(&mut self, packet: &PacketRecord, time: Option<Duration>) -> Option<usize> {
+        if packet.checksum == ChecksumStatus::Invalid {
+            return None;
+        }
+        let (client, server) = packet.client_and_server()?;
+        let optional = &packet.optional;
+
+        let index = if let Some(request) = &optional.login_request {
+            let account = match request {
+                LoginRequestHeader::Type2(request) => &request.account,
+                LoginRequestHeader::Type40000002(request) => &request.account,
+            };
+            let index = match self.logins.get(&(client, server)) {
+                Some(&index) => index,
+                None => {
+                    let index = self.open(client, server, None, ConnectionOrigin::Login, time);
+                    self.logins.insert((client, server), index);
+                    index
+                }
+            };
+            self.sessions[index].account = Some(account.clone());
+            index
+        } else if let Some(referral) = optional
+            .world_login_request
+            .and_then(|cookie| self.take_referral(|r| r.cookie == cookie))
+        {
+            self.open(
+                client,
+                server,
+                Some(referral),
+                ConnectionOrigin::Referral,
+                time,
+            )
+        } else if let Some(&index) = self.connections.get(&(client, server)) {
+            index
+        } else if let Some(referral) = optional.server_switch.as_ref().and_then(|_| {
+            // Prefer a referral naming this server, but fall back on the
+            // client's latest in case the address was left out
+            self.take_referral(|r| r.client == client.ip() && r.server == Some(server))
+                .or_else(|| self.take_referral(|r| r.client == client.ip()))
+        }) {
+            self.open(
+                client,
+                server,
+                Some(referral),
+                ConnectionOrigin::ServerSwitch,
+                time,
+            )
+        } else {
+            self.open(client, server, None, ConnectionOrigin::Unknown, time)
+        };
+
+        if let (Some(referral), Direction::ServerToClient) = (&optional.referral, packet.direction?)
+        {
+            self.referrals.push_back(PendingReferral {
+                session: index,
+                client: client.ip(),
+                cookie: referral.cookie,
+                server: socket_addr(&referral.address),
+                id_server: referral.id_server,
+            });
+            if self.referrals.len() > MAX_PENDING_REFERRALS {
+                self.referrals.pop_front();
+            }
+            self.logins.remove(&(client, server));
+        }
+        if packet
+            .header
+            .flags
+            .intersects(PacketHeaderFlags::DISCONNECT | PacketHeaderFlags::NET_ERROR_DISCONNECT)
+        {
+            self.logins.remove(&(client, server));
+        }
+
+        if let Some(connection) = self.sessions[index].connection(client, server) {
+            connection.packets += 1;
+            if let Some(time) = time {
+                connection.first_time.get_or_insert(time);
+                connection.last_time = Some(time);
+            }
+        }
+        Some(index)
+    }
+
+    /// Index of the logical session a connection currently belongs to
+    pub fn session_of(&self, client: SocketAddr, server: SocketAddr) -> Option<usize> {
+        self.connections.get(&(client, server)).copied()
+    }
+
+    /// Every logical session so far, in the order they started
+    pub fn sessions(&self) -> &[LogicalSession] {
+        &self.sessions
+    }
+
+    pub fn into_sessions(self) -> Vec<LogicalSession> {
+        self.sessions
+    }
+
+    /// Add a connection to the referred session, or to a new one
+    fn open(
+        &mut self,
+        client: SocketAddr,
+        server: SocketAddr,
+        referral: Option<PendingReferral>,
+        origin: ConnectionOrigin,
+        time: Option<Duration>,
+    ) -> usize {
+        let index = match &referral {
+            Some(referral) => referral.session,
+            None => {
+                self.sessions.push(LogicalSession::default());
+                self.sessions.len() - 1
+            }
+        };
+        self.sessions[index].connections.push(Connection {
+            client,
+            server,
+            origin,
+            cookie: referral.as_ref().map(|r| r.cookie),
+            id_server: referral.as_ref().map(|r| r.id_server),
+            packets: 0,
+            messages: 0,
+            first_time: time,
+            last_time: time,
+        });
+        self.connections.insert((client, server), index);
+        index
+    }
+
+    fn take_referral(
+        &mut self,
+        matches: impl Fn(&PendingReferral) -> bool,
+    ) -> Option<PendingReferral> {
+        let position = self.referrals.iter().rposition(matches)?;
+        self.referrals.remove(position)
+    }
+
+    /// Pick the account and character out of the login messages
+    fn add_message(session: &mut LogicalSession, message: &RawMessage) {
+        let is_login = match message.direction {
+            Direction::ClientToServer => {
+                message.opcode == crate::enums::C2SMessage::LoginSendEnterWorld as u32
+            }
+            Direction::ServerToClient => {
+                message.opcode == crate::enums::S2CMessage::LoginLoginCharacterSet as u32
+            }
+        };
+        if !is_login {
+            return;
+        }
+
+        match MessageKind::read(&mut Cursor::new(&message.data), message.direction) {
+            Ok(MessageKind::C2S(message)) => {
+                if let C2SMessage::LoginSendEnterWorld(message) = *message {
+                    session.entered_world(message.character_id.0);
+                }
+            }
+            Ok(MessageKind::S2C(message)) => {
+                if let S2CMessage::LoginLoginCharacterSet(message) = *message {
+                    session.account.get_or_insert(message.account);
+                    session.characters = message.characters.list;
+                    if let Some(character_id) = session.character_id {
+                        session.entered_world(character_id);
+                    }
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// The address in a `SocketAddress`, if it's IPv4
+fn socket_addr(address: &SocketAddress) -> Option<SocketAddr> {
+    if address.family != AF_INET {
+        return None;
+    }
+    // sin_port and sin_addr are in network byte order, which reads back
+    // little-endian
+    let ip = Ipv4Addr::from(address.address.to_le_bytes());
+    let port = u16::from_be_bytes(address.port.to_le_bytes());
+    Some(SocketAddr::from((ip, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{AuthFlags, ServerSwitchType};
+    use crate::types::{LoginRequestHeaderType2, ReferralHeader, ServerSwitchHeader, WString};
+
+    const CLIENT: &str = "192.0.2.33:50000";
+    const LOGIN: &str = "198.51.100.7:9000";
+    const WORLD: &str = "198.51.100.8:9010";
+
+    fn packet(client: &str, server: &str, direction: Direction) -> PacketRecord {
+        PacketRecord::between(
+            client,
+            server,
+            direction,
+            PacketHeaderFlags::ENCRYPTED_CHECKSUM,
+        )
+    }
+
+    fn login(account: &str) -> PacketRecord {
+        let mut packet = packet(CLIENT, LOGIN, Direction::ClientToServer);
+        packet.optional.login_request = Some(LoginRequestHeader::Type2(LoginRequestHeaderType2 {
+            client_version: "1802".to_string(),
+            length: 0,
+            flags: AuthFlags::None,
+            sequence: 1,
+            account: account.to_string(),
+            account_to_login_as: String::new(),
+            password: WString(String::new()),
+        }));
+        packet
+    }
+
+    fn referral(cookie: u64, to: &str) -> PacketRecord {
+        let to: SocketAddr = to.parse().unwrap();
+        let SocketAddr::V4(to) = to else {
+            unreachable!()
+        };
+        let mut packet = packet(CLIENT, LOGIN, Direction::ServerToClient);
+        packet.optional.referral = Some(ReferralHeader {
+            cookie,
+            address: SocketAddress {
+                family: AF_INET,
+                port: u16::from_le_bytes(to.port().to_be_bytes()),
+                address: u32::from_le_bytes(to.ip().octets()),
+                empty: 0,
+            },
+            id_server: 3,
+            unknown: 0,
+        });
+        packet
+    }
+
+    #[test]
+    fn test_referral_cookie_joins_world_connection() {
+        let mut tracker = SessionTracker::new();
+        assert_eq!(tracker.add_packet(&login("account"), None), Some(0));
+        tracker.add_packet(&referral(0x1234, WORLD), None);
+
+        // Another client referred at the same time doesn't get mixed in
+        let mut other = referral(0x9999, WORLD);
+        other.destination = "192.0.2.44:50000".parse().unwrap();
+        tracker.add_packet(&other, None);
+
+        let mut world_login = packet(CLIENT, WORLD, Direction::ClientToServer);
+        world_login.optional.world_login_request = Some(0x1234);
+        assert_eq!(tracker.add_packet(&world_login, None), Some(0));
+        let reply = packet(CLIENT, WORLD, Direction::ServerToClient);
+        assert_eq!(tracker.add_packet(&reply, None), Some(0));
+
+        let sessions = tracker.sessions();
+        assert_eq!(sessions.len(), 2);
+        let session = &sessions[0];
+        assert_eq!(session.account.as_deref(), Some("account"));
+        assert_eq!(session.connections.len(), 2);
+        let world = session.current_connection().unwrap();
+        assert_eq!(world.server, WORLD.parse().unwrap());
+        assert_eq!(world.origin, ConnectionOrigin::Referral);
+        assert_eq!((world.cookie, world.id_server), (Some(0x1234), Some(3)));
+        assert_eq!(world.packets, 2);
+    }
+
+    #[test]
+    fn test_server_switch_joins_referred_server() {
+        let mut tracker = SessionTracker::new();
+        tracker.add_packet(&login("account"), None);
+        tracker.add_packet(&referral(7, WORLD), None);
+
+        let mut switch = packet("192.0.2.33:50001", WORLD, Direction::ClientToServer);
+        switch.optional.server_switch = Some(ServerSwitchHeader {
+            sequence: 2,
+            type_: ServerSwitchType::World,
+        });
+        assert_eq!(tracker.add_packet(&switch, None), Some(0));
+        assert_eq!(
+            tracker.sessions()[0].current_connection().unwrap().origin,
+            ConnectionOrigin::ServerSwitch
+        );
+
+        // Once followed, the referral can't be followed again
+        let mut world_login = packet("192.0.2.33:50002", WORLD, Direction::ClientToServer);
+        world_login.optional.world_login_request = Some(7);
+        assert_eq!(tracker.add_packet(&world_login, None), Some(1));
+        assert_eq!(
+            tracker.sessions()[1].connections[0].origin,
+            ConnectionOrigin::Unknown
+        );
+    }
+
+    #[test]
+    fn test_resent_login_joins_its_session() {
+        let mut tracker = SessionTracker::new();
+        assert_eq!(tracker.add_packet(&login("account"), None), Some(0));
+        assert_eq!(tracker.add_packet(&login("account"), None), Some(0));
+        assert_eq!(tracker.sessions().len(), 1);
+        let session = &tracker.sessions()[0];
+        assert_eq!(session.connections.len(), 1);
+        assert_eq!(session.connections[0].packets, 2);
+
+        // Once referred away, a login on the same connection is a new one
+        tracker.add_packet(&referral(0x1234, WORLD), None);
+        assert_eq!(tracker.add_packet(&login("account"), None), Some(1));
+
+        // As it is after a disconnect
+        let mut disconnect = packet(CLIENT, LOGIN, Direction::ClientToServer);
+        disconnect.header.flags |= PacketHeaderFlags::DISCONNECT;
+        assert_eq!(tracker.add_packet(&disconnect, None), Some(1));
+        assert_eq!(tracker.add_packet(&login("account"), None), Some(2));
+        assert_eq!(tracker.sessions().len(), 3);
+    }
+
+    #[test]
+    fn test_new_login_starts_new_session() {
+        let mut tracker = SessionTracker::new();
+        let data = packet(CLIENT, LOGIN, Direction::ServerToClient);
+        assert_eq!(tracker.add_packet(&data, None), Some(0));
+        assert_eq!(tracker.add_packet(&login("account"), None), Some(1));
+        assert_eq!(tracker.add_packet(&data, None), Some(1));
+        assert_eq!(
+            tracker.session_of(CLIENT.parse().unwrap(), LOGIN.parse().unwrap()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_socket_address_byte_order() {
+        let SocketAddr::V4(address) = WORLD.parse().unwrap() else {
+            unreachable!()
+        };
+        let mut bytes = vec![0x02, 0x00];
+        bytes.extend(address.port().to_be_bytes());
+        bytes.extend(address.ip().octets());
+        bytes.extend([0; 8]);
+        let address =
+            <SocketAddress as crate::readers::ACDataType>::read(&mut Cursor::new(&bytes[..]))
+                .unwrap();
+        assert_eq!(socket_addr(&address), Some(WORLD.parse().unwrap()));
+    }
+}
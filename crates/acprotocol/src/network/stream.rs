@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use super::packet_parser::FragmentAssembler;
+use super::packet_record::ParsedDatagram;
+use super::pcap::PcapIterator;
+use super::raw_message::RawMessage;
+use super::reassembly::ReassemblyDiagnostics;
+
+/// Lazily parses the datagrams in a capture into packet records and
+/// completed messages, each with its capture time
+///
+/// A datagram without a single readable packet is skipped and counted in
+/// `skipped`, as well as in the diagnostics, and comes out as an empty
+/// `ParsedDatagram` like a frame that isn't UDP does, so there is still one
+/// item per captured frame. Only errors reading the capture itself end the
+/// stream.
+pub struct DatagramStream<R: Read> {
+    packets: PcapIterator<R>,
+    assembler: FragmentAssembler,
+    skipped: usize,
+}
+
+impl<R: Read> DatagramStream<R> {
+    pub fn new(packets: PcapIterator<R>) -> Self {
+        Self::with_assembler(packets, FragmentAssembler::new())
+    }
+
+    /// Stream datagrams using an assembler configured by the caller
+    pub fn with_assembler(packets: PcapIterator<R>, assembler: FragmentAssembler) -> Self {
+        Self {
+            packets,
+            assembler,
+            skipped: 0,
+        }
+    }
+
+    pub fn assembler(&self) -> &FragmentAssembler {
+        &self.assembler
+    }
+
+    /// Reassembly diagnostics for the datagrams read so far, skipped ones
+    /// included
+    pub fn diagnostics(&self) -> &ReassemblyDiagnostics {
+        self.assembler.diagnostics()
+    }
+
+    /// Datagrams skipped so far because none of their packets could be read
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Stop streaming and take back the assembler
+    pub fn into_assembler(self) -> FragmentAssembler {
+        self.assembler
+    }
+}
+
+impl<R: Read> Iterator for DatagramStream<R> {
+    type Item = io::Result<(ParsedDatagram, Duration)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let packet = match self.packets.next()? {
+            Ok(packet) => packet,
+            Err(e) => return Some(Err(e)),
+        };
+        let time = packet.time();
+        self.assembler.set_capture_time(time);
+        let parsed = self
+            .assembler
+            .parse_link_packets(packet.link_type, &packet.data)
+            .unwrap_or_else(|_| {
+                self.skipped += 1;
+                ParsedDatagram::default()
+            });
+        Some(Ok((parsed, time)))
+    }
+}
+
+/// Lazily reassembles the messages in a capture
+///
+/// Packets are read from the `PcapIterator` only as messages are asked for,
+/// so memory use is bounded by the assembler's limits on pending fragments
+/// rather than by the size of the capture. Once the capture runs out the
+/// assembler is finished, so messages still incomplete at that point show up
+/// in `diagnostics` as dropped. Malformed datagrams are skipped as in
+/// `DatagramStream`.
+pub struct MessageStream<R: Read> {
+    datagrams: DatagramStream<R>,
+    /// Messages completed by the last packet that haven't been yielded yet
+    ready: VecDeque<RawMessage>,
+    finished: bool,
+}
+
+impl<R: Read> MessageStream<R> {
+    pub fn new(packets: PcapIterator<R>) -> Self {
+        Self::with_assembler(packets, FragmentAssembler::new())
+    }
+
+    /// Stream messages using an assembler configured by the caller
+    pub fn with_assembler(packets: PcapIterator<R>, assembler: FragmentAssembler) -> Self {
+        Self {
+            datagrams: DatagramStream::with_assembler(packets, assembler),
+            ready: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Reassembly diagnostics, which also count the messages still
+    /// incomplete when the capture ran out as dropped
+    pub fn diagnostics(&self) -> &ReassemblyDiagnostics {
+        self.datagrams.diagnostics()
+    }
+
+    /// Datagrams skipped so far because none of their packets could be read
+    pub fn skipped(&self) -> usize {
+        self.datagrams.skipped()
+    }
+
+    /// Stop streaming and take back the assembler
+    pub fn into_assembler(self) -> FragmentAssembler {
+        self.datagrams.into_assembler()
+    }
+}
+
+impl<R: Read> Iterator for MessageStream<R> {
+    type Item = io::Result<RawMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.ready.pop_front() {
+                return Some(Ok(message));
+            }
+            if self.finished {
+                return None;
+            }
+
+            match self.datagrams.next() {
+                Some(Ok((parsed, _))) => self.ready.extend(parsed.messages),
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.datagrams.assembler.finish();
+                    self.finished = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::FragmentGroup;
+    use crate::network::{EvictionReason, FRAGMENT_CHUNK_SIZE, PacketBuilder};
+
+    /// Build a little-endian pcap of Ethernet/IPv4/UDP frames from the server
+    fn pcap(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        file.extend_from_slice(&[2, 0, 4, 0]);
+        file.extend_from_slice(&[0u8; 8]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&1u32.to_le_bytes());
+
+        for (i, packet) in packets.iter().enumerate() {
+            let mut frame = vec![0u8; 14];
+            frame[12] = 0x08;
+            frame.extend_from_slice(&[
+                0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00,
+                0x00, // IPv4
+                10, 0, 0, 100, // source
+                10, 0, 0, 1, // destination
+            ]);
+            frame.extend_from_slice(&9000u16.to_be_bytes());
+            frame.extend_from_slice(&50000u16.to_be_bytes());
+            frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+            frame.extend_from_slice(packet);
+
+            file.extend_from_slice(&(i as u32).to_le_bytes());
+            file.extend_from_slice(&0u32.to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&frame);
+        }
+        file
+    }
+
+    #[test]
+    fn test_stream_yields_messages_lazily() {
+        let mut builder = PacketBuilder::new();
+        let mut packets = Vec::new();
+        for _ in 0..3 {
+            builder
+                .queue_bytes(FragmentGroup::Private, &[0xDE, 0xF7, 0x00, 0x00])
+                .unwrap();
+            packets.push(builder.build_packet().unwrap().unwrap());
+        }
+        // Only the first chunk of a message that never completes
+        let mut partial = PacketBuilder::new().with_sequences(10, 10);
+        partial
+            .queue_bytes(FragmentGroup::Private, &[0u8; FRAGMENT_CHUNK_SIZE + 1])
+            .unwrap();
+        packets.push(partial.build_packet().unwrap().unwrap());
+
+        let capture = pcap(&packets);
+        let mut stream = MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap());
+
+        let first = stream.next().unwrap().unwrap();
+        assert_eq!(first.opcode, 0xF7DE);
+        // Only the packets needed for the first message have been read
+        assert_eq!(stream.diagnostics().completed, 1);
+        assert!(stream.diagnostics().dropped.is_empty());
+
+        assert_eq!(stream.by_ref().count(), 2);
+        assert_eq!(
+            stream
+                .diagnostics()
+                .dropped_for(EvictionReason::EndOfCapture),
+            1
+        );
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_reports_truncated_capture() {
+        let mut builder = PacketBuilder::new();
+        builder
+            .queue_bytes(FragmentGroup::Private, &[0xDE, 0xF7, 0x00, 0x00])
+            .unwrap();
+        let mut capture = pcap(&builder.build_all().unwrap());
+        capture.extend_from_slice(&[0u8; 16]);
+        capture.extend_from_slice(&[0xFF; 4]);
+        // Record header claims more data than is left
+        let len = capture.len();
+        capture[len - 12..len - 8].copy_from_slice(&100u32.to_le_bytes());
+
+        let results: Vec<_> =
+            MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap()).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn test_malformed_datagram_is_skipped() {
+        let mut builder = PacketBuilder::new();
+        let mut packets = Vec::new();
+        for _ in 0..2 {
+            builder
+                .queue_bytes(FragmentGroup::Private, &[0xDE, 0xF7, 0x00, 0x00])
+                .unwrap();
+            packets.push(builder.build_packet().unwrap().unwrap());
+        }
+        // Too short for a packet header
+        packets.insert(1, vec![0xFF; 3]);
+
+        let capture = pcap(&packets);
+        let mut datagrams =
+            DatagramStream::new(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap());
+        let parsed: Vec<_> = datagrams.by_ref().map(Result::unwrap).collect();
+        assert_eq!(parsed.len(), 3);
+        assert!(parsed[1].0.packets.is_empty());
+        assert_eq!(parsed[2].1, Duration::from_secs(2));
+        assert_eq!(datagrams.skipped(), 1);
+
+        let mut stream = MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap());
+        assert_eq!(stream.by_ref().map(Result::unwrap).count(), 2);
+        assert_eq!(stream.skipped(), 1);
+        assert_eq!(stream.diagnostics().malformed_datagrams, 1);
+    }
+}
@@ -0,0 +1,418 @@
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::message::Direction;
+
+use super::checksum::ChecksumStatus;
+use super::packet_record::PacketRecord;
+use super::raw_message::format_capture_time;
+use super::reliability::LatencyStats;
+use super::session_table::SessionTable;
+
+/// One echo request and the response to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RttSample {
+    /// Capture time of the request
+    pub time: Duration,
+    /// Capture time from the request to its response. Captures are usually
+    /// taken on the client, making this the round trip the player sees.
+    pub rtt: Duration,
+}
+
+impl Serialize for RttSample {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RttSample", 2)?;
+        state.serialize_field("time", &format_capture_time(self.time))?;
+        state.serialize_field("rtt_ms", &(self.rtt.as_secs_f64() * 1000.0))?;
+        state.end()
+    }
+}
+
+/// How a clock carried in packets compares with the capture's clock
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClockDrift {
+    pub samples: u64,
+    /// Clock reading minus capture time at the first sample, in seconds
+    pub offset: f64,
+    /// How much faster the clock ran than the capture's, in parts per
+    /// million. Needs at least two samples.
+    pub drift_ppm: Option<f64>,
+}
+
+/// Maps a side's `PacketHeader.time` to capture time
+///
+/// The header time is a 16-bit tick counter whose rate differs between
+/// clients and servers, so it's fitted against capture times rather than
+/// assumed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderClock {
+    pub samples: u64,
+    /// Header time ticks per second of capture time. Needs at least two
+    /// distinct header times.
+    pub ticks_per_second: Option<f64>,
+    /// A header time, along with the capture time it corresponds to
+    pub reference_tick: u16,
+    pub reference_time: Duration,
+}
+
+impl HeaderClock {
+    /// Capture time that a header time corresponds to
+    ///
+    /// The tick counter wraps, so `near` picks which wrap is meant; the
+    /// capture time of the packet carrying the header time works.
+    pub fn wall_clock(&self, time: u16, near: Duration) -> Option<Duration> {
+        let rate = self.ticks_per_second?;
+        let reference = self.reference_time.as_secs_f64();
+        let expected = (near.as_secs_f64() - reference) * rate;
+        let delta = time.wrapping_sub(self.reference_tick) as f64;
+        let wraps = ((expected - delta) / 65536.0).round();
+        let seconds = reference + (delta + wraps * 65536.0) / rate;
+        (seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+    }
+}
+
+impl Serialize for HeaderClock {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("HeaderClock", 4)?;
+        state.serialize_field("samples", &self.samples)?;
+        state.serialize_field("ticks_per_second", &self.ticks_per_second)?;
+        state.serialize_field("reference_tick", &self.reference_tick)?;
+        state.serialize_field("reference_time", &format_capture_time(self.reference_time))?;
+        state.end()
+    }
+}
+
+/// Round trips and clocks of one session
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionTiming {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    /// Every matched echo, in capture order
+    pub rtt: Vec<RttSample>,
+    pub rtt_stats: LatencyStats,
+    /// Echo requests that never got a response
+    pub unanswered_echoes: u64,
+    /// The server's clock, from the TIME_SYNC headers it sends
+    pub server_clock: Option<ClockDrift>,
+    /// The client's clock, from the local time in its ECHO_REQUEST headers
+    pub client_clock: Option<ClockDrift>,
+    pub client_header_clock: Option<HeaderClock>,
+    pub server_header_clock: Option<HeaderClock>,
+}
+
+/// Everything `TimingAnalyzer` found in a capture
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TimingReport {
+    /// One entry per session, in the order they were first seen
+    pub sessions: Vec<SessionTiming>,
+}
+
+/// Least-squares line through a series of points
+#[derive(Debug, Default)]
+struct Regression {
+    n: u64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_xy: f64,
+}
+
+impl Regression {
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_xy += x * y;
+    }
+
+    /// Slope and intercept, if the points aren't all at the same x
+    fn fit(&self) -> Option<(f64, f64)> {
+        let n = self.n as f64;
+        let denominator = n * self.sum_xx - self.sum_x * self.sum_x;
+        if self.n < 2 || denominator.abs() < f64::EPSILON {
+            return None;
+        }
+        let slope = (n * self.sum_xy - self.sum_x * self.sum_y) / denominator;
+        Some((slope, (self.sum_y - slope * self.sum_x) / n))
+    }
+}
+
+/// Readings of a clock against capture time
+#[derive(Debug, Default)]
+struct ClockState {
+    /// Capture time and clock reading of the first sample; later samples are
+    /// relative to it to keep the regression precise
+    first: Option<(f64, f64)>,
+    regression: Regression,
+}
+
+impl ClockState {
+    fn add(&mut self, time: Duration, reading: f64) {
+        let (first_time, first_reading) = *self.first.get_or_insert((time.as_secs_f64(), reading));
+        self.regression
+            .add(time.as_secs_f64() - first_time, reading - first_reading);
+    }
+
+    fn finish(&self) -> Option<ClockDrift> {
+        let (first_time, first_reading) = self.first?;
+        Some(ClockDrift {
+            samples: self.regression.n,
+            offset: first_reading - first_time,
+            drift_ppm: self
+                .regression
+                .fit()
+                .map(|(slope, _)| (slope - 1.0) * 1_000_000.0),
+        })
+    }
+}
+
+/// Header times of one side against capture time
+#[derive(Debug, Default)]
+struct HeaderClockState {
+    first: Option<(u16, Duration)>,
+    last_tick: u16,
+    /// Ticks since the first sample, counting wraps
+    unwrapped: i64,
+    regression: Regression,
+}
+
+impl HeaderClockState {
+    fn add(&mut self, tick: u16, time: Duration) {
+        let (_, first_time) = match self.first {
+            Some(first) => {
+                // Small steps backwards are reordering rather than a wrap
+                self.unwrapped += tick.wrapping_sub(self.last_tick) as i16 as i64;
+                first
+            }
+            None => *self.first.insert((tick, time)),
+        };
+        self.last_tick = tick;
+        self.regression.add(
+            self.unwrapped as f64,
+            time.as_secs_f64() - first_time.as_secs_f64(),
+        );
+    }
+
+    fn finish(&self) -> Option<HeaderClock> {
+        let (reference_tick, first_time) = self.first?;
+        let fit = self
+            .regression
+            .fit()
+            .filter(|(seconds_per_tick, _)| *seconds_per_tick > 0.0);
+        // The fitted line is a better estimate of when the first tick began
+        // than the first packet, which may have been sent late in its tick
+        let reference_time = match fit {
+            Some((_, intercept)) => {
+                Duration::from_secs_f64((first_time.as_secs_f64() + intercept).max(0.0))
+            }
+            None => first_time,
+        };
+        Some(HeaderClock {
+            samples: self.regression.n,
+            ticks_per_second: fit.map(|(seconds_per_tick, _)| 1.0 / seconds_per_tick),
+            reference_tick,
+            reference_time,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    /// Capture times of echo requests awaiting a response, keyed by the bits
+    /// of the local time the response echoes back
+    pending_echoes: HashMap<u32, Duration>,
+    rtt: Vec<RttSample>,
+    rtt_stats: LatencyStats,
+    server_clock: ClockState,
+    client_clock: ClockState,
+    client_header_clock: HeaderClockState,
+    server_header_clock: HeaderClockState,
+}
+
+impl SessionState {
+    fn finish(self, client: SocketAddr, server: SocketAddr) -> SessionTiming {
+        SessionTiming {
+            client,
+            server,
+            rtt: self.rtt,
+            rtt_stats: self.rtt_stats,
+            unanswered_echoes: self.pending_echoes.len() as u64,
+            server_clock: self.server_clock.finish(),
+            client_clock: self.client_clock.finish(),
+            client_header_clock: self.client_header_clock.finish(),
+            server_header_clock: self.server_header_clock.finish(),
+        }
+    }
+}
+
+/// Correlates echo and time sync headers into round trip times and clock
+/// estimates per session
+///
+/// Feed it every packet record of a capture in order, along with its capture
+/// time, then call `finish` for the report. Packets without a capture time,
+/// a known direction or a valid checksum are ignored.
+#[derive(Debug, Default)]
+pub struct TimingAnalyzer {
+    sessions: SessionTable<SessionState>,
+}
+
+impl TimingAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_packet(&mut self, packet: &PacketRecord, time: Option<Duration>) {
+        if packet.checksum == ChecksumStatus::Invalid {
+            return;
+        }
+        let (Some(time), Some(direction), Some(session)) = (
+            time,
+            packet.direction,
+            self.sessions.session(packet, SessionState::default),
+        ) else {
+            return;
+        };
+        let optional = &packet.optional;
+
+        match direction {
+            Direction::ClientToServer => {
+                session.client_header_clock.add(packet.header.time, time);
+                if let Some(local_time) = optional.echo_request {
+                    session.pending_echoes.insert(local_time.to_bits(), time);
+                    session.client_clock.add(time, local_time as f64);
+                }
+            }
+            Direction::ServerToClient => {
+                session.server_header_clock.add(packet.header.time, time);
+                if let Some(server_time) = optional.time_sync {
+                    session.server_clock.add(time, server_time);
+                }
+                if let Some(echo) = &optional.echo_response
+                    && let Some(sent) = session.pending_echoes.remove(&echo.local_time.to_bits())
+                {
+                    let rtt = time.saturating_sub(sent);
+                    session.rtt.push(RttSample { time: sent, rtt });
+                    session.rtt_stats.add(rtt);
+                }
+            }
+        }
+    }
+
+    /// Fit the clocks of every session and count the echoes still unanswered
+    pub fn finish(self) -> TimingReport {
+        TimingReport {
+            sessions: self
+                .sessions
+                .into_sessions()
+                .map(|(client, server, session)| session.finish(client, server))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::PacketHeaderFlags;
+    use crate::network::OptionalHeaders;
+    use crate::types::EchoResponseHeader;
+
+    fn packet(direction: Direction, tick: u16, optional: OptionalHeaders) -> PacketRecord {
+        let mut packet = PacketRecord::between(
+            "192.0.2.33:50000",
+            "198.51.100.7:9000",
+            direction,
+            PacketHeaderFlags::ENCRYPTED_CHECKSUM,
+        );
+        packet.header.time = tick;
+        packet.optional = optional;
+        packet
+    }
+
+    fn at(seconds: f64) -> Option<Duration> {
+        Some(Duration::from_secs_f64(1_763_490_000.0 + seconds))
+    }
+
+    #[test]
+    fn test_echo_round_trips() {
+        let mut analyzer = TimingAnalyzer::new();
+        for (sent, answered, local_time) in [(0.0, 0.08, 453.9493f32), (3.0, 3.25, 456.95)] {
+            let request = OptionalHeaders {
+                echo_request: Some(local_time),
+                ..Default::default()
+            };
+            analyzer.add_packet(&packet(Direction::ClientToServer, 0, request), at(sent));
+            let response = OptionalHeaders {
+                echo_response: Some(EchoResponseHeader {
+                    local_time,
+                    holding_time: 0.0,
+                }),
+                ..Default::default()
+            };
+            analyzer.add_packet(
+                &packet(Direction::ServerToClient, 0, response),
+                at(answered),
+            );
+        }
+        let unanswered = OptionalHeaders {
+            echo_request: Some(460.0),
+            ..Default::default()
+        };
+        analyzer.add_packet(&packet(Direction::ClientToServer, 0, unanswered), at(6.0));
+
+        let report = analyzer.finish();
+        let session = &report.sessions[0];
+        assert_eq!(session.rtt.len(), 2);
+        assert_eq!(session.rtt[0].time, at(0.0).unwrap());
+        assert!((session.rtt[0].rtt.as_secs_f64() - 0.08).abs() < 1e-6);
+        assert!((session.rtt[1].rtt.as_secs_f64() - 0.25).abs() < 1e-6);
+        assert_eq!(session.rtt_stats.samples, 2);
+        assert_eq!(session.unanswered_echoes, 1);
+
+        let client_clock = session.client_clock.as_ref().unwrap();
+        assert_eq!(client_clock.samples, 3);
+        assert!((client_clock.offset - (453.9493 - 1_763_490_000.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_server_clock_drift() {
+        let mut analyzer = TimingAnalyzer::new();
+        // The server's clock gains 1ms every 10s, i.e. 100ppm
+        for i in 0..5 {
+            let seconds = i as f64 * 10.0;
+            let optional = OptionalHeaders {
+                time_sync: Some(277_608_212.0 + seconds * 1.0001),
+                ..Default::default()
+            };
+            analyzer.add_packet(&packet(Direction::ServerToClient, 0, optional), at(seconds));
+        }
+
+        let report = analyzer.finish();
+        let clock = report.sessions[0].server_clock.as_ref().unwrap();
+        assert_eq!(clock.samples, 5);
+        assert!((clock.offset - (277_608_212.0 - 1_763_490_000.0)).abs() < 1e-3);
+        assert!((clock.drift_ppm.unwrap() - 100.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_header_clock_across_wrap() {
+        let mut analyzer = TimingAnalyzer::new();
+        // Two ticks a second, wrapping partway through
+        for i in 0..20u16 {
+            let tick = 65530u16.wrapping_add(i);
+            let record = packet(Direction::ClientToServer, tick, OptionalHeaders::default());
+            analyzer.add_packet(&record, at(i as f64 / 2.0 + 0.1));
+        }
+
+        let report = analyzer.finish();
+        let clock = report.sessions[0].client_header_clock.as_ref().unwrap();
+        assert!((clock.ticks_per_second.unwrap() - 2.0).abs() < 1e-6);
+        assert_eq!(clock.reference_tick, 65530);
+        let mapped = clock.wall_clock(14, at(10.0).unwrap()).unwrap();
+        assert!((mapped.as_secs_f64() - at(10.1).unwrap().as_secs_f64()).abs() < 1e-3);
+        assert!(report.sessions[0].server_header_clock.is_none());
+    }
+}
@@ -0,0 +1,558 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+
+/// UDP ports retail-style servers listen on (login and world servers)
+pub const SERVER_PORT_RANGE: RangeInclusive<u16> = 9000..=9013;
+
+/// BSD loopback: a 4-byte address family in the capturing host's byte order
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Bare IPv4 or IPv6 packets, told apart by the version nibble
+pub const LINKTYPE_RAW: u32 = 101;
+/// OpenBSD loopback: like `LINKTYPE_NULL` but the family is big-endian
+pub const LINKTYPE_LOOP: u32 = 108;
+/// Linux "cooked" capture, used when capturing on the `any` interface
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+/// Linux "cooked" capture v2
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+/// 802.1Q, 802.1ad and the pre-standard QinQ tag
+const ETHERTYPE_VLAN: [u16; 3] = [0x8100, 0x88A8, 0x9100];
+
+const IPPROTO_UDP: u8 = 17;
+
+/// A UDP datagram decoded from a captured frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpDatagram<'a> {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    /// The UDP payload, i.e. one or more AC packets
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    /// Decode a captured frame whose link type isn't known
+    ///
+    /// The frame is decoded as Ethernet, which also covers aclog captures
+    /// that carry a 4-byte prefix in front of the IPv4 header (see
+    /// `FragmentAssembler::parse_packet_payload`). Prefer `from_link_frame`
+    /// when the capture's link type is available.
+    pub fn from_frame(frame: &'a [u8]) -> Option<Self> {
+        Self::from_link_frame(LINKTYPE_ETHERNET, frame)
+    }
+
+    /// Decode a frame captured with the given `LINKTYPE_*` down to its UDP
+    /// payload
+    ///
+    /// Returns `None` for unsupported link types, frames that are too short,
+    /// anything that isn't UDP over IPv4 or IPv6, and IP fragments, whose
+    /// payload can't be decoded on its own.
+    pub fn from_link_frame(link_type: u32, frame: &'a [u8]) -> Option<Self> {
+        match link_type {
+            LINKTYPE_ETHERNET => Self::from_ethernet(frame),
+            LINKTYPE_NULL => {
+                let family: [u8; 4] = frame.get(..4)?.try_into().ok()?;
+                // Written in the byte order of whichever host captured it
+                let family = match u32::from_le_bytes(family) {
+                    family if family <= 0xFFFF => family,
+                    _ => u32::from_be_bytes(family),
+                };
+                Self::from_address_family(family, &frame[4..])
+            }
+            LINKTYPE_LOOP => {
+                let family = u32::from_be_bytes(frame.get(..4)?.try_into().ok()?);
+                Self::from_address_family(family, &frame[4..])
+            }
+            LINKTYPE_LINUX_SLL => {
+                let protocol = u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]);
+                Self::from_ethertype(protocol, &frame[16..])
+            }
+            LINKTYPE_LINUX_SLL2 => {
+                let protocol = u16::from_be_bytes([*frame.first()?, *frame.get(1)?]);
+                Self::from_ethertype(protocol, frame.get(20..)?)
+            }
+            LINKTYPE_RAW => match frame.first()? >> 4 {
+                4 => Self::from_ipv4(frame),
+                6 => Self::from_ipv6(frame),
+                _ => None,
+            },
+            LINKTYPE_IPV4 => Self::from_ipv4(frame),
+            LINKTYPE_IPV6 => Self::from_ipv6(frame),
+            _ => None,
+        }
+    }
+
+    /// Build an Ethernet frame carrying this datagram
+    ///
+    /// MAC addresses are zeroed and the IP and UDP headers are built from
+    /// scratch, with lengths and checksums to match the payload. A datagram
+    /// mixing IPv4 and IPv6 endpoints is sent as IPv6 with mapped addresses.
+    /// Fails if the payload is too long for the headers' length fields.
+    pub fn to_ethernet_frame(&self) -> io::Result<Vec<u8>> {
+        let too_long = || io::Error::new(io::ErrorKind::InvalidInput, "UDP payload too long");
+        let udp_length = u16::try_from(8 + self.payload.len()).map_err(|_| too_long())?;
+        let mut udp = Vec::with_capacity(udp_length as usize);
+        udp.extend_from_slice(&self.source.port().to_be_bytes());
+        udp.extend_from_slice(&self.destination.port().to_be_bytes());
+        udp.extend_from_slice(&udp_length.to_be_bytes());
+        udp.extend_from_slice(&[0, 0]);
+        udp.extend_from_slice(self.payload);
+
+        let mut frame = vec![0u8; 12];
+        match (self.source.ip(), self.destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+                let mut ip = [0u8; 20];
+                ip[0] = 0x45;
+                let ip_length = udp_length.checked_add(20).ok_or_else(too_long)?;
+                ip[2..4].copy_from_slice(&ip_length.to_be_bytes());
+                ip[6] = 0x40; // Don't fragment
+                ip[8] = 64; // TTL
+                ip[9] = IPPROTO_UDP;
+                ip[12..16].copy_from_slice(&source.octets());
+                ip[16..20].copy_from_slice(&destination.octets());
+                let checksum = internet_checksum(&[&ip]);
+                ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+                // The UDP checksum is optional over IPv4 and left as zero
+                frame.extend_from_slice(&ip);
+            }
+            (source, destination) => {
+                let source = to_ipv6(source).octets();
+                let destination = to_ipv6(destination).octets();
+                frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+                frame.extend_from_slice(&[0x60, 0, 0, 0]);
+                frame.extend_from_slice(&udp_length.to_be_bytes());
+                frame.extend_from_slice(&[IPPROTO_UDP, 64]);
+                frame.extend_from_slice(&source);
+                frame.extend_from_slice(&destination);
+
+                // Mandatory over IPv6, covering a pseudo-header of the addresses
+                let mut pseudo_header = [0u8; 8];
+                pseudo_header[2..4].copy_from_slice(&udp_length.to_be_bytes());
+                pseudo_header[7] = IPPROTO_UDP;
+                let checksum =
+                    match internet_checksum(&[&source, &destination, &pseudo_header, &udp]) {
+                        0 => 0xFFFF,
+                        checksum => checksum,
+                    };
+                udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&udp);
+        Ok(frame)
+    }
+
+    fn from_ethernet(frame: &'a [u8]) -> Option<Self> {
+        let ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+        if let Some(datagram) = Self::from_ethertype(ethertype, &frame[14..]) {
+            return Some(datagram);
+        }
+
+        // aclog captures are labelled Ethernet but put a 4-byte prefix in
+        // front of the IPv4 header instead
+        if frame[4] == 0x45 {
+            return Self::from_ipv4(&frame[4..]);
+        }
+        None
+    }
+
+    /// Decode the payload of an Ethernet or cooked-capture header, skipping
+    /// any VLAN tags
+    fn from_ethertype(mut ethertype: u16, mut data: &'a [u8]) -> Option<Self> {
+        while ETHERTYPE_VLAN.contains(&ethertype) {
+            // 2-byte tag control information, then the inner ethertype
+            ethertype = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]);
+            data = &data[4..];
+        }
+
+        match ethertype {
+            ETHERTYPE_IPV4 => Self::from_ipv4(data),
+            ETHERTYPE_IPV6 => Self::from_ipv6(data),
+            _ => None,
+        }
+    }
+
+    fn from_address_family(family: u32, packet: &'a [u8]) -> Option<Self> {
+        match family {
+            // AF_INET
+            2 => Self::from_ipv4(packet),
+            // AF_INET6 on Linux, NetBSD/OpenBSD, FreeBSD and macOS
+            10 | 24 | 28 | 30 => Self::from_ipv6(packet),
+            _ => None,
+        }
+    }
+
+    fn from_ipv4(ip: &'a [u8]) -> Option<Self> {
+        if ip.len() < 20 || ip[0] >> 4 != 4 || ip[9] != IPPROTO_UDP {
+            return None;
+        }
+
+        // More-fragments flag or a non-zero fragment offset
+        let fragment = u16::from_be_bytes([ip[6], ip[7]]);
+        if fragment & 0x3FFF != 0 {
+            return None;
+        }
+
+        // Extract IP IHL (Internet Header Length) from first byte's lower 4 bits
+        let ihl_bytes = (ip[0] & 0x0f) as usize * 4;
+        if ihl_bytes < 20 {
+            return None;
+        }
+        // Trim link-layer padding. A zero total length (segmentation offload,
+        // or aclog's synthesized headers) or one past the end of a truncated
+        // capture leaves the rest of the frame as is.
+        let total_length = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+        let ip = match ip.get(..total_length) {
+            Some(trimmed) if total_length >= ihl_bytes => trimmed,
+            _ => ip,
+        };
+
+        let source_ip = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+        let destination_ip = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+        Self::from_udp(
+            IpAddr::V4(source_ip),
+            IpAddr::V4(destination_ip),
+            ip.get(ihl_bytes..)?,
+        )
+    }
+
+    fn from_ipv6(ip: &'a [u8]) -> Option<Self> {
+        if ip.len() < 40 || ip[0] >> 4 != 6 {
+            return None;
+        }
+
+        let payload_length = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+        let source_ip: [u8; 16] = ip[8..24].try_into().ok()?;
+        let destination_ip: [u8; 16] = ip[24..40].try_into().ok()?;
+        let mut next_header = ip[6];
+        let mut data = &ip[40..];
+        // A zero payload length is a jumbogram; otherwise trim any padding
+        if payload_length != 0
+            && let Some(trimmed) = data.get(..payload_length)
+        {
+            data = trimmed;
+        }
+
+        // Walk the extension headers to the UDP header
+        while next_header != IPPROTO_UDP {
+            let length = match next_header {
+                // Hop-by-hop, routing and destination options
+                0 | 43 | 60 => (*data.get(1)? as usize + 1) * 8,
+                // Fragment: only an atomic fragment holds the whole datagram
+                44 => {
+                    let fragment = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]);
+                    if fragment & 0xFFF9 != 0 {
+                        return None;
+                    }
+                    8
+                }
+                // Authentication header, sized in 4-byte units
+                51 => (*data.get(1)? as usize + 2) * 4,
+                _ => return None,
+            };
+            next_header = *data.first()?;
+            data = data.get(length..)?;
+        }
+
+        Self::from_udp(
+            IpAddr::V6(Ipv6Addr::from(source_ip)),
+            IpAddr::V6(Ipv6Addr::from(destination_ip)),
+            data,
+        )
+    }
+
+    fn from_udp(source_ip: IpAddr, destination_ip: IpAddr, udp: &'a [u8]) -> Option<Self> {
+        if udp.len() < 8 {
+            return None;
+        }
+
+        let source_port = u16::from_be_bytes([udp[0], udp[1]]);
+        let destination_port = u16::from_be_bytes([udp[2], udp[3]]);
+        // As with the IP length, only trust the UDP length when it fits
+        let length = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+        let payload = match udp.get(8..length) {
+            Some(payload) => payload,
+            None => &udp[8..],
+        };
+
+        Some(Self {
+            source: SocketAddr::new(source_ip, source_port),
+            destination: SocketAddr::new(destination_ip, destination_port),
+            payload,
+        })
+    }
+}
+
+fn to_ipv6(address: IpAddr) -> Ipv6Addr {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped(),
+        IpAddr::V6(address) => address,
+    }
+}
+
+/// The ones' complement of the ones' complement sum of 16-bit words, as used
+/// by the IPv4 header and UDP checksums
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    // Every part but the UDP datagram has an even length
+    for part in parts {
+        for word in part.chunks(2) {
+            let high = word[0] as u32;
+            let low = word.get(1).copied().unwrap_or(0) as u32;
+            sum += (high << 8) | low;
+        }
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Whether a port is one of the well-known server ports
+pub fn is_server_port(port: u16) -> bool {
+    SERVER_PORT_RANGE.contains(&port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ethernet_frame(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 14];
+        frame[12] = 0x08; // EtherType: IPv4
+        frame.extend_from_slice(&[
+            0x45, 0x00, 0x00, 0x00, // version/IHL, TOS, total length
+            0x00, 0x00, 0x00, 0x00, // id, flags/fragment offset
+            0x40, 0x11, 0x00, 0x00, // TTL, protocol (UDP), checksum
+            127, 0, 0, 1, // source
+            10, 0, 0, 2, // destination
+        ]);
+        frame.extend_from_slice(&src_port.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_from_frame_ethernet() {
+        let frame = ethernet_frame(12345, 9000, &[0xAA, 0xBB]);
+        let datagram = UdpDatagram::from_frame(&frame).unwrap();
+
+        assert_eq!(datagram.source, "127.0.0.1:12345".parse().unwrap());
+        assert_eq!(datagram.destination, "10.0.0.2:9000".parse().unwrap());
+        assert_eq!(datagram.payload, &[0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn test_from_frame_with_4byte_prefix() {
+        let frame = ethernet_frame(9001, 50000, &[0x01]);
+        // Replace the Ethernet header with a 4-byte prefix
+        let mut prefixed = vec![0x02, 0x00, 0x00, 0x00];
+        prefixed.extend_from_slice(&frame[14..]);
+
+        let datagram = UdpDatagram::from_frame(&prefixed).unwrap();
+        assert_eq!(datagram.source.port(), 9001);
+        assert_eq!(datagram.destination.port(), 50000);
+        assert_eq!(datagram.payload, &[0x01]);
+    }
+
+    #[test]
+    fn test_from_frame_rejects_non_udp() {
+        let mut frame = ethernet_frame(1, 2, &[]);
+        frame[14 + 9] = 6; // TCP
+        assert!(UdpDatagram::from_frame(&frame).is_none());
+        assert!(UdpDatagram::from_frame(&[0u8; 10]).is_none());
+    }
+
+    /// The IPv4 packet inside `ethernet_frame`
+    fn ipv4_packet(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        ethernet_frame(src_port, dst_port, payload)[14..].to_vec()
+    }
+
+    fn ipv6_packet(next_header: u8, extensions: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&((extensions.len() + 8 + payload.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&[next_header, 64]);
+        packet.extend_from_slice(&"fe80::1".parse::<Ipv6Addr>().unwrap().octets());
+        packet.extend_from_slice(&"fe80::2".parse::<Ipv6Addr>().unwrap().octets());
+        packet.extend_from_slice(extensions);
+        packet.extend_from_slice(&50000u16.to_be_bytes());
+        packet.extend_from_slice(&9000u16.to_be_bytes());
+        packet.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn test_from_link_frame_cooked_captures() {
+        let ip = ipv4_packet(9000, 50000, &[0xAA]);
+
+        let mut sll = vec![0u8; 14];
+        sll.extend_from_slice(&0x0800u16.to_be_bytes());
+        sll.extend_from_slice(&ip);
+        let datagram = UdpDatagram::from_link_frame(LINKTYPE_LINUX_SLL, &sll).unwrap();
+        assert_eq!(datagram.source, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(datagram.payload, &[0xAA]);
+
+        let mut sll2 = 0x0800u16.to_be_bytes().to_vec();
+        sll2.resize(20, 0);
+        sll2.extend_from_slice(&ip);
+        let datagram = UdpDatagram::from_link_frame(LINKTYPE_LINUX_SLL2, &sll2).unwrap();
+        assert_eq!(datagram.destination, "10.0.0.2:50000".parse().unwrap());
+
+        // The SLL layout isn't Ethernet
+        assert!(UdpDatagram::from_link_frame(LINKTYPE_ETHERNET, &sll2).is_none());
+    }
+
+    #[test]
+    fn test_from_link_frame_loopback() {
+        let ip = ipv4_packet(9000, 50000, &[0xAA]);
+        for (link_type, family) in [
+            (LINKTYPE_NULL, 2u32.to_le_bytes()),
+            (LINKTYPE_NULL, 2u32.to_be_bytes()),
+            (LINKTYPE_LOOP, 2u32.to_be_bytes()),
+        ] {
+            let mut frame = family.to_vec();
+            frame.extend_from_slice(&ip);
+            let datagram = UdpDatagram::from_link_frame(link_type, &frame).unwrap();
+            assert_eq!(datagram.payload, &[0xAA]);
+        }
+
+        let mut frame = 30u32.to_le_bytes().to_vec();
+        frame.extend_from_slice(&ipv6_packet(17, &[], &[0xBB]));
+        let datagram = UdpDatagram::from_link_frame(LINKTYPE_NULL, &frame).unwrap();
+        assert_eq!(datagram.source, "[fe80::1]:50000".parse().unwrap());
+        assert_eq!(datagram.payload, &[0xBB]);
+    }
+
+    #[test]
+    fn test_from_link_frame_vlan_tags() {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x88, 0xA8, 0x00, 0x64]); // 802.1ad, VLAN 100
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x0A]); // 802.1Q, VLAN 10
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&ipv4_packet(12345, 9000, &[0xAA, 0xBB]));
+
+        let datagram = UdpDatagram::from_link_frame(LINKTYPE_ETHERNET, &frame).unwrap();
+        assert_eq!(datagram.destination.port(), 9000);
+        assert_eq!(datagram.payload, &[0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn test_from_link_frame_ipv6_extension_headers() {
+        // Hop-by-hop options, then an atomic fragment header
+        let extensions = [44, 0, 1, 4, 0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 1];
+        let packet = ipv6_packet(0, &extensions, &[0x01, 0x02]);
+        let datagram = UdpDatagram::from_link_frame(LINKTYPE_RAW, &packet).unwrap();
+        assert_eq!(datagram.destination, "[fe80::2]:9000".parse().unwrap());
+        assert_eq!(datagram.payload, &[0x01, 0x02]);
+
+        // A real fragment can't be decoded on its own
+        let fragment = [17, 0, 0, 1, 0, 0, 0, 1];
+        let packet = ipv6_packet(44, &fragment, &[0x01, 0x02]);
+        assert!(UdpDatagram::from_link_frame(LINKTYPE_IPV6, &packet).is_none());
+
+        // TCP
+        let packet = ipv6_packet(6, &[], &[]);
+        assert!(UdpDatagram::from_link_frame(LINKTYPE_IPV6, &packet).is_none());
+    }
+
+    #[test]
+    fn test_from_link_frame_ipv4_options_and_lengths() {
+        let mut packet = ipv4_packet(9000, 50000, &[0xAA, 0xBB]);
+        // Four bytes of no-op options
+        packet[0] = 0x46;
+        packet.splice(20..20, [1, 1, 1, 1]);
+        // Total and UDP lengths that exclude link-layer padding
+        packet[2..4].copy_from_slice(&34u16.to_be_bytes());
+        packet[28..30].copy_from_slice(&10u16.to_be_bytes());
+        packet.extend_from_slice(&[0u8; 12]);
+
+        let datagram = UdpDatagram::from_link_frame(LINKTYPE_IPV4, &packet).unwrap();
+        assert_eq!(datagram.source.port(), 9000);
+        assert_eq!(datagram.payload, &[0xAA, 0xBB]);
+
+        // First fragment (more fragments set), then a later one
+        for fragment in [0x2000u16, 0x0010] {
+            packet[6..8].copy_from_slice(&fragment.to_be_bytes());
+            assert!(UdpDatagram::from_link_frame(LINKTYPE_IPV4, &packet).is_none());
+        }
+    }
+
+    #[test]
+    fn test_from_link_frame_unknown_link_type() {
+        let frame = ethernet_frame(9000, 50000, &[0xAA]);
+        assert!(UdpDatagram::from_link_frame(147, &frame).is_none());
+    }
+
+    #[test]
+    fn test_to_ethernet_frame_round_trip() {
+        for (source, destination) in [
+            ("10.0.0.1:50000", "10.0.0.2:9000"),
+            ("[fd00::1]:50000", "[fd00::2]:9000"),
+        ] {
+            let datagram = UdpDatagram {
+                source: source.parse().unwrap(),
+                destination: destination.parse().unwrap(),
+                payload: &[0xAA, 0xBB, 0xCC],
+            };
+            let frame = datagram.to_ethernet_frame().unwrap();
+            assert_eq!(
+                UdpDatagram::from_link_frame(LINKTYPE_ETHERNET, &frame),
+                Some(datagram)
+            );
+
+            // Summing a header or datagram including its checksum gives zero
+            if datagram.source.is_ipv4() {
+                assert_eq!(internet_checksum(&[&frame[14..34]]), 0);
+            } else {
+                let pseudo_header = [0, 0, 0, 11, 0, 0, 0, IPPROTO_UDP];
+                let checksum = internet_checksum(&[&frame[22..54], &pseudo_header, &frame[54..]]);
+                assert_eq!(checksum, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_ethernet_frame_rejects_oversized_payloads() {
+        // Fits a UDP header's length but not an IPv4 header's
+        let payload = vec![0; u16::MAX as usize - 20];
+        let datagram = |source: &str, destination: &str, payload| UdpDatagram {
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            payload,
+        };
+        assert!(
+            datagram("10.0.0.1:50000", "10.0.0.2:9000", &payload)
+                .to_ethernet_frame()
+                .is_err()
+        );
+        assert!(
+            datagram("[fd00::1]:50000", "[fd00::2]:9000", &payload)
+                .to_ethernet_frame()
+                .is_ok()
+        );
+
+        let payload = vec![0; u16::MAX as usize];
+        assert!(
+            datagram("[fd00::1]:50000", "[fd00::2]:9000", &payload)
+                .to_ethernet_frame()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_is_server_port() {
+        assert!(is_server_port(9000));
+        assert!(is_server_port(9013));
+        assert!(!is_server_port(9014));
+        assert!(!is_server_port(12345));
+    }
+}
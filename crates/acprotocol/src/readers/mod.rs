@@ -174,7 +174,11 @@ pub fn read_string(reader: &mut dyn ACReader) -> Result<String, Box<dyn Error>>
     let len_i16 = read_i16(reader)?;
     let len = if len_i16 == -1 {
         // Special case: -1 means read a 32-bit length
-        read_i32(reader)? as usize
+        let len_i32 = read_i32(reader)?;
+        if len_i32 < 0 {
+            return Err(format!("Invalid string length: {}", len_i32).into());
+        }
+        len_i32 as usize
     } else if len_i16 < -1 {
         return Err(format!("Invalid string length: {} (must be -1 or >= 0)", len_i16).into());
     } else {
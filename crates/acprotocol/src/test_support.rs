@@ -0,0 +1,270 @@
+//! Message and type builders shared by the unit tests
+
+use std::hash::Hash;
+use std::io::Cursor;
+
+use crate::enums::{
+    ACBaseQualitiesFlags, ACQualitiesFlags, CharacterOptions1, ChatType, ItemType,
+    ObjectDescriptionFlag, PhysicsState, PropertyInt, PropertyString, WeenieType,
+};
+use crate::gameevents::LoginPlayerDescription;
+use crate::message::{C2SMessage, MessageKind, S2CMessage};
+use crate::messages::{c2s, s2c};
+use crate::types::{
+    ACBaseQualities, ACQualities, AttributeCache, AttributeInfo, ObjDesc, ObjectId,
+    PackableHashTable, PackableList, PackedDWORD, PhysicsDesc, PlayerModule, PublicWeenieDesc,
+    SecondaryAttributeInfo, WString,
+};
+use crate::writers::ACWritable;
+
+/// Wrap a server message the way the readers hand it out
+pub(crate) fn s2c(message: S2CMessage) -> MessageKind {
+    MessageKind::S2C(Box::new(message))
+}
+
+pub(crate) fn obj_desc(palette_count: u8) -> ObjDesc {
+    ObjDesc {
+        version: 0x11,
+        palette_count,
+        texture_count: 0,
+        model_count: 0,
+        palette: None,
+        subpalettes: Vec::new(),
+        tm_changes: Vec::new(),
+        ap_changes: Vec::new(),
+    }
+}
+
+pub(crate) fn physics_desc() -> PhysicsDesc {
+    PhysicsDesc {
+        flags: 0,
+        state: PhysicsState::NONE,
+        movement_buffer: None,
+        autonomous: None,
+        animation_frame: None,
+        position: None,
+        motion_id: None,
+        sound_id: None,
+        physics_script_id: None,
+        setup_id: None,
+        parent_id: None,
+        parent_location: None,
+        children: None,
+        scale: None,
+        friction: None,
+        elasticity: None,
+        translucency: None,
+        velocity: None,
+        acceleration: None,
+        omega: None,
+        default_script: None,
+        default_script_intensity: None,
+        object_position_sequence: 0,
+        object_movement_sequence: 0,
+        object_state_sequence: 0,
+        object_vector_sequence: 0,
+        object_teleport_sequence: 0,
+        object_server_control_sequence: 0,
+        object_force_position_sequence: 0,
+        object_visual_desc_sequence: 0,
+        object_instance_sequence: 0,
+    }
+}
+
+pub(crate) fn weenie_desc(name: &str) -> PublicWeenieDesc {
+    PublicWeenieDesc {
+        header: 0,
+        name: name.to_string(),
+        weenie_class_id: PackedDWORD(1),
+        icon: PackedDWORD(2),
+        type_: ItemType::MISC,
+        behavior: ObjectDescriptionFlag::empty(),
+        header2: None,
+        plural_name: None,
+        items_capacity: None,
+        container_capacity: None,
+        ammunition_type: None,
+        value: None,
+        useability: None,
+        use_radius: None,
+        target_type: None,
+        effects: None,
+        combat_use: None,
+        structure: None,
+        max_structure: None,
+        stack_size: None,
+        max_stack_size: None,
+        container_id: None,
+        wielder_id: None,
+        valid_slots: None,
+        slot: None,
+        priority: None,
+        blip_color: None,
+        radar_enum: None,
+        physics_script: None,
+        workmanship: None,
+        burden: None,
+        spell_id: None,
+        owner_id: None,
+        restrictions: None,
+        hook_item_types: None,
+        monarch_id: None,
+        hook_type: None,
+        icon_overlay: None,
+        icon_underlay: None,
+        material: None,
+        cooldown_id: None,
+        cooldown_duration: None,
+        pet_owner_id: None,
+    }
+}
+
+pub(crate) fn list<T>(list: Vec<T>) -> PackableList<T> {
+    PackableList {
+        count: list.len() as u32,
+        list,
+    }
+}
+
+pub(crate) fn hash_table<K: Eq + Hash, V>(table: Vec<(K, V)>) -> PackableHashTable<K, V> {
+    PackableHashTable {
+        count: table.len() as u16,
+        max_size: 0,
+        table: table.into_iter().collect(),
+    }
+}
+
+pub(crate) fn turbine_chat_event(display_name: &str, text: &str) -> S2CMessage {
+    S2CMessage::CommunicationTurbineChat(s2c::CommunicationTurbineChat::Type1(
+        s2c::CommunicationTurbineChatType1 {
+            message_size: 0,
+            target_type: 1,
+            target_id: 0,
+            transport_type: 1,
+            transport_id: 0,
+            cookie: 0,
+            payload_size: 0,
+            blob_dispatch_type: s2c::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(
+                s2c::CommunicationTurbineChatType1BlobDispatchTypeVariantType1 {
+                    room_id: 0x0011_0001,
+                    display_name: WString(display_name.to_string()),
+                    text: WString(text.to_string()),
+                    extra_data_size: 12,
+                    speaker_id: ObjectId(0x5000_0001),
+                    h_result: 0,
+                    chat_type: ChatType::Trade,
+                },
+            ),
+        },
+    ))
+}
+
+pub(crate) fn turbine_chat_request(display_name: &str, text: &str) -> C2SMessage {
+    C2SMessage::CommunicationTurbineChat(c2s::CommunicationTurbineChat::Type1(
+        c2s::CommunicationTurbineChatType1 {
+            mmessage_size: 0,
+            target_type: 1,
+            target_id: 0,
+            transport_type: 1,
+            transport_id: 0,
+            cookie: 0,
+            payload_size: 0,
+            blob_dispatch_type: c2s::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(
+                c2s::CommunicationTurbineChatType1BlobDispatchTypeVariantType1 {
+                    room_id: 0x0011_0001,
+                    display_name: WString(display_name.to_string()),
+                    text: WString(text.to_string()),
+                    extra_data_size: 12,
+                    speaker_id: ObjectId(0x5000_0001),
+                    h_result: 0,
+                    chat_type: ChatType::Trade as u32,
+                },
+            ),
+        },
+    ))
+}
+
+/// A login description for a level 126 character named Galahad, with a few
+/// attributes and nothing else
+pub(crate) fn player_description() -> LoginPlayerDescription {
+    let attribute = |points_raised| AttributeInfo {
+        points_raised,
+        innate_points: 100,
+        experience_spent: 0,
+    };
+    LoginPlayerDescription {
+        base_qualities: ACBaseQualities {
+            flags: ACBaseQualitiesFlags::PROPERTY_INT | ACBaseQualitiesFlags::PROPERTY_STRING,
+            weenie_type: WeenieType::Creature,
+            int_properties: Some(hash_table(vec![(PropertyInt::Level, 126)])),
+            int64_properties: None,
+            bool_properties: None,
+            float_properties: None,
+            string_properties: Some(hash_table(vec![(
+                PropertyString::Name,
+                "Galahad".to_string(),
+            )])),
+            data_properties: None,
+            instance_properties: None,
+            position_properties: None,
+        },
+        qualities: ACQualities {
+            flags: ACQualitiesFlags::ATTRIBUTES,
+            has_health: true,
+            attributes: Some(AttributeCache {
+                flags: 0x1FF,
+                strength: Some(attribute(90)),
+                endurance: Some(attribute(10)),
+                quickness: None,
+                coordination: None,
+                focus: None,
+                self_: None,
+                health: Some(SecondaryAttributeInfo {
+                    attribute: attribute(40),
+                    current: 200,
+                }),
+                stamina: None,
+                mana: None,
+            }),
+            skills: None,
+            body: None,
+            spell_book: None,
+            enchantments: None,
+            event_filter: None,
+            emotes: None,
+            creation_profile: None,
+            page_data: None,
+            generators: None,
+            generator_registry: None,
+            generator_queue: None,
+        },
+        player_module: PlayerModule {
+            flags: 0,
+            options: CharacterOptions1::empty(),
+            shortcuts: None,
+            tab1_spells: list(Vec::new()),
+            tab2_spells: list(Vec::new()),
+            tab3_spells: list(Vec::new()),
+            tab4_spells: list(Vec::new()),
+            tab5_spells: list(Vec::new()),
+            tab6_spells: list(Vec::new()),
+            tab7_spells: list(Vec::new()),
+            tab8_spells: list(Vec::new()),
+            fill_comps: None,
+            spell_book_filters: None,
+            option_flags: None,
+            unknown100_1: None,
+            option_strings: None,
+            gameplay_options: None,
+        },
+        content_profile: list(Vec::new()),
+        inventory_placement: list(Vec::new()),
+    }
+}
+
+/// Serialize a message with its writer
+pub(crate) fn serialize<T: ACWritable>(message: &T) -> Vec<u8> {
+    let mut data = Vec::new();
+    message.write(&mut Cursor::new(&mut data)).unwrap();
+    data
+}
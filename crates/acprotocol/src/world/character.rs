@@ -0,0 +1,812 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Cursor;
+
+use crate::enums::{
+    AttributeId, CurVitalId, PropertyBool, PropertyDataId, PropertyFloat, PropertyInstanceId,
+    PropertyInt, PropertyInt64, PropertyPosition, PropertyString, SkillAdvancementClass, SkillId,
+    VitalId,
+};
+use crate::gameevents::LoginPlayerDescription;
+use crate::message::{GameEventMessage, MessageKind, S2CMessage};
+use crate::network::RawMessage;
+use crate::types::{
+    AttributeInfo, ObjectId, PackableHashTable, PlayerModule, Position, SecondaryAttributeInfo,
+    Skill,
+};
+
+/// Number of primary attributes, Strength through Self
+const ATTRIBUTES: usize = 6;
+
+/// Number of vitals: health, stamina and mana
+const VITALS: usize = 3;
+
+/// The player's character sheet, as of the last message applied
+///
+/// `LoginPlayerDescription` fills it in and the `Qualities*Update*` and
+/// `Qualities*Remove*` messages change it from there. The private variants
+/// are always about the player; the public ones are only applied when they
+/// name the player, whose id comes from the `OrderedGameEvent` carrying the
+/// description. Until a description has been seen, only private messages
+/// are applied.
+///
+/// Clone it to keep a copy at some point in a capture, and `diff` two
+/// copies to see what changed between them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CharacterState {
+    id: Option<ObjectId>,
+    ints: HashMap<PropertyInt, i32>,
+    int64s: HashMap<PropertyInt64, i64>,
+    bools: HashMap<PropertyBool, bool>,
+    floats: HashMap<PropertyFloat, f64>,
+    strings: HashMap<PropertyString, String>,
+    data_ids: HashMap<PropertyDataId, u32>,
+    instance_ids: HashMap<PropertyInstanceId, ObjectId>,
+    positions: HashMap<PropertyPosition, Position>,
+    skills: HashMap<SkillId, Skill>,
+    /// Indexed by `AttributeId` - 1
+    attributes: [Option<AttributeInfo>; ATTRIBUTES],
+    /// Indexed by (`VitalId` - 1) / 2
+    vitals: [Option<SecondaryAttributeInfo>; VITALS],
+    player_module: Option<PlayerModule>,
+    /// Messages seen, whether or not they touched the sheet. A login
+    /// description starts the sheet over but keeps this count.
+    messages: usize,
+}
+
+/// A stat that differs between two `CharacterState`s. `None` means the
+/// stat wasn't set.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change<K, V> {
+    pub key: K,
+    pub before: Option<V>,
+    pub after: Option<V>,
+}
+
+/// Everything that differs between two `CharacterState`s, table by table
+///
+/// Changes within a table are in key order.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct CharacterDiff {
+    pub ints: Vec<Change<PropertyInt, i32>>,
+    pub int64s: Vec<Change<PropertyInt64, i64>>,
+    pub bools: Vec<Change<PropertyBool, bool>>,
+    pub floats: Vec<Change<PropertyFloat, f64>>,
+    pub strings: Vec<Change<PropertyString, String>>,
+    pub data_ids: Vec<Change<PropertyDataId, u32>>,
+    pub instance_ids: Vec<Change<PropertyInstanceId, ObjectId>>,
+    pub positions: Vec<Change<PropertyPosition, Position>>,
+    pub skills: Vec<Change<SkillId, Skill>>,
+    pub attributes: Vec<Change<AttributeId, AttributeInfo>>,
+    pub vitals: Vec<Change<VitalId, SecondaryAttributeInfo>>,
+}
+
+impl CharacterDiff {
+    pub fn is_empty(&self) -> bool {
+        self.ints.is_empty()
+            && self.int64s.is_empty()
+            && self.bools.is_empty()
+            && self.floats.is_empty()
+            && self.strings.is_empty()
+            && self.data_ids.is_empty()
+            && self.instance_ids.is_empty()
+            && self.positions.is_empty()
+            && self.skills.is_empty()
+            && self.attributes.is_empty()
+            && self.vitals.is_empty()
+    }
+}
+
+/// Set `$table[key] = value` from a qualities update. Float updates are
+/// sent as `f32` but kept as `f64`, like the login description has them.
+macro_rules! update {
+    ($table:expr, $event:expr) => {{
+        $table.insert($event.key.clone(), $event.value.clone().into());
+        true
+    }};
+}
+
+/// Remove `$table[type_]` for a qualities remove event
+macro_rules! remove {
+    ($table:expr, $event:expr) => {
+        $table.remove(&$event.type_).is_some()
+    };
+}
+
+impl CharacterState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The player's object id, once a `LoginPlayerDescription` has been seen
+    pub fn id(&self) -> Option<ObjectId> {
+        self.id
+    }
+
+    /// Index of the next message in the stream the sheet is built from
+    pub fn message_index(&self) -> usize {
+        self.messages
+    }
+
+    pub fn int(&self, key: PropertyInt) -> Option<i32> {
+        self.ints.get(&key).copied()
+    }
+
+    pub fn int64(&self, key: PropertyInt64) -> Option<i64> {
+        self.int64s.get(&key).copied()
+    }
+
+    pub fn bool(&self, key: PropertyBool) -> Option<bool> {
+        self.bools.get(&key).copied()
+    }
+
+    pub fn float(&self, key: PropertyFloat) -> Option<f64> {
+        self.floats.get(&key).copied()
+    }
+
+    pub fn string(&self, key: PropertyString) -> Option<&str> {
+        self.strings.get(&key).map(String::as_str)
+    }
+
+    pub fn data_id(&self, key: PropertyDataId) -> Option<u32> {
+        self.data_ids.get(&key).copied()
+    }
+
+    pub fn instance_id(&self, key: PropertyInstanceId) -> Option<ObjectId> {
+        self.instance_ids.get(&key).copied()
+    }
+
+    pub fn position(&self, key: PropertyPosition) -> Option<&Position> {
+        self.positions.get(&key)
+    }
+
+    pub fn skill(&self, key: SkillId) -> Option<&Skill> {
+        self.skills.get(&key)
+    }
+
+    pub fn attribute(&self, key: AttributeId) -> Option<&AttributeInfo> {
+        self.attributes[attribute_index(key)].as_ref()
+    }
+
+    pub fn vital(&self, key: VitalId) -> Option<&SecondaryAttributeInfo> {
+        self.vitals[vital_index(key)].as_ref()
+    }
+
+    pub fn player_module(&self) -> Option<&PlayerModule> {
+        self.player_module.as_ref()
+    }
+
+    /// Decode a raw message and apply it. Returns whether the sheet changed.
+    pub fn apply_raw(&mut self, message: &RawMessage) -> bool {
+        match MessageKind::read(&mut Cursor::new(&message.data), message.direction) {
+            Ok(decoded) => self.apply(&decoded),
+            Err(_) => {
+                self.messages += 1;
+                false
+            }
+        }
+    }
+
+    /// Apply a message. Returns whether the sheet changed.
+    pub fn apply(&mut self, message: &MessageKind) -> bool {
+        self.messages += 1;
+        let MessageKind::S2C(message) = message else {
+            return false;
+        };
+
+        match message.as_ref() {
+            S2CMessage::OrderedGameEvent {
+                object_id, event, ..
+            } => match event.as_ref() {
+                GameEventMessage::LoginPlayerDescription(description) => {
+                    self.login(ObjectId(*object_id), description);
+                    true
+                }
+                _ => false,
+            },
+
+            S2CMessage::QualitiesPrivateUpdateInt(e) => update!(self.ints, e),
+            S2CMessage::QualitiesPrivateUpdateInt64(e) => update!(self.int64s, e),
+            S2CMessage::QualitiesPrivateUpdateBool(e) => update!(self.bools, e),
+            S2CMessage::QualitiesPrivateUpdateFloat(e) => update!(self.floats, e),
+            S2CMessage::QualitiesPrivateUpdateString(e) => update!(self.strings, e),
+            S2CMessage::QualitiesPrivateUpdateDataId(e) => update!(self.data_ids, e),
+            S2CMessage::QualitiesPrivateUpdateInstanceId(e) => update!(self.instance_ids, e),
+            S2CMessage::QualitiesPrivateUpdatePosition(e) => update!(self.positions, e),
+            S2CMessage::QualitiesPrivateUpdateSkill(e) => update!(self.skills, e),
+            S2CMessage::QualitiesPrivateUpdateSkillLevel(e) => {
+                self.skill_level(e.key.clone(), e.value)
+            }
+            S2CMessage::QualitiesPrivateUpdateSkillAC(e) => {
+                self.skill_training(e.key.clone(), e.value.clone())
+            }
+            S2CMessage::QualitiesPrivateUpdateAttribute(e) => {
+                self.set_attribute(e.key.clone(), e.value.clone())
+            }
+            S2CMessage::QualitiesPrivateUpdateAttributeLevel(e) => {
+                self.attribute_level(e.key.clone(), e.value)
+            }
+            S2CMessage::QualitiesPrivateUpdateAttribute2nd(e) => {
+                self.set_vital(e.key.clone(), e.value.clone())
+            }
+            S2CMessage::QualitiesPrivateUpdateAttribute2ndLevel(e) => {
+                self.current_vital(e.key.clone(), e.value)
+            }
+
+            S2CMessage::QualitiesPrivateRemoveIntEvent(e) => remove!(self.ints, e),
+            S2CMessage::QualitiesPrivateRemoveInt64Event(e) => remove!(self.int64s, e),
+            S2CMessage::QualitiesPrivateRemoveBoolEvent(e) => remove!(self.bools, e),
+            S2CMessage::QualitiesPrivateRemoveFloatEvent(e) => remove!(self.floats, e),
+            S2CMessage::QualitiesPrivateRemoveStringEvent(e) => remove!(self.strings, e),
+            S2CMessage::QualitiesPrivateRemoveDataIdEvent(e) => remove!(self.data_ids, e),
+            S2CMessage::QualitiesPrivateRemoveInstanceIdEvent(e) => {
+                remove!(self.instance_ids, e)
+            }
+            S2CMessage::QualitiesPrivateRemovePositionEvent(e) => remove!(self.positions, e),
+
+            S2CMessage::QualitiesUpdateInt(e) if self.is_player(e.object_id) => {
+                update!(self.ints, e)
+            }
+            S2CMessage::QualitiesUpdateInt64(e) if self.is_player(e.object_id) => {
+                update!(self.int64s, e)
+            }
+            S2CMessage::QualitiesUpdateBool(e) if self.is_player(e.object_id) => {
+                update!(self.bools, e)
+            }
+            S2CMessage::QualitiesUpdateFloat(e) if self.is_player(e.object_id) => {
+                update!(self.floats, e)
+            }
+            S2CMessage::QualitiesUpdateString(e) if self.is_player(e.object_id) => {
+                update!(self.strings, e)
+            }
+            S2CMessage::QualitiesUpdateDataId(e) if self.is_player(e.object_id) => {
+                update!(self.data_ids, e)
+            }
+            S2CMessage::QualitiesUpdateInstanceId(e) if self.is_player(e.object_id) => {
+                update!(self.instance_ids, e)
+            }
+            S2CMessage::QualitiesUpdatePosition(e) if self.is_player(e.object_id) => {
+                update!(self.positions, e)
+            }
+            S2CMessage::QualitiesUpdateSkill(e) if self.is_player(e.object_id) => {
+                update!(self.skills, e)
+            }
+            S2CMessage::QualitiesUpdateSkillLevel(e) if self.is_player(e.object_id) => {
+                self.skill_level(e.key.clone(), e.value)
+            }
+            S2CMessage::QualitiesUpdateSkillAC(e) if self.is_player(e.object_id) => {
+                self.skill_training(e.key.clone(), e.value.clone())
+            }
+            S2CMessage::QualitiesUpdateAttribute(e) if self.is_player(e.object_id) => {
+                self.set_attribute(e.key.clone(), e.value.clone())
+            }
+            S2CMessage::QualitiesUpdateAttributeLevel(e) if self.is_player(e.object_id) => {
+                self.attribute_level(e.key.clone(), e.value)
+            }
+            S2CMessage::QualitiesUpdateAttribute2nd(e) if self.is_player(e.object_id) => {
+                self.set_vital(e.key.clone(), e.value.clone())
+            }
+            S2CMessage::QualitiesUpdateAttribute2ndLevel(e) if self.is_player(e.object_id) => {
+                self.current_vital(e.key.clone(), e.value)
+            }
+
+            S2CMessage::QualitiesRemoveIntEvent(e) if self.is_player(e.object_id) => {
+                remove!(self.ints, e)
+            }
+            S2CMessage::QualitiesRemoveInt64Event(e) if self.is_player(e.object_id) => {
+                remove!(self.int64s, e)
+            }
+            S2CMessage::QualitiesRemoveBoolEvent(e) if self.is_player(e.object_id) => {
+                remove!(self.bools, e)
+            }
+            S2CMessage::QualitiesRemoveFloatEvent(e) if self.is_player(e.object_id) => {
+                remove!(self.floats, e)
+            }
+            S2CMessage::QualitiesRemoveStringEvent(e) if self.is_player(e.object_id) => {
+                remove!(self.strings, e)
+            }
+            S2CMessage::QualitiesRemoveDataIdEvent(e) if self.is_player(e.object_id) => {
+                remove!(self.data_ids, e)
+            }
+            S2CMessage::QualitiesRemoveInstanceIdEvent(e) if self.is_player(e.object_id) => {
+                remove!(self.instance_ids, e)
+            }
+            S2CMessage::QualitiesRemovePositionEvent(e) if self.is_player(e.object_id) => {
+                remove!(self.positions, e)
+            }
+
+            _ => false,
+        }
+    }
+
+    /// What changed from this sheet to `later`
+    pub fn diff(&self, later: &CharacterState) -> CharacterDiff {
+        CharacterDiff {
+            ints: diff_tables(&self.ints, &later.ints),
+            int64s: diff_tables(&self.int64s, &later.int64s),
+            bools: diff_tables(&self.bools, &later.bools),
+            floats: diff_tables(&self.floats, &later.floats),
+            strings: diff_tables(&self.strings, &later.strings),
+            data_ids: diff_tables(&self.data_ids, &later.data_ids),
+            instance_ids: diff_tables(&self.instance_ids, &later.instance_ids),
+            positions: diff_tables(&self.positions, &later.positions),
+            skills: diff_tables(&self.skills, &later.skills),
+            attributes: diff_slots(&self.attributes, &later.attributes, |i| {
+                AttributeId::try_from(i as u32 + 1).ok()
+            }),
+            vitals: diff_slots(&self.vitals, &later.vitals, |i| {
+                VitalId::try_from(i as u32 * 2 + 1).ok()
+            }),
+        }
+    }
+
+    /// Start over from a login description, which holds the whole sheet
+    fn login(&mut self, id: ObjectId, description: &LoginPlayerDescription) {
+        let base = &description.base_qualities;
+        let qualities = &description.qualities;
+        let attributes = qualities.attributes.as_ref();
+        *self = Self {
+            id: Some(id),
+            ints: table(&base.int_properties),
+            int64s: table(&base.int64_properties),
+            bools: table(&base.bool_properties),
+            floats: table(&base.float_properties),
+            strings: table(&base.string_properties),
+            data_ids: base
+                .data_properties
+                .iter()
+                .flat_map(|t| t.table.iter().map(|(k, v)| (k.clone(), v.0)))
+                .collect(),
+            instance_ids: table(&base.instance_properties),
+            positions: table(&base.position_properties),
+            skills: table(&qualities.skills),
+            attributes: [
+                attributes.and_then(|a| a.strength.clone()),
+                attributes.and_then(|a| a.endurance.clone()),
+                attributes.and_then(|a| a.quickness.clone()),
+                attributes.and_then(|a| a.coordination.clone()),
+                attributes.and_then(|a| a.focus.clone()),
+                attributes.and_then(|a| a.self_.clone()),
+            ],
+            vitals: [
+                attributes.and_then(|a| a.health.clone()),
+                attributes.and_then(|a| a.stamina.clone()),
+                attributes.and_then(|a| a.mana.clone()),
+            ],
+            player_module: Some(description.player_module.clone()),
+            messages: self.messages,
+        };
+    }
+
+    fn is_player(&self, id: ObjectId) -> bool {
+        self.id == Some(id)
+    }
+
+    /// A level update sets the points raised, keeping the rest of the skill
+    fn skill_level(&mut self, key: SkillId, level: u32) -> bool {
+        let Some(skill) = self.skills.get_mut(&key) else {
+            return false;
+        };
+        skill.points_raised = u16::try_from(level).unwrap_or(u16::MAX);
+        true
+    }
+
+    fn skill_training(&mut self, key: SkillId, training: SkillAdvancementClass) -> bool {
+        let Some(skill) = self.skills.get_mut(&key) else {
+            return false;
+        };
+        skill.training_level = training;
+        true
+    }
+
+    fn set_attribute(&mut self, key: AttributeId, value: AttributeInfo) -> bool {
+        self.attributes[attribute_index(key)] = Some(value);
+        true
+    }
+
+    /// A level update sets the points raised, keeping the rest of the
+    /// attribute
+    fn attribute_level(&mut self, key: AttributeId, level: u32) -> bool {
+        let Some(attribute) = self.attributes[attribute_index(key)].as_mut() else {
+            return false;
+        };
+        attribute.points_raised = level;
+        true
+    }
+
+    fn set_vital(&mut self, key: VitalId, value: SecondaryAttributeInfo) -> bool {
+        self.vitals[vital_index(key)] = Some(value);
+        true
+    }
+
+    /// Current vitals have their own ids, each one more than the maximum's
+    fn current_vital(&mut self, key: CurVitalId, current: u32) -> bool {
+        let index = (key as usize - 2) / 2;
+        let Some(vital) = self.vitals[index].as_mut() else {
+            return false;
+        };
+        vital.current = current;
+        true
+    }
+}
+
+fn attribute_index(key: AttributeId) -> usize {
+    key as usize - 1
+}
+
+fn vital_index(key: VitalId) -> usize {
+    (key as usize - 1) / 2
+}
+
+fn table<K, V>(table: &Option<PackableHashTable<K, V>>) -> HashMap<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    table.as_ref().map(|t| t.table.clone()).unwrap_or_default()
+}
+
+/// A key of one of the sheet's tables, which diffs are sorted by
+trait TableKey: Clone + Eq + Hash {
+    /// The key as sent on the wire
+    fn value(&self) -> i64;
+}
+
+macro_rules! table_keys {
+    ($($key:ty),*) => {
+        $(impl TableKey for $key {
+            fn value(&self) -> i64 {
+                self.clone() as i64
+            }
+        })*
+    };
+}
+
+table_keys!(
+    PropertyInt,
+    PropertyInt64,
+    PropertyBool,
+    PropertyFloat,
+    PropertyString,
+    PropertyDataId,
+    PropertyInstanceId,
+    PropertyPosition,
+    SkillId
+);
+
+fn diff_tables<K, V>(before: &HashMap<K, V>, after: &HashMap<K, V>) -> Vec<Change<K, V>>
+where
+    K: TableKey,
+    V: Clone + PartialEq,
+{
+    let changed = before.iter().filter_map(|(key, value)| {
+        let later = after.get(key);
+        (later != Some(value)).then(|| Change {
+            key: key.clone(),
+            before: Some(value.clone()),
+            after: later.cloned(),
+        })
+    });
+    let added = after
+        .iter()
+        .filter(|(key, _)| !before.contains_key(key))
+        .map(|(key, value)| Change {
+            key: key.clone(),
+            before: None,
+            after: Some(value.clone()),
+        });
+    let mut changes: Vec<_> = changed.chain(added).collect();
+    changes.sort_by_key(|change| change.key.value());
+    changes
+}
+
+fn diff_slots<K, V: Clone + PartialEq, const N: usize>(
+    before: &[Option<V>; N],
+    after: &[Option<V>; N],
+    key: impl Fn(usize) -> Option<K>,
+) -> Vec<Change<K, V>> {
+    (0..N)
+        .filter(|&i| before[i] != after[i])
+        .filter_map(|i| {
+            Some(Change {
+                key: key(i)?,
+                before: before[i].clone(),
+                after: after[i].clone(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{
+        ACBaseQualitiesFlags, ACQualitiesFlags, CharacterOptions1, SkillAdvancementClass,
+        WeenieType,
+    };
+    use crate::messages::s2c;
+    use crate::test_support::{hash_table, list, s2c};
+    use crate::types::{ACBaseQualities, ACQualities, AttributeCache};
+
+    const PLAYER: ObjectId = ObjectId(0x5000_0001);
+
+    fn attribute(points_raised: u32) -> AttributeInfo {
+        AttributeInfo {
+            points_raised,
+            innate_points: 100,
+            experience_spent: 0,
+        }
+    }
+
+    fn skill(points_raised: u16) -> Skill {
+        Skill {
+            points_raised,
+            adjust_pp: 0,
+            training_level: SkillAdvancementClass::Trained,
+            experience_spent: 0,
+            innate_points: 10,
+            resistance_of_last_check: 0,
+            last_used_time: 0.0,
+        }
+    }
+
+    fn login() -> MessageKind {
+        let description = LoginPlayerDescription {
+            base_qualities: ACBaseQualities {
+                flags: ACBaseQualitiesFlags::PROPERTY_INT | ACBaseQualitiesFlags::PROPERTY_STRING,
+                weenie_type: WeenieType::Creature,
+                int_properties: Some(hash_table(vec![(PropertyInt::Level, 126)])),
+                int64_properties: None,
+                bool_properties: None,
+                float_properties: None,
+                string_properties: Some(hash_table(vec![(
+                    PropertyString::Name,
+                    "Galahad".to_string(),
+                )])),
+                data_properties: None,
+                instance_properties: None,
+                position_properties: None,
+            },
+            qualities: ACQualities {
+                flags: ACQualitiesFlags::ATTRIBUTES | ACQualitiesFlags::SKILLS,
+                has_health: true,
+                attributes: Some(AttributeCache {
+                    flags: 0x1FF,
+                    strength: Some(attribute(90)),
+                    endurance: Some(attribute(10)),
+                    quickness: None,
+                    coordination: None,
+                    focus: None,
+                    self_: None,
+                    health: Some(SecondaryAttributeInfo {
+                        attribute: attribute(40),
+                        current: 200,
+                    }),
+                    stamina: None,
+                    mana: None,
+                }),
+                skills: Some(hash_table(vec![(SkillId::Sword, skill(50))])),
+                body: None,
+                spell_book: None,
+                enchantments: None,
+                event_filter: None,
+                emotes: None,
+                creation_profile: None,
+                page_data: None,
+                generators: None,
+                generator_registry: None,
+                generator_queue: None,
+            },
+            player_module: PlayerModule {
+                flags: 0,
+                options: CharacterOptions1::empty(),
+                shortcuts: None,
+                tab1_spells: list(Vec::new()),
+                tab2_spells: list(Vec::new()),
+                tab3_spells: list(Vec::new()),
+                tab4_spells: list(Vec::new()),
+                tab5_spells: list(Vec::new()),
+                tab6_spells: list(Vec::new()),
+                tab7_spells: list(Vec::new()),
+                tab8_spells: list(Vec::new()),
+                fill_comps: None,
+                spell_book_filters: None,
+                option_flags: None,
+                unknown100_1: None,
+                option_strings: None,
+                gameplay_options: None,
+            },
+            content_profile: list(Vec::new()),
+            inventory_placement: list(Vec::new()),
+        };
+        s2c(S2CMessage::OrderedGameEvent {
+            object_id: PLAYER.0,
+            sequence: 1,
+            event: Box::new(GameEventMessage::LoginPlayerDescription(Box::new(
+                description,
+            ))),
+        })
+    }
+
+    #[test]
+    fn test_login_fills_sheet() {
+        let mut character = CharacterState::new();
+        assert!(character.apply(&login()));
+
+        assert_eq!(character.id(), Some(PLAYER));
+        assert_eq!(character.int(PropertyInt::Level), Some(126));
+        assert_eq!(character.string(PropertyString::Name), Some("Galahad"));
+        assert_eq!(character.skill(SkillId::Sword).unwrap().points_raised, 50);
+        assert_eq!(
+            character
+                .attribute(AttributeId::Strength)
+                .unwrap()
+                .points_raised,
+            90
+        );
+        assert!(character.attribute(AttributeId::Self_).is_none());
+        assert_eq!(
+            character.vital(VitalId::MaximumHealth).unwrap().current,
+            200
+        );
+        assert!(character.player_module().is_some());
+    }
+
+    #[test]
+    fn test_private_updates_and_removes() {
+        let mut character = CharacterState::new();
+        character.apply(&login());
+
+        assert!(character.apply(&s2c(S2CMessage::QualitiesPrivateUpdateInt(
+            s2c::QualitiesPrivateUpdateInt {
+                sequence: 1,
+                key: PropertyInt::Level,
+                value: 127,
+            }
+        ))));
+        assert!(
+            character.apply(&s2c(S2CMessage::QualitiesPrivateUpdateFloat(
+                s2c::QualitiesPrivateUpdateFloat {
+                    sequence: 2,
+                    key: PropertyFloat::HealthRate,
+                    value: 1.5,
+                }
+            )))
+        );
+        assert!(
+            character.apply(&s2c(S2CMessage::QualitiesPrivateUpdateSkillAC(
+                s2c::QualitiesPrivateUpdateSkillAC {
+                    sequence: 3,
+                    key: SkillId::Sword,
+                    value: SkillAdvancementClass::Specialized,
+                }
+            )))
+        );
+        assert!(
+            character.apply(&s2c(S2CMessage::QualitiesPrivateUpdateAttribute2ndLevel(
+                s2c::QualitiesPrivateUpdateAttribute2ndLevel {
+                    sequence: 4,
+                    key: CurVitalId::CurrentHealth,
+                    value: 150,
+                }
+            )))
+        );
+        assert!(
+            character.apply(&s2c(S2CMessage::QualitiesPrivateRemoveStringEvent(
+                s2c::QualitiesPrivateRemoveStringEvent {
+                    sequence: 5,
+                    type_: PropertyString::Name,
+                }
+            )))
+        );
+
+        assert_eq!(character.int(PropertyInt::Level), Some(127));
+        assert_eq!(character.float(PropertyFloat::HealthRate), Some(1.5));
+        assert_eq!(
+            character.skill(SkillId::Sword).unwrap().training_level,
+            SkillAdvancementClass::Specialized
+        );
+        assert_eq!(
+            character.vital(VitalId::MaximumHealth).unwrap().current,
+            150
+        );
+        assert_eq!(character.string(PropertyString::Name), None);
+        assert_eq!(character.message_index(), 6);
+    }
+
+    #[test]
+    fn test_public_updates_only_for_player() {
+        let mut character = CharacterState::new();
+        let update = |object_id| {
+            s2c(S2CMessage::QualitiesUpdateBool(s2c::QualitiesUpdateBool {
+                sequence: 1,
+                object_id,
+                key: PropertyBool::Open,
+                value: true,
+            }))
+        };
+
+        // Nobody is the player before the login description
+        assert!(!character.apply(&update(PLAYER)));
+        character.apply(&login());
+        assert!(!character.apply(&update(ObjectId(0x8000_0001))));
+        assert_eq!(character.bool(PropertyBool::Open), None);
+        assert!(character.apply(&update(PLAYER)));
+        assert_eq!(character.bool(PropertyBool::Open), Some(true));
+    }
+
+    #[test]
+    fn test_diff_between_points() {
+        let mut character = CharacterState::new();
+        character.apply(&login());
+        let before = character.clone();
+        assert!(before.diff(&character).is_empty());
+
+        character.apply(&s2c(S2CMessage::QualitiesPrivateUpdateInt64(
+            s2c::QualitiesPrivateUpdateInt64 {
+                sequence: 1,
+                key: PropertyInt64::TotalExperience,
+                value: 1_000_000,
+            },
+        )));
+        character.apply(&s2c(S2CMessage::QualitiesPrivateUpdateAttributeLevel(
+            s2c::QualitiesPrivateUpdateAttributeLevel {
+                sequence: 2,
+                key: AttributeId::Strength,
+                value: 91,
+            },
+        )));
+
+        let diff = before.diff(&character);
+        assert_eq!(
+            diff.int64s,
+            vec![Change {
+                key: PropertyInt64::TotalExperience,
+                before: None,
+                after: Some(1_000_000),
+            }]
+        );
+        assert_eq!(
+            diff.attributes,
+            vec![Change {
+                key: AttributeId::Strength,
+                before: Some(attribute(90)),
+                after: Some(attribute(91)),
+            }]
+        );
+        assert!(diff.ints.is_empty() && diff.skills.is_empty() && diff.vitals.is_empty());
+    }
+
+    #[test]
+    fn test_diff_in_key_order() {
+        let before = CharacterState::new();
+        let mut character = before.clone();
+        for (sequence, key) in [
+            PropertyInt::Level,
+            PropertyInt::EncumbranceVal,
+            PropertyInt::ItemType,
+            PropertyInt::Value,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            character.apply(&s2c(S2CMessage::QualitiesPrivateUpdateInt(
+                s2c::QualitiesPrivateUpdateInt {
+                    sequence: sequence as u8,
+                    key,
+                    value: 1,
+                },
+            )));
+        }
+
+        let keys: Vec<_> = before
+            .diff(&character)
+            .ints
+            .into_iter()
+            .map(|change| change.key as u32)
+            .collect();
+        assert_eq!(keys.len(), 4);
+        assert!(keys.is_sorted());
+    }
+}
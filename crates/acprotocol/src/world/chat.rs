@@ -0,0 +1,459 @@
+use serde::Serialize;
+use std::fmt;
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::enums::{Channel, ChatFragmentType, ChatType};
+use crate::message::{
+    C2SMessage, Direction, GameActionMessage, GameEventMessage, MessageKind, S2CMessage,
+};
+use crate::messages::{c2s, s2c};
+use crate::network::{RawMessage, serialize_capture_time};
+use crate::types::ObjectId;
+
+/// Where a line of chat was heard
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum ChatChannel {
+    /// `CommunicationHearSpeech`, said nearby
+    Local,
+    /// `CommunicationHearRangedSpeech`, shouted or said by a creature
+    Ranged,
+    /// `CommunicationHearDirectSpeech`
+    Tell,
+    /// `CommunicationHearEmote` and `CommunicationHearSoulEmote`
+    Emote,
+    /// `CommunicationChannelBroadcast`: allegiance, fellowship and the
+    /// other legacy channels
+    Broadcast(Channel),
+    /// `CommunicationTurbineChat`: general, trade, LFG and the other rooms
+    Turbine(ChatType),
+    /// `CommunicationTextboxString` and `CommunicationTransientString`
+    System,
+}
+
+impl fmt::Display for ChatChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatChannel::Local => f.write_str("Local"),
+            ChatChannel::Ranged => f.write_str("Ranged"),
+            ChatChannel::Tell => f.write_str("Tell"),
+            ChatChannel::Emote => f.write_str("Emote"),
+            ChatChannel::Broadcast(channel) => {
+                let names: Vec<_> = channel.iter_names().map(|(name, _)| name).collect();
+                if names.is_empty() {
+                    write!(f, "Channel 0x{:08X}", channel.bits())
+                } else {
+                    f.write_str(&names.join("|"))
+                }
+            }
+            ChatChannel::Turbine(chat_type) => write!(f, "{chat_type}"),
+            ChatChannel::System => f.write_str("System"),
+        }
+    }
+}
+
+/// A line of chat, whichever message carried it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChatLine {
+    pub message_index: usize,
+    /// Capture time of the message, since the Unix epoch
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_capture_time"
+    )]
+    pub time: Option<Duration>,
+    pub direction: Direction,
+    pub channel: ChatChannel,
+    /// The chat window category, for messages that carry one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ChatFragmentType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_name: Option<String>,
+    pub text: String,
+}
+
+/// Chat collected from a message stream, in the order it was seen
+#[derive(Debug, Clone, Default)]
+pub struct ChatLog {
+    lines: Vec<ChatLine>,
+    messages: usize,
+}
+
+impl ChatLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines(&self) -> &[ChatLine] {
+        &self.lines
+    }
+
+    pub fn into_lines(self) -> Vec<ChatLine> {
+        self.lines
+    }
+
+    /// Decode and apply a captured message, timed by its last fragment.
+    /// Returns whether it carried chat.
+    pub fn apply_raw(&mut self, message: &RawMessage) -> bool {
+        match MessageKind::read(&mut Cursor::new(&message.data), message.direction) {
+            Ok(decoded) => self.apply(&decoded, message.last_time),
+            Err(_) => {
+                self.messages += 1;
+                false
+            }
+        }
+    }
+
+    /// Apply a message captured at `time`. Returns whether it carried chat.
+    pub fn apply(&mut self, message: &MessageKind, time: Option<Duration>) -> bool {
+        let message_index = self.messages;
+        self.messages += 1;
+        let (direction, parts) = match message {
+            MessageKind::C2S(message) => (Direction::ClientToServer, c2s_chat(message)),
+            MessageKind::S2C(message) => (Direction::ServerToClient, s2c_chat(message)),
+        };
+        let Some(parts) = parts else {
+            return false;
+        };
+
+        self.lines.push(ChatLine {
+            message_index,
+            time,
+            direction,
+            channel: parts.channel,
+            kind: parts.kind,
+            sender_id: parts.sender_id,
+            sender_name: parts.sender_name,
+            text: parts.text,
+        });
+        true
+    }
+}
+
+/// The fields of a `ChatLine` a single message provides
+struct Parts {
+    channel: ChatChannel,
+    kind: Option<ChatFragmentType>,
+    sender_id: Option<ObjectId>,
+    sender_name: Option<String>,
+    text: String,
+}
+
+impl Parts {
+    fn new(channel: ChatChannel, text: &str) -> Self {
+        Self {
+            channel,
+            kind: None,
+            sender_id: None,
+            sender_name: None,
+            text: text.to_string(),
+        }
+    }
+
+    fn kind(mut self, kind: &ChatFragmentType) -> Self {
+        self.kind = Some(kind.clone());
+        self
+    }
+
+    fn sender(mut self, id: Option<ObjectId>, name: Option<&str>) -> Self {
+        self.sender_id = id;
+        self.sender_name = name.map(str::to_string);
+        self
+    }
+}
+
+/// Chat the client sent. The client's own turbine chat carries the room type
+/// as a plain number.
+fn c2s_chat(message: &C2SMessage) -> Option<Parts> {
+    let turbine = |chat_type: u32| ChatChannel::Turbine(chat_type_of(chat_type));
+    match message {
+        C2SMessage::OrderedGameAction {
+            action: GameActionMessage::CommunicationChannelBroadcast(action),
+            ..
+        } => Some(
+            Parts::new(ChatChannel::Broadcast(action.channel), &action.message)
+                .sender(None, Some(&action.sender_name)),
+        ),
+        C2SMessage::CommunicationTurbineChat(c2s::CommunicationTurbineChat::Type1(message)) => {
+            let c2s::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(event) =
+                &message.blob_dispatch_type;
+            Some(
+                Parts::new(turbine(event.chat_type), &event.text.0)
+                    .sender(Some(event.speaker_id), Some(&event.display_name.0)),
+            )
+        }
+        C2SMessage::CommunicationTurbineChat(c2s::CommunicationTurbineChat::Type3(message)) => {
+            let c2s::CommunicationTurbineChatType3BlobDispatchTypeVariant::Type2(request) =
+                &message.blob_dispatch_type;
+            Some(
+                Parts::new(turbine(request.chat_type), &request.text.0)
+                    .sender(Some(request.speaker_id), None),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn s2c_chat(message: &S2CMessage) -> Option<Parts> {
+    let parts = match message {
+        S2CMessage::OrderedGameEvent { event, .. } => return game_event_chat(event),
+        S2CMessage::CommunicationHearSpeech(message) => {
+            Parts::new(ChatChannel::Local, &message.message)
+                .kind(&message.type_)
+                .sender(Some(message.sender_id), Some(&message.sender_name))
+        }
+        S2CMessage::CommunicationHearRangedSpeech(message) => {
+            Parts::new(ChatChannel::Ranged, &message.message)
+                .kind(&message.type_)
+                .sender(Some(message.sender_id), Some(&message.sender_name))
+        }
+        S2CMessage::CommunicationHearEmote(message) => {
+            Parts::new(ChatChannel::Emote, &message.text)
+                .sender(Some(message.sender_id), Some(&message.sender_name))
+        }
+        S2CMessage::CommunicationHearSoulEmote(message) => {
+            Parts::new(ChatChannel::Emote, &message.text)
+                .sender(Some(message.sender_id), Some(&message.sender_name))
+        }
+        S2CMessage::CommunicationTextboxString(message) => {
+            Parts::new(ChatChannel::System, &message.text).kind(&message.type_)
+        }
+        S2CMessage::CommunicationTurbineChat(s2c::CommunicationTurbineChat::Type1(message)) => {
+            let s2c::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(event) =
+                &message.blob_dispatch_type;
+            Parts::new(ChatChannel::Turbine(event.chat_type.clone()), &event.text.0)
+                .sender(Some(event.speaker_id), Some(&event.display_name.0))
+        }
+        S2CMessage::CommunicationTurbineChat(s2c::CommunicationTurbineChat::Type3(message)) => {
+            let s2c::CommunicationTurbineChatType3BlobDispatchTypeVariant::Type2(request) =
+                &message.blob_dispatch_type;
+            Parts::new(
+                ChatChannel::Turbine(request.chat_type.clone()),
+                &request.text.0,
+            )
+            .sender(Some(request.speaker_id), None)
+        }
+        _ => return None,
+    };
+    Some(parts)
+}
+
+fn game_event_chat(event: &GameEventMessage) -> Option<Parts> {
+    let parts = match event {
+        GameEventMessage::CommunicationHearDirectSpeech(event) => {
+            Parts::new(ChatChannel::Tell, &event.message)
+                .kind(&event.type_)
+                .sender(Some(event.sender_id), Some(&event.sender_name))
+        }
+        GameEventMessage::CommunicationChannelBroadcast(event) => {
+            Parts::new(ChatChannel::Broadcast(event.channel), &event.message)
+        }
+        GameEventMessage::CommunicationTransientString(event) => {
+            Parts::new(ChatChannel::System, &event.message)
+        }
+        _ => return None,
+    };
+    Some(parts)
+}
+
+/// Room types the protocol doesn't name fall back to `Undef`
+fn chat_type_of(value: u32) -> ChatType {
+    ChatType::try_from(value).unwrap_or(ChatType::Undef)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameevents;
+    use crate::test_support::{s2c, turbine_chat_event, turbine_chat_request};
+
+    #[test]
+    fn test_speech_and_tells() {
+        let mut log = ChatLog::new();
+        let speech = s2c(S2CMessage::CommunicationHearSpeech(
+            s2c::CommunicationHearSpeech {
+                message: "hello".to_string(),
+                sender_name: "Asheron".to_string(),
+                sender_id: ObjectId(0x5000_0001),
+                type_: ChatFragmentType::Speech,
+            },
+        ));
+        let tell = s2c(S2CMessage::OrderedGameEvent {
+            object_id: 0x5000_0002,
+            sequence: 1,
+            event: Box::new(GameEventMessage::CommunicationHearDirectSpeech(
+                gameevents::CommunicationHearDirectSpeech {
+                    message: "psst".to_string(),
+                    sender_name: "Bael'Zharon".to_string(),
+                    sender_id: ObjectId(0x5000_0003),
+                    target_id: ObjectId(0x5000_0002),
+                    type_: ChatFragmentType::Tell,
+                    secret_flags: 0,
+                },
+            )),
+        });
+        let time = Duration::from_secs(1_763_490_291);
+
+        assert!(log.apply(&speech, Some(time)));
+        assert!(!log.apply(
+            &s2c(S2CMessage::ItemServerSaysRemove(
+                s2c::ItemServerSaysRemove {
+                    object_id: ObjectId(1),
+                }
+            )),
+            None
+        ));
+        assert!(log.apply(&tell, None));
+
+        let lines = log.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].channel, ChatChannel::Local);
+        assert_eq!(lines[0].kind, Some(ChatFragmentType::Speech));
+        assert_eq!(lines[0].sender_name.as_deref(), Some("Asheron"));
+        assert_eq!(lines[0].time, Some(time));
+        assert_eq!(lines[1].message_index, 2);
+        assert_eq!(lines[1].channel, ChatChannel::Tell);
+        assert_eq!(lines[1].sender_id, Some(ObjectId(0x5000_0003)));
+        assert_eq!(lines[1].text, "psst");
+    }
+
+    #[test]
+    fn test_channel_broadcasts() {
+        let mut log = ChatLog::new();
+        let sent = MessageKind::C2S(Box::new(C2SMessage::OrderedGameAction {
+            sequence: 1,
+            action: GameActionMessage::CommunicationChannelBroadcast(
+                crate::gameactions::CommunicationChannelBroadcast {
+                    channel: Channel::FELLOW,
+                    sender_name: String::new(),
+                    message: "heal me".to_string(),
+                },
+            ),
+        }));
+        let heard = s2c(S2CMessage::OrderedGameEvent {
+            object_id: 0x5000_0002,
+            sequence: 2,
+            event: Box::new(GameEventMessage::CommunicationChannelBroadcast(
+                gameevents::CommunicationChannelBroadcast {
+                    channel: Channel::FELLOW,
+                    message: "on it".to_string(),
+                },
+            )),
+        });
+
+        assert!(log.apply(&sent, None));
+        assert!(log.apply(&heard, None));
+
+        let lines = log.into_lines();
+        assert_eq!(lines[0].direction, Direction::ClientToServer);
+        assert_eq!(lines[1].direction, Direction::ServerToClient);
+        assert_eq!(lines[1].channel, ChatChannel::Broadcast(Channel::FELLOW));
+        assert_eq!(lines[1].channel.to_string(), "FELLOW");
+        assert_eq!(ChatChannel::Turbine(ChatType::Trade).to_string(), "Trade");
+    }
+
+    #[test]
+    fn test_turbine_chat_both_ways() {
+        let mut log = ChatLog::new();
+        let sent = turbine_chat_request("Galahad", "WTS sword");
+        let mut unnamed_room = turbine_chat_request("Galahad", "hello?");
+        let C2SMessage::CommunicationTurbineChat(c2s::CommunicationTurbineChat::Type1(message)) =
+            &mut unnamed_room
+        else {
+            unreachable!()
+        };
+        let c2s::CommunicationTurbineChatType1BlobDispatchTypeVariant::Type1(event) =
+            &mut message.blob_dispatch_type;
+        event.chat_type = 0x99;
+
+        assert!(log.apply(&MessageKind::C2S(Box::new(sent)), None));
+        assert!(log.apply(&MessageKind::C2S(Box::new(unnamed_room)), None));
+        assert!(log.apply(&s2c(turbine_chat_event("Lancelot", "WTB sword")), None));
+
+        let lines = log.lines();
+        assert_eq!(lines[0].direction, Direction::ClientToServer);
+        assert_eq!(lines[0].channel, ChatChannel::Turbine(ChatType::Trade));
+        assert_eq!(lines[0].sender_name.as_deref(), Some("Galahad"));
+        assert_eq!(lines[0].text, "WTS sword");
+        assert_eq!(lines[1].channel, ChatChannel::Turbine(ChatType::Undef));
+        assert_eq!(lines[2].direction, Direction::ServerToClient);
+        assert_eq!(lines[2].channel, ChatChannel::Turbine(ChatType::Trade));
+        assert_eq!(lines[2].sender_id, Some(ObjectId(0x5000_0001)));
+        assert_eq!(lines[2].sender_name.as_deref(), Some("Lancelot"));
+        assert_eq!(lines[2].text, "WTB sword");
+
+        assert_eq!(chat_type_of(ChatType::LFG as u32), ChatType::LFG);
+        assert_eq!(chat_type_of(0x99), ChatType::Undef);
+    }
+
+    #[test]
+    fn test_ranged_emotes_and_system_text() {
+        let mut log = ChatLog::new();
+        let drudge = ObjectId(0x8000_0001);
+        let messages = [
+            s2c(S2CMessage::CommunicationHearRangedSpeech(
+                s2c::CommunicationHearRangedSpeech {
+                    message: "You'll never take me alive!".to_string(),
+                    sender_name: "Drudge Skulker".to_string(),
+                    sender_id: drudge,
+                    range: 30.0,
+                    type_: ChatFragmentType::Speech,
+                },
+            )),
+            s2c(S2CMessage::CommunicationHearEmote(
+                s2c::CommunicationHearEmote {
+                    sender_id: drudge,
+                    sender_name: "Drudge Skulker".to_string(),
+                    text: "snarls".to_string(),
+                },
+            )),
+            s2c(S2CMessage::CommunicationHearSoulEmote(
+                s2c::CommunicationHearSoulEmote {
+                    sender_id: drudge,
+                    sender_name: "Drudge Skulker".to_string(),
+                    text: "flees".to_string(),
+                },
+            )),
+            s2c(S2CMessage::CommunicationTextboxString(
+                s2c::CommunicationTextboxString {
+                    text: "You have slain the Drudge Skulker!".to_string(),
+                    type_: ChatFragmentType::Combat,
+                },
+            )),
+            s2c(S2CMessage::OrderedGameEvent {
+                object_id: 0x5000_0001,
+                sequence: 1,
+                event: Box::new(GameEventMessage::CommunicationTransientString(
+                    gameevents::CommunicationTransientString {
+                        message: "You are too encumbered to move!".to_string(),
+                    },
+                )),
+            }),
+        ];
+        for message in &messages {
+            assert!(log.apply(message, None));
+        }
+
+        let lines = log.lines();
+        assert_eq!(lines[0].channel, ChatChannel::Ranged);
+        assert_eq!(lines[0].kind, Some(ChatFragmentType::Speech));
+        assert_eq!(lines[0].sender_id, Some(drudge));
+        assert_eq!(
+            (&lines[1].channel, lines[1].text.as_str()),
+            (&ChatChannel::Emote, "snarls")
+        );
+        assert_eq!(
+            (&lines[2].channel, lines[2].text.as_str()),
+            (&ChatChannel::Emote, "flees")
+        );
+        assert_eq!(lines[2].sender_name.as_deref(), Some("Drudge Skulker"));
+        assert_eq!(lines[3].channel, ChatChannel::System);
+        assert_eq!(lines[3].kind, Some(ChatFragmentType::Combat));
+        assert_eq!(lines[3].sender_name, None);
+        assert_eq!(lines[4].channel, ChatChannel::System);
+        assert_eq!(lines[4].kind, None);
+        assert_eq!(lines[4].text, "You are too encumbered to move!");
+    }
+}
@@ -0,0 +1,601 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::enums::{EnchantmentTypeFlags, SpellCategory};
+use crate::gameevents::LoginPlayerDescription;
+use crate::message::{GameEventMessage, MessageKind, S2CMessage};
+use crate::network::{RawMessage, serialize_capture_time};
+use crate::types::{Enchantment, LayeredSpellId, ObjectId, StatMod};
+
+/// How close to its due time a removal has to come to count as expiry
+const EXPIRY_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Which list of the registry an enchantment lives in
+///
+/// The registry sent at login calls the multiplicative list `LifeSpells`
+/// and the additive one `CreatureSpells`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Bucket {
+    Multiplicative,
+    Additive,
+    Vitae,
+    Cooldown,
+}
+
+impl Bucket {
+    pub fn of(enchantment: &Enchantment) -> Self {
+        let flags = enchantment.stat_mod.type_;
+        if flags.contains(EnchantmentTypeFlags::COOLDOWN) {
+            Bucket::Cooldown
+        } else if flags.contains(EnchantmentTypeFlags::VITAE) {
+            Bucket::Vitae
+        } else if flags.contains(EnchantmentTypeFlags::MULTIPLICATIVE) {
+            Bucket::Multiplicative
+        } else {
+            Bucket::Additive
+        }
+    }
+}
+
+/// Why an enchantment stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum EndReason {
+    /// Removed around when its duration ran out
+    Expired,
+    /// Removed before its duration ran out, or with no capture times to
+    /// tell
+    Removed,
+    Dispelled,
+    /// Taken away by `MagicPurgeEnchantments` or `MagicPurgeBadEnchantments`
+    Purged,
+    /// The server sent the same spell and layer again
+    Refreshed,
+    /// A new login description replaced the whole registry
+    Reset,
+}
+
+/// A point in the message stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Moment {
+    pub message_index: usize,
+    /// Capture time of the message, since the Unix epoch
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_capture_time"
+    )]
+    pub time: Option<Duration>,
+}
+
+/// One stretch of time an enchantment was on the player
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuffSpan {
+    pub spell: LayeredSpellId,
+    pub category: SpellCategory,
+    pub bucket: Bucket,
+    pub caster: ObjectId,
+    pub power_level: u32,
+    pub stat_mod: StatMod,
+    /// Seconds the enchantment was cast to last. `None` for enchantments
+    /// that last until removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Seconds from `started` to when the enchantment was cast: zero for a
+    /// new cast, negative for one in the registry sent at login that had
+    /// already been on for a while
+    pub start_time: f64,
+    pub started: Moment,
+    /// `None` while the enchantment is still on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended: Option<Moment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_reason: Option<EndReason>,
+}
+
+impl BuffSpan {
+    /// When the enchantment is due to run out, if its start time and
+    /// duration are known
+    pub fn due(&self) -> Option<Duration> {
+        let started = self.started.time?.as_secs_f64();
+        Duration::try_from_secs_f64(started + self.start_time + self.duration?).ok()
+    }
+}
+
+/// The combined effect of the enchantments that apply to one stat
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectiveMod {
+    /// Kind of stat, like `ATTRIBUTE` or `SKILL`, with `ATTACK_SKILLS` or
+    /// `DEFENSE_SKILLS` for mods that cover a group of skills
+    pub stat: EnchantmentTypeFlags,
+    /// Which stat of that kind, like an `AttributeId` or `SkillId`
+    pub key: u32,
+    /// Product of the multiplicative mods
+    pub multiplier: f32,
+    /// Sum of the additive mods
+    pub additive: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Active {
+    enchantment: Enchantment,
+    /// Index of the enchantment's span in the timeline
+    span: usize,
+}
+
+/// The player's enchantments, maintained from a stream of messages, with a
+/// timeline of when each one started and stopped
+///
+/// `LoginPlayerDescription` fills the registry in, `MagicUpdateEnchantment`
+/// and `MagicUpdateMultipleEnchantments` add to it, and the remove, dispel
+/// and purge events take away from it. Purges leave vitae, cooldowns and
+/// enchantments without a duration alone, and `MagicPurgeBadEnchantments`
+/// only takes the ones that aren't beneficial, like the client does.
+///
+/// Every cast of a spell is its own layer, and several enchantments of the
+/// same spell category can be on at once. Only the strongest of each
+/// category applies: the highest power level, and of those the latest
+/// cast.
+#[derive(Debug, Default, Clone)]
+pub struct EnchantmentState {
+    active: HashMap<LayeredSpellId, Active>,
+    timeline: Vec<BuffSpan>,
+    /// Messages seen, which stamp the timeline's start and end points
+    messages: usize,
+}
+
+impl EnchantmentState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index the timeline will stamp on whatever the next message changes
+    pub fn message_index(&self) -> usize {
+        self.messages
+    }
+
+    pub fn get(&self, spell: &LayeredSpellId) -> Option<&Enchantment> {
+        self.active.get(spell).map(|active| &active.enchantment)
+    }
+
+    /// Every enchantment on the player, including ones a stronger
+    /// enchantment of the same category surpasses
+    pub fn active(&self) -> impl Iterator<Item = &Enchantment> {
+        self.active.values().map(|active| &active.enchantment)
+    }
+
+    /// Every enchantment started so far, in the order they started
+    pub fn timeline(&self) -> &[BuffSpan] {
+        &self.timeline
+    }
+
+    /// The enchantments that apply, one per spell category, plus vitae. In
+    /// no particular order.
+    pub fn effective(&self) -> Vec<&Enchantment> {
+        let mut strongest: HashMap<u16, &Enchantment> = HashMap::new();
+        let mut effective = Vec::new();
+        for enchantment in self.active() {
+            match Bucket::of(enchantment) {
+                Bucket::Cooldown => {}
+                Bucket::Vitae => effective.push(enchantment),
+                Bucket::Multiplicative | Bucket::Additive => {
+                    let category = enchantment.spell_category.clone() as u16;
+                    strongest
+                        .entry(category)
+                        .and_modify(|current| {
+                            if outranks(enchantment, current) {
+                                *current = enchantment;
+                            }
+                        })
+                        .or_insert(enchantment);
+                }
+            }
+        }
+        effective.extend(strongest.into_values());
+        effective
+    }
+
+    /// What the effective enchantments do to each stat, ordered by stat
+    pub fn stat_mods(&self) -> Vec<EffectiveMod> {
+        let stat_types = EnchantmentTypeFlags::STAT_TYPES
+            | EnchantmentTypeFlags::ATTACK_SKILLS
+            | EnchantmentTypeFlags::DEFENSE_SKILLS;
+        let mut mods: BTreeMap<(u32, u32), EffectiveMod> = BTreeMap::new();
+        for enchantment in self.effective() {
+            let stat_mod = &enchantment.stat_mod;
+            let stat = stat_mod.type_ & stat_types;
+            let effective = mods
+                .entry((stat.bits(), stat_mod.key))
+                .or_insert(EffectiveMod {
+                    stat,
+                    key: stat_mod.key,
+                    multiplier: 1.0,
+                    additive: 0.0,
+                });
+            if stat_mod
+                .type_
+                .contains(EnchantmentTypeFlags::MULTIPLICATIVE)
+            {
+                effective.multiplier *= stat_mod.value;
+            } else {
+                effective.additive += stat_mod.value;
+            }
+        }
+        mods.into_values().collect()
+    }
+
+    /// Decode a raw message and apply it at its capture time. Returns
+    /// whether any enchantment changed.
+    pub fn apply_raw(&mut self, message: &RawMessage) -> bool {
+        match MessageKind::read(&mut Cursor::new(&message.data), message.direction) {
+            Ok(decoded) => self.apply(&decoded, message.last_time),
+            Err(_) => {
+                self.messages += 1;
+                false
+            }
+        }
+    }
+
+    /// Apply a message captured at `time`. Returns whether any enchantment
+    /// changed.
+    pub fn apply(&mut self, message: &MessageKind, time: Option<Duration>) -> bool {
+        let now = Moment {
+            message_index: self.messages,
+            time,
+        };
+        self.messages += 1;
+        let MessageKind::S2C(message) = message else {
+            return false;
+        };
+        let S2CMessage::OrderedGameEvent { event, .. } = message.as_ref() else {
+            return false;
+        };
+
+        match event.as_ref() {
+            GameEventMessage::LoginPlayerDescription(description) => {
+                self.login(description, now);
+                true
+            }
+            GameEventMessage::MagicUpdateEnchantment(event) => {
+                self.add(&event.enchantment, now);
+                true
+            }
+            GameEventMessage::MagicUpdateMultipleEnchantments(event) => {
+                for enchantment in &event.enchantments.list {
+                    self.add(enchantment, now);
+                }
+                !event.enchantments.list.is_empty()
+            }
+            GameEventMessage::MagicRemoveEnchantment(event) => self.remove(&event.spell_id, now),
+            GameEventMessage::MagicRemoveMultipleEnchantments(event) => {
+                let mut removed = false;
+                for spell in &event.enchantments.list {
+                    removed |= self.remove(spell, now);
+                }
+                removed
+            }
+            GameEventMessage::MagicDispelEnchantment(event) => {
+                self.end(&event.spell_id, now, EndReason::Dispelled)
+            }
+            GameEventMessage::MagicDispelMultipleEnchantments(event) => {
+                let mut dispelled = false;
+                for spell in &event.enchantments.list {
+                    dispelled |= self.end(spell, now, EndReason::Dispelled);
+                }
+                dispelled
+            }
+            GameEventMessage::MagicPurgeEnchantments(_) => self.purge(now, |_| true),
+            GameEventMessage::MagicPurgeBadEnchantments(_) => self.purge(now, |enchantment| {
+                !enchantment
+                    .stat_mod
+                    .type_
+                    .contains(EnchantmentTypeFlags::BENEFICIAL)
+            }),
+            _ => false,
+        }
+    }
+
+    /// Start over from the registry in a login description
+    fn login(&mut self, description: &LoginPlayerDescription, now: Moment) {
+        let spells: Vec<_> = self.active.keys().cloned().collect();
+        for spell in spells {
+            self.end(&spell, now, EndReason::Reset);
+        }
+
+        let Some(registry) = &description.qualities.enchantments else {
+            return;
+        };
+        let lists = [
+            &registry.life_spells,
+            &registry.creature_spells,
+            &registry.cooldowns,
+        ];
+        let listed = lists.into_iter().flatten().flat_map(|list| &list.list);
+        for enchantment in listed.chain(&registry.vitae) {
+            self.add(enchantment, now);
+        }
+    }
+
+    fn add(&mut self, enchantment: &Enchantment, now: Moment) {
+        self.end(&enchantment.id, now, EndReason::Refreshed);
+        self.timeline.push(BuffSpan {
+            spell: enchantment.id.clone(),
+            category: enchantment.spell_category.clone(),
+            bucket: Bucket::of(enchantment),
+            caster: enchantment.caster_id,
+            power_level: enchantment.power_level,
+            stat_mod: enchantment.stat_mod.clone(),
+            // Enchantments that last until removed have a negative duration
+            duration: (enchantment.duration >= 0.0).then_some(enchantment.duration),
+            start_time: enchantment.start_time,
+            started: now,
+            ended: None,
+            end_reason: None,
+        });
+        self.active.insert(
+            enchantment.id.clone(),
+            Active {
+                enchantment: enchantment.clone(),
+                span: self.timeline.len() - 1,
+            },
+        );
+    }
+
+    /// End an enchantment the server removed, telling expiry apart from
+    /// early removal by when it was due
+    fn remove(&mut self, spell: &LayeredSpellId, now: Moment) -> bool {
+        let Some(active) = self.active.get(spell) else {
+            return false;
+        };
+        let due = self.timeline[active.span].due();
+        let reason = match (due, now.time) {
+            (Some(due), Some(time)) if time + EXPIRY_TOLERANCE >= due => EndReason::Expired,
+            _ => EndReason::Removed,
+        };
+        self.end(spell, now, reason)
+    }
+
+    fn end(&mut self, spell: &LayeredSpellId, now: Moment, reason: EndReason) -> bool {
+        let Some(active) = self.active.remove(spell) else {
+            return false;
+        };
+        let span = &mut self.timeline[active.span];
+        span.ended = Some(now);
+        span.end_reason = Some(reason);
+        true
+    }
+
+    /// End the purgeable enchantments `purge` picks
+    fn purge(&mut self, now: Moment, purge: impl Fn(&Enchantment) -> bool) -> bool {
+        let spells: Vec<_> = self
+            .active
+            .iter()
+            .filter(|(_, active)| {
+                let enchantment = &active.enchantment;
+                matches!(
+                    Bucket::of(enchantment),
+                    Bucket::Multiplicative | Bucket::Additive
+                ) && enchantment.duration >= 0.0
+                    && purge(enchantment)
+            })
+            .map(|(spell, _)| spell.clone())
+            .collect();
+        for spell in &spells {
+            self.end(spell, now, EndReason::Purged);
+        }
+        !spells.is_empty()
+    }
+}
+
+/// Whether `a` takes precedence over `b` in their spell category
+fn outranks(a: &Enchantment, b: &Enchantment) -> bool {
+    (a.power_level, a.start_time) > (b.power_level, b.start_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{ACQualitiesFlags, EnchantmentRegistryFlags};
+    use crate::gameevents;
+    use crate::test_support::{list, player_description};
+    use crate::types::{EnchantmentRegistry, PackableList, SpellId};
+
+    const CASTER: ObjectId = ObjectId(0x5000_0001);
+
+    fn spell(id: u16, layer: u16) -> LayeredSpellId {
+        LayeredSpellId {
+            id: SpellId(id),
+            layer,
+        }
+    }
+
+    /// Strength raised by `value`, from a spell of `power_level`
+    fn strength(id: LayeredSpellId, power_level: u32, value: f32) -> Enchantment {
+        Enchantment {
+            id,
+            has_equipment_set: 0,
+            spell_category: SpellCategory::StrengthRaising,
+            power_level,
+            start_time: 0.0,
+            duration: 1800.0,
+            caster_id: CASTER,
+            degrade_modifier: 0.0,
+            degrade_limit: -666.0,
+            last_time_degraded: 0.0,
+            stat_mod: StatMod {
+                type_: EnchantmentTypeFlags::ATTRIBUTE
+                    | EnchantmentTypeFlags::SINGLE_STAT
+                    | EnchantmentTypeFlags::ADDITIVE
+                    | EnchantmentTypeFlags::BENEFICIAL,
+                key: 1,
+                value,
+            },
+            equipment_set: None,
+        }
+    }
+
+    fn event(event: GameEventMessage) -> MessageKind {
+        MessageKind::S2C(Box::new(S2CMessage::OrderedGameEvent {
+            object_id: CASTER.0,
+            sequence: 1,
+            event: Box::new(event),
+        }))
+    }
+
+    fn update(enchantment: Enchantment) -> MessageKind {
+        event(GameEventMessage::MagicUpdateEnchantment(
+            gameevents::MagicUpdateEnchantment { enchantment },
+        ))
+    }
+
+    fn at(secs: u64) -> Option<Duration> {
+        Some(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn test_strongest_of_category_applies() {
+        let mut state = EnchantmentState::new();
+        state.apply(&update(strength(spell(2, 1), 6, 35.0)), at(0));
+        let mut later = strength(spell(1, 1), 6, 30.0);
+        later.start_time = 10.0;
+        state.apply(&update(later), at(10));
+        state.apply(&update(strength(spell(3, 1), 5, 40.0)), at(20));
+
+        assert_eq!(state.active().count(), 3);
+        let effective = state.effective();
+        assert_eq!(effective.len(), 1);
+        // Same power level, so the later cast wins over the bigger one
+        assert_eq!(effective[0].id, spell(1, 1));
+        assert_eq!(
+            state.stat_mods(),
+            vec![EffectiveMod {
+                stat: EnchantmentTypeFlags::ATTRIBUTE,
+                key: 1,
+                multiplier: 1.0,
+                additive: 30.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_timeline_end_reasons() {
+        let mut state = EnchantmentState::new();
+        state.apply(&update(strength(spell(1, 1), 6, 30.0)), at(100));
+        state.apply(&update(strength(spell(2, 1), 6, 30.0)), at(100));
+        state.apply(&update(strength(spell(3, 1), 6, 30.0)), at(100));
+        state.apply(&update(strength(spell(3, 1), 6, 30.0)), at(200));
+
+        // Removed right on time is expiry, early is just removal
+        state.apply(
+            &event(GameEventMessage::MagicRemoveEnchantment(
+                gameevents::MagicRemoveEnchantment {
+                    spell_id: spell(1, 1),
+                },
+            )),
+            at(1900),
+        );
+        state.apply(
+            &event(GameEventMessage::MagicRemoveMultipleEnchantments(
+                gameevents::MagicRemoveMultipleEnchantments {
+                    enchantments: PackableList {
+                        count: 1,
+                        list: vec![spell(2, 1)],
+                    },
+                },
+            )),
+            at(500),
+        );
+        state.apply(
+            &event(GameEventMessage::MagicDispelEnchantment(
+                gameevents::MagicDispelEnchantment {
+                    spell_id: spell(3, 1),
+                },
+            )),
+            at(600),
+        );
+
+        let reasons: Vec<_> = state
+            .timeline()
+            .iter()
+            .map(|span| (span.spell.id.0, span.end_reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (1, Some(EndReason::Expired)),
+                (2, Some(EndReason::Removed)),
+                (3, Some(EndReason::Refreshed)),
+                (3, Some(EndReason::Dispelled)),
+            ]
+        );
+        let dispelled = &state.timeline()[3];
+        assert_eq!(dispelled.started.message_index, 3);
+        assert_eq!(dispelled.ended.unwrap().time, at(600));
+        assert_eq!(state.active().count(), 0);
+    }
+
+    #[test]
+    fn test_purge_bad_keeps_beneficial() {
+        let mut state = EnchantmentState::new();
+        state.apply(&update(strength(spell(1, 1), 6, 30.0)), None);
+        let mut curse = strength(spell(2, 1), 6, -30.0);
+        curse.spell_category = SpellCategory::StrengthLowering;
+        curse
+            .stat_mod
+            .type_
+            .remove(EnchantmentTypeFlags::BENEFICIAL);
+        state.apply(&update(curse), None);
+        let mut item_spell = strength(spell(3, 1), 6, -10.0);
+        item_spell
+            .stat_mod
+            .type_
+            .remove(EnchantmentTypeFlags::BENEFICIAL);
+        item_spell.duration = -1.0;
+        state.apply(&update(item_spell), None);
+
+        let purge_bad = event(GameEventMessage::MagicPurgeBadEnchantments(
+            gameevents::MagicPurgeBadEnchantments {},
+        ));
+        assert!(state.apply(&purge_bad, None));
+        assert!(state.get(&spell(1, 1)).is_some());
+        assert!(state.get(&spell(2, 1)).is_none());
+        // Lasts until removed, so purges leave it alone
+        assert!(state.get(&spell(3, 1)).is_some());
+        assert_eq!(state.timeline()[1].end_reason, Some(EndReason::Purged));
+    }
+
+    #[test]
+    fn test_login_enchantment_due_from_its_start_time() {
+        // Cast 1000 seconds before login, so 800 of its 1800 are left
+        let mut running = strength(spell(1, 1), 6, 30.0);
+        running.start_time = -1000.0;
+        let mut description = player_description();
+        description.qualities.flags |= ACQualitiesFlags::ENCHANTMENTS;
+        description.qualities.enchantments = Some(EnchantmentRegistry {
+            flags: EnchantmentRegistryFlags::CREATURE_SPELLS,
+            life_spells: None,
+            creature_spells: Some(list(vec![running])),
+            vitae: None,
+            cooldowns: None,
+        });
+
+        let mut state = EnchantmentState::new();
+        state.apply(
+            &event(GameEventMessage::LoginPlayerDescription(Box::new(
+                description,
+            ))),
+            at(100),
+        );
+        assert_eq!(state.timeline()[0].due(), at(900));
+
+        state.apply(
+            &event(GameEventMessage::MagicRemoveEnchantment(
+                gameevents::MagicRemoveEnchantment {
+                    spell_id: spell(1, 1),
+                },
+            )),
+            at(900),
+        );
+        assert_eq!(state.timeline()[0].end_reason, Some(EndReason::Expired));
+    }
+}
@@ -0,0 +1,398 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use crate::enums::{ContainerProperties, EquipMask};
+use crate::gameevents::LoginPlayerDescription;
+use crate::message::{GameEventMessage, MessageKind, S2CMessage};
+use crate::network::RawMessage;
+use crate::types::ObjectId;
+
+use super::objects::World;
+
+/// An item in the player's inventory, described as far as the world knows
+/// it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InventoryItem {
+    pub id: ObjectId,
+    /// `None` if the item was created before the capture started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Slots an equipped item covers. Empty for items in a pack.
+    #[serde(skip_serializing_if = "EquipMask::is_empty")]
+    pub slot: EquipMask,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack_size: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burden: Option<u16>,
+}
+
+/// A side pack in the main pack and what's in it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SidePack {
+    #[serde(flatten)]
+    pub pack: InventoryItem,
+    pub contents: Vec<InventoryItem>,
+}
+
+/// What the player carries, laid out like the inventory panel
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct InventoryLayout {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player: Option<ObjectId>,
+    /// Items in the main pack other than side packs, in slot order
+    pub pack: Vec<InventoryItem>,
+    /// Side packs in the main pack, in slot order
+    pub side_packs: Vec<SidePack>,
+    /// Worn and wielded items
+    pub equipped: Vec<InventoryItem>,
+    /// Total burden of everything carried. A container's burden already
+    /// includes its contents, as the server reports it.
+    pub burden: u32,
+}
+
+/// Where the player's items are, maintained from a stream of messages
+///
+/// The player's `LoginPlayerDescription` lists what's equipped and what's in
+/// the main pack. After that, `ItemOnViewContents` fills in a container when
+/// it's opened, `ItemServerSaysContainId` moves an item into a container
+/// slot, `ItemWearItem` equips it, and `ItemServerSaysMoveItem`,
+/// `ItemServerSaysRemove` and `ItemDeleteObject` take it out of the
+/// inventory. Names, stack sizes and burdens come from the world's objects,
+/// which are kept from the same messages.
+///
+/// Game events are addressed to the player, so without a login description
+/// the first one tells us who that is.
+#[derive(Debug, Default, Clone)]
+pub struct Inventory {
+    world: World,
+    player: Option<ObjectId>,
+    /// Items in each container, in slot order
+    contents: BTreeMap<ObjectId, Vec<ObjectId>>,
+    /// What kind of container each contained item is
+    container_types: BTreeMap<ObjectId, ContainerProperties>,
+    /// The player's worn and wielded items, with the slots they cover
+    equipped: BTreeMap<ObjectId, EquipMask>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn player(&self) -> Option<ObjectId> {
+        self.player
+    }
+
+    /// The objects the inventory was built alongside
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Items in a container, in slot order
+    pub fn contents(&self, container: ObjectId) -> &[ObjectId] {
+        self.contents.get(&container).map_or(&[], Vec::as_slice)
+    }
+
+    /// Slots an equipped item covers, or `None` if it isn't equipped
+    pub fn equipped_slot(&self, item: ObjectId) -> Option<EquipMask> {
+        self.equipped.get(&item).copied()
+    }
+
+    /// Decode a raw message and apply it. Returns whether the inventory or
+    /// any object changed.
+    pub fn apply_raw(&mut self, message: &RawMessage) -> bool {
+        match MessageKind::read(&mut Cursor::new(&message.data), message.direction) {
+            Ok(decoded) => self.apply(&decoded),
+            Err(_) => self.world.apply_raw(message),
+        }
+    }
+
+    /// Apply a message. Returns whether the inventory or any object changed.
+    pub fn apply(&mut self, message: &MessageKind) -> bool {
+        let world_changed = self.world.apply(message);
+        let MessageKind::S2C(message) = message else {
+            return world_changed;
+        };
+
+        let changed = match message.as_ref() {
+            S2CMessage::OrderedGameEvent {
+                object_id, event, ..
+            } => {
+                let player = ObjectId(*object_id);
+                self.player.get_or_insert(player);
+                self.game_event(player, event)
+            }
+            S2CMessage::ItemServerSaysRemove(event) => self.remove(event.object_id, true),
+            S2CMessage::ItemDeleteObject(event) => self.remove(event.object_id, true),
+            _ => false,
+        };
+        changed || world_changed
+    }
+
+    /// The player's inventory as it is now
+    pub fn layout(&self) -> InventoryLayout {
+        let Some(player) = self.player else {
+            return InventoryLayout::default();
+        };
+
+        let mut layout = InventoryLayout {
+            player: Some(player),
+            ..Default::default()
+        };
+        for &id in self.contents(player) {
+            match self.container_types.get(&id) {
+                Some(ContainerProperties::Container) => layout.side_packs.push(SidePack {
+                    pack: self.item(id, EquipMask::empty()),
+                    contents: self
+                        .contents(id)
+                        .iter()
+                        .map(|&id| self.item(id, EquipMask::empty()))
+                        .collect(),
+                }),
+                _ => layout.pack.push(self.item(id, EquipMask::empty())),
+            }
+        }
+        layout.equipped = self
+            .equipped
+            .iter()
+            .map(|(&id, &slot)| self.item(id, slot))
+            .collect();
+
+        let carried = layout
+            .pack
+            .iter()
+            .chain(layout.side_packs.iter().map(|pack| &pack.pack))
+            .chain(&layout.equipped);
+        layout.burden = carried.filter_map(|item| item.burden).map(u32::from).sum();
+        layout
+    }
+
+    fn game_event(&mut self, player: ObjectId, event: &GameEventMessage) -> bool {
+        match event {
+            GameEventMessage::LoginPlayerDescription(description) => {
+                self.login(player, description);
+                true
+            }
+            GameEventMessage::ItemOnViewContents(event) => {
+                for item in &event.items.list {
+                    self.remove(item.object_id, false);
+                    self.container_types
+                        .insert(item.object_id, item.container_type.clone());
+                }
+                let items = event.items.list.iter().map(|item| item.object_id);
+                self.contents.insert(event.container_id, items.collect());
+                true
+            }
+            GameEventMessage::ItemServerSaysContainId(event) => {
+                self.remove(event.object_id, false);
+                self.container_types
+                    .insert(event.object_id, event.container_type.clone());
+                let contents = self.contents.entry(event.container_id).or_default();
+                let slot = (event.slot_index as usize).min(contents.len());
+                contents.insert(slot, event.object_id);
+                true
+            }
+            GameEventMessage::ItemWearItem(event) => {
+                self.remove(event.object_id, false);
+                self.equipped.insert(event.object_id, event.slot);
+                true
+            }
+            // The item left the inventory, like when it's dropped
+            GameEventMessage::ItemServerSaysMoveItem(event) => self.remove(event.object_id, false),
+            _ => false,
+        }
+    }
+
+    /// Start over from a login description, which lists the main pack and
+    /// equipped items
+    fn login(&mut self, player: ObjectId, description: &LoginPlayerDescription) {
+        self.player = Some(player);
+        self.contents.clear();
+        self.container_types.clear();
+        self.equipped = description
+            .inventory_placement
+            .list
+            .iter()
+            .map(|placement| (placement.object_id, placement.location))
+            .collect();
+
+        let pack = &description.content_profile.list;
+        for item in pack {
+            self.container_types
+                .insert(item.object_id, item.container_type.clone());
+        }
+        self.contents
+            .insert(player, pack.iter().map(|item| item.object_id).collect());
+    }
+
+    /// Take an item out of whatever container or slot holds it. A destroyed
+    /// item takes its own contents with it.
+    fn remove(&mut self, item: ObjectId, destroyed: bool) -> bool {
+        let mut removed = self.equipped.remove(&item).is_some();
+        for contents in self.contents.values_mut() {
+            if let Some(slot) = contents.iter().position(|&id| id == item) {
+                contents.remove(slot);
+                removed = true;
+            }
+        }
+        self.container_types.remove(&item);
+        if destroyed {
+            removed |= self.contents.remove(&item).is_some();
+        }
+        removed
+    }
+
+    fn item(&self, id: ObjectId, slot: EquipMask) -> InventoryItem {
+        let object = self.world.get(id);
+        InventoryItem {
+            id,
+            name: object.map(|object| object.name().to_string()),
+            slot,
+            stack_size: object.and_then(|object| object.weenie.stack_size),
+            burden: object.and_then(|object| object.weenie.burden),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameevents;
+    use crate::messages::s2c;
+    use crate::test_support::{obj_desc, physics_desc, s2c, weenie_desc};
+    use crate::types::{ContentProfile, PackableList};
+
+    const PLAYER: ObjectId = ObjectId(0x5000_0001);
+    const BACKPACK: ObjectId = ObjectId(0x8000_0001);
+    const SWORD: ObjectId = ObjectId(0x8000_0002);
+    const PYREALS: ObjectId = ObjectId(0x8000_0003);
+
+    fn create(id: ObjectId, name: &str, burden: u16) -> MessageKind {
+        let mut weenie = weenie_desc(name);
+        weenie.burden = Some(burden);
+        s2c(S2CMessage::ItemCreateObject(s2c::ItemCreateObject {
+            object_id: id,
+            object_description: obj_desc(0),
+            physics_description: physics_desc(),
+            weenie_description: weenie,
+        }))
+    }
+
+    fn event(event: GameEventMessage) -> MessageKind {
+        s2c(S2CMessage::OrderedGameEvent {
+            object_id: PLAYER.0,
+            sequence: 1,
+            event: Box::new(event),
+        })
+    }
+
+    fn contain(item: ObjectId, container: ObjectId, slot_index: u32) -> MessageKind {
+        event(GameEventMessage::ItemServerSaysContainId(
+            gameevents::ItemServerSaysContainId {
+                object_id: item,
+                container_id: container,
+                slot_index,
+                container_type: ContainerProperties::None,
+            },
+        ))
+    }
+
+    /// A player carrying a backpack, with a sword and pyreals in the main pack
+    fn carrying() -> Inventory {
+        let mut inventory = Inventory::new();
+        inventory.apply(&create(BACKPACK, "Backpack", 65));
+        inventory.apply(&create(SWORD, "Sword", 300));
+        inventory.apply(&create(PYREALS, "Pyreal", 25));
+        inventory.apply(&event(GameEventMessage::ItemOnViewContents(
+            gameevents::ItemOnViewContents {
+                container_id: PLAYER,
+                items: PackableList {
+                    count: 3,
+                    list: vec![
+                        ContentProfile {
+                            object_id: SWORD,
+                            container_type: ContainerProperties::None,
+                        },
+                        ContentProfile {
+                            object_id: BACKPACK,
+                            container_type: ContainerProperties::Container,
+                        },
+                        ContentProfile {
+                            object_id: PYREALS,
+                            container_type: ContainerProperties::None,
+                        },
+                    ],
+                },
+            },
+        )));
+        inventory
+    }
+
+    fn ids(items: &[InventoryItem]) -> Vec<ObjectId> {
+        items.iter().map(|item| item.id).collect()
+    }
+
+    #[test]
+    fn test_layout_of_viewed_contents() {
+        let inventory = carrying();
+        assert_eq!(inventory.player(), Some(PLAYER));
+
+        let layout = inventory.layout();
+        assert_eq!(ids(&layout.pack), vec![SWORD, PYREALS]);
+        assert_eq!(layout.side_packs.len(), 1);
+        assert_eq!(layout.side_packs[0].pack.name.as_deref(), Some("Backpack"));
+        assert!(layout.side_packs[0].contents.is_empty());
+        assert_eq!(layout.burden, 390);
+    }
+
+    #[test]
+    fn test_moves_between_containers_and_slots() {
+        let mut inventory = carrying();
+        assert!(inventory.apply(&contain(PYREALS, BACKPACK, 0)));
+        assert!(inventory.apply(&event(GameEventMessage::ItemWearItem(
+            gameevents::ItemWearItem {
+                object_id: SWORD,
+                slot: EquipMask::MELEE_WEAPON,
+            }
+        ))));
+
+        let layout = inventory.layout();
+        assert!(layout.pack.is_empty());
+        assert_eq!(ids(&layout.side_packs[0].contents), vec![PYREALS]);
+        assert_eq!(ids(&layout.equipped), vec![SWORD]);
+        assert_eq!(layout.equipped[0].slot, EquipMask::MELEE_WEAPON);
+        // Inside the backpack, the pyreals count towards its burden once the
+        // server updates it
+        assert_eq!(layout.burden, 365);
+
+        // Back into the main pack, ahead of the backpack
+        inventory.apply(&contain(SWORD, PLAYER, 0));
+        assert_eq!(inventory.contents(PLAYER), &[SWORD, BACKPACK]);
+        assert_eq!(inventory.equipped_slot(SWORD), None);
+    }
+
+    #[test]
+    fn test_items_leave_the_inventory() {
+        let mut inventory = carrying();
+        inventory.apply(&contain(PYREALS, BACKPACK, 0));
+
+        assert!(
+            inventory.apply(&event(GameEventMessage::ItemServerSaysMoveItem(
+                gameevents::ItemServerSaysMoveItem { object_id: SWORD }
+            )))
+        );
+        // Dropped, but still in the world
+        assert!(inventory.world().get(SWORD).is_some());
+
+        assert!(inventory.apply(&s2c(S2CMessage::ItemServerSaysRemove(
+            s2c::ItemServerSaysRemove {
+                object_id: BACKPACK
+            }
+        ))));
+        assert!(inventory.contents(BACKPACK).is_empty());
+        let layout = inventory.layout();
+        assert!(layout.pack.is_empty() && layout.side_packs.is_empty());
+        assert_eq!(layout.burden, 0);
+    }
+}
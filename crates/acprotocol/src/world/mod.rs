@@ -0,0 +1,11 @@
+pub mod character;
+pub mod chat;
+pub mod enchantments;
+pub mod inventory;
+pub mod objects;
+
+pub use character::{Change, CharacterDiff, CharacterState};
+pub use chat::{ChatChannel, ChatLine, ChatLog};
+pub use enchantments::{Bucket, BuffSpan, EffectiveMod, EnchantmentState, EndReason, Moment};
+pub use inventory::{Inventory, InventoryItem, InventoryLayout, SidePack};
+pub use objects::{World, WorldObject, WorldSnapshot};
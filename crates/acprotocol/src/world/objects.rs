@@ -0,0 +1,444 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use crate::enums::Placement;
+use crate::message::{MessageKind, S2CMessage};
+use crate::network::RawMessage;
+use crate::types::{
+    Frame, ObjDesc, ObjectId, PhysicsDesc, Position, PositionPack, PublicWeenieDesc, Quaternion,
+};
+
+/// An object the server has told the client about, as of its latest update
+///
+/// The descriptions are kept as the server last sent them, with the smaller
+/// updates applied to the matching fields: states and parents to `physics`,
+/// stack sizes and values to `weenie`, and so on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WorldObject {
+    pub id: ObjectId,
+    pub weenie: PublicWeenieDesc,
+    pub physics: PhysicsDesc,
+    pub visual: ObjDesc,
+    /// How the object is held by its parent, from the last `ItemParentEvent`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placement: Option<Placement>,
+    /// Index of the message that created the object
+    pub created_at: usize,
+    /// Index of the last message that changed the object
+    pub updated_at: usize,
+}
+
+impl WorldObject {
+    fn new(
+        id: ObjectId,
+        weenie: PublicWeenieDesc,
+        physics: PhysicsDesc,
+        visual: ObjDesc,
+        index: usize,
+    ) -> Self {
+        Self {
+            id,
+            weenie,
+            physics,
+            visual,
+            placement: None,
+            created_at: index,
+            updated_at: index,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.weenie.name
+    }
+
+    /// Where the object was last placed in the world. `None` while it's
+    /// held by another object or inside a container.
+    pub fn position(&self) -> Option<&Position> {
+        self.physics.position.as_ref()
+    }
+
+    /// The object holding this one, like a wielder or container
+    pub fn parent(&self) -> Option<ObjectId> {
+        self.physics.parent_id.or(self.weenie.container_id)
+    }
+
+    /// Move the object to where a `PositionPack` puts it, which also takes
+    /// it out of whatever was holding it
+    fn place(&mut self, pack: &PositionPack) {
+        // Quaternion components that are left out are zero
+        let position = Position {
+            landcell: pack.origin.landcell,
+            frame: Frame {
+                origin: pack.origin.location.clone(),
+                orientation: Quaternion {
+                    w: pack.w_quat.unwrap_or(0.0),
+                    x: pack.x_quat.unwrap_or(0.0),
+                    y: pack.y_quat.unwrap_or(0.0),
+                    z: pack.z_quat.unwrap_or(0.0),
+                },
+            },
+        };
+        if pack.velocity.is_some() {
+            self.physics.velocity = pack.velocity.clone();
+        }
+        self.physics.position = Some(position);
+        self.physics.parent_id = None;
+        self.physics.parent_location = None;
+        self.placement = None;
+    }
+}
+
+/// The world's objects at one point in a message stream
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WorldSnapshot {
+    /// Number of messages applied before the snapshot was taken
+    pub message_index: usize,
+    pub objects: BTreeMap<ObjectId, WorldObject>,
+}
+
+impl WorldSnapshot {
+    pub fn get(&self, id: ObjectId) -> Option<&WorldObject> {
+        self.objects.get(&id)
+    }
+}
+
+/// Which objects exist right now, maintained from a stream of messages
+///
+/// Objects are created by `ItemCreateObject` and removed by
+/// `ItemDeleteObject` and `ItemServerSaysRemove`. In between,
+/// `ItemUpdateObject` replaces their descriptions wholesale, while
+/// `ItemObjDescEvent`, `ItemSetState`, `ItemParentEvent`,
+/// `ItemUpdateStackSize`, `MovementPositionEvent` and
+/// `MovementPositionAndMovementEvent` change parts of them and
+/// `InventoryPickupEvent` takes them off the landscape. Updates are
+/// applied in the order they're given; the sequence numbers that let the
+/// client discard stale ones aren't checked.
+///
+/// Feed it every message of a capture, in both directions, so message
+/// indexes line up with the stream's.
+#[derive(Debug, Default, Clone)]
+pub struct World {
+    objects: BTreeMap<ObjectId, WorldObject>,
+    /// Messages applied so far
+    messages: usize,
+    /// Raw messages that couldn't be decoded
+    undecoded: usize,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the world as it was after the first `index` messages
+    pub fn replay<'a, I>(messages: I, index: usize) -> WorldSnapshot
+    where
+        I: IntoIterator<Item = &'a MessageKind>,
+    {
+        let mut world = Self::new();
+        for message in messages.into_iter().take(index) {
+            world.apply(message);
+        }
+        world.snapshot()
+    }
+
+    /// Number of messages applied so far, i.e. the index of the next one
+    pub fn message_index(&self) -> usize {
+        self.messages
+    }
+
+    /// Raw messages that couldn't be decoded, which were counted but
+    /// otherwise ignored
+    pub fn undecoded(&self) -> usize {
+        self.undecoded
+    }
+
+    pub fn get(&self, id: ObjectId) -> Option<&WorldObject> {
+        self.objects.get(&id)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &WorldObject> {
+        self.objects.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Copy of the objects as they are now
+    pub fn snapshot(&self) -> WorldSnapshot {
+        WorldSnapshot {
+            message_index: self.messages,
+            objects: self.objects.clone(),
+        }
+    }
+
+    /// Decode a raw message and apply it. Returns whether any object changed.
+    pub fn apply_raw(&mut self, message: &RawMessage) -> bool {
+        match MessageKind::read(&mut Cursor::new(&message.data), message.direction) {
+            Ok(decoded) => self.apply(&decoded),
+            Err(_) => {
+                self.messages += 1;
+                self.undecoded += 1;
+                false
+            }
+        }
+    }
+
+    /// Apply a message. Returns whether any object changed.
+    pub fn apply(&mut self, message: &MessageKind) -> bool {
+        let index = self.messages;
+        self.messages += 1;
+        let MessageKind::S2C(message) = message else {
+            return false;
+        };
+
+        match message.as_ref() {
+            S2CMessage::ItemCreateObject(create) => {
+                let object = WorldObject::new(
+                    create.object_id,
+                    create.weenie_description.clone(),
+                    create.physics_description.clone(),
+                    create.object_description.clone(),
+                    index,
+                );
+                self.objects.insert(create.object_id, object);
+                true
+            }
+            S2CMessage::ItemUpdateObject(update) => {
+                let mut object = WorldObject::new(
+                    update.object_id,
+                    update.weenie_desc.clone(),
+                    update.physics_desc.clone(),
+                    update.object_desc.clone(),
+                    index,
+                );
+                // Without the create, which came before the capture started,
+                // the update is the first we know of the object
+                if let Some(existing) = self.objects.get(&update.object_id) {
+                    object.created_at = existing.created_at;
+                }
+                self.objects.insert(update.object_id, object);
+                true
+            }
+            S2CMessage::ItemObjDescEvent(event) => self.update(event.object_id, index, |object| {
+                object.visual = event.object_description.clone();
+            }),
+            S2CMessage::ItemSetState(event) => self.update(event.object_id, index, |object| {
+                object.physics.state = event.new_state;
+            }),
+            S2CMessage::ItemParentEvent(event) => self.update(event.child_id, index, |object| {
+                object.physics.parent_id = Some(event.parent_id);
+                object.physics.parent_location = Some(event.location.clone());
+                object.placement = Some(event.placement.clone());
+                object.physics.position = None;
+            }),
+            S2CMessage::ItemUpdateStackSize(event) => {
+                self.update(event.object_id, index, |object| {
+                    object.weenie.stack_size = u16::try_from(event.amount).ok();
+                    object.weenie.value = Some(event.new_value);
+                })
+            }
+            S2CMessage::MovementPositionEvent(event) => {
+                self.update(event.object_id, index, |object| {
+                    object.place(&event.position);
+                })
+            }
+            S2CMessage::MovementPositionAndMovementEvent(event) => {
+                self.update(event.object_id, index, |object| {
+                    object.place(&event.position);
+                })
+            }
+            S2CMessage::InventoryPickupEvent(event) => {
+                self.update(event.object_id, index, |object| {
+                    object.physics.position = None;
+                })
+            }
+            S2CMessage::ItemDeleteObject(event) => self.objects.remove(&event.object_id).is_some(),
+            S2CMessage::ItemServerSaysRemove(event) => {
+                self.objects.remove(&event.object_id).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Change an object the world knows about. Updates to objects created
+    /// before the stream started are ignored.
+    fn update(
+        &mut self,
+        id: ObjectId,
+        index: usize,
+        change: impl FnOnce(&mut WorldObject),
+    ) -> bool {
+        let Some(object) = self.objects.get_mut(&id) else {
+            return false;
+        };
+        change(object);
+        object.updated_at = index;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{ParentLocation, PhysicsState, PositionFlags};
+    use crate::messages::s2c;
+    use crate::test_support::{obj_desc, physics_desc, s2c, weenie_desc};
+    use crate::types::{LandcellId, Origin, Vector3};
+
+    const SWORD: ObjectId = ObjectId(0x8000_0001);
+    const PLAYER: ObjectId = ObjectId(0x5000_0001);
+
+    fn create(id: ObjectId, name: &str) -> MessageKind {
+        s2c(S2CMessage::ItemCreateObject(s2c::ItemCreateObject {
+            object_id: id,
+            object_description: obj_desc(0),
+            physics_description: physics_desc(),
+            weenie_description: weenie_desc(name),
+        }))
+    }
+
+    fn wield(child: ObjectId, parent: ObjectId) -> MessageKind {
+        s2c(S2CMessage::ItemParentEvent(s2c::ItemParentEvent {
+            parent_id: parent,
+            child_id: child,
+            location: ParentLocation::RightHand,
+            placement: Placement::RightHandCombat,
+            object_instance_sequence: 0,
+            child_position_sequence: 0,
+        }))
+    }
+
+    fn move_to(id: ObjectId, x: f32) -> MessageKind {
+        s2c(S2CMessage::MovementPositionEvent(
+            s2c::MovementPositionEvent {
+                object_id: id,
+                position: PositionPack {
+                    flags: PositionFlags::ORIENTATION_HAS_NO_X
+                        | PositionFlags::ORIENTATION_HAS_NO_Y
+                        | PositionFlags::ORIENTATION_HAS_NO_Z,
+                    origin: Origin {
+                        landcell: LandcellId(0xA9B4_0021),
+                        location: Vector3 { x, y: 20.0, z: 0.0 },
+                    },
+                    w_quat: Some(1.0),
+                    x_quat: None,
+                    y_quat: None,
+                    z_quat: None,
+                    velocity: None,
+                    placement_id: None,
+                    object_instance_sequence: 0,
+                    object_position_sequence: 0,
+                    object_teleport_sequence: 0,
+                    object_force_position_sequence: 0,
+                },
+            },
+        ))
+    }
+
+    #[test]
+    fn test_object_lifecycle() {
+        let mut world = World::new();
+        assert!(world.apply(&create(PLAYER, "Galahad")));
+        assert!(world.apply(&create(SWORD, "Sword")));
+        assert!(world.apply(&s2c(S2CMessage::ItemUpdateStackSize(
+            s2c::ItemUpdateStackSize {
+                sequence: 1,
+                object_id: SWORD,
+                amount: 3,
+                new_value: 150,
+            }
+        ))));
+        assert!(
+            world.apply(&s2c(S2CMessage::ItemSetState(s2c::ItemSetState {
+                object_id: SWORD,
+                new_state: PhysicsState::ETHEREAL,
+                object_instance_sequence: 0,
+                object_state_sequence: 1,
+            })))
+        );
+        assert!(world.apply(&wield(SWORD, PLAYER)));
+
+        let sword = world.get(SWORD).unwrap();
+        assert_eq!(sword.name(), "Sword");
+        assert_eq!(sword.weenie.stack_size, Some(3));
+        assert_eq!(sword.weenie.value, Some(150));
+        assert_eq!(sword.physics.state, PhysicsState::ETHEREAL);
+        assert_eq!(sword.parent(), Some(PLAYER));
+        assert_eq!(sword.placement, Some(Placement::RightHandCombat));
+        assert_eq!((sword.created_at, sword.updated_at), (1, 4));
+
+        assert!(
+            world.apply(&s2c(S2CMessage::ItemDeleteObject(s2c::ItemDeleteObject {
+                object_id: SWORD,
+                object_instance_sequence: 0,
+            })))
+        );
+        assert!(world.get(SWORD).is_none());
+        assert_eq!(world.len(), 1);
+        assert_eq!(world.message_index(), 6);
+    }
+
+    #[test]
+    fn test_position_takes_object_out_of_parent() {
+        let mut world = World::new();
+        world.apply(&create(SWORD, "Sword"));
+        world.apply(&wield(SWORD, PLAYER));
+        assert!(world.get(SWORD).unwrap().position().is_none());
+
+        world.apply(&move_to(SWORD, 10.0));
+        let sword = world.get(SWORD).unwrap();
+        let position = sword.position().unwrap();
+        assert_eq!(position.landcell, LandcellId(0xA9B4_0021));
+        assert_eq!(position.frame.origin.x, 10.0);
+        assert_eq!(position.frame.orientation.w, 1.0);
+        assert_eq!(position.frame.orientation.z, 0.0);
+        assert_eq!(sword.parent(), None);
+        assert_eq!(sword.placement, None);
+    }
+
+    #[test]
+    fn test_updates_to_unknown_objects_are_ignored() {
+        let mut world = World::new();
+        assert!(!world.apply(&move_to(SWORD, 10.0)));
+        assert!(!world.apply(&s2c(S2CMessage::ItemServerSaysRemove(
+            s2c::ItemServerSaysRemove { object_id: SWORD }
+        ))));
+        assert!(world.is_empty());
+        assert_eq!(world.message_index(), 2);
+    }
+
+    #[test]
+    fn test_replay_to_index() {
+        let messages = [
+            create(SWORD, "Sword"),
+            move_to(SWORD, 10.0),
+            move_to(SWORD, 30.0),
+        ];
+
+        let before = World::replay(&messages, 0);
+        assert!(before.objects.is_empty());
+
+        let middle = World::replay(&messages, 2);
+        assert_eq!(middle.message_index, 2);
+        let x = middle
+            .get(SWORD)
+            .unwrap()
+            .position()
+            .unwrap()
+            .frame
+            .origin
+            .x;
+        assert_eq!(x, 10.0);
+
+        let end = World::replay(&messages, messages.len());
+        let x = end.get(SWORD).unwrap().position().unwrap().frame.origin.x;
+        assert_eq!(x, 30.0);
+    }
+}
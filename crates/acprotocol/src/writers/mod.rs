@@ -654,6 +654,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_string_negative_long_length_rejected() {
+        // -1 announces a 32-bit length, which can't be negative either
+        let mut data = (-1i16).to_le_bytes().to_vec();
+        data.extend_from_slice(&(-2i32).to_le_bytes());
+
+        assert!(read_string(&mut Cursor::new(&data)).is_err());
+    }
+
     #[test]
     fn test_wstring_roundtrip() {
         let test_cases = vec!["", "Hello", "Unicode: こんにちは", "Emoji: 😀"];
@@ -0,0 +1,65 @@
+mod common;
+
+use acprotocol::network::pcap::{PcapIterator, PcapWriter};
+use acprotocol::network::udp::LINKTYPE_ETHERNET;
+use acprotocol::network::{CaptureAnonymizer, ChecksumStatus, FragmentAssembler, MessageStream};
+use std::net::IpAddr;
+
+/// Anonymizing the sample capture keeps every message, in the same order and
+/// with the same sizes, inside packets whose checksums still verify
+#[test]
+fn test_anonymized_capture_stays_valid() {
+    let Some(bytes) = common::sample_capture() else {
+        return;
+    };
+    let open = || PcapIterator::<&[u8]>::from_bytes(&bytes).unwrap();
+
+    let mut anonymizer = CaptureAnonymizer::new();
+    anonymizer.scan(open()).unwrap();
+    let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+    anonymizer.write(open(), &mut writer).unwrap();
+    let anonymized = writer.into_inner();
+
+    let stats = anonymizer.stats();
+    assert_eq!(stats.packets, open().count());
+    assert!(stats.anonymized > 0);
+    assert_eq!(stats.blanked, 0);
+
+    let mut assembler = FragmentAssembler::new();
+    for packet in PcapIterator::<&[u8]>::from_bytes(&anonymized).unwrap() {
+        let packet = packet.unwrap();
+        let parsed = assembler
+            .parse_link_packets(packet.link_type, &packet.data)
+            .unwrap();
+        for record in &parsed.packets {
+            assert_ne!(record.checksum, ChecksumStatus::Invalid);
+            for address in [record.source.ip(), record.destination.ip()] {
+                let IpAddr::V4(address) = address else {
+                    panic!("expected an IPv4 address, got {address}");
+                };
+                assert!(address.is_private());
+            }
+        }
+    }
+
+    let expected: Vec<_> = MessageStream::new(open()).map(Result::unwrap).collect();
+    let messages: Vec<_> =
+        MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&anonymized).unwrap())
+            .map(Result::unwrap)
+            .collect();
+    assert_eq!(messages.len(), expected.len());
+
+    let mut changed = 0;
+    for (a, b) in expected.iter().zip(&messages) {
+        assert_eq!(
+            (a.id, &a.message_type, a.direction),
+            (b.id, &b.message_type, b.direction)
+        );
+        assert_eq!(a.data.len(), b.data.len());
+        assert_eq!(a.first_time, b.first_time);
+        if a.data != b.data {
+            changed += 1;
+        }
+    }
+    assert_eq!(changed, stats.anonymized);
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use acprotocol::network::BandwidthAnalyzer;
+
+/// The sample capture's server traffic lines up with the FLOW headers the
+/// client sent back about it
+#[test]
+fn test_bandwidth_report_matches_flow_headers() {
+    let Some(datagrams) = common::sample_datagrams() else {
+        return;
+    };
+    let mut analyzer = BandwidthAnalyzer::new();
+    let mut messages = 0;
+    for (parsed, time) in &datagrams {
+        messages += parsed.messages.len() as u64;
+        analyzer.add_datagram(parsed, Some(*time));
+    }
+
+    let report = analyzer.finish();
+    assert_eq!(report.sessions.len(), 1);
+    let session = &report.sessions[0];
+    assert!(session.duration > 0.0);
+
+    let (sent, received) = (&session.client_to_server, &session.server_to_client);
+    assert_eq!(sent.packets.count + received.packets.count, 632);
+    for direction in [sent, received] {
+        let by_type: u64 = direction.message_types.values().map(|c| c.count).sum();
+        let by_queue: u64 = direction.queues.values().map(|c| c.count).sum();
+        assert_eq!(by_type, by_queue);
+        assert!(direction.packets.bytes_per_second.unwrap() > 0.0);
+    }
+    let total: u64 = [sent, received]
+        .iter()
+        .flat_map(|direction| direction.message_types.values())
+        .map(|c| c.count)
+        .sum();
+    assert_eq!(total, messages);
+
+    // Only the client reports on what it received
+    assert_eq!(sent.flow.reports, 0);
+    let flow = &received.flow;
+    assert!(flow.reports > 0);
+    assert!(flow.unmatched_reports < flow.reports);
+    // Within the one packet the capture missed
+    assert!(flow.advertised_bytes >= flow.captured_bytes);
+    assert!(flow.advertised_bytes - flow.captured_bytes < 500);
+}
@@ -0,0 +1,47 @@
+//! Helpers shared by the integration tests
+//!
+//! Each test binary uses only some of these.
+#![allow(dead_code)]
+
+use acprotocol::network::pcap::PcapIterator;
+use acprotocol::network::{DatagramStream, ParsedDatagram, RawMessage};
+use std::path::Path;
+use std::time::Duration;
+
+/// A capture of one character's session, starting after it entered the world
+const SAMPLE_CAPTURE: &str = "../../data/pcaps/pkt_2025-11-18_1763490291_log.pcap";
+
+/// The sample capture's bytes, or `None` if it isn't there, in which case
+/// the test should skip
+pub fn sample_capture() -> Option<Vec<u8>> {
+    let pcap_path = Path::new(SAMPLE_CAPTURE);
+    if !pcap_path.exists() {
+        eprintln!(
+            "Warning: Test pcap file not found at {:?}, skipping test",
+            pcap_path
+        );
+        return None;
+    }
+    Some(std::fs::read(pcap_path).unwrap())
+}
+
+/// Every datagram of the sample capture parsed in order, along with its
+/// capture time
+pub fn sample_datagrams() -> Option<Vec<(ParsedDatagram, Duration)>> {
+    let bytes = sample_capture()?;
+    let datagrams = DatagramStream::new(PcapIterator::<&[u8]>::from_bytes(&bytes).unwrap())
+        .map(Result::unwrap)
+        .collect();
+    Some(datagrams)
+}
+
+/// Every message reassembled from the sample capture, in order
+pub fn sample_messages() -> Option<Vec<RawMessage>> {
+    let datagrams = sample_datagrams()?;
+    Some(
+        datagrams
+            .into_iter()
+            .flat_map(|(parsed, _)| parsed.messages)
+            .collect(),
+    )
+}
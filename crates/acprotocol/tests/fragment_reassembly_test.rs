@@ -0,0 +1,127 @@
+use acprotocol::message::Direction;
+use acprotocol::network::FragmentAssembler;
+use acprotocol::network::pcap::PcapIterator;
+use std::net::SocketAddrV4;
+
+const CHUNK_SIZE: usize = 448;
+
+/// Build a single AC packet carrying one fragment chunk
+fn ac_packet(sequence: u32, id: u32, count: u16, index: u16, chunk: &[u8]) -> Vec<u8> {
+    let fragment_size = 16 + chunk.len() as u16;
+
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&sequence.to_le_bytes());
+    packet.extend_from_slice(&0x4u32.to_le_bytes()); // BLOB_FRAGMENTS
+    packet.extend_from_slice(&0u32.to_le_bytes()); // checksum
+    packet.extend_from_slice(&0u16.to_le_bytes()); // id
+    packet.extend_from_slice(&0u16.to_le_bytes()); // time
+    packet.extend_from_slice(&fragment_size.to_le_bytes()); // size
+    packet.extend_from_slice(&0u16.to_le_bytes()); // iteration
+
+    packet.extend_from_slice(&sequence.to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&count.to_le_bytes());
+    packet.extend_from_slice(&fragment_size.to_le_bytes());
+    packet.extend_from_slice(&index.to_le_bytes());
+    packet.extend_from_slice(&9u16.to_le_bytes()); // group
+    packet.extend_from_slice(chunk);
+    packet
+}
+
+/// Wrap a UDP payload in Ethernet/IPv4/UDP headers
+fn udp_frame(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; 14];
+    frame[12] = 0x08; // EtherType: IPv4
+    frame.extend_from_slice(&[0x45, 0x00]);
+    frame.extend_from_slice(&(28 + payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00]);
+    frame.extend_from_slice(&source.ip().octets());
+    frame.extend_from_slice(&destination.ip().octets());
+    frame.extend_from_slice(&source.port().to_be_bytes());
+    frame.extend_from_slice(&destination.port().to_be_bytes());
+    frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&[0x00, 0x00]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Build a little-endian pcap file from Ethernet frames
+fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut file = Vec::new();
+    file.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    file.extend_from_slice(&2u16.to_le_bytes());
+    file.extend_from_slice(&4u16.to_le_bytes());
+    file.extend_from_slice(&[0u8; 8]);
+    file.extend_from_slice(&65535u32.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes()); // Ethernet
+
+    for (i, frame) in frames.iter().enumerate() {
+        file.extend_from_slice(&(i as u32).to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(frame);
+    }
+    file
+}
+
+/// A two-chunk CommunicationTurbineChat message filled with `tag`
+fn message_chunks(tag: u8) -> [Vec<u8>; 2] {
+    let mut first = vec![0xDE, 0xF7, 0x00, 0x00];
+    first.resize(CHUNK_SIZE, tag);
+    [first, vec![tag; 8]]
+}
+
+/// Two clients talking to the same server at once, with every message in
+/// both directions of both sessions using the same fragment sequence and id
+#[test]
+fn test_interleaved_sessions_reassemble_independently() {
+    let server: SocketAddrV4 = "10.0.0.100:9000".parse().unwrap();
+    let client_a: SocketAddrV4 = "10.0.0.1:50000".parse().unwrap();
+    let client_b: SocketAddrV4 = "10.0.0.2:50000".parse().unwrap();
+
+    let streams = [
+        (client_a, server, 0xA1),
+        (client_b, server, 0xB1),
+        (server, client_a, 0xA2),
+        (server, client_b, 0xB2),
+    ];
+
+    let mut frames = Vec::new();
+    for index in 0..2 {
+        for (source, destination, tag) in streams {
+            let chunk = &message_chunks(tag)[index];
+            let packet = ac_packet(2, 0x8000_0001, 2, index as u16, chunk);
+            frames.push(udp_frame(source, destination, &packet));
+        }
+    }
+
+    let capture = pcap(&frames);
+    let mut assembler = FragmentAssembler::new();
+    let mut messages = Vec::new();
+    for packet in PcapIterator::<&[u8]>::from_bytes(&capture).unwrap() {
+        let packet = packet.unwrap();
+        messages.extend(assembler.parse_packet_payload(&packet.data).unwrap());
+    }
+
+    assert_eq!(messages.len(), 4);
+
+    for (message, (_, destination, tag)) in messages.iter().zip(streams) {
+        let expected_direction = if destination == server {
+            Direction::ClientToServer
+        } else {
+            Direction::ServerToClient
+        };
+        assert_eq!(message.direction, expected_direction);
+        assert_eq!(message.opcode, 0xF7DE);
+        assert_eq!(message.sequence, 2);
+
+        // Both chunks must come from the same session and direction
+        assert!(message.data[4..CHUNK_SIZE].iter().all(|&b| b == tag));
+        assert!(
+            message.data[CHUNK_SIZE..CHUNK_SIZE + 8]
+                .iter()
+                .all(|&b| b == tag)
+        );
+    }
+}
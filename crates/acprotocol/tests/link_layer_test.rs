@@ -0,0 +1,81 @@
+mod common;
+
+use acprotocol::network::MessageStream;
+use acprotocol::network::pcap::{Packet, PcapIterator};
+use acprotocol::network::udp::{LINKTYPE_LINUX_SLL, LINKTYPE_NULL};
+
+/// Write a little-endian pcap with nanosecond timestamps
+fn nanosecond_pcap(link_type: u32, packets: &[(Packet, Vec<u8>)]) -> Vec<u8> {
+    let mut file = 0xa1b23c4du32.to_le_bytes().to_vec();
+    file.extend_from_slice(&[2, 0, 4, 0]);
+    file.extend_from_slice(&[0u8; 8]);
+    file.extend_from_slice(&65535u32.to_le_bytes());
+    file.extend_from_slice(&link_type.to_le_bytes());
+
+    for (packet, frame) in packets {
+        file.extend_from_slice(&packet.ts_sec.to_le_bytes());
+        file.extend_from_slice(&(packet.ts_usec * 1000 + 999).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(frame);
+    }
+    file
+}
+
+/// Re-frame the sample capture's Ethernet frames for other link types and
+/// check the same messages come out
+#[test]
+fn test_link_types_match_ethernet() {
+    let Some(bytes) = common::sample_capture() else {
+        return;
+    };
+    let packets: Vec<Packet> = PcapIterator::<&[u8]>::from_bytes(&bytes)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    let expected: Vec<_> = MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&bytes).unwrap())
+        .map(Result::unwrap)
+        .collect();
+    assert!(!expected.is_empty());
+
+    let reframe = |header: &[u8]| -> Vec<(Packet, Vec<u8>)> {
+        packets
+            .iter()
+            .map(|packet| {
+                let mut frame = header.to_vec();
+                frame.extend_from_slice(&packet.data[14..]);
+                (packet.clone(), frame)
+            })
+            .collect()
+    };
+
+    // Linux cooked capture: packet type, address type and length, address,
+    // then the protocol
+    let mut sll = vec![0u8; 14];
+    sll.extend_from_slice(&0x0800u16.to_be_bytes());
+    let loopback = 2u32.to_le_bytes();
+
+    for (link_type, header) in [(LINKTYPE_LINUX_SLL, &sll[..]), (LINKTYPE_NULL, &loopback)] {
+        let capture = nanosecond_pcap(link_type, &reframe(header));
+
+        let reframed: Vec<Packet> = PcapIterator::<&[u8]>::from_bytes(&capture)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        for (a, b) in packets.iter().zip(&reframed) {
+            assert_eq!((a.ts_sec, a.ts_usec), (b.ts_sec, b.ts_usec));
+            assert_eq!(b.link_type, link_type);
+        }
+
+        let messages: Vec<_> =
+            MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&capture).unwrap())
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(messages.len(), expected.len());
+        for (a, b) in expected.iter().zip(&messages) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.direction, b.direction);
+            assert_eq!(a.data, b.data);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use acprotocol::enums::{ChatFragmentType, FragmentGroup};
+use acprotocol::message::{C2SMessage, GameActionMessage, S2CMessage};
+use acprotocol::messages::s2c;
+use acprotocol::network::{ChecksumStatus, FragmentAssembler, PacketBuilder, UdpDatagram};
+use acprotocol::types::ObjectId;
+use acprotocol::{gameactions, message::Direction};
+use std::io::Cursor;
+use std::net::SocketAddr;
+
+/// A server stand-in and a test client exchanging real wire-format packets
+#[test]
+fn test_builder_round_trips_through_assembler() {
+    let server: SocketAddr = "10.0.0.100:9000".parse().unwrap();
+    let client: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+
+    // Long enough to span several fragment chunks
+    let speech = s2c::CommunicationHearSpeech {
+        message: "The quick brown fox jumps over the lazy dog. ".repeat(30),
+        sender_name: "Town Crier".to_string(),
+        sender_id: ObjectId(0x5000_0001),
+        type_: ChatFragmentType::Speech,
+    };
+    let mut server_out = PacketBuilder::new();
+    server_out
+        .queue_message(
+            FragmentGroup::Private,
+            &S2CMessage::CommunicationHearSpeech(speech.clone()),
+        )
+        .unwrap();
+
+    let mut client_out = PacketBuilder::new();
+    client_out
+        .queue_message(
+            FragmentGroup::Private,
+            &C2SMessage::OrderedGameAction {
+                sequence: 1,
+                action: GameActionMessage::CharacterLoginCompleteNotification(
+                    gameactions::CharacterLoginCompleteNotification {},
+                ),
+            },
+        )
+        .unwrap();
+    client_out.ack(server_out.sequence());
+
+    let mut assembler = FragmentAssembler::new();
+    let mut messages = Vec::new();
+    let flows = [
+        (server, client, server_out.build_all().unwrap()),
+        (client, server, client_out.build_all().unwrap()),
+    ];
+    for (source, destination, packets) in &flows {
+        for packet in packets {
+            let datagram = UdpDatagram {
+                source: *source,
+                destination: *destination,
+                payload: packet,
+            };
+            let parsed = assembler.parse_datagram_packets(&datagram).unwrap();
+            assert!(
+                parsed
+                    .packets
+                    .iter()
+                    .all(|p| p.checksum == ChecksumStatus::Valid)
+            );
+            messages.extend(parsed.messages);
+        }
+    }
+
+    assert!(flows[0].2.len() > 1);
+    assert_eq!(messages.len(), 2);
+    assert!(assembler.diagnostics().is_clean());
+
+    assert_eq!(messages[0].direction, Direction::ServerToClient);
+    match S2CMessage::read(&mut Cursor::new(&messages[0].data[..])).unwrap() {
+        S2CMessage::CommunicationHearSpeech(read) => {
+            assert_eq!(read.message, speech.message);
+            assert_eq!(read.sender_name, speech.sender_name);
+            assert_eq!(read.sender_id, speech.sender_id);
+        }
+        other => panic!("unexpected message {other:?}"),
+    }
+
+    assert_eq!(messages[1].direction, Direction::ClientToServer);
+    assert!(matches!(
+        C2SMessage::read(&mut Cursor::new(&messages[1].data[..])).unwrap(),
+        C2SMessage::OrderedGameAction { sequence: 1, .. }
+    ));
+}
@@ -0,0 +1,60 @@
+#![cfg(feature = "cli")]
+
+mod common;
+
+use acprotocol::cli::pcap::{MessageFilter, select_packets};
+use acprotocol::network::MessageStream;
+use acprotocol::network::pcap::{PcapIterator, PcapWriter};
+
+/// Copying the selected packets into a new capture keeps every matching
+/// message intact
+#[test]
+fn test_filtered_capture_reassembles_matches() {
+    let Some(bytes) = common::sample_capture() else {
+        return;
+    };
+    let open = || PcapIterator::<&[u8]>::from_bytes(&bytes).unwrap();
+    let filter = MessageFilter::new(None, Some("setappraiseinfo"), None, None).unwrap();
+
+    let expected: Vec<_> = MessageStream::new(open())
+        .map(Result::unwrap)
+        .filter(|m| filter.matches(m))
+        .collect();
+    assert!(!expected.is_empty());
+
+    let selection = select_packets(open(), &filter).unwrap();
+    assert_eq!(selection.messages, expected.len());
+    let total = open().count();
+    assert!(selection.packets.len() < total);
+
+    let mut writer = None;
+    for (index, packet) in open().enumerate() {
+        let packet = packet.unwrap();
+        if selection.packets.contains(&index) {
+            writer
+                .get_or_insert_with(|| PcapWriter::new(Vec::new(), packet.link_type).unwrap())
+                .write_packet(&packet)
+                .unwrap();
+        }
+    }
+    let filtered = writer.unwrap().into_inner();
+
+    let messages: Vec<_> =
+        MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&filtered).unwrap())
+            .map(Result::unwrap)
+            .filter(|m| filter.matches(m))
+            .collect();
+    assert_eq!(messages.len(), expected.len());
+    for (a, b) in expected.iter().zip(&messages) {
+        assert_eq!(a.data, b.data);
+        assert_eq!(a.first_time, b.first_time);
+        assert_eq!(a.last_time, b.last_time);
+    }
+}
+
+#[test]
+fn test_invalid_opcode_filter_is_an_error() {
+    assert!(MessageFilter::new(None, None, Some("0xZZ"), None).is_err());
+    let filter = MessageFilter::new(None, None, Some("0xF7B1"), None).unwrap();
+    assert_eq!(filter.opcode, Some(0xF7B1));
+}
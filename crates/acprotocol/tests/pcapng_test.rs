@@ -0,0 +1,77 @@
+mod common;
+
+use acprotocol::network::MessageStream;
+use acprotocol::network::pcap::{Packet, PcapIterator};
+
+fn block(file: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let mut body = body.to_vec();
+    body.resize(body.len().next_multiple_of(4), 0);
+    let length = (12 + body.len() as u32).to_le_bytes();
+    file.extend_from_slice(&block_type.to_le_bytes());
+    file.extend_from_slice(&length);
+    file.extend_from_slice(&body);
+    file.extend_from_slice(&length);
+}
+
+/// Rewrite classic pcap packets as a pcapng file like Wireshark would save
+fn to_pcapng(packets: &[Packet]) -> Vec<u8> {
+    let mut file = Vec::new();
+
+    let mut section = 0x1A2B3C4Du32.to_le_bytes().to_vec();
+    section.extend_from_slice(&[1, 0, 0, 0]);
+    section.extend_from_slice(&(-1i64).to_le_bytes());
+    block(&mut file, 0x0A0D0D0A, &section);
+
+    let mut interface = (packets[0].link_type as u16).to_le_bytes().to_vec();
+    interface.extend_from_slice(&[0, 0]);
+    interface.extend_from_slice(&0u32.to_le_bytes());
+    block(&mut file, 1, &interface);
+
+    for packet in packets {
+        let timestamp = packet.ts_sec as u64 * 1_000_000 + packet.ts_usec as u64;
+        let mut body = 0u32.to_le_bytes().to_vec();
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&packet.data);
+        block(&mut file, 6, &body);
+    }
+    file
+}
+
+/// The same capture read as pcap and as pcapng produces the same messages
+#[test]
+fn test_pcapng_matches_pcap() {
+    let Some(bytes) = common::sample_capture() else {
+        return;
+    };
+    let open = || PcapIterator::<&[u8]>::from_bytes(&bytes).unwrap();
+    let packets: Vec<Packet> = open().map(Result::unwrap).collect();
+    let pcapng = to_pcapng(&packets);
+
+    let from_pcapng: Vec<Packet> = PcapIterator::<&[u8]>::from_bytes(&pcapng)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(from_pcapng.len(), packets.len());
+    for (a, b) in packets.iter().zip(&from_pcapng) {
+        assert_eq!(
+            (a.ts_sec, a.ts_usec, a.link_type),
+            (b.ts_sec, b.ts_usec, b.link_type)
+        );
+        assert_eq!(a.data, b.data);
+    }
+
+    let messages: Vec<_> = MessageStream::new(open()).map(Result::unwrap).collect();
+    let pcapng_messages: Vec<_> =
+        MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&pcapng).unwrap())
+            .map(Result::unwrap)
+            .collect();
+    assert!(!messages.is_empty());
+    assert_eq!(messages.len(), pcapng_messages.len());
+    for (a, b) in messages.iter().zip(&pcapng_messages) {
+        assert_eq!(a.opcode, b.opcode);
+        assert_eq!(a.data, b.data);
+    }
+}
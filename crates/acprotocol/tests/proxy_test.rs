@@ -0,0 +1,140 @@
+mod common;
+
+use acprotocol::enums::{FragmentGroup, GameAction};
+use acprotocol::gameactions;
+use acprotocol::message::{C2SMessage, Direction, GameActionMessage};
+use acprotocol::network::pcap::PcapIterator;
+use acprotocol::network::udp::is_server_port;
+use acprotocol::network::{
+    FragmentAssembler, HookAction, Isaac, LiveStream, MessageHooks, MessageStream, PacketBuilder,
+    PacketChecksum, RawMessage, UdpDatagram, UdpProxy,
+};
+use acprotocol::writers::ACWritable;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Replaying the sample capture through the proxy, with a stand-in server on
+/// loopback, yields the same messages as reading the capture
+#[test]
+fn test_replayed_capture_through_proxy() {
+    let Some(bytes) = common::sample_capture() else {
+        return;
+    };
+    let expected: Vec<_> = MessageStream::new(PcapIterator::<&[u8]>::from_bytes(&bytes).unwrap())
+        .map(Result::unwrap)
+        .collect();
+
+    let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let server = UdpSocket::bind(loopback).unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let proxy = UdpProxy::bind(&[(loopback, server.local_addr().unwrap())]).unwrap();
+    let listen = proxy.local_addrs()[0];
+    let mut stream = LiveStream::new(proxy);
+    let client = UdpSocket::bind(loopback).unwrap();
+
+    // An empty datagram holds no packets, but tells the server where the
+    // proxy is sending from
+    client.send_to(&[], listen).unwrap();
+    let mut buffer = vec![0u8; 65_535];
+    let (_, upstream) = server.recv_from(&mut buffer).unwrap();
+
+    let mut messages = Vec::new();
+    for packet in PcapIterator::<&[u8]>::from_bytes(&bytes).unwrap() {
+        let packet = packet.unwrap();
+        let datagram = UdpDatagram::from_link_frame(packet.link_type, &packet.data).unwrap();
+        if is_server_port(datagram.source.port()) {
+            server.send_to(datagram.payload, upstream).unwrap();
+        } else {
+            client.send_to(datagram.payload, listen).unwrap();
+            server.recv_from(&mut buffer).unwrap();
+        }
+        // Keep up with the relay so neither side's socket buffer fills
+        while let Some(message) = stream.try_next() {
+            messages.push(message.unwrap());
+        }
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while messages.len() < expected.len() && Instant::now() < deadline {
+        match stream.try_next() {
+            Some(message) => messages.push(message.unwrap()),
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+
+    assert_eq!(messages.len(), expected.len());
+    // The two directions interleave differently than in the capture, but
+    // each one's messages arrive in the same order
+    let going = |messages: &[RawMessage], direction| {
+        messages
+            .iter()
+            .filter(|m| m.direction == direction)
+            .map(|m| (m.opcode, m.data.clone()))
+            .collect::<Vec<_>>()
+    };
+    for direction in [Direction::ClientToServer, Direction::ServerToClient] {
+        assert_eq!(going(&messages, direction), going(&expected, direction));
+    }
+    assert_eq!(stream.diagnostics().duplicate_chunks, 0);
+}
+
+/// A hook rewrites a game action on its way to a stand-in server, which
+/// receives a valid packet holding the rewritten message
+#[test]
+fn test_hooks_rewrite_relayed_messages() {
+    let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let server = UdpSocket::bind(loopback).unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut hooks = MessageHooks::new();
+    hooks.on_game_action(GameAction::CommunicationTalk, |action| {
+        if let GameActionMessage::CommunicationTalk(talk) = action {
+            talk.message = talk.message.replace("hello", "goodbye");
+        }
+        HookAction::Rewrite
+    });
+    let proxy =
+        UdpProxy::bind_with_hooks(&[(loopback, server.local_addr().unwrap())], hooks).unwrap();
+    let client = UdpSocket::bind(loopback).unwrap();
+
+    let talk = |text: &str| C2SMessage::OrderedGameAction {
+        sequence: 1,
+        action: GameActionMessage::CommunicationTalk(gameactions::CommunicationTalk {
+            message: text.to_string(),
+        }),
+    };
+    let mut builder = PacketBuilder::new().with_isaac_seed(0x5EED);
+    builder
+        .queue_message(FragmentGroup::Private, &talk("hello there"))
+        .unwrap();
+    let packet = builder.build_packet().unwrap().unwrap();
+    client.send_to(&packet, proxy.local_addrs()[0]).unwrap();
+
+    let mut buffer = vec![0u8; 65_535];
+    let (length, _) = server.recv_from(&mut buffer).unwrap();
+    let received = &buffer[..length];
+    assert_eq!(received[..4], packet[..4]);
+    let checksum = PacketChecksum::compute(received).unwrap();
+    let sealed = u32::from_le_bytes(received[8..12].try_into().unwrap());
+    assert_eq!(checksum.recover_key(sealed), Isaac::new(0x5EED).next_key());
+
+    let messages = FragmentAssembler::new()
+        .parse_datagram(&UdpDatagram {
+            source: client.local_addr().unwrap(),
+            destination: server.local_addr().unwrap(),
+            payload: received,
+        })
+        .unwrap();
+    let mut expected = Vec::new();
+    talk("goodbye there")
+        .write(&mut std::io::Cursor::new(&mut expected))
+        .unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].data, expected);
+
+    let stats = proxy.hook_stats().unwrap();
+    assert_eq!((stats.messages, stats.rewritten), (1, 1));
+}
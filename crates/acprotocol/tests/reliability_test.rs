@@ -0,0 +1,36 @@
+mod common;
+
+use acprotocol::network::ReliabilityAnalyzer;
+
+/// The sample capture misses one server packet, which the client asks for
+/// and the server resends
+#[test]
+fn test_sample_capture_reliability() {
+    let Some(datagrams) = common::sample_datagrams() else {
+        return;
+    };
+    let mut analyzer = ReliabilityAnalyzer::new();
+    for (parsed, time) in &datagrams {
+        for record in &parsed.packets {
+            analyzer.add_packet(record, Some(*time));
+        }
+    }
+
+    let report = analyzer.finish();
+    assert_eq!(report.sessions.len(), 1);
+    assert_eq!(report.unknown_direction, 0);
+    assert_eq!(report.invalid_checksums, 0);
+
+    let server = &report.sessions[0].server_to_client;
+    assert_eq!(server.retransmit_requests, 1);
+    assert_eq!(server.retransmissions, 1);
+    assert_eq!(server.recovered, 1);
+    assert_eq!(server.lost, 0);
+    assert_eq!(server.unanswered_requests, 0);
+    assert!(server.ack_latency.samples > 0);
+
+    let client = &report.sessions[0].client_to_server;
+    assert_eq!(client.retransmissions, 0);
+    assert_eq!(client.lost, 0);
+    assert!(client.ack_latency.samples > 0);
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use acprotocol::network::{ConnectionOrigin, SessionTracker};
+
+/// The sample capture starts mid-session, so its one connection makes up a
+/// single logical session holding every message
+#[test]
+fn test_sample_capture_is_one_session() {
+    let Some(datagrams) = common::sample_datagrams() else {
+        return;
+    };
+    let mut tracker = SessionTracker::new();
+    let mut ids = Vec::new();
+    for (parsed, time) in &datagrams {
+        ids.extend(parsed.messages.iter().map(|m| m.id));
+        assert_eq!(tracker.add_datagram(parsed, Some(*time)), Some(0));
+    }
+
+    let sessions = tracker.into_sessions();
+    assert_eq!(sessions.len(), 1);
+    let session = &sessions[0];
+    assert_eq!(session.connections.len(), 1);
+    let connection = &session.connections[0];
+    assert_eq!(connection.origin, ConnectionOrigin::Unknown);
+    assert_eq!(connection.packets, 632);
+    assert_eq!(session.messages(), ids.len() as u64);
+    assert_eq!(session.first_message_id, ids.first().copied());
+    assert_eq!(session.last_message_id, ids.last().copied());
+    assert!(session.account.is_none());
+}
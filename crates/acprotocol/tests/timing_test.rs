@@ -0,0 +1,41 @@
+mod common;
+
+use acprotocol::network::TimingAnalyzer;
+
+/// Every echo in the sample capture is answered, and each side's header
+/// clock ticks at its usual rate
+#[test]
+fn test_sample_capture_timing() {
+    let Some(datagrams) = common::sample_datagrams() else {
+        return;
+    };
+    let mut analyzer = TimingAnalyzer::new();
+    for (parsed, time) in &datagrams {
+        for record in &parsed.packets {
+            analyzer.add_packet(record, Some(*time));
+        }
+    }
+
+    let report = analyzer.finish();
+    assert_eq!(report.sessions.len(), 1);
+    let session = &report.sessions[0];
+    assert_eq!(session.rtt.len(), 17);
+    assert_eq!(session.unanswered_echoes, 0);
+    for sample in &session.rtt {
+        assert!(sample.rtt.as_millis() > 0 && sample.rtt.as_millis() < 1000);
+    }
+    assert_eq!(session.server_clock.as_ref().unwrap().samples, 3);
+
+    let client_rate = session
+        .client_header_clock
+        .as_ref()
+        .unwrap()
+        .ticks_per_second;
+    let server_rate = session
+        .server_header_clock
+        .as_ref()
+        .unwrap()
+        .ticks_per_second;
+    assert!((client_rate.unwrap() - 2.0).abs() < 0.05);
+    assert!((server_rate.unwrap() - 1.0).abs() < 0.05);
+}
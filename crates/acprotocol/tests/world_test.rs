@@ -0,0 +1,125 @@
+mod common;
+
+use acprotocol::types::ObjectId;
+use acprotocol::world::{
+    CharacterState, ChatChannel, ChatLog, EnchantmentState, EndReason, Inventory, World,
+};
+
+/// The sample capture starts mid-session, after its objects were created, so
+/// the updates it holds are all to objects the world never heard of
+#[test]
+fn test_sample_capture_world() {
+    let Some(messages) = common::sample_messages() else {
+        return;
+    };
+    let mut world = World::new();
+    for message in &messages {
+        assert!(!world.apply_raw(message));
+    }
+
+    assert_eq!(world.message_index(), messages.len());
+    assert_eq!(world.undecoded(), 0);
+    assert!(world.is_empty());
+}
+
+/// Without the login description, only the private qualities updates can
+/// be applied, since the player's id isn't known
+#[test]
+fn test_sample_capture_character() {
+    let Some(messages) = common::sample_messages() else {
+        return;
+    };
+    let mut character = CharacterState::new();
+    for message in &messages {
+        character.apply_raw(message);
+    }
+
+    assert_eq!(character.id(), None);
+    let diff = CharacterState::new().diff(&character);
+    assert!(!diff.ints.is_empty());
+    assert!(diff.ints.iter().all(|change| change.before.is_none()));
+    // Current vitals can't be set without knowing the maximums
+    assert!(diff.vitals.is_empty());
+}
+
+/// Game events name the player, and the equipment and pack moves in the
+/// sample capture fill in their inventory
+#[test]
+fn test_sample_capture_inventory() {
+    let Some(messages) = common::sample_messages() else {
+        return;
+    };
+    let mut inventory = Inventory::new();
+    for message in &messages {
+        inventory.apply_raw(message);
+    }
+
+    let layout = inventory.layout();
+    assert_eq!(layout.player, Some(ObjectId(0x5000_2B16)));
+    assert_eq!(layout.equipped.len(), 10);
+    assert!(layout.equipped.iter().all(|item| !item.slot.is_empty()));
+    assert!(!layout.pack.is_empty());
+    // Nothing is in both places
+    for item in &layout.equipped {
+        assert!(!inventory.contents(ObjectId(0x5000_2B16)).contains(&item.id));
+    }
+}
+
+/// Buffs in the sample capture are cast and dispelled over and over, and
+/// each cast gets its own span in the timeline
+#[test]
+fn test_sample_capture_enchantments() {
+    let Some(messages) = common::sample_messages() else {
+        return;
+    };
+    let mut enchantments = EnchantmentState::new();
+    let mut updates = 0;
+    for message in &messages {
+        if message.message_type == "MagicUpdateEnchantment" {
+            updates += 1;
+        }
+        enchantments.apply_raw(message);
+    }
+
+    let timeline = enchantments.timeline();
+    assert_eq!(timeline.len(), updates);
+    assert!(
+        timeline
+            .iter()
+            .any(|span| span.end_reason == Some(EndReason::Dispelled))
+    );
+    for span in timeline {
+        assert!(span.started.time.is_some());
+        if let Some(ended) = span.ended {
+            assert!(ended.message_index > span.started.message_index);
+        }
+    }
+}
+
+#[test]
+fn test_sample_capture_chat() {
+    let Some(messages) = common::sample_messages() else {
+        return;
+    };
+    let mut chat = ChatLog::new();
+    let mut textbox = 0;
+    for message in &messages {
+        if message.message_type == "CommunicationTextboxString" {
+            textbox += 1;
+        }
+        chat.apply_raw(message);
+    }
+
+    let lines = chat.lines();
+    let system = lines
+        .iter()
+        .filter(|line| line.channel == ChatChannel::System && line.kind.is_some())
+        .count();
+    assert_eq!(system, textbox);
+    assert!(lines.iter().all(|line| line.time.is_some()));
+    assert!(
+        lines
+            .windows(2)
+            .all(|pair| pair[0].message_index < pair[1].message_index)
+    );
+}
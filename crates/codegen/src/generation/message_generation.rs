@@ -25,7 +25,7 @@ pub fn generate_message_types(
 
     // Generate Direction enum
     out.push_str("/// Message direction\n");
-    out.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]\n");
+    out.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]\n");
     out.push_str("pub enum Direction {\n");
     out.push_str("    ClientToServer,\n");
     out.push_str("    ServerToClient,\n");
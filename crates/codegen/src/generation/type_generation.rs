@@ -100,6 +100,19 @@ pub fn generate_type(protocol_type: &ProtocolType) -> String {
         // No fields, generate empty struct
         let derives = build_derive_string(&protocol_type.extra_derives);
 
+        // Variable-length packed types keep the value they decode to, so it can be written back
+        let packed_type = match type_name.as_str() {
+            "PackedDWORD" => Some("u32"),
+            "PackedWORD" => Some("i16"),
+            _ => None,
+        };
+        if let Some(rust_type) = packed_type {
+            out.push_str(&format!(
+                "{derives}\n#[serde(transparent)]\npub struct {type_name}(pub {rust_type});\n\n"
+            ));
+            return out;
+        }
+
         if safe_type_name.needs_rename {
             out.push_str(&format!(
                 "{derives}\n#[serde(rename = \"{original_type_name}\")]
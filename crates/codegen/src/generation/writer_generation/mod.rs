@@ -81,7 +81,7 @@ pub fn generate_writer_impl(
         let (impl_code, acwritable_code) = match safe_type_name.name.as_str() {
             "PackedDWORD" => {
                 let impl_code = format!(
-                    "impl {} {{\n    pub fn write(&self, _writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {{\n        Ok(())\n    }}\n}}\n\n",
+                    "impl {} {{\n    pub fn write(&self, writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {{\n        crate::writers::write_packed_dword(writer, self.0)?;\n        Ok(())\n    }}\n}}\n\n",
                     safe_type_name.name
                 );
                 let acwritable_code = format!(
@@ -92,7 +92,7 @@ pub fn generate_writer_impl(
             }
             "PackedWORD" => {
                 let impl_code = format!(
-                    "impl {} {{\n    pub fn write(&self, _writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {{\n        Ok(())\n    }}\n}}\n\n",
+                    "impl {} {{\n    pub fn write(&self, writer: &mut dyn ACWriter) -> Result<(), Box<dyn std::error::Error>> {{\n        crate::writers::write_packed_word(writer, self.0)?;\n        Ok(())\n    }}\n}}\n\n",
                     safe_type_name.name
                 );
                 let acwritable_code = format!(
@@ -349,73 +349,14 @@ fn generate_variant_struct_writer_impl(
             }
         }
 
-        // Write the nested switch variant fields (discriminator already written in common fields)
+        // Write the nested switch, which writes its own discriminator before its fields
         let nested_enum_field_name =
             safe_identifier(&nested_switch.switch_field, IdentifierType::Field).name;
-
-        // Generate the nested enum type name
-        let nested_enum_name_raw =
-            format!("{}{}{}", struct_name, nested_switch.switch_field, "Variant");
-        let nested_enum_type_name =
-            safe_identifier(&nested_enum_name_raw, IdentifierType::Type).name;
-
-        out.push_str("        // Write nested switch variant fields\n");
         out.push_str(&format!(
-            "        match &self.{} {{\n",
+            "        self.{}.write(writer)?;\n",
             nested_enum_field_name
         ));
 
-        // Group nested case values by field signature to handle aliased variants
-        let all_case_values: Vec<i64> = nested_switch.variant_fields.keys().copied().collect();
-        let sorted_groups = helpers::group_case_values_by_field_signature(
-            &all_case_values,
-            &nested_switch.variant_fields,
-            &None, // No nested-nested switches
-        );
-
-        // Generate match arms for each unique variant
-        for (_field_sig, (_primary_value, all_values)) in sorted_groups {
-            let mut sorted_values = all_values.clone();
-            sorted_values.sort();
-            let first_value = sorted_values[0];
-            let case_fields = nested_switch
-                .variant_fields
-                .get(&first_value)
-                .expect("Field group must have fields");
-            let variant_name = super::super::helpers::generate_variant_name(first_value);
-
-            out.push_str(&format!(
-                "            {}::{}(variant_struct) => {{\n",
-                nested_enum_type_name, variant_name
-            ));
-
-            // Write only the variant fields, NOT the discriminator
-            for field in case_fields {
-                let field_name = safe_identifier(&field.name, IdentifierType::Field).name;
-                let write_call =
-                    crate::generation::write_generation::primitive_writers::generate_write_call(
-                        ctx,
-                        field,
-                        case_fields,
-                    );
-
-                // Replace self.field access with variant_struct.field
-                let write_call = write_call.replace(
-                    &format!("self.{}", field_name),
-                    &format!("variant_struct.{}", field_name),
-                );
-
-                if write_call.contains('\n') || write_call.trim_end().ends_with('}') {
-                    out.push_str(&format!("                {}\n", write_call));
-                } else {
-                    out.push_str(&format!("                {};\n", write_call));
-                }
-            }
-
-            out.push_str("            },\n");
-        }
-        out.push_str("        }\n");
-
         // Write trailing fields
         for field in &nested_switch.trailing_fields {
             let mut all_fields = field_set.common_fields.clone();
@@ -525,8 +466,11 @@ fn generate_nested_switch_enum_writer(
             "            Self::{variant_name}(variant_struct) => {{\n",
         ));
 
-        // NOTE: Discriminator is written by the parent struct in the common fields section
-        // Do NOT write it here to avoid duplication
+        // The nested reader reads its own u8 discriminator after the parent's common fields
+        out.push_str(&format!(
+            "                write_u8(writer, 0x{:02X})?;\n",
+            first_value
+        ));
 
         // Write case fields
         for field in case_fields {